@@ -1,16 +1,28 @@
-use std::cmp::min;
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::cmp::{min, Ordering};
+use std::collections::{BTreeSet, HashSet};
 use std::convert::Infallible;
 use std::fmt::Display;
 use std::io::{self, BufRead, BufReader};
-use std::slice::Iter;
+use std::slice::{Iter, IterMut};
 use std::str::FromStr;
 
 use libciphey::crypto::Encrypted;
 use libciphey::filetype::Filetype;
+pub use zeroize::Zeroizing;
+
+#[cfg(test)]
+mod tests;
 
 const DELIMETER: char = '=';
 const SENSITIVITY: char = '!';
+const COMMENT: char = '#';
+
+/// The default character that redacted values are masked with.
+pub const MASK: char = '*';
+
+/// The key of the field that holds an entry's icon, by convention.
+pub const ICON: &str = "icon";
 
 /// The key of a [`KeyValuePair`].
 ///
@@ -18,7 +30,7 @@ const SENSITIVITY: char = '!';
 /// are enumerated and their meanings are documented.
 ///
 /// All other unknown keys are handled by `Other`.
-#[derive(PartialEq, Eq, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum Key {
     /// A name for the entry.
     Name,
@@ -31,6 +43,8 @@ pub enum Key {
     /// A URL of the service. This can be a
     Url,
     Notes,
+    /// A comma-separated set of tags, such as `work, shared`.
+    Tags,
     Other(String),
 }
 
@@ -45,11 +59,77 @@ impl FromStr for Key {
             "password" => Self::Password,
             "url" => Self::Url,
             "notes" => Self::Notes,
+            "tags" => Self::Tags,
             _ => Self::Other(s.to_string()),
         })
     }
 }
 
+impl Key {
+    /// The keys that have their own variant, rather than [`Key::Other`].
+    pub const KNOWN: &'static [Key] = &[
+        Key::Name,
+        Key::Username,
+        Key::Email,
+        Key::Password,
+        Key::Url,
+        Key::Notes,
+        Key::Tags,
+    ];
+
+    /// Parses a key like [`FromStr`], but rejects keys that would be a
+    /// [`Key::Other`] unless they are in `allowed`, such as to catch typos in
+    /// a fixed schema.
+    pub fn parse_known(s: &str, allowed: &HashSet<Key>) -> Result<Self, Error> {
+        let key = Key::from(s);
+        if matches!(key, Key::Other(_)) && !allowed.contains(&key) {
+            return Err(Error::UnknownKey(s.to_string()));
+        }
+
+        Ok(key)
+    }
+
+    /// Parses a key like [`FromStr`], but rejects keys that would not be read
+    /// back as the same key, such as an empty key or one containing `=`.
+    pub fn parse_valid(s: &str) -> Result<Self, Error> {
+        let valid = !s.is_empty()
+            && !s.contains([DELIMETER, '\n', '\r'])
+            && !s.ends_with(SENSITIVITY)
+            && !s.starts_with(COMMENT);
+
+        match valid {
+            true => Ok(Key::from(s)),
+            false => Err(Error::InvalidKey(s.to_string())),
+        }
+    }
+
+    /// Returns the position of the key in [`Key::KNOWN`], or the number of
+    /// known keys for a [`Key::Other`] key.
+    fn rank(&self) -> usize {
+        Self::KNOWN
+            .iter()
+            .position(|key| key == self)
+            .unwrap_or(Self::KNOWN.len())
+    }
+}
+
+/// Known keys sort in the order of [`Key::KNOWN`], so that the name comes
+/// first, and [`Key::Other`] keys sort lexicographically after all of them.
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Key::Other(key), Key::Other(other)) => key.cmp(other),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl From<&str> for Key {
     fn from(s: &str) -> Self {
         // Unwrap is safe because [`Self::FromStr::Err`] is [`Infallible`].
@@ -66,6 +146,7 @@ impl Display for Key {
             Key::Password => "password",
             Key::Url => "url",
             Key::Notes => "notes",
+            Key::Tags => "tags",
             Key::Other(v) => v,
         };
 
@@ -73,6 +154,29 @@ impl Display for Key {
     }
 }
 
+/// Keys serialize as the same string used in the key/value format, so
+/// [`Key::Other`] round-trips as its raw string.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Key {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Key {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ok(Key::from(s.as_str()))
+    }
+}
+
 /// Contains the data associated with a [`Key`] in a [`KeyValuePair`].
 ///
 /// A [`Sensitive`] value indicates to client applications that its data is
@@ -84,11 +188,112 @@ impl Display for Key {
 /// necessary to access the value associated with the [`KeyValuePair`]. The
 /// treatment of [`Sensitive`] and [`Insensitive`] values is entirely up to the
 /// client applications.
+///
+/// The data of a [`Sensitive`] value is wiped from memory when it is dropped.
+///
+/// With the `serde` feature, a value serializes as a map with a single
+/// `sensitive` or `insensitive` field holding its data.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Value {
-    Sensitive(String),
+    Sensitive(
+        #[cfg_attr(feature = "serde", serde(with = "serde_zeroizing"))]
+        Zeroizing<String>,
+    ),
     Insensitive(String),
 }
 
+/// Serializes the data of a sensitive value as a plain string.
+#[cfg(feature = "serde")]
+mod serde_zeroizing {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use zeroize::Zeroizing;
+
+    pub fn serialize<S>(
+        value: &Zeroizing<String>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(value)
+    }
+
+    pub fn deserialize<'de, D>(
+        deserializer: D,
+    ) -> Result<Zeroizing<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer).map(Zeroizing::new)
+    }
+}
+
+/// Sensitive data is redacted so that it never appears in logs or panic
+/// messages.
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Sensitive(_) => write!(f, "Sensitive(***)"),
+            Value::Insensitive(value) => {
+                f.debug_tuple("Insensitive").field(value).finish()
+            }
+        }
+    }
+}
+
+impl Value {
+    /// Creates a [`Value::Sensitive`] holding `data`.
+    pub fn sensitive(data: impl Into<String>) -> Self {
+        Value::Sensitive(Zeroizing::new(data.into()))
+    }
+
+    /// Creates a [`Value::Insensitive`] holding `data`.
+    pub fn insensitive(data: impl Into<String>) -> Self {
+        Value::Insensitive(data.into())
+    }
+
+    /// Returns the underlying data regardless of its sensitivity.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Value::Sensitive(value) => value,
+            Value::Insensitive(value) => value,
+        }
+    }
+
+    /// Returns `true` if the value is [`Value::Sensitive`].
+    pub fn is_sensitive(&self) -> bool {
+        matches!(self, Value::Sensitive(_))
+    }
+
+    /// Returns the underlying data mutably, so that it can be changed while
+    /// keeping the sensitivity of the value.
+    pub fn as_mut_string(&mut self) -> &mut String {
+        match self {
+            Value::Sensitive(value) => value,
+            Value::Insensitive(value) => value,
+        }
+    }
+
+    /// Changes the sensitivity of the value, keeping its data.
+    pub fn set_sensitive(&mut self, sensitive: bool) {
+        let data = match self {
+            // Taking the string moves its buffer without copying the data.
+            Value::Sensitive(data) => std::mem::take(&mut **data),
+            Value::Insensitive(data) => std::mem::take(data),
+        };
+
+        *self = if sensitive {
+            Value::sensitive(data)
+        } else {
+            Value::Insensitive(data)
+        };
+    }
+}
+
+/// The `Debug` output of a pair redacts sensitive values, like [`Value`].
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KeyValuePair {
     pub key: Key,
     pub value: Value,
@@ -103,44 +308,121 @@ impl KeyValuePair {
     }
 }
 
+impl KeyValuePair {
+    /// Parses a key/value pair from a line in the default format, like
+    /// [`FromStr`].
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        Self::parse_with(s, &KvFormat::default())
+    }
+
+    /// Parses a key/value pair from a line in the provided format.
+    pub fn parse_with(s: &str, format: &KvFormat) -> Result<Self, Error> {
+        Self::parse_line(s.to_string(), format)
+    }
+
+    /// Parses a key/value pair from an owned line, reusing the line's buffer
+    /// for the value. Only keys that are not well-known are allocated.
+    fn parse_line(mut line: String, format: &KvFormat) -> Result<Self, Error> {
+        let delimiter = match line.find(format.delimiter) {
+            Some(delimiter) => delimiter,
+            None => return Err(Error::MissingDelimeter(line)),
+        };
+
+        let key = &line[..delimiter];
+        let (key, sensitive) = match key.strip_suffix(format.sensitivity) {
+            Some(key) => (key, true),
+            None => (key, false),
+        };
+        let key = match &format.allowed_keys {
+            Some(allowed) => Key::parse_known(key, allowed)?,
+            None => Key::from(key),
+        };
+
+        // Shift the value to the start of the buffer.
+        line.drain(..delimiter + format.delimiter.len_utf8());
+        let value = match sensitive {
+            true => Value::sensitive(line),
+            false => Value::Insensitive(line),
+        };
+
+        Ok(Self { key, value })
+    }
+
+    /// Formats the key/value pair as a line in the provided format.
+    ///
+    /// The line holds the value, so it is zeroized on drop.
+    pub fn format_with(&self, format: &KvFormat) -> Zeroizing<String> {
+        let mut line = Zeroizing::new(String::new());
+        // Writing to a `String` cannot fail.
+        let _ = self.write_with(&mut *line, format);
+        line
+    }
+
+    /// Writes the key/value pair as a line in the provided format, without
+    /// copying the value into an intermediate buffer.
+    fn write_with<W>(
+        &self,
+        writer: &mut W,
+        format: &KvFormat,
+    ) -> std::fmt::Result
+    where
+        W: std::fmt::Write,
+    {
+        write!(writer, "{}", self.key)?;
+        if let Value::Sensitive(_) = self.value {
+            writer.write_char(format.sensitivity)?;
+        }
+        writer.write_char(format.delimiter)?;
+        writer.write_str(self.value.as_str())
+    }
+}
+
 impl FromStr for KeyValuePair {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if let Some((key, value)) = s.split_once(DELIMETER) {
-            let mut key: String = key.to_string();
-
-            let value: Value = match key.ends_with(SENSITIVITY) {
-                true => Value::Sensitive(value.to_string()),
-                false => Value::Insensitive(value.to_string()),
-            };
+        Self::parse_with(s, &KvFormat::default())
+    }
+}
 
-            if key.ends_with(SENSITIVITY) {
-                assert_eq!(key.pop(), Some(SENSITIVITY));
-            }
+impl TryFrom<&str> for KeyValuePair {
+    type Error = Error;
 
-            let key: Key = Key::from(key.as_str());
-            Ok(Self { key, value })
-        } else {
-            Err(Error::MissingDelimeter(s.to_string()))
-        }
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Self::parse(s)
     }
 }
 
 impl Display for KeyValuePair {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let sensitivity = if let Value::Sensitive(_) = self.value {
-            SENSITIVITY.to_string()
-        } else {
-            String::default()
-        };
+        self.write_with(f, &KvFormat::default())
+    }
+}
 
-        let value = match &self.value {
-            Value::Sensitive(value) => value,
-            Value::Insensitive(value) => value,
-        };
+/// The characters that separate the parts of each line of a [`KvStore`], and
+/// the keys that a line may have.
+///
+/// The default format uses `=` between the key and the value, and marks
+/// sensitive keys with a trailing `!`, as in `password!=hunter2`. A value may
+/// contain the delimiter, but a key may not. Any key is accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KvFormat {
+    /// Separates the key from the value.
+    pub delimiter: char,
+    /// Appended to the key of a sensitive value.
+    pub sensitivity: char,
+    /// If set, keys are parsed with [`Key::parse_known`], so that keys other
+    /// than the [`Key::KNOWN`] ones must be in this set.
+    pub allowed_keys: Option<HashSet<Key>>,
+}
 
-        write!(f, "{}{}{}{}", self.key, sensitivity, DELIMETER, value)
+impl Default for KvFormat {
+    fn default() -> Self {
+        Self {
+            delimiter: DELIMETER,
+            sensitivity: SENSITIVITY,
+            allowed_keys: None,
+        }
     }
 }
 
@@ -157,6 +439,243 @@ impl KvStore {
     pub fn iter(&self) -> Iter<'_, KeyValuePair> {
         self.key_value_pairs.iter()
     }
+
+    /// Iterates over the pairs in order, allowing their keys and values to
+    /// be changed in place.
+    pub fn iter_mut(&mut self) -> IterMut<'_, KeyValuePair> {
+        self.key_value_pairs.iter_mut()
+    }
+
+    /// Returns the value of the first pair with the provided key, if any.
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.iter()
+            .find(|kv_pair| &kv_pair.key == key)
+            .map(|kv_pair| &kv_pair.value)
+    }
+
+    /// Returns a mutable reference to the value of the first pair with the
+    /// provided key, if any.
+    pub fn get_mut(&mut self, key: &Key) -> Option<&mut Value> {
+        self.key_value_pairs
+            .iter_mut()
+            .find(|kv_pair| &kv_pair.key == key)
+            .map(|kv_pair| &mut kv_pair.value)
+    }
+
+    /// Appends a pair to the end of the store.
+    pub fn push(&mut self, kv_pair: KeyValuePair) {
+        self.key_value_pairs.push(kv_pair);
+    }
+
+    /// Removes every pair with the provided key, returning the value of the
+    /// first one, if any.
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        let mut removed = None;
+        let mut kept = Vec::with_capacity(self.key_value_pairs.len());
+
+        for kv_pair in self.key_value_pairs.drain(..) {
+            if &kv_pair.key != key {
+                kept.push(kv_pair);
+            } else if removed.is_none() {
+                removed = Some(kv_pair.value);
+            }
+        }
+
+        self.key_value_pairs = kept;
+        removed
+    }
+
+    /// Keeps only the pairs for which `f` returns `true`, in their order.
+    pub fn retain(&mut self, f: impl FnMut(&KeyValuePair) -> bool) {
+        self.key_value_pairs.retain(f);
+    }
+
+    /// Returns the keys of the values that are longer than `limit` bytes, in
+    /// order.
+    pub fn oversized(&self, limit: usize) -> Vec<&Key> {
+        self.iter()
+            .filter(|kv_pair| kv_pair.value.as_str().len() > limit)
+            .map(|kv_pair| &kv_pair.key)
+            .collect()
+    }
+
+    /// Rejects values that are longer than `limit` bytes.
+    ///
+    /// Unlike [`Filetype::validate`], the limit is a choice of the store
+    /// rather than a rule of the format, so it is checked separately.
+    pub fn validate_lengths(&self, limit: usize) -> Result<(), Error> {
+        match self
+            .iter()
+            .find(|kv_pair| kv_pair.value.as_str().len() > limit)
+        {
+            Some(kv_pair) => Err(Error::ValueTooLong {
+                key: kv_pair.key.clone(),
+                length: kv_pair.value.as_str().len(),
+                limit,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the tags of the entry, or an empty set if it has none.
+    pub fn tags(&self) -> BTreeSet<String> {
+        self.get(&Key::Tags)
+            .map(|tags| parse_tags(tags.as_str()))
+            .unwrap_or_default()
+    }
+
+    /// Returns the icon of the entry, for frontends to display alongside it.
+    ///
+    /// By convention, the icon is the insensitive [`ICON`] field, holding a
+    /// URL or an emoji. It is otherwise an ordinary field. A sensitive icon is
+    /// not returned, since it could not be displayed without showing secrets.
+    pub fn icon(&self) -> Option<&str> {
+        match self.get(&Key::from(ICON))? {
+            Value::Insensitive(icon) => Some(icon),
+            Value::Sensitive(_) => None,
+        }
+    }
+
+    /// Merges the pairs of `other` into the store.
+    ///
+    /// Pairs whose keys are not in the store are appended in order. The
+    /// `strategy` decides which value is kept when both stores contain a key,
+    /// and the kept value keeps its sensitivity.
+    pub fn merge(mut self, other: KvStore, strategy: MergeStrategy) -> Self {
+        for kv_pair in other {
+            match (self.get_mut(&kv_pair.key), strategy) {
+                (None, _) | (Some(_), MergeStrategy::KeepBoth) => {
+                    self.push(kv_pair)
+                }
+                (Some(value), MergeStrategy::KeepOther) => {
+                    *value = kv_pair.value
+                }
+                (Some(_), MergeStrategy::KeepSelf) => {}
+            }
+        }
+
+        self
+    }
+
+    /// Deserializes a store in the provided format.
+    pub fn deserialize_with<R>(
+        reader: &mut R,
+        format: &KvFormat,
+    ) -> Result<Self, Error>
+    where
+        R: io::Read,
+    {
+        // Lines are split as bytes so that invalid UTF-8 can be reported
+        // separately from errors reading the entry.
+        let lines = BufReader::new(reader).split(b'\n');
+
+        // Strip the "\r" of a "\r\n" line ending to parse stores saved on
+        // Windows.
+        let key_value_pairs: Result<Vec<String>, Error> = lines
+            .enumerate()
+            .map(|(number, line)| {
+                let mut line = line.map_err(Error::Io)?;
+                if line.ends_with(b"\r") {
+                    line.pop();
+                }
+                String::from_utf8(line)
+                    .map_err(|_| Error::InvalidUtf8 { line: number + 1 })
+            })
+            .collect();
+
+        let key_value_pairs: Vec<String> = key_value_pairs?;
+
+        let key_value_pairs: Result<Vec<KeyValuePair>, Error> = key_value_pairs
+            .into_iter()
+            .filter(|line| !is_ignored(line))
+            .map(|line| KeyValuePair::parse_line(line, format))
+            .collect();
+
+        let key_value_pairs: Vec<KeyValuePair> = key_value_pairs?;
+
+        Ok(Self { key_value_pairs })
+    }
+
+    /// Passes each field that `opts` enables to `visitor`, in display order,
+    /// with sensitive values redacted unless `show_secrets` is set.
+    ///
+    /// This is what [`Filetype::display`] renders as text, so that other
+    /// frontends can render the same fields differently.
+    pub fn visit_fields<V>(
+        &self,
+        opts: &DisplayOptions,
+        show_secrets: bool,
+        visitor: &mut V,
+    ) -> Result<(), V::Error>
+    where
+        V: FieldVisitor,
+    {
+        let mut kv_pairs: Vec<&KeyValuePair> = self.iter().collect();
+        // The sort is stable, so unlisted fields keep their stored order.
+        kv_pairs.sort_by_key(|kv_pair| {
+            opts.field_order
+                .iter()
+                .position(|key| *key == kv_pair.key)
+                .unwrap_or(usize::MAX)
+        });
+
+        for kv_pair in kv_pairs {
+            let key = &kv_pair.key;
+            let sensitive = kv_pair.value.is_sensitive();
+
+            if opts.only_sensitive && !sensitive {
+                continue;
+            }
+            if !opts.show_all && !opts.enabled_keys.contains(key) {
+                continue;
+            }
+
+            // Only show sensitive values if secret_visibility is Show
+            let redacted = sensitive && !show_secrets;
+            let text = match redacted {
+                true => Cow::Owned(
+                    opts.redaction.redact(kv_pair.value.as_str(), opts.mask),
+                ),
+                false => Cow::Borrowed(kv_pair.value.as_str()),
+            };
+
+            let value = DisplayValue {
+                text,
+                sensitive,
+                redacted,
+            };
+            visitor.on_field(key, &value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Serializes the store in the provided format.
+    pub fn serialize_with<W, E>(
+        self,
+        mut writer: &mut E,
+        format: &KvFormat,
+    ) -> Result<(), io::Error>
+    where
+        W: io::Write,
+        E: Encrypted<W>,
+    {
+        // Each part of the line is written straight to the writer, so that
+        // sensitive values are never copied into an unzeroized buffer.
+        struct Line<'a>(&'a KeyValuePair, &'a KvFormat);
+
+        impl Display for Line<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.write_with(f, self.1)
+            }
+        }
+
+        for key_value_pair in self.into_iter() {
+            writeln!(&mut writer, "{}", Line(&key_value_pair, format))?;
+        }
+
+        Ok(())
+    }
 }
 
 impl IntoIterator for KvStore {
@@ -168,20 +687,124 @@ impl IntoIterator for KvStore {
     }
 }
 
-#[derive(Debug)]
+impl<'a> IntoIterator for &'a mut KvStore {
+    type IntoIter = IterMut<'a, KeyValuePair>;
+    type Item = &'a mut KeyValuePair;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+/// Decides which value is kept when two merged stores contain the same key.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Keep the value of the store being merged into.
+    #[default]
+    KeepSelf,
+    /// Keep the value of the store being merged.
+    KeepOther,
+    /// Keep both values, so that the key appears more than once.
+    KeepBoth,
+}
+
+impl FromStr for MergeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep-self" => Ok(Self::KeepSelf),
+            "keep-other" => Ok(Self::KeepOther),
+            "keep-both" => Ok(Self::KeepBoth),
+            _ => Err(format!("unknown merge strategy: {}", s)),
+        }
+    }
+}
+
 pub enum Error {
     Io(io::Error),
+    /// The provided line has no delimiter between its key and value. The line
+    /// may contain a secret, so it is never displayed.
     MissingDelimeter(String),
+    /// The line with the provided number is not valid UTF-8, so the data is
+    /// likely not a key/value store.
+    InvalidUtf8 {
+        line: usize,
+    },
+    /// The store has no `name`, or its name is empty.
+    MissingName,
+    /// The key appears more than once in the store.
+    DuplicateKey(Key),
+    /// The value of the key is longer than the limit, in bytes.
+    ValueTooLong {
+        key: Key,
+        length: usize,
+        limit: usize,
+    },
+    /// The key is not one of the allowed keys.
+    UnknownKey(String),
+    /// The key cannot be written to a store and read back.
+    InvalidKey(String),
+}
+
+/// The line of a [`Error::MissingDelimeter`] is redacted, like sensitive
+/// values.
+impl std::fmt::Debug for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => f.debug_tuple("Io").field(e).finish(),
+            Error::MissingDelimeter(_) => write!(f, "MissingDelimeter(***)"),
+            Error::InvalidUtf8 { line } => {
+                f.debug_struct("InvalidUtf8").field("line", line).finish()
+            }
+            Error::MissingName => write!(f, "MissingName"),
+            Error::DuplicateKey(key) => {
+                f.debug_tuple("DuplicateKey").field(key).finish()
+            }
+            Error::ValueTooLong { key, length, limit } => f
+                .debug_struct("ValueTooLong")
+                .field("key", key)
+                .field("length", length)
+                .field("limit", limit)
+                .finish(),
+            Error::UnknownKey(key) => {
+                f.debug_tuple("UnknownKey").field(key).finish()
+            }
+            Error::InvalidKey(key) => {
+                f.debug_tuple("InvalidKey").field(key).finish()
+            }
+        }
+    }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:#?}", self)
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::MissingDelimeter(_) => {
+                write!(f, "A line is missing the delimiter after its key")
+            }
+            Error::InvalidUtf8 { line } => {
+                write!(f, "Line {} is not valid UTF-8", line)
+            }
+            Error::MissingName => write!(f, "The entry has no name"),
+            Error::DuplicateKey(key) => {
+                write!(f, "The key '{}' appears more than once", key)
+            }
+            Error::ValueTooLong { key, length, limit } => write!(
+                f,
+                "The value of '{}' is {} bytes, over the limit of {}",
+                key, length, limit
+            ),
+            Error::UnknownKey(key) => write!(f, "Unknown key '{}'", key),
+            Error::InvalidKey(key) => write!(f, "Invalid key {:?}", key),
+        }
     }
 }
 
 impl std::error::Error for Error {}
 
+#[derive(Clone)]
 pub struct DisplayOptions {
     /// Whether to show all keys.
     ///
@@ -190,6 +813,150 @@ pub struct DisplayOptions {
     /// out.
     pub show_all: bool,
     pub enabled_keys: HashSet<Key>,
+    /// Whether to hide insensitive values, even if their keys are enabled.
+    pub only_sensitive: bool,
+    /// How sensitive values are redacted when secrets are hidden.
+    pub redaction: Redaction,
+    /// The character that redacted values are masked with, [`MASK`] by
+    /// default.
+    pub mask: char,
+    /// The order to display fields in. Fields whose keys are not listed
+    /// follow in their stored order.
+    pub field_order: Vec<Key>,
+}
+
+/// How a sensitive value is displayed when secrets are hidden.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum Redaction {
+    /// The provided number of mask characters, regardless of the value's
+    /// length.
+    Fixed(usize),
+    /// One mask character per byte of the value, up to 16. An empty value is
+    /// displayed as nothing.
+    #[default]
+    Matched,
+    /// The provided text, such as `<hidden>`.
+    Placeholder(String),
+}
+
+impl Redaction {
+    /// Returns the redacted form of `value`, masked with `mask`.
+    pub fn redact(&self, value: &str, mask: char) -> String {
+        match self {
+            Self::Fixed(len) => mask.to_string().repeat(*len),
+            Self::Matched => mask.to_string().repeat(min(value.len(), 16)),
+            Self::Placeholder(placeholder) => placeholder.clone(),
+        }
+    }
+}
+
+/// A value as it is displayed, after any redaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayValue<'a> {
+    /// The text to display, which is masked if the value is redacted.
+    pub text: Cow<'a, str>,
+    /// Whether the value is [`Value::Sensitive`].
+    pub sensitive: bool,
+    /// Whether the text was redacted, because secrets are hidden.
+    pub redacted: bool,
+}
+
+/// Receives the displayed fields of a [`KvStore`] from
+/// [`KvStore::visit_fields`], to render them.
+///
+/// Closures taking a key and a value implement `FieldVisitor`.
+pub trait FieldVisitor {
+    type Error;
+
+    /// Renders a single field, in display order.
+    fn on_field(
+        &mut self,
+        key: &Key,
+        value: &DisplayValue<'_>,
+    ) -> Result<(), Self::Error>;
+}
+
+impl<F, E> FieldVisitor for F
+where
+    F: FnMut(&Key, &DisplayValue<'_>) -> Result<(), E>,
+{
+    type Error = E;
+
+    fn on_field(
+        &mut self,
+        key: &Key,
+        value: &DisplayValue<'_>,
+    ) -> Result<(), Self::Error> {
+        self(key, value)
+    }
+}
+
+/// Renders fields as `key: value` lines, as [`Filetype::display`] does.
+pub struct TextVisitor<'a, W> {
+    writer: &'a mut W,
+}
+
+impl<'a, W: io::Write> TextVisitor<'a, W> {
+    pub fn new(writer: &'a mut W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: io::Write> FieldVisitor for TextVisitor<'_, W> {
+    type Error = io::Error;
+
+    fn on_field(
+        &mut self,
+        key: &Key,
+        value: &DisplayValue<'_>,
+    ) -> Result<(), Self::Error> {
+        writeln!(self.writer, "{}: {}", key, value.text)
+    }
+}
+
+impl FromStr for Redaction {
+    type Err = String;
+
+    /// Parses `matched`, `fixed:<count>`, `placeholder`, or
+    /// `placeholder:<text>`. The placeholder defaults to `<hidden>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "matched" => Ok(Self::Matched),
+            None if s == "placeholder" => {
+                Ok(Self::Placeholder("<hidden>".to_string()))
+            }
+            Some(("fixed", len)) => len
+                .parse()
+                .map(Self::Fixed)
+                .map_err(|_| format!("invalid redaction length: {}", len)),
+            Some(("placeholder", text)) => {
+                Ok(Self::Placeholder(text.to_string()))
+            }
+            _ => Err(format!("unknown redaction style: {}", s)),
+        }
+    }
+}
+
+/// Parses a comma-separated list of tags, such as the value of [`Key::Tags`].
+///
+/// Whitespace around each tag is trimmed, and empty and repeated tags are
+/// dropped.
+pub fn parse_tags(tags: &str) -> BTreeSet<String> {
+    tags.split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Returns `true` if the line is blank or a comment.
+///
+/// A comment is any line whose first non-whitespace character is
+/// [`COMMENT`]. Comments and blank lines are not preserved, so they are
+/// dropped when an entry is serialized again.
+fn is_ignored(line: &str) -> bool {
+    let line = line.trim_start();
+    line.is_empty() || line.starts_with(COMMENT)
 }
 
 impl Filetype for KvStore {
@@ -200,30 +967,29 @@ impl Filetype for KvStore {
     where
         R: io::Read,
     {
-        let lines = BufReader::new(reader).lines();
-
-        let key_value_pairs: Result<Vec<String>, Error> =
-            lines.map(|line| line.map_err(Error::Io)).collect();
-
-        let key_value_pairs: Vec<String> = key_value_pairs?;
-
-        let key_value_pairs: Result<Vec<KeyValuePair>, Error> = key_value_pairs
-            .into_iter()
-            .map(|line| KeyValuePair::from_str(&line))
-            .collect();
-
-        let key_value_pairs: Vec<KeyValuePair> = key_value_pairs?;
-
-        Ok(Self { key_value_pairs })
+        Self::deserialize_with(reader, &KvFormat::default())
     }
 
-    fn serialize<W, E>(self, mut writer: &mut E) -> Result<(), io::Error>
+    fn serialize<W, E>(self, writer: &mut E) -> Result<(), io::Error>
     where
         W: io::Write,
         E: Encrypted<W>,
     {
-        for key_value_pair in self.into_iter() {
-            writeln!(&mut writer, "{}", key_value_pair)?;
+        self.serialize_with(writer, &KvFormat::default())
+    }
+
+    /// Requires a non-empty name, and that no key appears more than once.
+    fn validate(&self) -> Result<(), Self::Error> {
+        match self.get(&Key::Name) {
+            Some(name) if !name.as_str().is_empty() => {}
+            _ => return Err(Error::MissingName),
+        }
+
+        let mut keys = HashSet::new();
+        for kv_pair in self.iter() {
+            if !keys.insert(&kv_pair.key) {
+                return Err(Error::DuplicateKey(kv_pair.key.clone()));
+            }
         }
 
         Ok(())
@@ -238,32 +1004,6 @@ impl Filetype for KvStore {
     where
         W: io::Write,
     {
-        for kv_pair in self.iter() {
-            let key = &kv_pair.key;
-
-            // Determine whether to show the value
-            let value: String = match &kv_pair.value {
-                Value::Sensitive(value) => {
-                    if show_secrets {
-                        // Only show sensitive values if secret_visibility is
-                        // Show
-                        value.clone()
-                    } else {
-                        // Otherwise, redact the secret with '*'s
-                        // Show up to 16 '*'s.
-                        let len = min(value.len(), 16);
-                        "*".repeat(len)
-                    }
-                }
-                // Always show insensitive values
-                Value::Insensitive(value) => value.to_string(),
-            };
-
-            if opts.show_all || opts.enabled_keys.contains(key) {
-                writeln!(writer, "{}: {}", key, value)?;
-            }
-        }
-
-        Ok(())
+        self.visit_fields(&opts, show_secrets, &mut TextVisitor::new(writer))
     }
 }
@@ -0,0 +1,791 @@
+//! These tests aim to ensure that the key/value format is parsed as expected.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashSet};
+use std::io::{self, Write};
+
+use libciphey::crypto::Encrypted;
+use libciphey::filetype::Filetype;
+
+use crate::{
+    parse_tags, DisplayOptions, DisplayValue, Error, Key, KeyValuePair,
+    KvFormat, KvStore, MergeStrategy, Redaction, Value, MASK,
+};
+
+// Deserializes a `KvStore` from the provided string.
+pub fn parse(input: &str) -> KvStore {
+    KvStore::deserialize(&mut input.as_bytes()).unwrap()
+}
+
+// A writer that passes data through unchanged, to test serialization.
+struct Plaintext(Vec<u8>);
+
+impl Write for Plaintext {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Encrypted<Vec<u8>> for Plaintext {
+    type Error = io::Error;
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        Ok(self.0)
+    }
+}
+
+#[test]
+fn test_comments_and_blank_lines() {
+    let store = parse(
+        "# A comment\nname=example\n\n   # An indented comment\n\
+         password!=hunter2\n   \nurl=https://example.com\n",
+    );
+
+    let keys: Vec<&Key> = store.iter().map(|kv_pair| &kv_pair.key).collect();
+    assert_eq!(keys, [&Key::Name, &Key::Password, &Key::Url]);
+
+    assert!(matches!(
+        store.get(&Key::Password),
+        Some(Value::Sensitive(value)) if value.as_str() == "hunter2"
+    ));
+}
+
+#[test]
+fn test_comment_marker_in_value() {
+    // Only lines starting with the comment marker are comments.
+    let store = parse("notes=see #channel\n");
+    assert_eq!(store.get(&Key::Notes).unwrap().as_str(), "see #channel");
+}
+
+#[test]
+fn test_only_comments() {
+    let store = parse("# Nothing to see here\n\n");
+    assert_eq!(store.iter().count(), 0);
+}
+
+#[test]
+fn test_crlf_line_endings() {
+    let store = parse("name=example\r\npassword!=hunter2\r\n\r\nurl=\r\n");
+
+    let values: Vec<&str> =
+        store.iter().map(|kv_pair| kv_pair.value.as_str()).collect();
+    assert_eq!(values, ["example", "hunter2", ""]);
+    assert!(store.get(&Key::Password).unwrap().is_sensitive());
+}
+
+#[test]
+fn test_invalid_utf8() {
+    let mut data: &[u8] = b"name=example\nsecret!=\xff\xfe\n";
+    let err = KvStore::deserialize(&mut data).err().unwrap();
+    assert!(matches!(err, Error::InvalidUtf8 { line: 2 }));
+}
+
+#[test]
+fn test_display_only_sensitive() {
+    let store = parse("name=example\npassword!=hunter2\nurl=example.com\n");
+
+    let display = |show_all, only_sensitive, show_secrets| {
+        let opts = DisplayOptions {
+            show_all,
+            enabled_keys: HashSet::from([Key::Name]),
+            only_sensitive,
+            redaction: Redaction::default(),
+            mask: MASK,
+            field_order: Vec::new(),
+        };
+        let mut output = Vec::new();
+        store.display(&mut output, opts, show_secrets).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(display(true, true, false), "password: *******\n");
+    assert_eq!(display(true, true, true), "password: hunter2\n");
+    // Enabled keys are hidden if they are insensitive.
+    assert_eq!(display(false, true, true), "");
+    assert_eq!(display(false, false, true), "name: example\n");
+}
+
+#[test]
+fn test_display_field_order() {
+    let store = parse("url=example.com\nname=example\nnote=a\nusername=user\n");
+
+    let display = |field_order| {
+        let opts = DisplayOptions {
+            show_all: true,
+            enabled_keys: HashSet::new(),
+            only_sensitive: false,
+            redaction: Redaction::default(),
+            mask: MASK,
+            field_order,
+        };
+        let mut output = Vec::new();
+        store.display(&mut output, opts, false).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(
+        display(Vec::new()),
+        "url: example.com\nname: example\nnote: a\nusername: user\n"
+    );
+    // Unlisted fields follow in their stored order, and listed keys that the
+    // entry does not have are skipped.
+    assert_eq!(
+        display(vec![Key::Name, Key::from("username"), Key::from("email")]),
+        "name: example\nusername: user\nurl: example.com\nnote: a\n"
+    );
+}
+
+#[test]
+fn test_display_redaction() {
+    let store = parse("name=example\npassword!=hunter2\npin!=\n");
+
+    let display = |redaction| {
+        let opts = DisplayOptions {
+            show_all: true,
+            enabled_keys: HashSet::new(),
+            only_sensitive: true,
+            redaction,
+            mask: MASK,
+            field_order: Vec::new(),
+        };
+        let mut output = Vec::new();
+        store.display(&mut output, opts, false).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    // An empty value is redacted to nothing by default.
+    assert_eq!(display(Redaction::Matched), "password: *******\npin: \n");
+    assert_eq!(display(Redaction::Fixed(3)), "password: ***\npin: ***\n");
+    assert_eq!(
+        display(Redaction::Placeholder("<hidden>".to_string())),
+        "password: <hidden>\npin: <hidden>\n"
+    );
+
+    // Any character can mask values, including multi-byte characters.
+    assert_eq!(Redaction::Matched.redact("hunter2", '•'), "•••••••");
+    assert_eq!(Redaction::Fixed(2).redact("hunter2", '#'), "##");
+
+    // Long values are capped at 16 `*`s.
+    assert_eq!(
+        Redaction::Matched.redact(&"a".repeat(32), MASK),
+        "*".repeat(16)
+    );
+}
+
+#[test]
+fn test_visit_fields() {
+    let store = parse("name=example\npassword!=hunter2\nurl=example.com\n");
+    let opts = DisplayOptions {
+        show_all: false,
+        enabled_keys: HashSet::from([Key::Name, Key::Password]),
+        only_sensitive: false,
+        redaction: Redaction::Fixed(3),
+        mask: MASK,
+        field_order: vec![Key::Password],
+    };
+
+    // Each field is seen with its text and flags, in display order.
+    let visit = |show_secrets| {
+        let mut fields = Vec::new();
+        let mut collect = |key: &Key, value: &DisplayValue<'_>| {
+            let text = value.text.to_string();
+            fields.push((key.clone(), text, value.sensitive, value.redacted));
+            Ok::<_, ()>(())
+        };
+        store
+            .visit_fields(&opts, show_secrets, &mut collect)
+            .unwrap();
+        fields
+    };
+
+    let password = |text: &str, redacted| {
+        (Key::Password, text.to_string(), true, redacted)
+    };
+    let name = (Key::Name, "example".to_string(), false, false);
+    assert_eq!(visit(false), [password("***", true), name.clone()]);
+    assert_eq!(visit(true), [password("hunter2", false), name]);
+
+    // The text adapter renders the same fields.
+    let mut output = Vec::new();
+    store.display(&mut output, opts, false).unwrap();
+    assert_eq!(output, b"password: ***\nname: example\n");
+}
+
+#[test]
+fn test_redaction_from_str() {
+    assert_eq!("matched".parse(), Ok(Redaction::Matched));
+    assert_eq!("fixed:8".parse(), Ok(Redaction::Fixed(8)));
+    assert_eq!(
+        "placeholder".parse(),
+        Ok(Redaction::Placeholder("<hidden>".to_string()))
+    );
+    assert_eq!(
+        "placeholder:[secret]".parse(),
+        Ok(Redaction::Placeholder("[secret]".to_string()))
+    );
+    assert!("fixed:many".parse::<Redaction>().is_err());
+    assert!("stars".parse::<Redaction>().is_err());
+}
+
+#[test]
+fn test_validate() {
+    assert!(parse("name=example\npassword!=hunter2\n")
+        .validate()
+        .is_ok());
+
+    // A name is required, and must not be empty.
+    for input in ["password!=hunter2\n", "name=\n", "name!=\n"] {
+        let err = parse(input).validate().unwrap_err();
+        assert!(matches!(err, Error::MissingName));
+    }
+
+    let err = parse("name=a\nurl=b\nurl=c\n").validate().unwrap_err();
+    assert!(matches!(err, Error::DuplicateKey(Key::Url)));
+}
+
+#[test]
+fn test_tags() {
+    let store = parse("name=example\ntags= work,shared , ,work,\n");
+    assert_eq!(
+        store.get(&Key::from("tags")).unwrap().as_str(),
+        " work,shared , ,work,"
+    );
+    assert_eq!(
+        store.tags().into_iter().collect::<Vec<_>>(),
+        ["shared", "work"]
+    );
+
+    assert!(parse("name=example\n").tags().is_empty());
+    assert!(parse_tags("").is_empty());
+    assert!(parse_tags(" , ").is_empty());
+}
+
+#[test]
+fn test_error_messages() {
+    let err = KvStore::deserialize(&mut &b"name=example\nhunter2\n"[..])
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::MissingDelimeter(_)));
+
+    // The offending line may be a secret, so it is never printed.
+    for message in [err.to_string(), format!("{:?}", err)] {
+        assert!(!message.contains("hunter2"));
+    }
+    assert_eq!(
+        err.to_string(),
+        "A line is missing the delimiter after its key"
+    );
+
+    let err = parse("name=a\nurl=b\nurl=c\n").validate().unwrap_err();
+    assert_eq!(err.to_string(), "The key 'url' appears more than once");
+    assert_eq!(Error::MissingName.to_string(), "The entry has no name");
+    assert_eq!(
+        Error::InvalidUtf8 { line: 2 }.to_string(),
+        "Line 2 is not valid UTF-8"
+    );
+    let io = Error::Io(io::Error::other("disk on fire"));
+    assert_eq!(io.to_string(), "I/O error: disk on fire");
+}
+
+#[test]
+fn test_icon() {
+    let store = parse("name=example\nicon=https://example.com/favicon.ico\n");
+    assert_eq!(store.icon(), Some("https://example.com/favicon.ico"));
+
+    assert_eq!(parse("name=example\nicon=🔑\n").icon(), Some("🔑"));
+    assert_eq!(parse("name=example\n").icon(), None);
+    assert_eq!(parse("name=example\nicon!=🔑\n").icon(), None);
+}
+
+// A format whose delimiter allows values to contain '='.
+fn colon_format() -> KvFormat {
+    KvFormat {
+        delimiter: ':',
+        ..KvFormat::default()
+    }
+}
+
+#[test]
+fn test_deserialize_with_delimiter() {
+    let input = "name:example\nurl:https://example.com/?a=b\npassword!:x:y\n";
+    let store =
+        KvStore::deserialize_with(&mut input.as_bytes(), &colon_format())
+            .unwrap();
+
+    let url = store.get(&Key::Url).unwrap();
+    assert_eq!(url.as_str(), "https://example.com/?a=b");
+
+    // Only the first delimiter separates the key from the value.
+    let password = store.get(&Key::Password).unwrap();
+    assert!(password.is_sensitive());
+    assert_eq!(password.as_str(), "x:y");
+}
+
+#[test]
+fn test_serialize_with_delimiter() {
+    let input = "name:example\nurl:https://example.com/?a=b\npassword!:x:y\n";
+    let store =
+        KvStore::deserialize_with(&mut input.as_bytes(), &colon_format())
+            .unwrap();
+
+    let mut output = Plaintext(Vec::new());
+    store.serialize_with(&mut output, &colon_format()).unwrap();
+    assert_eq!(String::from_utf8(output.0).unwrap(), input);
+
+    // The default format is unchanged.
+    let mut output = Plaintext(Vec::new());
+    parse("url=https://example.com/?a=b\n")
+        .serialize(&mut output)
+        .unwrap();
+    assert_eq!(output.0, b"url=https://example.com/?a=b\n");
+}
+
+#[test]
+fn test_debug_redacts_sensitive_values() {
+    let value = Value::sensitive("hunter2");
+    assert!(!format!("{:?}", value).contains("hunter2"));
+    assert!(!format!("{:#?}", value).contains("hunter2"));
+
+    let kv_pair = KeyValuePair::new("password", value);
+    assert!(!format!("{:?}", kv_pair).contains("hunter2"));
+
+    // Insensitive values are shown.
+    let value = Value::Insensitive("example".into());
+    assert_eq!(format!("{:?}", value), r#"Insensitive("example")"#);
+}
+
+#[test]
+fn test_sensitive_value_data() {
+    let mut value = Value::sensitive("hunter2");
+    assert!(matches!(&value, Value::Sensitive(data) if **data == "hunter2"));
+    assert_eq!(value.as_str(), "hunter2");
+
+    let kv_pair = KeyValuePair::new("password", Value::sensitive("hunter2"));
+    assert_eq!(kv_pair.to_string(), "password!=hunter2");
+
+    // The data is kept when the sensitivity changes either way.
+    value.set_sensitive(false);
+    assert_eq!(value.as_str(), "hunter2");
+    value.set_sensitive(true);
+    assert!(value.is_sensitive());
+    assert_eq!(value.as_str(), "hunter2");
+}
+
+// Returns the serialized pairs of the store.
+fn pairs(store: &KvStore) -> Vec<String> {
+    store.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn test_value_lengths() {
+    let store = parse("name=a\nsecret!=1234\nnote=123456");
+
+    // Values at the limit are allowed, and values above it are not.
+    assert!(store.oversized(6).is_empty());
+    assert_eq!(store.oversized(5), [&Key::from("note")]);
+    assert_eq!(
+        store.oversized(3),
+        [&Key::from("secret"), &Key::from("note")]
+    );
+
+    assert!(store.validate_lengths(6).is_ok());
+    let err = store.validate_lengths(4).unwrap_err();
+    assert!(matches!(
+        &err,
+        Error::ValueTooLong { key, length: 6, limit: 4 } if *key == Key::from("note")
+    ));
+    // The value is never part of the error.
+    assert!(!format!("{} {:?}", err, err).contains("123456"));
+}
+
+// Parses a pair like `KeyValuePair::parse_with` did before it reused the
+// line's buffer, as a reference for the current implementation.
+fn parse_reference(s: &str, format: &KvFormat) -> Option<(Key, String, bool)> {
+    let (key, value) = s.split_once(format.delimiter)?;
+    Some(match key.strip_suffix(format.sensitivity) {
+        Some(key) => (Key::from(key), value.to_string(), true),
+        None => (Key::from(key), value.to_string(), false),
+    })
+}
+
+#[test]
+fn test_parse_parity() {
+    let unicode = KvFormat {
+        delimiter: '→',
+        sensitivity: '🔒',
+        ..KvFormat::default()
+    };
+    let inputs = [
+        "",
+        "=",
+        "!=",
+        "==",
+        "!",
+        "key",
+        "key!",
+        "a=b",
+        "a!=b",
+        "a!!=b",
+        "a=!b",
+        "a=b=c",
+        "a!=b!=c",
+        "=value",
+        "!=value",
+        "a =b",
+        " a= b ",
+        "a\t=b",
+        "name=",
+        "NAME=x",
+        "ключ!=значение",
+        "a→b",
+        "a🔒→b→c",
+        "🔒→",
+        "→",
+        "a🔒🔒→b",
+    ];
+
+    for format in [KvFormat::default(), unicode] {
+        for input in inputs {
+            let parsed = KeyValuePair::parse_with(input, &format);
+            match (parse_reference(input, &format), parsed) {
+                (Some((key, value, sensitive)), Ok(kv_pair)) => {
+                    assert_eq!(kv_pair.key, key, "{:?}", input);
+                    assert_eq!(kv_pair.value.as_str(), value, "{:?}", input);
+                    assert_eq!(kv_pair.value.is_sensitive(), sensitive);
+                }
+                (None, Err(Error::MissingDelimeter(line))) => {
+                    assert_eq!(line, input)
+                }
+                (expected, parsed) => {
+                    panic!("{:?}: {:?} != {:?}", input, expected, parsed)
+                }
+            }
+        }
+    }
+
+    // Every way to parse the default format agrees.
+    for input in inputs {
+        let parsed = [
+            KeyValuePair::parse(input),
+            KeyValuePair::try_from(input),
+            input.parse(),
+        ];
+        let parsed: Vec<Option<String>> = parsed
+            .iter()
+            .map(|kv_pair| kv_pair.as_ref().ok().map(ToString::to_string))
+            .collect();
+        assert!(parsed.windows(2).all(|pair| pair[0] == pair[1]));
+    }
+
+    // Deserializing a large store parses each line the same way.
+    let lines: Vec<String> = (0..5000)
+        .map(|i| {
+            format!("key{}{}=value={}", i, if i % 3 == 0 { "!" } else { "" }, i)
+        })
+        .collect();
+    let store = parse(&lines.join("\n"));
+    for (kv_pair, line) in store.iter().zip(&lines) {
+        let (key, value, sensitive) =
+            parse_reference(line, &KvFormat::default()).unwrap();
+        assert_eq!((&kv_pair.key, kv_pair.value.as_str()), (&key, &*value));
+        assert_eq!(kv_pair.value.is_sensitive(), sensitive);
+    }
+    assert_eq!(store.iter().count(), lines.len());
+}
+
+#[test]
+fn test_push() {
+    let mut store = parse("name=a");
+    store.push(KeyValuePair::new("pin", Value::sensitive("1234")));
+    assert_eq!(pairs(&store), ["name=a", "pin!=1234"]);
+}
+
+#[test]
+fn test_remove() {
+    let mut store = parse("name=a\nurl=x\nsecret!=1\nurl=y");
+
+    // Every pair with the key is removed, and the first value is returned.
+    let removed = store.remove(&Key::Url).unwrap();
+    assert_eq!(removed.as_str(), "x");
+    assert_eq!(pairs(&store), ["name=a", "secret!=1"]);
+
+    let removed = store.remove(&Key::from("secret")).unwrap();
+    assert!(removed.is_sensitive());
+    assert!(store.remove(&Key::Url).is_none());
+    assert_eq!(pairs(&store), ["name=a"]);
+}
+
+#[test]
+fn test_iter_mut() {
+    let mut store = parse("name=a\nurl=x\npin!=1");
+
+    for kv_pair in store.iter_mut() {
+        if kv_pair.key == Key::Url {
+            kv_pair.key = Key::from("website");
+        }
+        kv_pair.value.as_mut_string().push('!');
+    }
+    assert_eq!(pairs(&store), ["name=a!", "website=x!", "pin!=1!"]);
+
+    for kv_pair in &mut store {
+        let sensitive = kv_pair.value.is_sensitive();
+        kv_pair.value.set_sensitive(!sensitive);
+    }
+    assert_eq!(pairs(&store), ["name!=a!", "website!=x!", "pin=1!"]);
+}
+
+#[test]
+fn test_get_mut() {
+    let mut store = parse("name=a\nurl=x\nurl=y");
+
+    // Only the value of the first pair with the key is changed.
+    let value = store.get_mut(&Key::Url).unwrap();
+    *value.as_mut_string() = "z".to_string();
+    value.set_sensitive(true);
+    assert_eq!(pairs(&store), ["name=a", "url!=z", "url=y"]);
+
+    // The data of a value is changed without its sensitivity.
+    store.get_mut(&Key::Url).unwrap().as_mut_string().clear();
+    assert_eq!(pairs(&store), ["name=a", "url!=", "url=y"]);
+    assert!(store.get_mut(&Key::from("secret")).is_none());
+}
+
+#[test]
+fn test_retain() {
+    let mut store = parse("name=a\nsecret!=1\nurl=x\npin!=2");
+    store.retain(|kv_pair| !kv_pair.value.is_sensitive());
+    assert_eq!(pairs(&store), ["name=a", "url=x"]);
+}
+
+// Returns the serialized pairs of the result of merging `b` into `a`.
+fn merge(a: &str, b: &str, strategy: MergeStrategy) -> Vec<String> {
+    parse(a)
+        .merge(parse(b), strategy)
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[test]
+fn test_merge_keep_self() {
+    let merged = merge(
+        "name=a\nsecret!=1\nurl=x",
+        "name=b\nsecret=2\nemail=e",
+        MergeStrategy::KeepSelf,
+    );
+    assert_eq!(merged, ["name=a", "secret!=1", "url=x", "email=e"]);
+}
+
+#[test]
+fn test_merge_keep_other() {
+    // The winning value keeps its own sensitivity, in the original position.
+    let merged = merge(
+        "name=a\nsecret!=1\nurl=x",
+        "name=b\nsecret=2\nemail=e",
+        MergeStrategy::KeepOther,
+    );
+    assert_eq!(merged, ["name=b", "secret=2", "url=x", "email=e"]);
+}
+
+#[test]
+fn test_merge_keep_both() {
+    let merged = merge(
+        "name=a\nsecret!=1",
+        "name=a\nsecret=2\nemail=e",
+        MergeStrategy::KeepBoth,
+    );
+    assert_eq!(
+        merged,
+        ["name=a", "secret!=1", "name=a", "secret=2", "email=e"]
+    );
+}
+
+#[test]
+fn test_merge_strategy_from_str() {
+    assert_eq!("keep-other".parse(), Ok(MergeStrategy::KeepOther));
+    assert!("other".parse::<MergeStrategy>().is_err());
+}
+
+#[cfg(feature = "serde")]
+mod serde {
+    use crate::{Key, KeyValuePair, Value};
+
+    #[test]
+    fn test_key_round_trip() {
+        for key in [Key::Name, Key::Password, Key::Other("pin".to_string())] {
+            let json = serde_json::to_string(&key).unwrap();
+            assert_eq!(json, format!("\"{}\"", key));
+            assert_eq!(serde_json::from_str::<Key>(&json).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn test_value_round_trip() {
+        let json = serde_json::to_string(&Value::sensitive("a")).unwrap();
+        assert_eq!(json, r#"{"sensitive":"a"}"#);
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert!(value.is_sensitive());
+        assert_eq!(value.as_str(), "a");
+
+        let json =
+            serde_json::to_string(&Value::Insensitive("b".into())).unwrap();
+        assert_eq!(json, r#"{"insensitive":"b"}"#);
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert!(!value.is_sensitive());
+        assert_eq!(value.as_str(), "b");
+    }
+
+    #[test]
+    fn test_key_value_pair_round_trip() {
+        let kv_pair = KeyValuePair::new("pin", Value::sensitive("1234"));
+
+        let json = serde_json::to_string(&kv_pair).unwrap();
+        assert_eq!(json, r#"{"key":"pin","value":{"sensitive":"1234"}}"#);
+
+        let kv_pair: KeyValuePair = serde_json::from_str(&json).unwrap();
+        assert_eq!(kv_pair.key, Key::Other("pin".to_string()));
+        assert!(kv_pair.value.is_sensitive());
+        assert_eq!(kv_pair.value.as_str(), "1234");
+    }
+}
+
+#[test]
+fn test_parse_known_keys() {
+    let allowed = HashSet::from([Key::from("secret")]);
+    assert_eq!(
+        Key::parse_known("password", &allowed).unwrap(),
+        Key::Password
+    );
+    assert_eq!(
+        Key::parse_known("secret", &allowed).unwrap(),
+        Key::from("secret")
+    );
+    let err = Key::parse_known("passwrod", &allowed).unwrap_err();
+    assert!(matches!(err, Error::UnknownKey(key) if key == "passwrod"));
+
+    let input = "name=example\npasswrod!=hunter2\n";
+
+    // Any key is accepted by default.
+    let store = parse(input);
+    assert_eq!(
+        store.get(&Key::from("passwrod")).unwrap().as_str(),
+        "hunter2"
+    );
+
+    let strict = KvFormat {
+        allowed_keys: Some(allowed),
+        ..KvFormat::default()
+    };
+    let err = KvStore::deserialize_with(&mut input.as_bytes(), &strict)
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::UnknownKey(key) if key == "passwrod"));
+
+    let input = "name=example\nsecret!=hunter2\n";
+    let store = KvStore::deserialize_with(&mut input.as_bytes(), &strict);
+    assert!(store.is_ok());
+}
+
+#[test]
+fn test_key_order() {
+    let mut keys: Vec<Key> = ["zip", "url", "api_token", "tags", "name"]
+        .into_iter()
+        .chain(["notes", "password", "email", "username", "Name"])
+        .map(Key::from)
+        .collect();
+    keys.sort();
+
+    let keys: Vec<String> = keys.iter().map(Key::to_string).collect();
+    assert_eq!(
+        keys,
+        [
+            "name",
+            "username",
+            "email",
+            "password",
+            "url",
+            "notes",
+            "tags",
+            "Name",
+            "api_token",
+            "zip",
+        ]
+    );
+
+    // Keys can be used in ordered collections.
+    let set = BTreeSet::from([Key::from("totp"), Key::Url, Key::Name]);
+    let set: Vec<&Key> = set.iter().collect();
+    assert_eq!(set, [&Key::Name, &Key::Url, &Key::from("totp")]);
+    assert!(Key::Tags < Key::from("a"));
+    assert_eq!(Key::from("a").cmp(&Key::from("a")), Ordering::Equal);
+}
+
+#[test]
+fn test_parse_valid_keys() {
+    assert_eq!(Key::parse_valid("password").unwrap(), Key::Password);
+    assert_eq!(
+        Key::parse_valid("api_token").unwrap(),
+        Key::from("api_token")
+    );
+
+    for key in ["", "a=b", "pin!", "#pin", "a\nb"] {
+        let err = Key::parse_valid(key).unwrap_err();
+        assert!(matches!(err, Error::InvalidKey(k) if k == key));
+    }
+}
+
+// Includes the files of the test vector with the provided name.
+macro_rules! vector {
+    ($name:literal) => {
+        (
+            $name,
+            include_str!(concat!("../vectors/", $name, ".input")),
+            include_str!(concat!("../vectors/", $name, ".serialized")),
+            include_str!(concat!("../vectors/", $name, ".redacted")),
+        )
+    };
+}
+
+// Test vectors that lock down the format. Each `.input` file must serialize
+// to exactly its `.serialized` file, and display with every field and hidden
+// secrets as exactly its `.redacted` file.
+const VECTORS: &[(&str, &str, &str, &str)] = &[
+    vector!("basic"),
+    vector!("empty_value"),
+    vector!("sensitive_empty_value"),
+    vector!("first_delimiter"),
+    vector!("sensitivity_marker"),
+    vector!("whitespace"),
+    vector!("normalized"),
+    vector!("unicode"),
+];
+
+#[test]
+fn test_vectors() {
+    for (name, input, serialized, redacted) in VECTORS {
+        let opts = DisplayOptions {
+            show_all: true,
+            enabled_keys: HashSet::new(),
+            only_sensitive: false,
+            redaction: Redaction::default(),
+            mask: MASK,
+            field_order: Vec::new(),
+        };
+        let mut output = Vec::new();
+        parse(input).display(&mut output, opts, false).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), *redacted, "{}", name);
+
+        let mut output = Plaintext(Vec::new());
+        parse(input).serialize(&mut output).unwrap();
+        assert_eq!(output.0, serialized.as_bytes(), "{}", name);
+
+        // Serialized stores are read back unchanged.
+        let mut output = Plaintext(Vec::new());
+        parse(serialized).serialize(&mut output).unwrap();
+        assert_eq!(output.0, serialized.as_bytes(), "{}", name);
+    }
+}
@@ -1,51 +1,351 @@
-use std::io::{self, Read, Write};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
 
+use age::armor::ArmoredReader;
+use age::plugin::{self, IdentityPluginV1, RecipientPluginV1};
+use age::secrecy::{ExposeSecret, SecretString};
 use age::stream::{StreamReader, StreamWriter};
+pub use age::IdentityFileEntry as Identity;
 use age::{
-    DecryptError, Decryptor, EncryptError, Encryptor, Identity, Recipient,
+    ssh, x25519, DecryptError, Decryptor, EncryptError, Encryptor, IdentityFile,
 };
 use libciphey::crypto::{self, Decrypted, Encrypted};
 
 #[cfg(test)]
 mod tests;
 
-struct Age {}
+pub struct Age {
+    identities: Vec<Identity>,
+    passphrase: Option<Box<dyn PassphraseSource>>,
+}
 
 impl Age {
-    pub fn new(identities: &[Box<dyn Identity>]) -> Self {
-        Age {}
+    pub fn new(identities: Vec<Identity>) -> Self {
+        Age {
+            identities,
+            passphrase: None,
+        }
+    }
+
+    /// Decrypts passphrase-encrypted entries with the passphrase provided by
+    /// `source`.
+    pub fn with_passphrase(self, source: Box<dyn PassphraseSource>) -> Self {
+        Age {
+            passphrase: Some(source),
+            ..self
+        }
+    }
+
+    /// Converts the loaded identities into identities that age can use for
+    /// decryption. Plugin identities are routed to their plugin binaries.
+    fn age_identities(&self) -> Result<Vec<Box<dyn age::Identity>>, Error> {
+        self.identities
+            .iter()
+            .map(|identity| -> Result<Box<dyn age::Identity>, Error> {
+                match identity {
+                    Identity::Native(identity) => {
+                        Ok(Box::new(identity.clone()))
+                    }
+                    Identity::Plugin(identity) => {
+                        Ok(Box::new(IdentityPluginV1::new(
+                            identity.plugin(),
+                            std::slice::from_ref(identity),
+                            Callbacks,
+                        )?))
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Provides the passphrase for passphrase-encrypted entries, such as by
+/// prompting for it or asking an agent that has cached it.
+pub trait PassphraseSource: Send + Sync {
+    /// Returns the passphrase to decrypt with.
+    fn passphrase(&self) -> io::Result<SecretString>;
+
+    /// Called when the last passphrase returned could not decrypt an entry,
+    /// so that it is not provided again.
+    fn reject(&self) {}
+}
+
+impl<F> PassphraseSource for F
+where
+    F: Fn() -> io::Result<SecretString> + Send + Sync,
+{
+    fn passphrase(&self) -> io::Result<SecretString> {
+        self()
+    }
+}
+
+/// Environment variables that name an identity file, in order of precedence.
+pub const IDENTITY_VARS: &[&str] = &["RAGE_IDENTITY", "AGE_IDENTITY"];
+
+/// Returns the paths of the identity files to load identities from.
+///
+/// Keyfiles passed on the command line take precedence over the first of
+/// [`IDENTITY_VARS`] that is set. If neither provide a keyfile, rage's default
+/// `$XDG_CONFIG_HOME/rage/keys.txt` is used if it exists.
+///
+/// `var` looks up an environment variable, so that tests do not need to
+/// modify the process environment.
+pub fn identity_files<F>(keyfiles: &[PathBuf], var: F) -> Vec<PathBuf>
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    if !keyfiles.is_empty() {
+        return keyfiles.to_vec();
+    }
+
+    if let Some(path) = IDENTITY_VARS.iter().find_map(|name| var(name)) {
+        return vec![PathBuf::from(path)];
+    }
+
+    let config_dir = var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| var("HOME").map(|home| Path::new(&home).join(".config")));
+
+    config_dir
+        .map(|dir| dir.join("rage").join("keys.txt"))
+        .filter(|path| path.exists())
+        .into_iter()
+        .collect()
+}
+
+/// Generates a new native identity, returning the contents of an identity file
+/// that holds it and its recipient, in the format of `rage-keygen`.
+pub fn generate_identity() -> (String, String) {
+    let identity = x25519::Identity::generate();
+    let recipient = identity.to_public().to_string();
+    let contents = format!(
+        "# public key: {}\n{}\n",
+        recipient,
+        identity.to_string().expose_secret()
+    );
+    (contents, recipient)
+}
+
+/// Loads every identity from the provided identity files.
+pub fn load_identities(paths: &[PathBuf]) -> Result<Vec<Identity>, Error> {
+    let mut identities = Vec::new();
+
+    for path in paths {
+        let file = IdentityFile::from_file(path.to_string_lossy().into_owned())
+            .map_err(|e| Error::IdentityFile(path.clone(), e))?;
+        identities.extend(file.into_identities());
     }
+
+    Ok(identities)
+}
+
+/// A public key that an entry can be encrypted to.
+///
+/// SSH recipients are `ssh-ed25519` or `ssh-rsa` public keys. Plugin recipients
+/// have the form `age1<plugin name>1...` and are encrypted
+/// to by the `age-plugin-<plugin name>` binary, which must be on the `PATH`.
+#[derive(Clone)]
+pub enum Recipient {
+    Native(x25519::Recipient),
+    Ssh(ssh::Recipient),
+    Plugin(plugin::Recipient),
+}
+
+impl crypto::Recipient for Recipient {}
+
+impl std::fmt::Display for Recipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Recipient::Native(recipient) => recipient.fmt(f),
+            Recipient::Ssh(recipient) => recipient.fmt(f),
+            Recipient::Plugin(recipient) => recipient.fmt(f),
+        }
+    }
+}
+
+impl TryFrom<String> for Recipient {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if let Ok(recipient) = s.parse() {
+            Ok(Self::Native(recipient))
+        } else if let Ok(recipient) = s.parse() {
+            Ok(Self::Ssh(recipient))
+        } else if let Ok(recipient) = s.parse() {
+            Ok(Self::Plugin(recipient))
+        } else {
+            Err(Error::InvalidRecipient(s))
+        }
+    }
+}
+
+/// Reads a recipients file in the format of age's `-R` option, and returns
+/// its recipients.
+pub fn read_recipients_file(path: &Path) -> Result<Vec<String>, Error> {
+    let recipients_file = |e| Error::RecipientsFile(path.to_path_buf(), e);
+
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| recipients_file(Box::new(Error::Io(e))))?;
+
+    parse_recipients_file(&contents).map_err(|e| recipients_file(Box::new(e)))
+}
+
+/// Parses the contents of a recipients file in the format of age's `-R`
+/// option.
+///
+/// As in age, every line must be a recipient, except for empty lines and
+/// lines starting with `#`. Lines are not trimmed, so an indented comment or
+/// a recipient followed by a comment is malformed.
+pub fn parse_recipients_file(contents: &str) -> Result<Vec<String>, Error> {
+    let mut recipients = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // The line is not included in the error, since it could be an
+        // identity pasted into the wrong file.
+        Recipient::try_from(line.to_string())
+            .map_err(|_| Error::MalformedRecipient(index + 1))?;
+        recipients.push(line.to_string());
+    }
+
+    Ok(recipients)
 }
 
-struct DecryptedReader<R>(StreamReader<R>);
+/// Converts recipients into recipients that age can encrypt to. Plugin
+/// recipients are grouped so that each plugin binary is only invoked once.
+fn age_recipients(
+    recipients: Vec<Recipient>,
+) -> Result<Vec<Box<dyn age::Recipient>>, Error> {
+    let mut age_recipients: Vec<Box<dyn age::Recipient>> = Vec::new();
+    let mut plugins: BTreeMap<String, Vec<plugin::Recipient>> = BTreeMap::new();
+
+    for recipient in recipients {
+        match recipient {
+            Recipient::Native(recipient) => {
+                age_recipients.push(Box::new(recipient))
+            }
+            Recipient::Ssh(recipient) => {
+                age_recipients.push(Box::new(recipient))
+            }
+            Recipient::Plugin(recipient) => plugins
+                .entry(recipient.plugin().to_string())
+                .or_default()
+                .push(recipient),
+        }
+    }
+
+    for (name, recipients) in plugins {
+        let recipient =
+            RecipientPluginV1::new(&name, &recipients, &[], Callbacks)?;
+        age_recipients.push(Box::new(recipient));
+    }
+
+    Ok(age_recipients)
+}
+
+/// Handles requests from age plugins to interact with the user.
+#[derive(Clone)]
+struct Callbacks;
+
+impl age::Callbacks for Callbacks {
+    fn display_message(&self, message: &str) {
+        eprintln!("{}", message);
+    }
+
+    fn confirm(
+        &self,
+        message: &str,
+        yes_string: &str,
+        no_string: Option<&str>,
+    ) -> Option<bool> {
+        let no_string = no_string.unwrap_or("no");
+        let prompt = format!("{} [{}/{}]: ", message, yes_string, no_string);
+        let response = self.request_public_string(&prompt)?;
+
+        if response == yes_string {
+            Some(true)
+        } else if response == no_string {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    fn request_public_string(&self, description: &str) -> Option<String> {
+        eprint!("{}", description);
+
+        let mut response = String::new();
+        io::stdin().read_line(&mut response).ok()?;
+        Some(response.trim_end().to_string())
+    }
+
+    fn request_passphrase(&self, description: &str) -> Option<SecretString> {
+        rpassword::prompt_password(description)
+            .ok()
+            .map(SecretString::new)
+    }
+}
+
+/// Decrypts an entry in either the binary age format or the ASCII armored
+/// format, so that a store can hold a mix of both.
+pub struct DecryptedReader<R: Read>(StreamReader<ArmoredReader<BufReader<R>>>);
 
 impl<R: Read> DecryptedReader<R> {
-    fn new<'a>(
-        input: R,
-        identities: &[&'a dyn Identity],
-    ) -> Result<Self, DecryptError> {
-        match Decryptor::new(input)? {
+    fn new(input: R, age: &Age) -> Result<Self, Error> {
+        // The reader peeks at the start of the input for the armor header,
+        // and passes binary input through unchanged.
+        match Decryptor::new(ArmoredReader::new(input))? {
             Decryptor::Recipients(d) => {
-                Ok(Self(d.decrypt::<'a>(identities.iter().copied())?))
+                if age.identities.is_empty() {
+                    return Err(Error::NoIdentities);
+                }
+
+                let identities = age.age_identities()?;
+                Ok(Self(d.decrypt(
+                    identities.iter().map(|identity| identity.as_ref()),
+                )?))
+            }
+            Decryptor::Passphrase(d) => {
+                let source =
+                    age.passphrase.as_ref().ok_or(Error::Passphrase)?;
+                match d.decrypt(&source.passphrase()?, None) {
+                    Ok(reader) => Ok(Self(reader)),
+                    Err(e) => {
+                        source.reject();
+                        Err(e.into())
+                    }
+                }
             }
-            Decryptor::Passphrase(_) => todo!(),
         }
     }
 }
 
+impl<R: Read> Read for DecryptedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
 impl<R: Read> Decrypted<R> for DecryptedReader<R> {
     type Error = Error;
 }
 
-struct EncryptedWriter<W: Write>(StreamWriter<W>);
+pub struct EncryptedWriter<W: Write>(StreamWriter<W>);
 
 impl<W: Write> EncryptedWriter<W> {
-    fn new(
-        output: W,
-        recipients: Vec<Box<dyn Recipient>>,
-    ) -> Result<Self, EncryptError> {
-        let encryptor =
-            Encryptor::with_recipients(recipients).wrap_output(output)?;
+    fn new(output: W, recipients: Vec<Recipient>) -> Result<Self, Error> {
+        // The entry could never be decrypted.
+        if recipients.is_empty() {
+            return Err(Error::NoRecipients);
+        }
+
+        let encryptor = Encryptor::with_recipients(age_recipients(recipients)?)
+            .wrap_output(output)?;
         Ok(Self(encryptor))
     }
 }
@@ -62,10 +362,8 @@ impl<W: Write> Write for EncryptedWriter<W> {
 
 impl<W: Write> Encrypted<W> for EncryptedWriter<W> {
     type Error = Error;
-}
 
-impl<W: Write> EncryptedWriter<W> {
-    pub fn finish(self) -> io::Result<W> {
+    fn finish(self) -> io::Result<W> {
         self.0.finish()
     }
 }
@@ -74,34 +372,141 @@ impl crypto::Backend for Age {
     type Decrypted<R: Read> = DecryptedReader<R>;
     type Encrypted<W: Write> = EncryptedWriter<W>;
     type Error = Error;
-    type Recipient = Box<dyn Recipient>;
+    type Recipient = Recipient;
 
-    fn encrypt_entry<W: Write>(
+    fn encrypt_output<W: Write>(
         &self,
         output: W,
         recipients: Vec<Self::Recipient>,
     ) -> Result<EncryptedWriter<W>, Self::Error> {
-        EncryptedWriter::new(output, recipients).map_err(Into::into)
+        EncryptedWriter::new(output, recipients)
+    }
+
+    /// Derives the recipient of each native identity. The recipients of plugin
+    /// identities can only be derived by their plugins, so they are skipped.
+    fn own_recipients(&self) -> Result<Vec<Self::Recipient>, Self::Error> {
+        Ok(self
+            .identities
+            .iter()
+            .filter_map(|identity| match identity {
+                Identity::Native(identity) => {
+                    Some(Recipient::Native(identity.to_public()))
+                }
+                Identity::Plugin(_) => None,
+            })
+            .collect())
+    }
+
+    /// Native recipients are compared against the public keys of the native
+    /// identities. SSH identities are never loaded, so SSH recipients never
+    /// belong to them. Plugin identities cannot be checked, so whether a plugin
+    /// recipient belongs to one is unknown.
+    fn includes_own_recipient(
+        &self,
+        recipients: &[Self::Recipient],
+    ) -> Result<Option<bool>, Self::Error> {
+        let own: Vec<String> = self
+            .own_recipients()?
+            .iter()
+            .filter_map(|recipient| match recipient {
+                Recipient::Native(recipient) => Some(recipient.to_string()),
+                Recipient::Ssh(_) | Recipient::Plugin(_) => None,
+            })
+            .collect();
+
+        let mut unknown = false;
+        for recipient in recipients {
+            match recipient {
+                Recipient::Native(recipient) => {
+                    if own.contains(&recipient.to_string()) {
+                        return Ok(Some(true));
+                    }
+                }
+                Recipient::Ssh(_) => {}
+                Recipient::Plugin(_) => {
+                    unknown |= self.identities.iter().any(|identity| {
+                        matches!(identity, Identity::Plugin(_))
+                    });
+                }
+            }
+        }
+
+        Ok(if unknown { None } else { Some(false) })
     }
 
     fn decrypt_input<R: Read>(
         &self,
         ciphertext: R,
     ) -> Result<Self::Decrypted<R>, Self::Error> {
-        todo!()
+        DecryptedReader::new(ciphertext, self)
     }
 }
 
 #[derive(Debug)]
-enum Error {
+pub enum Error {
     Io(std::io::Error),
     Encrypt(age::EncryptError),
     Decrypt(age::DecryptError),
+    /// The binary for an age plugin could not be found.
+    MissingPlugin(String),
+    InvalidRecipient(String),
+    /// The line with the provided number of a recipients file is not a
+    /// recipient.
+    MalformedRecipient(usize),
+    /// The recipients file at the path could not be read.
+    RecipientsFile(PathBuf, Box<Error>),
+    /// A passphrase-encrypted entry was decrypted without a passphrase.
+    Passphrase,
+    /// The identity file at the path could not be read.
+    IdentityFile(PathBuf, io::Error),
+    /// Decryption was attempted without any identities.
+    NoIdentities,
+    /// Encryption was attempted without any recipients.
+    NoRecipients,
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        todo!()
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::Encrypt(e) => e.fmt(f),
+            Error::Decrypt(e) => e.fmt(f),
+            Error::MissingPlugin(binary_name) => write!(
+                f,
+                "Could not find '{}' on the PATH. Install the age plugin to \
+                 use this recipient or identity.",
+                binary_name
+            ),
+            Error::InvalidRecipient(recipient) => {
+                write!(f, "Invalid age recipient: {}", recipient)
+            }
+            Error::MalformedRecipient(line) => {
+                write!(f, "Malformed recipient at line {}", line)
+            }
+            Error::RecipientsFile(path, e) => write!(
+                f,
+                "Could not read recipients file {}: {}",
+                path.display(),
+                e
+            ),
+            Error::Passphrase => {
+                write!(f, "No passphrase is available to decrypt the entry")
+            }
+            Error::IdentityFile(path, e) => write!(
+                f,
+                "Could not read identity file {}: {}",
+                path.display(),
+                e
+            ),
+            Error::NoIdentities => write!(
+                f,
+                "No age identities were found to decrypt with. Pass \
+                 --age-keyfile, or set $RAGE_IDENTITY or $AGE_IDENTITY."
+            ),
+            Error::NoRecipients => {
+                write!(f, "At least one recipient is required")
+            }
+        }
     }
 }
 
@@ -115,12 +520,22 @@ impl From<std::io::Error> for Error {
 
 impl From<age::EncryptError> for Error {
     fn from(e: age::EncryptError) -> Self {
-        Self::Encrypt(e)
+        match e {
+            EncryptError::MissingPlugin { binary_name } => {
+                Self::MissingPlugin(binary_name)
+            }
+            e => Self::Encrypt(e),
+        }
     }
 }
 
 impl From<age::DecryptError> for Error {
     fn from(e: age::DecryptError) -> Self {
-        Self::Decrypt(e)
+        match e {
+            DecryptError::MissingPlugin { binary_name } => {
+                Self::MissingPlugin(binary_name)
+            }
+            e => Self::Decrypt(e),
+        }
     }
 }
@@ -1,2 +1,650 @@
-#[cfg(test)]
-mod transparent;
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use age::plugin;
+use age::secrecy::{ExposeSecret, SecretString};
+use libciphey::crypto::{Backend, DynCrypto, Encrypted, Recipient as _};
+
+use super::{
+    generate_identity, identity_files, load_identities, parse_recipients_file,
+    read_recipients_file, Age, Error, Identity, PassphraseSource, Recipient,
+};
+use crate::tests::random_string;
+
+// A valid recipient for a plugin that is not installed.
+const PLUGIN_RECIPIENT: &str =
+    "age1cipheytest1qqqsyqcyq5rqwzqfpg9scrgwpugpzysnzs23v9ccrydpk8qarc0sfgq83a";
+
+#[test]
+fn test_recipient_fingerprint() {
+    // The fingerprint is stable for a known recipient, since it only depends
+    // on the recipient's encoding.
+    let native =
+        "age19cnld7jr8nvdm7x9xf09m322axwvgv2rn6h8kx5luwthl4zrnvzsg75j77";
+    let recipient = Recipient::try_from(native.to_string()).unwrap();
+    assert_eq!(recipient.to_string(), native);
+    assert_eq!(recipient.fingerprint(), "f501:c59f:9014:5b78");
+
+    let recipient = Recipient::try_from(PLUGIN_RECIPIENT.to_string()).unwrap();
+    assert_eq!(recipient.to_string(), PLUGIN_RECIPIENT);
+    assert_eq!(recipient.fingerprint(), "d54a:4e7b:1da3:7d53");
+}
+
+#[test]
+fn test_parse_native_recipient() {
+    let identity = age::x25519::Identity::generate();
+    let public = identity.to_public().to_string();
+
+    let recipient = Recipient::try_from(public).unwrap();
+    assert!(matches!(recipient, Recipient::Native(_)));
+}
+
+#[test]
+fn test_parse_plugin_recipient() {
+    let recipient = Recipient::try_from(PLUGIN_RECIPIENT.to_string()).unwrap();
+
+    match recipient {
+        Recipient::Plugin(recipient) => {
+            assert_eq!(recipient.plugin(), "cipheytest")
+        }
+        _ => panic!("expected a plugin recipient"),
+    }
+}
+
+#[test]
+fn test_parse_invalid_recipient() {
+    let err = Recipient::try_from("not a recipient".to_string())
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::InvalidRecipient(_)));
+}
+
+// SSH public keys, as written by `ssh-keygen`.
+const SSH_ED25519_RECIPIENT: &str =
+    "ssh-ed25519 \
+     AAAAC3NzaC1lZDI1NTE5AAAAIClV0L5+BZVosnDBcYO6cqga5Bd6Lq7Y17anEgDpXEv4";
+const SSH_RSA_RECIPIENT: &str = "ssh-rsa \
+    AAAAB3NzaC1yc2EAAAADAQABAAABAQDfzdWcNGz+IdjoOiDTky2RrqxqHmRtcTPIT5ESxe/vWF\
+    ygI6pftzponvyAeirARgFZXlt+lDHNWjtb4Qbejlot4NPdsQNsOq75eJafBvE4iy481NR3Ilxa\
+    SI0N0tQx9afhLYcGNkUr/KFpHwKHFMUsxJsciQX/ZglIDKIHodWXh3MT3fi/JOrAV+tqYAMXhN\
+    fRV7vCePWhx3uOAyUkjrsKd0iqQ2wKObbLJZ2PK06DQH1nkUAmYBFoz468D5aVndAwM+I75mnF\
+    TkcmQoOd3hD3LG3pHWun1FrhqnmNmquRt93tF//TLqlb4jb+3RiV4z+C/1s4xAoOI+TXNsC1pO\
+    b5";
+
+#[test]
+fn test_parse_ssh_recipients() {
+    for ssh in [SSH_ED25519_RECIPIENT, SSH_RSA_RECIPIENT] {
+        let recipient = Recipient::try_from(ssh.to_string()).unwrap();
+        assert!(matches!(recipient, Recipient::Ssh(_)));
+
+        // Encryption to SSH recipients happens without any plugins.
+        let crypto = Age::new(Vec::new());
+        let mut encrypted =
+            crypto.encrypt_output(Vec::new(), vec![recipient]).unwrap();
+        encrypted.write_all(b"secret").unwrap();
+        encrypted.finish().unwrap();
+    }
+}
+
+#[test]
+fn test_parse_recipients_file() {
+    let native = age::x25519::Identity::generate().to_public().to_string();
+
+    // Blank lines and comments are skipped, and Windows line endings are
+    // accepted, as in age.
+    let contents = format!(
+        "# created: 2022-01-01\n{}\n\n#{}\r\n{}\r\n{} user@host\n{}\n",
+        native,
+        native,
+        SSH_ED25519_RECIPIENT,
+        SSH_RSA_RECIPIENT,
+        PLUGIN_RECIPIENT,
+    );
+    let recipients = parse_recipients_file(&contents).unwrap();
+    assert_eq!(
+        recipients,
+        [
+            native.clone(),
+            SSH_ED25519_RECIPIENT.to_string(),
+            format!("{} user@host", SSH_RSA_RECIPIENT),
+            PLUGIN_RECIPIENT.to_string(),
+        ]
+    );
+
+    assert!(parse_recipients_file("").unwrap().is_empty());
+}
+
+#[test]
+fn test_parse_recipients_file_malformed() {
+    let native = age::x25519::Identity::generate().to_public().to_string();
+
+    // Lines are not trimmed, and comments must start the line.
+    for (contents, line) in [
+        (format!("{}\nnot a recipient\n", native), 2),
+        (format!("# comment\n  {}\n", native), 2),
+        (format!("  # comment\n{}\n", native), 1),
+        (format!("{} # comment\n", native), 1),
+        ("AGE-SECRET-KEY-1QQQQ\n".to_string(), 1),
+    ] {
+        let err = parse_recipients_file(&contents).err().unwrap();
+        assert!(matches!(err, Error::MalformedRecipient(l) if l == line));
+    }
+}
+
+#[test]
+fn test_read_recipients_file() {
+    let path = std::env::temp_dir().join(random_string(32));
+    let err = read_recipients_file(&path).err().unwrap();
+    assert!(matches!(err, Error::RecipientsFile(..)));
+
+    std::fs::write(&path, "# comment\nnot a recipient\n").unwrap();
+    let err = read_recipients_file(&path).err().unwrap();
+    assert!(err.to_string().contains("Malformed recipient at line 2"));
+    assert!(!err.to_string().contains("not a recipient"));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_encrypt_missing_plugin() {
+    let recipients = vec![PLUGIN_RECIPIENT.to_string().try_into().unwrap()];
+
+    let crypto = Age::new(Vec::new());
+    let err = crypto.encrypt_output(Vec::new(), recipients).err().unwrap();
+
+    // The error should name the plugin binary that needs to be installed.
+    assert!(
+        matches!(&err, Error::MissingPlugin(name) if name == "age-plugin-cipheytest")
+    );
+    assert!(err.to_string().contains("age-plugin-cipheytest"));
+}
+
+#[test]
+fn test_decrypt_missing_plugin() {
+    let identity = plugin::Identity::default_for_plugin("cipheytest");
+
+    // Encrypt to a native recipient so that the file can be parsed.
+    let native = age::x25519::Identity::generate();
+    let recipients = vec![Recipient::Native(native.to_public())];
+    let mut ciphertext = Vec::new();
+    let encrypted = Age::new(Vec::new())
+        .encrypt_output(&mut ciphertext, recipients)
+        .unwrap();
+    encrypted.finish().unwrap();
+
+    let crypto = Age::new(vec![Identity::Plugin(identity)]);
+    let err = crypto.decrypt_input(ciphertext.as_slice()).err().unwrap();
+
+    assert!(matches!(err, Error::MissingPlugin(_)));
+}
+
+// Returns a lookup function over the provided environment variables.
+fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<OsString> + 'a {
+    move |name| {
+        vars.iter()
+            .find(|(var, _)| *var == name)
+            .map(|(_, value)| OsString::from(value))
+    }
+}
+
+#[test]
+fn test_identity_files_precedence() {
+    let vars = [
+        ("RAGE_IDENTITY", "/rage"),
+        ("AGE_IDENTITY", "/age"),
+        ("XDG_CONFIG_HOME", "/config"),
+    ];
+    let keyfiles = [PathBuf::from("/a"), PathBuf::from("/b")];
+
+    // Keyfiles passed as flags take precedence over everything.
+    assert_eq!(identity_files(&keyfiles, env(&vars)), keyfiles);
+
+    // $RAGE_IDENTITY takes precedence over $AGE_IDENTITY.
+    assert_eq!(identity_files(&[], env(&vars)), [PathBuf::from("/rage")]);
+    assert_eq!(
+        identity_files(&[], env(&vars[1..])),
+        [PathBuf::from("/age")]
+    );
+
+    // The default keyfile is only used if it exists.
+    assert!(identity_files(&[], env(&vars[2..])).is_empty());
+}
+
+#[test]
+fn test_identity_files_default() {
+    let config = std::env::temp_dir().join(random_string(32));
+    std::fs::create_dir_all(config.join("rage")).unwrap();
+    std::fs::write(config.join("rage").join("keys.txt"), "").unwrap();
+
+    let config = config.to_str().unwrap();
+    assert_eq!(
+        identity_files(&[], env(&[("XDG_CONFIG_HOME", config)])),
+        [Path::new(config).join("rage").join("keys.txt")]
+    );
+}
+
+#[test]
+fn test_load_identities() {
+    let identity = age::x25519::Identity::generate();
+    let path = std::env::temp_dir().join(random_string(32));
+    let contents =
+        format!("# A comment\n{}\n", identity.to_string().expose_secret());
+    std::fs::write(&path, contents).unwrap();
+
+    let crypto = Age::new(load_identities(&[path]).unwrap());
+
+    // Entries encrypted to the identity can be decrypted.
+    let recipients = vec![Recipient::Native(identity.to_public())];
+    let mut ciphertext = Vec::new();
+    let mut encrypted =
+        crypto.encrypt_output(&mut ciphertext, recipients).unwrap();
+    encrypted.write_all(b"hunter2").unwrap();
+    encrypted.finish().unwrap();
+
+    let mut plaintext = Vec::new();
+    let mut decrypted = crypto.decrypt_input(ciphertext.as_slice()).unwrap();
+    decrypted.read_to_end(&mut plaintext).unwrap();
+    assert_eq!(plaintext, b"hunter2");
+
+    // A missing identity file names the file in its error.
+    let missing = PathBuf::from("/does/not/exist");
+    let err = load_identities(&[missing]).err().unwrap();
+    assert!(err.to_string().contains("/does/not/exist"));
+}
+
+#[test]
+fn test_generate_identity() {
+    let (contents, recipient) = generate_identity();
+    let path = std::env::temp_dir().join(random_string(32));
+    std::fs::write(&path, &contents).unwrap();
+
+    let crypto = Age::new(load_identities(&[path]).unwrap());
+    let own = crypto.own_recipients().unwrap();
+    assert_eq!(own.len(), 1);
+    assert!(
+        matches!(&own[0], Recipient::Native(r) if r.to_string() == recipient)
+    );
+    assert!(contents.starts_with(&format!("# public key: {}\n", recipient)));
+}
+
+#[test]
+fn test_encrypt_without_recipients() {
+    let err = Age::new(Vec::new())
+        .encrypt_output(Vec::new(), Vec::new())
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::NoRecipients));
+}
+
+#[test]
+fn test_decrypt_without_identities() {
+    let native = age::x25519::Identity::generate();
+    let recipients = vec![Recipient::Native(native.to_public())];
+    let mut ciphertext = Vec::new();
+    let encrypted = Age::new(Vec::new())
+        .encrypt_output(&mut ciphertext, recipients)
+        .unwrap();
+    encrypted.finish().unwrap();
+
+    let err = Age::new(Vec::new())
+        .decrypt_input(ciphertext.as_slice())
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::NoIdentities));
+}
+
+#[test]
+fn test_own_recipients() {
+    use std::str::FromStr;
+
+    // A fixed keypair, so that the derived recipient can be checked.
+    const SECRET: &str =
+        "AGE-SECRET-KEY-1RREV4ARZ2QWEGKMK3TPALU2XX65VKX6XKHEDS6S9CSDZS4WYG4YQ288323";
+    const PUBLIC: &str =
+        "age19cnld7jr8nvdm7x9xf09m322axwvgv2rn6h8kx5luwthl4zrnvzsg75j77";
+
+    let identity = age::x25519::Identity::from_str(SECRET).unwrap();
+    let crypto = Age::new(vec![Identity::Native(identity)]);
+
+    let recipients = crypto.own_recipients().unwrap();
+    assert_eq!(recipients.len(), 1);
+    match &recipients[0] {
+        Recipient::Native(recipient) => {
+            assert_eq!(recipient.to_string(), PUBLIC)
+        }
+        _ => panic!("expected a native recipient"),
+    }
+
+    // Without identities, there are no recipients to derive.
+    assert!(Age::new(Vec::new()).own_recipients().unwrap().is_empty());
+}
+
+#[test]
+fn test_dyn_crypto() {
+    let identity = age::x25519::Identity::generate();
+    let recipient = identity.to_public().to_string();
+    let crypto: Box<dyn DynCrypto> =
+        Box::new(Age::new(vec![Identity::Native(identity)]));
+
+    let mut ciphertext = Vec::new();
+    crypto
+        .dyn_encrypt_to(&mut &b"hunter2"[..], &mut ciphertext, &[recipient])
+        .unwrap();
+    assert!(!ciphertext.windows(7).any(|window| window == b"hunter2"));
+
+    let mut plaintext = Vec::new();
+    let written = crypto
+        .dyn_decrypt_to(&mut ciphertext.as_slice(), &mut plaintext)
+        .unwrap();
+    assert_eq!(written, 7);
+    assert_eq!(plaintext, b"hunter2");
+
+    let invalid = ["not a recipient".to_string()];
+    let err = crypto
+        .dyn_encrypt_to(&mut &b""[..], &mut Vec::new(), &invalid)
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Invalid recipient: not a recipient");
+}
+
+#[test]
+fn test_new_round_trip() {
+    use crate::cli::{self, Config, SecretVisibility};
+    use crate::tests::{new_opts, show_opts, temporary_store};
+
+    let identity = age::x25519::Identity::generate();
+    let crypto = Age::new(vec![Identity::Native(identity)]);
+    let mut storage = temporary_store();
+
+    // The entry is only decryptable if `new` finishes the age stream.
+    let opts = new_opts(&["-n", "a", "-s", "hunter2", "--recipient-self"]);
+    cli::new(
+        &opts,
+        &Config::default(),
+        &crypto,
+        &mut storage,
+        None,
+        &mut std::io::empty(),
+        &mut Vec::new(),
+        &mut std::io::sink(),
+    )
+    .unwrap();
+
+    let mut output = Vec::new();
+    cli::show(
+        &show_opts(&["a", "--raw"]),
+        SecretVisibility::Show,
+        &crypto,
+        &storage,
+        None,
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(output, b"hunter2");
+}
+
+#[test]
+fn test_new_refuses_lockout() {
+    use libciphey::storage::Backend;
+
+    use crate::cli::{self, Config};
+    use crate::tests::{new_opts, temporary_store};
+
+    let identity = age::x25519::Identity::generate();
+    let own = identity.to_public().to_string();
+    let other = age::x25519::Identity::generate().to_public().to_string();
+    let crypto = Age::new(vec![Identity::Native(identity)]);
+    let mut storage = temporary_store();
+
+    let mut new = |args: &[&str]| {
+        let args = [&["-n", "a", "-s", "b"], args].concat();
+        cli::new(
+            &new_opts(&args),
+            &Config::default(),
+            &crypto,
+            &mut storage,
+            None,
+            &mut std::io::empty(),
+            &mut Vec::new(),
+            &mut std::io::sink(),
+        )
+    };
+
+    let err = new(&["-r", &other]).unwrap_err();
+    assert!(matches!(err, cli::Error::Lockout));
+
+    new(&["-r", &other, "-r", &own]).unwrap();
+    new(&["-r", &other, "--allow-lockout"]).unwrap();
+
+    assert_eq!(storage.entries().unwrap().len(), 2);
+}
+
+#[test]
+fn test_mv_refuses_lockout() {
+    use libciphey::storage::Backend;
+    use libciphey_fs::Filesystem;
+
+    use crate::cli::{self, Config};
+    use crate::tests::{mv_opts, new_opts, temporary_path, temporary_store};
+
+    let identity = age::x25519::Identity::generate();
+    let own = identity.to_public().to_string();
+    let other = age::x25519::Identity::generate().to_public().to_string();
+    let crypto = Age::new(vec![Identity::Native(identity)]);
+    let mut source = temporary_store();
+    for name in ["a", "b"] {
+        cli::new(
+            &new_opts(&["-n", name, "-s", "hunter2", "-r", &own]),
+            &Config::default(),
+            &crypto,
+            &mut source,
+            None,
+            &mut std::io::empty(),
+            &mut Vec::new(),
+            &mut std::io::sink(),
+        )
+        .unwrap();
+    }
+
+    let dest_path = temporary_path();
+    let mut destination = Filesystem::new(&dest_path).unwrap();
+    cli::init(&mut destination, false).unwrap();
+    let recipients = dest_path.join(".recipients");
+    let to = dest_path.to_str().unwrap().to_string();
+
+    let mut mv = |name: &str, args: &[&str]| {
+        cli::mv(
+            &mv_opts(&[&[name, "--to", &to], args].concat()),
+            &crypto,
+            &mut source,
+            None,
+            &mut destination,
+            &mut Vec::new(),
+        )
+    };
+
+    // The only copy of the entry is kept if it would be locked out.
+    std::fs::write(&recipients, &other).unwrap();
+    let err = mv("a", &[]).unwrap_err();
+    assert!(matches!(err, cli::Error::Lockout));
+    mv("a", &["--allow-lockout"]).unwrap();
+
+    std::fs::write(&recipients, format!("{}\n{}\n", other, own)).unwrap();
+    mv("b", &[]).unwrap();
+
+    assert!(source.entries().unwrap().is_empty());
+    assert_eq!(destination.entries().unwrap().len(), 2);
+}
+
+#[test]
+fn test_decrypt_armored_and_binary_entries() {
+    use age::armor::{ArmoredWriter, Format};
+    use libciphey::storage::{Backend as _, Reference as _};
+    use uuid::Uuid;
+
+    use crate::cli::{self, Config, SecretVisibility};
+    use crate::tests::{list_opts, temporary_store};
+
+    let identity = age::x25519::Identity::generate();
+    let recipient = identity.to_public();
+    let crypto = Age::new(vec![Identity::Native(identity)]);
+    let mut storage = temporary_store();
+
+    for (name, format, armored) in [
+        ("binary", Format::Binary, false),
+        ("armored", Format::AsciiArmor, true),
+    ] {
+        let mut reference = storage.add_entry(&Uuid::new_v4()).unwrap();
+        let output =
+            ArmoredWriter::wrap_output(reference.writer().unwrap(), format)
+                .unwrap();
+        let encryptor =
+            age::Encryptor::with_recipients(vec![Box::new(recipient.clone())]);
+        let mut writer = encryptor.wrap_output(output).unwrap();
+        write!(writer, "name={}\nsecret!=hunter2\n", name).unwrap();
+        writer.finish().unwrap().finish().unwrap();
+
+        let mut contents = Vec::new();
+        reference
+            .reader()
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        let header = b"-----BEGIN AGE ENCRYPTED FILE-----";
+        assert_eq!(contents.starts_with(header), armored);
+    }
+
+    let mut output = Vec::new();
+    cli::list(
+        &list_opts(&["--quiet", "--no-default", "--display", "name"]),
+        SecretVisibility::Hide,
+        &Config::default(),
+        &crypto,
+        &mut storage,
+        &mut output,
+        &mut std::io::sink(),
+    )
+    .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("name: binary\n"));
+    assert!(output.contains("name: armored\n"));
+}
+
+const KVSTORE: &str = "name=a\nsecret!=hunter2\nusername=alice\n";
+
+/// Encrypts the entry in [`KVSTORE`] to the recipient.
+fn encrypt_kvstore(recipient: age::x25519::Recipient) -> Vec<u8> {
+    use ciphey_kvstore::KvStore;
+    use libciphey::filetype::Filetype;
+
+    let store = KvStore::deserialize(&mut KVSTORE.as_bytes()).unwrap();
+
+    let mut ciphertext = Vec::new();
+    let mut encrypted = Age::new(Vec::new())
+        .encrypt_output(&mut ciphertext, vec![Recipient::Native(recipient)])
+        .unwrap();
+    store.serialize(&mut encrypted).unwrap();
+    encrypted.finish().unwrap();
+    ciphertext
+}
+
+#[test]
+fn test_kvstore_round_trip() {
+    use ciphey_kvstore::{Key, KvStore};
+    use libciphey::filetype::Filetype;
+
+    let identity = age::x25519::Identity::generate();
+    let ciphertext = encrypt_kvstore(identity.to_public());
+    assert!(!ciphertext.windows(7).any(|window| window == b"hunter2"));
+
+    let crypto = Age::new(vec![Identity::Native(identity)]);
+    let mut plaintext = String::new();
+    crypto
+        .decrypt_input(ciphertext.as_slice())
+        .unwrap()
+        .read_to_string(&mut plaintext)
+        .unwrap();
+    assert_eq!(plaintext, KVSTORE);
+
+    let store = KvStore::deserialize(&mut plaintext.as_bytes()).unwrap();
+    assert_eq!(store.get(&Key::Name).unwrap().as_str(), "a");
+    assert_eq!(store.get(&Key::from("secret")).unwrap().as_str(), "hunter2");
+}
+
+#[test]
+fn test_decrypt_with_wrong_identity() {
+    let recipient = age::x25519::Identity::generate().to_public();
+    let ciphertext = encrypt_kvstore(recipient);
+
+    let other = age::x25519::Identity::generate();
+    let err = Age::new(vec![Identity::Native(other)])
+        .decrypt_input(ciphertext.as_slice())
+        .err()
+        .unwrap();
+    assert!(matches!(
+        err,
+        Error::Decrypt(age::DecryptError::NoMatchingKeys)
+    ));
+}
+
+/// Provides a fixed passphrase, counting how often it was rejected.
+struct FixedPassphrase {
+    passphrase: &'static str,
+    rejected: Arc<AtomicUsize>,
+}
+
+impl PassphraseSource for FixedPassphrase {
+    fn passphrase(&self) -> std::io::Result<SecretString> {
+        Ok(SecretString::new(self.passphrase.to_string()))
+    }
+
+    fn reject(&self) {
+        self.rejected.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn test_decrypt_passphrase_entry() {
+    let passphrase = SecretString::new("hunter2".to_string());
+    let mut ciphertext = Vec::new();
+    let mut encrypted = age::Encryptor::with_user_passphrase(passphrase)
+        .wrap_output(&mut ciphertext)
+        .unwrap();
+    encrypted.write_all(KVSTORE.as_bytes()).unwrap();
+    encrypted.finish().unwrap();
+
+    let err = Age::new(Vec::new())
+        .decrypt_input(ciphertext.as_slice())
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::Passphrase));
+
+    let rejected = Arc::new(AtomicUsize::new(0));
+    let crypto = |passphrase| {
+        let rejected = rejected.clone();
+        let source = FixedPassphrase {
+            passphrase,
+            rejected,
+        };
+        Age::new(Vec::new()).with_passphrase(Box::new(source))
+    };
+
+    let mut plaintext = String::new();
+    crypto("hunter2")
+        .decrypt_input(ciphertext.as_slice())
+        .unwrap()
+        .read_to_string(&mut plaintext)
+        .unwrap();
+    assert_eq!(plaintext, KVSTORE);
+    assert_eq!(rejected.load(Ordering::SeqCst), 0);
+
+    let err = crypto("hunter3")
+        .decrypt_input(ciphertext.as_slice())
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::Decrypt(_)));
+    assert_eq!(rejected.load(Ordering::SeqCst), 1);
+}
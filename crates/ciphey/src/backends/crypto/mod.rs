@@ -1,2 +1,3 @@
-// TODO: Add cfg(test) condition
+pub mod age;
+#[cfg(test)]
 pub mod transparent;
@@ -7,25 +7,64 @@ mod tests;
 
 pub struct Transparent {}
 
-#[derive(Clone)]
+/// The version of the header written by [`Transparent`]. Entries written
+/// before the header was versioned have no version line, and are read as
+/// version 0.
+pub const VERSION: u32 = 1;
+
+/// The prefix of the line that records the version of the header.
+const VERSION_PREFIX: &str = "ciphey-transparent: ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Recipient(String);
 
 impl crypto::Recipient for Recipient {}
 
+impl Recipient {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
 impl From<String> for Recipient {
     fn from(s: String) -> Self {
         Self(s)
     }
 }
 
-pub struct Decrypted<R: Read>(BufReader<R>);
+impl std::fmt::Display for Recipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The header that precedes the plaintext of an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    /// The recipients, in the order they were written.
+    pub recipients: Vec<Recipient>,
+}
+
+pub struct Decrypted<R: Read> {
+    header: Header,
+    reader: BufReader<R>,
+}
+
+impl<R: Read> Decrypted<R> {
+    /// Returns the header that was read before the plaintext.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+}
+
 impl<R: Read> crypto::Decrypted<R> for Decrypted<R> {
     type Error = Error;
 }
 
 impl<R: Read> Read for Decrypted<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+        self.reader.read(buf)
     }
 }
 
@@ -43,6 +82,11 @@ impl<W: Write> Write for Encrypted<W> {
 
 impl<W: Write> crypto::Encrypted<W> for Encrypted<W> {
     type Error = Error;
+
+    fn finish(mut self) -> io::Result<W> {
+        self.0.flush()?;
+        Ok(self.0)
+    }
 }
 
 impl crypto::Backend for Transparent {
@@ -56,8 +100,17 @@ impl crypto::Backend for Transparent {
         mut output: W,
         recipients: Vec<Self::Recipient>,
     ) -> Result<Self::Encrypted<W>, Self::Error> {
+        writeln!(output, "{}{}", VERSION_PREFIX, VERSION)?;
+
         // Add recipients header
         for recipient in recipients {
+            // A newline would split the recipient into another header line.
+            if recipient.0.contains('\n') {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "recipient contains a newline",
+                ));
+            }
             writeln!(output, "-> {}", recipient.0)?;
         }
         // Add separator
@@ -69,19 +122,56 @@ impl crypto::Backend for Transparent {
         &self,
         ciphertext: R,
     ) -> Result<Self::Decrypted<R>, Self::Error> {
-        // Get all recipients
-        let mut recipients = Vec::new();
-        let mut reader = BufReader::new(ciphertext);
-
-        while let mut line = String::new()
-                && let _ = reader.read_line(&mut line)?
-                && line.starts_with("-> ") {
-            let recipient = line.trim_start_matches("-> ");
-            recipients.push(recipient.to_string());
-            line = String::new();
-            reader.read_line(&mut line)?;
+        let (header, reader) = read_header(ciphertext)?;
+        Ok(Decrypted { header, reader })
+    }
+
+    fn recipients_of<R: Read>(
+        &self,
+        ciphertext: R,
+    ) -> Result<Option<Vec<String>>, Self::Error> {
+        let (header, _) = read_header(ciphertext)?;
+        Ok(Some(header.recipients.into_iter().map(|r| r.0).collect()))
+    }
+}
+
+/// Reads the header, returning it and a reader that is positioned at the
+/// start of the plaintext.
+fn read_header<R: Read>(ciphertext: R) -> io::Result<(Header, BufReader<R>)> {
+    let invalid =
+        |message: String| Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut header = Header {
+        version: 0,
+        recipients: Vec::new(),
+    };
+    let mut reader = BufReader::new(ciphertext);
+    let mut first = true;
+
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end_matches('\n');
+
+        // The version line is only recognized at the start of the header.
+        let version = line.strip_prefix(VERSION_PREFIX).filter(|_| first);
+
+        if let Some(version) = version {
+            header.version = version
+                .parse()
+                .ok()
+                .filter(|version| (1..=VERSION).contains(version))
+                .ok_or_else(|| {
+                    invalid(format!("unsupported version: {}", version))
+                })?;
+        } else if let Some(recipient) = line.strip_prefix("-> ") {
+            header.recipients.push(Recipient(recipient.to_string()));
+        } else if line == "---" {
+            return Ok((header, reader));
+        } else {
+            return Err(invalid("missing header separator".to_string()));
         }
 
-        Ok(Decrypted(reader))
+        first = false;
     }
 }
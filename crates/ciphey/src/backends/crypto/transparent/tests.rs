@@ -1,11 +1,12 @@
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 
-use libciphey::crypto::Backend;
+use libciphey::crypto::{Backend, DynCrypto};
 
-use super::Transparent;
+use super::{Header, Recipient, Transparent, VERSION};
 
 const PLAINTEXT: &str = "Secret Data";
-const CIPHERTEXT: &str = r"-> Public Key A
+const CIPHERTEXT: &str = r"ciphey-transparent: 1
+-> Public Key A
 -> Public Key B
 ---
 Secret Data";
@@ -38,3 +39,116 @@ fn test_decrypt() {
 
     assert_eq!(plaintext, PLAINTEXT);
 }
+
+#[test]
+fn test_decrypt_to() {
+    let crypto = Transparent {};
+
+    let mut plaintext = Vec::new();
+    let written = crypto
+        .decrypt_to(CIPHERTEXT.as_bytes(), &mut plaintext)
+        .unwrap();
+
+    assert_eq!(written, PLAINTEXT.len() as u64);
+    assert_eq!(plaintext, PLAINTEXT.as_bytes());
+}
+
+#[test]
+fn test_dyn_crypto() {
+    let crypto: Box<dyn DynCrypto> = Box::new(Transparent {});
+
+    let mut ciphertext = Vec::new();
+    let recipients = ["Public Key A".to_string(), "Public Key B".to_string()];
+    let read = crypto
+        .dyn_encrypt_to(&mut PLAINTEXT.as_bytes(), &mut ciphertext, &recipients)
+        .unwrap();
+    assert_eq!(read, PLAINTEXT.len() as u64);
+    assert_eq!(ciphertext, CIPHERTEXT.as_bytes());
+
+    let mut plaintext = Vec::new();
+    let written = crypto
+        .dyn_decrypt_to(&mut ciphertext.as_slice(), &mut plaintext)
+        .unwrap();
+    assert_eq!(written, PLAINTEXT.len() as u64);
+    assert_eq!(plaintext, PLAINTEXT.as_bytes());
+}
+
+#[test]
+fn test_header_round_trip() {
+    let crypto = Transparent {};
+    let recipients: Vec<Recipient> =
+        ["age1 key", "-> nested", "ssh-ed25519 AAAA "]
+            .iter()
+            .map(|recipient| recipient.to_string().into())
+            .collect();
+
+    let mut ciphertext = Vec::new();
+    let mut encrypted = crypto
+        .encrypt_output(&mut ciphertext, recipients.clone())
+        .unwrap();
+    write!(&mut encrypted, "{}", PLAINTEXT).unwrap();
+
+    let mut decrypted = crypto.decrypt_input(ciphertext.as_slice()).unwrap();
+    assert_eq!(
+        decrypted.header(),
+        &Header {
+            version: VERSION,
+            recipients
+        }
+    );
+
+    let mut plaintext = String::new();
+    decrypted.read_to_string(&mut plaintext).unwrap();
+    assert_eq!(plaintext, PLAINTEXT);
+
+    let recipients = crypto.recipients_of(ciphertext.as_slice()).unwrap();
+    assert_eq!(
+        recipients.unwrap(),
+        ["age1 key", "-> nested", "ssh-ed25519 AAAA "]
+    );
+}
+
+#[test]
+fn test_header_unversioned() {
+    let crypto = Transparent {};
+    let ciphertext = "-> Public Key A\n---\nSecret Data";
+
+    let mut decrypted = crypto.decrypt_input(ciphertext.as_bytes()).unwrap();
+    assert_eq!(decrypted.header().version, 0);
+    assert_eq!(decrypted.header().recipients[0].as_str(), "Public Key A");
+
+    let mut plaintext = String::new();
+    decrypted.read_to_string(&mut plaintext).unwrap();
+    assert_eq!(plaintext, PLAINTEXT);
+}
+
+#[test]
+fn test_header_invalid() {
+    let crypto = Transparent {};
+    let decrypt = |ciphertext: &str| {
+        crypto
+            .decrypt_input(ciphertext.as_bytes())
+            .err()
+            .unwrap()
+            .kind()
+    };
+
+    assert_eq!(
+        decrypt("ciphey-transparent: 2\n---\n"),
+        ErrorKind::InvalidData
+    );
+    assert_eq!(
+        decrypt("ciphey-transparent: x\n---\n"),
+        ErrorKind::InvalidData
+    );
+    // The version is only read from the first line.
+    assert_eq!(
+        decrypt("-> A\nciphey-transparent: 1\n---\n"),
+        ErrorKind::InvalidData
+    );
+    assert_eq!(decrypt("-> A\n"), ErrorKind::InvalidData);
+
+    let recipients = vec!["Key\nA".to_string().into()];
+    let err = crypto.encrypt_output(Vec::new(), recipients).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
@@ -0,0 +1,356 @@
+use std::fs::{self, DirBuilder, Permissions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use age::secrecy::{ExposeSecret, SecretString};
+use tracing::debug;
+
+use super::{defaults, Error};
+use crate::backends::crypto::age::PassphraseSource;
+use crate::flags;
+
+/// The environment variable that holds the path of the agent's socket.
+pub const SOCK_VAR: &str = "CIPHEY_AGENT_SOCK";
+
+/// A request to the agent, sent as a single line.
+#[derive(Debug)]
+pub enum Request {
+    /// Returns the cached passphrase, if any.
+    Get,
+    /// Caches the passphrase, replacing any cached passphrase.
+    Set(SecretString),
+    /// Forgets the cached passphrase.
+    Clear,
+    /// Forgets the cached passphrase and stops the agent.
+    Stop,
+}
+
+impl Request {
+    /// Parses a request from a line without its line ending.
+    pub fn parse(line: &str) -> Option<Self> {
+        match line.split_once(' ') {
+            Some(("SET", passphrase)) => {
+                Some(Request::Set(SecretString::new(passphrase.to_string())))
+            }
+            Some(_) => None,
+            None => match line {
+                "GET" => Some(Request::Get),
+                "CLEAR" => Some(Request::Clear),
+                "STOP" => Some(Request::Stop),
+                _ => None,
+            },
+        }
+    }
+
+    /// Writes the request as a line to `output`.
+    pub fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        match self {
+            Request::Get => writeln!(output, "GET"),
+            Request::Set(passphrase) => {
+                writeln!(output, "SET {}", single_line(passphrase)?)
+            }
+            Request::Clear => writeln!(output, "CLEAR"),
+            Request::Stop => writeln!(output, "STOP"),
+        }
+    }
+}
+
+/// The agent's answer to a [`Request`], sent as a single line.
+#[derive(Debug)]
+pub enum Response {
+    /// The request was carried out.
+    Ok,
+    /// The cached passphrase.
+    Passphrase(SecretString),
+    /// No passphrase is cached.
+    Empty,
+    /// The request could not be parsed.
+    Invalid,
+}
+
+impl Response {
+    /// Parses a response from a line without its line ending.
+    pub fn parse(line: &str) -> Option<Self> {
+        match line.split_once(' ') {
+            Some(("PASSPHRASE", passphrase)) => Some(Response::Passphrase(
+                SecretString::new(passphrase.to_string()),
+            )),
+            Some(_) => None,
+            None => match line {
+                "OK" => Some(Response::Ok),
+                "EMPTY" => Some(Response::Empty),
+                "INVALID" => Some(Response::Invalid),
+                _ => None,
+            },
+        }
+    }
+
+    /// Writes the response as a line to `output`.
+    pub fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        match self {
+            Response::Ok => writeln!(output, "OK"),
+            Response::Passphrase(passphrase) => {
+                writeln!(output, "PASSPHRASE {}", single_line(passphrase)?)
+            }
+            Response::Empty => writeln!(output, "EMPTY"),
+            Response::Invalid => writeln!(output, "INVALID"),
+        }
+    }
+}
+
+/// Returns the passphrase if it can be sent as part of a single line.
+fn single_line(passphrase: &SecretString) -> io::Result<&str> {
+    let passphrase = passphrase.expose_secret();
+    if passphrase.contains('\n') {
+        let message = "passphrases containing a newline cannot be cached";
+        return Err(io::Error::new(ErrorKind::InvalidInput, message));
+    }
+    Ok(passphrase)
+}
+
+/// Reads a line without its line ending from `input`. The line is wiped from
+/// memory when it is dropped, since it may contain a passphrase.
+fn read_line<R: BufRead>(input: &mut R) -> io::Result<SecretString> {
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let line = SecretString::new(line);
+    let trimmed = line.expose_secret().trim_end_matches(['\r', '\n']);
+    Ok(SecretString::new(trimmed.to_string()))
+}
+
+/// Caches a passphrase in memory for the clients of a socket, like
+/// `ssh-agent`. The passphrase is never written to disk, and is wiped from
+/// memory when the agent is dropped.
+pub struct Agent {
+    passphrase: Option<SecretString>,
+    read_timeout: Duration,
+}
+
+impl Agent {
+    /// Creates an agent without a cached passphrase. A client may take up to
+    /// `read_timeout` to send its request before the agent moves on to the
+    /// next client.
+    pub fn new(read_timeout: Duration) -> Self {
+        Self {
+            passphrase: None,
+            read_timeout,
+        }
+    }
+
+    /// Carries out a request, returning the response to send to the client.
+    pub fn handle(&mut self, request: Request) -> Response {
+        match request {
+            Request::Get => match &self.passphrase {
+                Some(passphrase) => Response::Passphrase(passphrase.clone()),
+                None => Response::Empty,
+            },
+            Request::Set(passphrase) => {
+                self.passphrase = Some(passphrase);
+                Response::Ok
+            }
+            Request::Clear | Request::Stop => {
+                self.passphrase = None;
+                Response::Ok
+            }
+        }
+    }
+
+    /// Answers one request per connection to `listener` until a client asks
+    /// the agent to stop. A connection that fails, or that sends nothing
+    /// within the read timeout, does not stop the agent.
+    pub fn serve(mut self, listener: &UnixListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            match self.answer(stream?) {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(e) => debug!(%e, "Agent connection failed"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Answers the request sent on `stream`, returning whether it asked the
+    /// agent to stop.
+    fn answer(&mut self, stream: UnixStream) -> io::Result<bool> {
+        stream.set_read_timeout(Some(self.read_timeout))?;
+        let mut input = BufReader::new(&stream);
+        let line = read_line(&mut input)?;
+
+        let request = Request::parse(line.expose_secret());
+        let stop = matches!(request, Some(Request::Stop));
+        let response = match request {
+            Some(request) => self.handle(request),
+            None => Response::Invalid,
+        };
+
+        response.write_to(&mut &stream)?;
+        Ok(stop)
+    }
+}
+
+/// Sends a request to the agent listening at `socket`, and returns its
+/// response.
+pub fn request(socket: &Path, request: &Request) -> io::Result<Response> {
+    let stream = UnixStream::connect(socket)?;
+    request.write_to(&mut &stream)?;
+
+    let line = read_line(&mut BufReader::new(&stream))?;
+    Response::parse(line.expose_secret()).ok_or_else(|| {
+        io::Error::new(ErrorKind::InvalidData, "invalid response from agent")
+    })
+}
+
+/// Binds a socket at `path` that only the current user can connect to.
+///
+/// The socket is bound in a new directory that only the current user can
+/// access, and only linked to `path` once its permissions are restricted, so
+/// that no other user can connect in between. Fails if `path` exists.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let dir = parent.join(format!(".ciphey-agent-{}", std::process::id()));
+    DirBuilder::new().mode(0o700).create(&dir)?;
+
+    let private = dir.join("agent.sock");
+    let result = UnixListener::bind(&private).and_then(|listener| {
+        fs::set_permissions(&private, Permissions::from_mode(0o600))?;
+        fs::hard_link(&private, path)?;
+        Ok(listener)
+    });
+
+    // The listener keeps accepting connections through `path`.
+    let _ = fs::remove_file(&private);
+    let _ = fs::remove_dir(&dir);
+    result
+}
+
+/// Returns the path of a socket in a new directory that only the current
+/// user can access, so that no other user can connect before the socket's
+/// permissions are restricted.
+pub fn default_socket() -> io::Result<PathBuf> {
+    let dir = std::env::temp_dir()
+        .join(format!("ciphey-agent-{}", std::process::id()));
+    DirBuilder::new().mode(0o700).create(&dir)?;
+    Ok(dir.join("agent.sock"))
+}
+
+/// Starts the agent in the foreground, or stops a running agent.
+pub fn agent<W: Write>(
+    opts: &flags::Agent,
+    env_socket: Option<PathBuf>,
+    output: &mut W,
+) -> Result<(), Error> {
+    if opts.stop {
+        let socket = opts.socket.clone().or(env_socket).ok_or_else(|| {
+            xflags::Error::new(format!(
+                "--stop requires --socket or ${}",
+                SOCK_VAR
+            ))
+        })?;
+        request(&socket, &Request::Stop).map_err(Error::Agent)?;
+        return Ok(());
+    }
+
+    let (socket, dir) = match &opts.socket {
+        Some(socket) => (socket.clone(), None),
+        None => {
+            let socket = default_socket().map_err(Error::Agent)?;
+            let dir = socket.parent().map(Path::to_path_buf);
+            (socket, dir)
+        }
+    };
+    let listener = bind(&socket).map_err(Error::Agent)?;
+
+    writeln!(
+        output,
+        "{}={}; export {};",
+        SOCK_VAR,
+        socket.display(),
+        SOCK_VAR
+    )?;
+    output.flush()?;
+
+    let read_timeout = Duration::from_secs(defaults::AGENT_READ_TIMEOUT);
+    let result = Agent::new(read_timeout).serve(&listener);
+
+    // Removing the socket is best effort, since the agent has stopped anyway.
+    let _ = fs::remove_file(&socket);
+    if let Some(dir) = dir {
+        let _ = fs::remove_dir(dir);
+    }
+
+    result.map_err(Error::Agent)
+}
+
+/// Provides the passphrase cached by the agent at a socket, or else prompts
+/// for it and caches it in the agent. The passphrase is also cached for the
+/// rest of the process, so that it is requested at most once.
+///
+/// An agent that cannot be reached is skipped, so that the passphrase is
+/// prompted for as if no agent was running.
+pub struct AgentPassphrase<F> {
+    socket: Option<PathBuf>,
+    prompt: F,
+    cached: Mutex<Option<SecretString>>,
+}
+
+impl<F> AgentPassphrase<F>
+where
+    F: Fn() -> io::Result<SecretString>,
+{
+    pub fn new(socket: Option<PathBuf>, prompt: F) -> Self {
+        Self {
+            socket,
+            prompt,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Sends a request to the agent, if there is one that can be reached.
+    fn request(&self, request: Request) -> Option<Response> {
+        let socket = self.socket.as_ref()?;
+        match self::request(socket, &request) {
+            Ok(response) => Some(response),
+            Err(e) => {
+                debug!(%e, socket = %socket.display(), "Agent not reachable");
+                None
+            }
+        }
+    }
+}
+
+impl<F> PassphraseSource for AgentPassphrase<F>
+where
+    F: Fn() -> io::Result<SecretString> + Send + Sync,
+{
+    fn passphrase(&self) -> io::Result<SecretString> {
+        let mut cached = self.cached.lock().expect("lock is not poisoned");
+        if let Some(passphrase) = &*cached {
+            return Ok(passphrase.clone());
+        }
+
+        let passphrase = match self.request(Request::Get) {
+            Some(Response::Passphrase(passphrase)) => passphrase,
+            _ => {
+                let passphrase = (self.prompt)()?;
+                self.request(Request::Set(passphrase.clone()));
+                passphrase
+            }
+        };
+
+        *cached = Some(passphrase.clone());
+        Ok(passphrase)
+    }
+
+    fn reject(&self) {
+        *self.cached.lock().expect("lock is not poisoned") = None;
+        self.request(Request::Clear);
+    }
+}
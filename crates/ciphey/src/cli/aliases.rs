@@ -0,0 +1,112 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use super::Error;
+
+/// Short names for recipients, read from the optional `aliases` file at the
+/// root of a store.
+///
+/// Each line of the file defines an alias as `<name> = <recipient>`, and
+/// blank lines and lines starting with `#` are ignored. An alias is used in
+/// place of a recipient as `@<name>`, and may itself refer to another alias.
+///
+/// EXAMPLE: `alice = age1...`
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Aliases {
+    aliases: HashMap<String, String>,
+}
+
+impl Aliases {
+    /// Returns the path of the aliases of the store at `store`.
+    pub fn path(store: &Path) -> PathBuf {
+        store.join("aliases")
+    }
+
+    /// Parses aliases from the contents of an aliases file.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut aliases = HashMap::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |reason| format!("line {}: {}", index + 1, reason);
+
+            let (name, recipient) =
+                line.split_once('=').ok_or_else(|| invalid("missing '='"))?;
+            let (name, recipient) = (name.trim(), recipient.trim());
+
+            if name.is_empty() || name.starts_with('@') {
+                return Err(invalid("invalid alias name"));
+            }
+            if recipient.is_empty() {
+                return Err(invalid("missing recipient"));
+            }
+
+            let name = name.to_string();
+            if aliases.insert(name, recipient.to_string()).is_some() {
+                return Err(invalid("alias is defined more than once"));
+            }
+        }
+
+        Ok(Self { aliases })
+    }
+
+    /// Loads the aliases at `path`, or no aliases if the file does not exist.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            Err(e) => return Err(Error::Storage(e)),
+        };
+
+        Self::parse(&contents)
+            .map_err(|reason| Error::InvalidConfig(path.to_path_buf(), reason))
+    }
+
+    /// Replaces each `@<name>` in `recipients` with the recipient of the
+    /// alias, following aliases that refer to other aliases. Other recipients
+    /// are returned unchanged.
+    pub fn resolve(
+        &self,
+        recipients: &[OsString],
+    ) -> Result<Vec<OsString>, Error> {
+        recipients
+            .iter()
+            .map(|recipient| match recipient.to_str() {
+                Some(recipient) if recipient.starts_with('@') => {
+                    self.resolve_alias(recipient).map(OsString::from)
+                }
+                _ => Ok(recipient.clone()),
+            })
+            .collect()
+    }
+
+    /// Resolves a single `@<name>` to its recipient.
+    fn resolve_alias<'a>(
+        &'a self,
+        mut recipient: &'a str,
+    ) -> Result<&'a str, Error> {
+        let mut seen = HashSet::new();
+
+        while let Some(name) = recipient.strip_prefix('@') {
+            if !seen.insert(name) {
+                return Err(Error::AliasCycle(name.to_string()));
+            }
+
+            recipient = self
+                .aliases
+                .get(name)
+                .ok_or_else(|| Error::UnknownAlias(name.to_string()))?;
+        }
+
+        Ok(recipient)
+    }
+}
@@ -0,0 +1,135 @@
+use std::io::{self, ErrorKind, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+/// A clipboard that values can be copied to, so that the system clipboard
+/// can be replaced in tests.
+pub trait Clipboard {
+    /// Replaces the contents of the clipboard.
+    fn set(&mut self, contents: &str) -> io::Result<()>;
+
+    /// Returns the contents of the clipboard.
+    fn get(&mut self) -> io::Result<String>;
+
+    /// Clears the clipboard.
+    fn clear(&mut self) -> io::Result<()> {
+        self.set("")
+    }
+}
+
+/// The programs that can write to the system clipboard, with their
+/// arguments, in the order they are tried.
+const PROGRAMS: &[(&str, &[&str])] = &[
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+    ("pbcopy", &[]),
+];
+
+/// The programs that read the system clipboard, in the same order as
+/// [`PROGRAMS`].
+const PASTE_PROGRAMS: &[(&str, &[&str])] = &[
+    ("wl-paste", &["--no-newline"]),
+    ("xclip", &["-selection", "clipboard", "-o"]),
+    ("xsel", &["--clipboard", "--output"]),
+    ("pbpaste", &[]),
+];
+
+/// The system clipboard, written to with the first of `wl-copy`, `xclip`,
+/// `xsel` and `pbcopy` that is installed, and read with its counterpart.
+#[derive(Default)]
+pub struct SystemClipboard {
+    /// The index in [`PROGRAMS`] of the program that was found, if any.
+    program: Option<usize>,
+}
+
+impl SystemClipboard {
+    /// Writes `contents` to the clipboard with the program at `index` in
+    /// [`PROGRAMS`].
+    fn run(index: usize, contents: &str) -> io::Result<()> {
+        let (program, args) = PROGRAMS[index];
+        // The programs may stay in the background to serve the clipboard,
+        // so their output is not waited on.
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        stdin.write_all(contents.as_bytes())?;
+        drop(stdin);
+
+        if !child.wait()?.success() {
+            let message = format!("{} failed", program);
+            return Err(io::Error::other(message));
+        }
+        Ok(())
+    }
+}
+
+impl Clipboard for SystemClipboard {
+    /// Reads the clipboard with the counterpart of the program that last
+    /// wrote to it, failing if nothing was written yet.
+    fn get(&mut self) -> io::Result<String> {
+        let index = self.program.ok_or_else(|| {
+            io::Error::new(ErrorKind::NotFound, "no clipboard program found")
+        })?;
+        let (program, args) = PASTE_PROGRAMS[index];
+
+        let output = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            let message = format!("{} failed", program);
+            return Err(io::Error::other(message));
+        }
+        String::from_utf8(output.stdout)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+
+    fn set(&mut self, contents: &str) -> io::Result<()> {
+        if let Some(index) = self.program {
+            return Self::run(index, contents);
+        }
+
+        for index in 0..PROGRAMS.len() {
+            match Self::run(index, contents) {
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                result => {
+                    self.program = Some(index);
+                    return result;
+                }
+            }
+        }
+
+        let message = "no clipboard program found, install wl-copy or xclip";
+        Err(io::Error::new(ErrorKind::NotFound, message))
+    }
+}
+
+/// Copies `contents` to `clipboard`, then clears it once `timeout` has
+/// passed or a message is received on `interrupted`, whichever is first.
+///
+/// Returns whether the wait was interrupted. The clipboard is also cleared
+/// right away if `interrupted` has no sender left. If something else was
+/// copied in the meantime, it is left on the clipboard, but if the clipboard
+/// cannot be read, it is cleared regardless.
+pub fn copy_and_clear<C: Clipboard>(
+    clipboard: &mut C,
+    contents: &str,
+    timeout: Duration,
+    interrupted: &Receiver<()>,
+) -> io::Result<bool> {
+    clipboard.set(contents)?;
+    let interrupted = interrupted.recv_timeout(timeout).is_ok();
+    match clipboard.get() {
+        Ok(current) if current != contents => {}
+        _ => clipboard.clear()?,
+    }
+    Ok(interrupted)
+}
@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use ciphey_kvstore::Key;
+use serde::Deserialize;
+
+use super::{defaults, util, Error, SecretVisibility};
+use crate::backends::crypto::age;
+use crate::flags::Ciphey;
+
+/// Settings for a store, read from the optional `config.toml` file at its
+/// root.
+///
+/// EXAMPLE: `default_fields = ["name", "username", "totp"]`
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The fields that `list` displays unless `--no-default` is passed.
+    pub default_fields: Vec<Key>,
+    /// Values longer than this many bytes are written with a warning, or
+    /// without one if 0.
+    pub warn_value_length: usize,
+    /// Values longer than this many bytes are rejected, or none are if 0.
+    pub max_value_length: usize,
+    /// The keys that `new --normalize-keys` maps each alias to. Aliases are
+    /// matched regardless of case.
+    pub key_aliases: BTreeMap<String, Key>,
+    /// Keys other than the well-known ones that `new --strict-keys` accepts,
+    /// besides the keys that ciphey itself uses.
+    pub allowed_keys: Vec<Key>,
+    /// The recipients of new entries that are created without any recipients
+    /// on the command line or in a team's `.age-recipients` file.
+    pub recipients: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_fields: defaults::KEYS.to_vec(),
+            warn_value_length: defaults::WARN_VALUE_LENGTH,
+            max_value_length: defaults::MAX_VALUE_LENGTH,
+            key_aliases: defaults::KEY_ALIASES
+                .iter()
+                .map(|&(alias, key)| (alias.to_string(), Key::from(key)))
+                .collect(),
+            allowed_keys: Vec::new(),
+            recipients: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Returns the path of the config of the store at `store`.
+    pub fn path(store: &Path) -> PathBuf {
+        store.join("config.toml")
+    }
+
+    /// Parses a config from the contents of a config file.
+    ///
+    /// Settings that are not present keep their default values.
+    pub fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    /// Returns the contents of a config file that documents each setting,
+    /// with every setting commented out at its default value.
+    pub fn template() -> String {
+        let default_fields: Vec<String> = defaults::KEYS
+            .iter()
+            .map(|key| format!("\"{}\"", key))
+            .collect();
+        let key_aliases: Vec<String> = defaults::KEY_ALIASES
+            .iter()
+            .map(|(alias, key)| format!("\"{}\" = \"{}\"", alias, key))
+            .collect();
+
+        let lines = [
+            "# Settings for this ciphey store.".to_string(),
+            String::new(),
+            "# The fields that `list` displays unless `--no-default` is \
+             passed."
+                .to_string(),
+            format!("# default_fields = [{}]", default_fields.join(", ")),
+            String::new(),
+            "# Values longer than this many bytes are written with a warning,"
+                .to_string(),
+            "# or without one if 0.".to_string(),
+            format!("# warn_value_length = {}", defaults::WARN_VALUE_LENGTH),
+            String::new(),
+            "# Values longer than this many bytes are rejected, or none are \
+             if 0."
+                .to_string(),
+            format!("# max_value_length = {}", defaults::MAX_VALUE_LENGTH),
+            String::new(),
+            "# The keys that `new --normalize-keys` maps each alias to."
+                .to_string(),
+            format!("# key_aliases = {{ {} }}", key_aliases.join(", ")),
+            String::new(),
+            "# Keys other than the well-known ones that `new --strict-keys`"
+                .to_string(),
+            "# accepts, besides the keys that ciphey itself uses.".to_string(),
+            "# allowed_keys = []".to_string(),
+            String::new(),
+            "# The recipients of new entries that are created without any"
+                .to_string(),
+            "# recipients on the command line or in a team's .age-recipients"
+                .to_string(),
+            "# file. Overrides $CIPHEY_RECIPIENTS.".to_string(),
+            "# recipients = []".to_string(),
+        ];
+
+        lines.join("\n") + "\n"
+    }
+
+    /// Normalizes a key entered by the user: [`Key::Other`] keys are trimmed
+    /// and lowercased, then replaced by the key they are an alias of, if any.
+    /// Known keys are already canonical.
+    pub fn normalize_key(&self, key: Key) -> Key {
+        let key = match key {
+            Key::Other(key) => key.trim().to_lowercase(),
+            key => return key,
+        };
+
+        self.key_aliases
+            .iter()
+            .find(|(alias, _)| alias.to_lowercase() == key)
+            .map(|(_, canonical)| canonical.clone())
+            .unwrap_or_else(|| Key::from(key.as_str()))
+    }
+
+    /// Returns the keys other than [`Key::KNOWN`] that `new --strict-keys`
+    /// accepts: [`Config::allowed_keys`], and the keys that ciphey itself
+    /// uses.
+    pub fn strict_keys(&self) -> HashSet<Key> {
+        let own = ["secret", defaults::RECIPIENTS_KEY, ciphey_kvstore::ICON];
+        let own = own.into_iter().map(Key::from);
+        own.chain(self.allowed_keys.iter().cloned()).collect()
+    }
+
+    /// Loads the config at `path`, or the default config if the file does not
+    /// exist.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            Err(e) => return Err(Error::Storage(e)),
+        };
+
+        Self::parse(&contents).map_err(|e| {
+            Error::InvalidConfig(path.to_path_buf(), e.to_string())
+        })
+    }
+}
+
+/// The settings that apply to every command, as resolved by [`resolve`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct Settings {
+    /// The path to the store.
+    pub store: PathBuf,
+    /// The age identity files to decrypt with.
+    pub identity_files: Vec<PathBuf>,
+    /// The recipients of new entries that are created without any recipients
+    /// on the command line, unless the config of the store sets them.
+    pub recipients: Vec<OsString>,
+    /// Whether to show secret material in the output.
+    pub visibility: SecretVisibility,
+}
+
+impl Settings {
+    /// Loads the config of the store.
+    ///
+    /// The config is only loaded by the commands that use it, so that a
+    /// malformed config can still be reported by `doctor` or replaced by
+    /// `init --force --scaffold`.
+    pub fn config(&self) -> Result<Config, Error> {
+        Config::load(&Config::path(&self.store))
+    }
+
+    /// Returns the recipients of new entries that are created without any
+    /// recipients on the command line: those of `config`, if any, or else
+    /// [`Settings::recipients`].
+    pub fn default_recipients(&self, config: &Config) -> Vec<OsString> {
+        match config.recipients.is_empty() {
+            false => config.recipients.iter().map(OsString::from).collect(),
+            true => self.recipients.clone(),
+        }
+    }
+}
+
+/// Resolves the settings that apply to every command. Each setting is taken
+/// from the first of these that sets it:
+///
+/// 1. The command line flags.
+/// 2. The store's config file, which only sets the recipients, since it is
+///    found through the other settings. It is loaded separately, see
+///    [`Settings::config`].
+/// 3. The environment: `$CIPHEY_STORE`, `$CIPHEY_IDENTITY`,
+///    `$CIPHEY_RECIPIENTS` as a comma-separated list, and `$CIPHEY_SHOW` as
+///    `1`/`true`/`yes` or `0`/`false`/`no`.
+/// 4. The defaults, including the identity variables of
+///    [`age::identity_files`]. The default store is `.local/share/ciphey` in
+///    `$HOME`, which is looked up when ciphey runs rather than when it is
+///    built.
+///
+/// `$CIPHEY_CRYPTO` selects the crypto backend, and `age` is the only one
+/// available. Empty variables are ignored.
+///
+/// `var` looks up an environment variable, so that tests do not need to
+/// modify the process environment.
+pub fn resolve<F>(args: &Ciphey, var: F) -> Result<Settings, Error>
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    let var = |name: &str| var(name).filter(|value| !value.is_empty());
+    let invalid =
+        |name, reason: &str| Error::InvalidVariable(name, reason.to_string());
+    let utf8 = |name| -> Result<Option<String>, Error> {
+        var(name)
+            .map(|value| value.into_string())
+            .transpose()
+            .map_err(|_| invalid(name, "not valid UTF-8"))
+    };
+
+    if let Some(crypto) = utf8(defaults::CRYPTO_VAR)? {
+        if crypto != "age" {
+            return Err(invalid(
+                defaults::CRYPTO_VAR,
+                "only `age` is available",
+            ));
+        }
+    }
+
+    let store = match &args.path {
+        Some(path) => path.clone(),
+        None => match (var(defaults::STORE_VAR), var(defaults::HOME_VAR)) {
+            (Some(store), _) => PathBuf::from(store),
+            (None, Some(home)) => defaults::store_dir(Path::new(&home)),
+            (None, None) => {
+                let message = format!(
+                    "no store path: pass --path, or set ${} or ${}",
+                    defaults::STORE_VAR,
+                    defaults::HOME_VAR
+                );
+                return Err(xflags::Error::new(message).into());
+            }
+        },
+    };
+
+    let keyfiles = match var(defaults::IDENTITY_VAR) {
+        Some(path) if args.age_keyfile.is_empty() => vec![PathBuf::from(path)],
+        _ => args.age_keyfile.clone(),
+    };
+    let identity_files = age::identity_files(&keyfiles, var);
+
+    let show = match utf8(defaults::SHOW_VAR)?.as_deref() {
+        None => false,
+        Some("1" | "true" | "yes") => true,
+        Some("0" | "false" | "no") => false,
+        Some(_) => {
+            let reason = "expected 1, true, yes, 0, false, or no";
+            return Err(invalid(defaults::SHOW_VAR, reason));
+        }
+    };
+    let visibility = match args.show || show {
+        true => SecretVisibility::Show,
+        false => SecretVisibility::Hide,
+    };
+
+    let recipients = utf8(defaults::RECIPIENTS_VAR)?
+        .map(|list| util::split_recipients(&list))
+        .unwrap_or_default()
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+
+    Ok(Settings {
+        store,
+        identity_files,
+        recipients,
+        visibility,
+    })
+}
@@ -1,15 +1,94 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use ciphey_kvstore::Key;
 
-// Default path for ciphey store
-pub const STORE_DIR: &[&str] = &[env!("HOME"), ".local", "share", "ciphey"];
-// pub const RECIPIENTS_PATH: &[&str] = &[".identities"];
+// Default path for ciphey store, relative to the home directory
+pub const STORE_PATH: &[&str] = &[".local", "share", "ciphey"];
+// Environment variable holding the home directory, read at runtime
+pub const HOME_VAR: &str = "HOME";
+// Path of a store's default recipients file, relative to the store
+pub const RECIPIENTS_PATH: &[&str] = &[".recipients"];
+
+// Path of a store's generated identities file, relative to the store
+pub const IDENTITIES_PATH: &[&str] = &["identities"];
+// Path of a store's gitignore file, which keeps the generated identities file
+// out of the store's git repository
+pub const GITIGNORE_PATH: &[&str] = &[".gitignore"];
+
+// Field holding an entry's own list of recipients
+pub const RECIPIENTS_KEY: &str = "recipients";
+
+// Default length in bytes above which a value is written with a warning
+pub const WARN_VALUE_LENGTH: usize = 4096;
+// Default length in bytes above which a value is rejected
+pub const MAX_VALUE_LENGTH: usize = 65536;
+
+// Default number of bytes that `cat` reads from storage at a time
+pub const READER_BUFFER_SIZE: usize = 64 * 1024;
+
+// Default number of seconds after which `copy` clears the clipboard
+pub const CLIPBOARD_TIMEOUT: u64 = 45;
+
+// Number of seconds that a client of the agent may take to send its request
+pub const AGENT_READ_TIMEOUT: u64 = 5;
+
+// Number of bytes at the start of an entry that are read to find its
+// recipients, before falling back to reading the whole entry
+pub const HEADER_PEEK_LENGTH: u64 = 4096;
+
+// Default aliases that `new --normalize-keys` maps keys to
+pub const KEY_ALIASES: &[(&str, &str)] = &[
+    ("e-mail", "email"),
+    ("login", "username"),
+    ("mail", "email"),
+    ("note", "notes"),
+    ("pass", "password"),
+    ("passwd", "password"),
+    ("tag", "tags"),
+    ("user", "username"),
+    ("website", "url"),
+];
+
+// Environment variables that set defaults for every command, see
+// `config::resolve`
+pub const STORE_VAR: &str = "CIPHEY_STORE";
+pub const IDENTITY_VAR: &str = "CIPHEY_IDENTITY";
+pub const RECIPIENTS_VAR: &str = "CIPHEY_RECIPIENTS";
+pub const CRYPTO_VAR: &str = "CIPHEY_CRYPTO";
+pub const SHOW_VAR: &str = "CIPHEY_SHOW";
+
+// Name of the age recipients file that lists a team's recipients, found
+// from the store up to the root of its git repository
+pub const TEAM_RECIPIENTS_FILE: &str = ".age-recipients";
 
 // Default field keys to display
 pub const KEYS: &[Key] = &[Key::Name, Key::Username, Key::Email, Key::Url];
 
-// Returns `PathBuf` of default path to ciphey store.
-pub fn store_dir() -> PathBuf {
-    STORE_DIR.iter().collect()
+// Returns `PathBuf` of default path to ciphey store in the home directory
+// `home`.
+pub fn store_dir(home: &Path) -> PathBuf {
+    let mut path = home.to_path_buf();
+    path.extend(STORE_PATH);
+    path
+}
+
+// Returns `PathBuf` of the default recipients file of the store at `store`.
+pub fn recipients_file(store: &Path) -> PathBuf {
+    let mut path = store.to_path_buf();
+    path.extend(RECIPIENTS_PATH);
+    path
+}
+
+// Returns `PathBuf` of the generated identities file of the store at `store`.
+pub fn identities_file(store: &Path) -> PathBuf {
+    let mut path = store.to_path_buf();
+    path.extend(IDENTITIES_PATH);
+    path
+}
+
+// Returns `PathBuf` of the gitignore file of the store at `store`.
+pub fn gitignore_file(store: &Path) -> PathBuf {
+    let mut path = store.to_path_buf();
+    path.extend(GITIGNORE_PATH);
+    path
 }
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use ciphey_kvstore::{DisplayOptions, Key, KeyValuePair, KvStore, Value};
+use libciphey::crypto::Encrypted;
+use libciphey::filetype::Filetype;
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+
+/// The formats that the decrypted contents of an entry can be in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A JSON object that maps each key to its value, in order. A value is
+    /// either a string, or an object like `{"sensitive": "hunter2"}`.
+    Json,
+    /// The key/value format of [`KvStore`].
+    KvStore,
+    /// Contents that are not text, which can only be shown raw.
+    Raw,
+}
+
+impl Format {
+    /// Detects the format of an entry from its contents: JSON entries start
+    /// with `{`, after any whitespace, and other text is a [`KvStore`].
+    pub fn sniff(contents: &[u8]) -> Self {
+        let start = contents.iter().find(|byte| !byte.is_ascii_whitespace());
+
+        if start == Some(&b'{') {
+            Format::Json
+        } else if std::str::from_utf8(contents).is_ok() {
+            Format::KvStore
+        } else {
+            Format::Raw
+        }
+    }
+}
+
+/// An entry in any of the supported formats.
+///
+/// Text entries are read into a [`KvStore`] regardless of their format, so
+/// that every command can use them. They are written back in the key/value
+/// format.
+pub enum AnyEntry {
+    KvStore(KvStore),
+    /// An entry that is not text, which can only be read raw with `ciphey
+    /// cat --uuid`.
+    Raw,
+}
+
+/// The reasons that a text entry could not be read.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    KvStore(ciphey_kvstore::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            // The message of the error may quote the input, so only its
+            // position is shown.
+            Error::Json(e) => write!(
+                f,
+                "Invalid JSON entry at line {} column {}",
+                e.line(),
+                e.column()
+            ),
+            Error::KvStore(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Entries are read with [`read_entry`], so that a vault can decrypt entries in
+/// any format. Text entries are written in the key/value format, but a raw
+/// entry has no contents to write or display.
+impl Filetype for AnyEntry {
+    type Error = Error;
+    type Options = DisplayOptions;
+
+    fn deserialize<R>(reader: &mut R) -> Result<Self, Self::Error>
+    where
+        R: Read,
+    {
+        read_entry(reader)
+    }
+
+    fn serialize<W, E>(self, writer: &mut E) -> Result<(), io::Error>
+    where
+        W: Write,
+        E: Encrypted<W>,
+    {
+        match self {
+            AnyEntry::KvStore(store) => store.serialize(writer),
+            AnyEntry::Raw => Err(raw_entry_error()),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Self::Error> {
+        match self {
+            AnyEntry::KvStore(store) => {
+                store.validate().map_err(Error::KvStore)
+            }
+            AnyEntry::Raw => Ok(()),
+        }
+    }
+
+    fn display<W>(
+        &self,
+        writer: &mut W,
+        opts: DisplayOptions,
+        show_secrets: bool,
+    ) -> Result<(), io::Error>
+    where
+        W: Write,
+    {
+        match self {
+            AnyEntry::KvStore(store) => {
+                store.display(writer, opts, show_secrets)
+            }
+            AnyEntry::Raw => Err(raw_entry_error()),
+        }
+    }
+}
+
+fn raw_entry_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "a raw entry can only be read with `ciphey cat`",
+    )
+}
+
+/// Reads an entry, detecting its format with [`Format::sniff`].
+pub fn read_entry<R: Read>(reader: &mut R) -> Result<AnyEntry, Error> {
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents).map_err(Error::Io)?;
+
+    match Format::sniff(&contents) {
+        Format::Json => {
+            let JsonEntry(pairs) =
+                serde_json::from_slice(&contents).map_err(Error::Json)?;
+            Ok(AnyEntry::KvStore(KvStore::new(pairs)))
+        }
+        Format::KvStore => KvStore::deserialize(&mut contents.as_slice())
+            .map(AnyEntry::KvStore)
+            .map_err(Error::KvStore),
+        Format::Raw => Ok(AnyEntry::Raw),
+    }
+}
+
+/// The pairs of a JSON entry, in the order of its keys.
+struct JsonEntry(Vec<KeyValuePair>);
+
+/// A value of a JSON entry. Plain strings are insensitive.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum JsonValue {
+    Plain(String),
+    Tagged(Value),
+}
+
+impl<'de> Deserialize<'de> for JsonEntry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // A map type would not keep the order of the keys.
+        struct PairsVisitor;
+
+        impl<'de> Visitor<'de> for PairsVisitor {
+            type Value = JsonEntry;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an object of keys and values")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<JsonEntry, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut pairs = Vec::new();
+                while let Some((key, value)) =
+                    map.next_entry::<String, JsonValue>()?
+                {
+                    let value = match value {
+                        JsonValue::Plain(value) => Value::Insensitive(value),
+                        JsonValue::Tagged(value) => value,
+                    };
+                    pairs.push(KeyValuePair::new(
+                        Key::from(key.as_str()),
+                        value,
+                    ));
+                }
+
+                Ok(JsonEntry(pairs))
+            }
+        }
+
+        deserializer.deserialize_map(PairsVisitor)
+    }
+}
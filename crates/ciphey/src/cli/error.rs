@@ -1,17 +1,97 @@
 use std::ffi::OsString;
 use std::fmt::Display;
-use std::io;
+use std::io::{self, Write};
+use std::path::PathBuf;
 use std::process::Termination;
+use std::str::FromStr;
+
+use ciphey_kvstore::Key;
+use libciphey::vault;
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub enum Error {
     // TODO: Should we create a wrapper type to handle all supported backends?
     Storage(io::Error),
-    Crypto(Box<dyn std::error::Error>),
-    Filetype(Box<dyn std::error::Error>),
+    Crypto(Box<dyn std::error::Error + Send + Sync>),
+    Filetype(Box<dyn std::error::Error + Send + Sync>),
     Input(io::Error),
     Xflags(xflags::Error),
     OsStringConversionError(OsString),
+    /// No entry with the provided name exists in the store.
+    EntryNotFound(String),
+    /// The entry does not contain a field with the provided key.
+    FieldNotFound(Key),
+    /// The field is sensitive and secret material is hidden.
+    SecretHidden(Key),
+    /// Secrets would be written to a file that other users can read.
+    InsecureOutput(PathBuf),
+    /// The crypto backend cannot determine the recipients of an entry.
+    UnknownRecipients,
+    /// The provided number of entries could not be decrypted or parsed.
+    VerificationFailed(usize),
+    /// Raw entry data would be written to a terminal.
+    TerminalOutput,
+    /// The provided number of problems were found in the store's structure.
+    UnhealthyStore(usize),
+    /// No recipients could be derived from the loaded identities.
+    NoOwnRecipients,
+    /// An entry with the provided UUID already exists in the store.
+    EntryExists(Uuid),
+    /// The record on the provided line of a batch could not be parsed.
+    InvalidRecord(usize, String),
+    /// The store's config file at the provided path could not be parsed.
+    InvalidConfig(PathBuf, String),
+    /// The entry at the provided path is not text, so it is not in the
+    /// key/value format.
+    BinaryEntry(String),
+    /// None of the recipients belong to the loaded identities.
+    Lockout,
+    /// The store cannot be modified.
+    ReadOnlyStore,
+    /// The user did not confirm that the store should be purged.
+    PurgeNotConfirmed,
+    /// The user did not confirm that the matching entries should be removed.
+    RemoveNotConfirmed,
+    /// The provided number of entries could not be removed.
+    RemoveFailed(usize),
+    /// A pattern matches the entries with the provided names, but only one
+    /// entry can be selected.
+    AmbiguousName(Vec<String>),
+    /// The store does not support the described operation.
+    Unsupported(&'static str),
+    /// No alias with the provided name is defined.
+    UnknownAlias(String),
+    /// The alias with the provided name refers back to itself.
+    AliasCycle(String),
+    /// No entry was picked interactively.
+    NothingSelected,
+    /// The passphrase agent could not be started, stopped or reached.
+    Agent(io::Error),
+    /// The clipboard could not be written to or cleared.
+    Clipboard(io::Error),
+    /// The handler that clears the clipboard when the process receives a
+    /// signal could not be installed.
+    SignalHandler(io::Error),
+    /// The command was interrupted by a signal.
+    Interrupted,
+    /// The store's format version, the first, is newer than the current
+    /// format version, the second.
+    UnsupportedVersion(usize, usize),
+    /// No entries were listed, and `--fail-empty` was passed.
+    NoEntries,
+    /// The provided number of entries could not be listed.
+    ListFailed(usize),
+    /// The provided number of secrets are shared by more than one entry.
+    ReusedSecrets(usize),
+    /// The provided number of entries could not be re-encrypted.
+    ReshareFailed(usize),
+    /// The recipient, with the provided fingerprint, does not have any of the
+    /// expected fingerprints.
+    UnexpectedFingerprint(String, String),
+    /// The environment variable with the provided name has an invalid value,
+    /// for the provided reason.
+    InvalidVariable(&'static str, String),
 }
 
 impl Display for Error {
@@ -24,6 +104,222 @@ impl Display for Error {
             Error::OsStringConversionError(os_str) => {
                 write!(f, "Could not parse invalid input: {:#?}", os_str)
             }
+            Error::EntryNotFound(name) => {
+                write!(f, "No entry found with name: {}", name)
+            }
+            Error::FieldNotFound(key) => {
+                write!(f, "Entry has no field with key: {}", key)
+            }
+            Error::SecretHidden(key) => write!(
+                f,
+                "Field '{}' is sensitive, use --show to display it",
+                key
+            ),
+            Error::InsecureOutput(path) => write!(
+                f,
+                "Refusing to write secrets to {}, which is accessible by \
+                 other users",
+                path.display()
+            ),
+            Error::UnknownRecipients => write!(
+                f,
+                "The current recipients of the entry cannot be determined, so \
+                 re-encrypting it would drop them"
+            ),
+            Error::VerificationFailed(count) => {
+                write!(f, "{} entries failed verification", count)
+            }
+            Error::NoOwnRecipients => write!(
+                f,
+                "No recipients could be derived from the loaded identities, \
+                 pass --age-keyfile with a native age identity"
+            ),
+            Error::EntryExists(uuid) => write!(
+                f,
+                "An entry with UUID {} already exists, refusing to overwrite \
+                 it",
+                uuid
+            ),
+            Error::InvalidRecord(line, reason) => {
+                write!(f, "Invalid record on line {}: {}", line, reason)
+            }
+            Error::InvalidConfig(path, reason) => {
+                write!(f, "Invalid config {}: {}", path.display(), reason)
+            }
+            Error::BinaryEntry(path) => write!(
+                f,
+                "Entry {} is not in the key/value format, use `ciphey cat \
+                 --uuid` to read its raw contents",
+                path
+            ),
+            Error::Lockout => write!(
+                f,
+                "None of the recipients belong to the loaded identities, so \
+                 the entry could not be decrypted again, use --allow-lockout \
+                 to encrypt it anyway"
+            ),
+            Error::ReadOnlyStore => write!(f, "The store is read-only"),
+            Error::PurgeNotConfirmed => {
+                write!(f, "Purge was not confirmed, no entries were removed")
+            }
+            Error::RemoveNotConfirmed => {
+                write!(f, "Removal was not confirmed, no entries were removed")
+            }
+            Error::RemoveFailed(count) => {
+                write!(f, "{} entries could not be removed", count)
+            }
+            Error::AmbiguousName(names) => write!(
+                f,
+                "Multiple entries match, use --all to select all of them: {}",
+                names.join(", ")
+            ),
+            Error::Unsupported(operation) => {
+                write!(f, "The store does not support {}", operation)
+            }
+            Error::UnknownAlias(name) => {
+                write!(f, "No alias is defined with name: {}", name)
+            }
+            Error::AliasCycle(name) => {
+                write!(f, "Alias '{}' refers back to itself", name)
+            }
+            Error::NothingSelected => write!(f, "No entry was selected"),
+            Error::Agent(e) => write!(f, "Passphrase agent failed: {}", e),
+            Error::Clipboard(e) => write!(f, "Clipboard failed: {}", e),
+            Error::SignalHandler(e) => {
+                write!(f, "Could not install the signal handler: {}", e)
+            }
+            Error::Interrupted => write!(f, "Interrupted"),
+            Error::UnsupportedVersion(version, current) => write!(
+                f,
+                "Store format version {} is newer than the supported version \
+                 {}, upgrade ciphey to use it",
+                version, current
+            ),
+            Error::NoEntries => write!(f, "No entries were found"),
+            Error::ListFailed(count) => {
+                write!(f, "{} entries could not be listed", count)
+            }
+            Error::ReshareFailed(count) => {
+                write!(f, "{} entries could not be re-encrypted", count)
+            }
+            Error::InvalidVariable(name, reason) => {
+                write!(f, "Invalid ${}: {}", name, reason)
+            }
+            Error::UnexpectedFingerprint(recipient, fingerprint) => write!(
+                f,
+                "Recipient {} has fingerprint {}, which was not expected",
+                recipient, fingerprint
+            ),
+            Error::ReusedSecrets(count) => {
+                write!(f, "Found {} secrets shared by multiple entries", count)
+            }
+            Error::UnhealthyStore(count) => {
+                write!(f, "Found {} problems in the store", count)
+            }
+            Error::TerminalOutput => write!(
+                f,
+                "Refusing to write raw entry data to a terminal, use --force \
+                 to write it anyway"
+            ),
+        }
+    }
+}
+
+impl Error {
+    /// Returns a stable name for the kind of error, for machine consumers.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Storage(_) => "storage",
+            Error::Crypto(_) => "crypto",
+            Error::Filetype(_) => "filetype",
+            Error::Input(_) => "input",
+            Error::Xflags(_) => "arguments",
+            Error::OsStringConversionError(_) => "invalid_input",
+            Error::EntryNotFound(_) => "entry_not_found",
+            Error::FieldNotFound(_) => "field_not_found",
+            Error::SecretHidden(_) => "secret_hidden",
+            Error::InsecureOutput(_) => "insecure_output",
+            Error::UnknownRecipients => "unknown_recipients",
+            Error::VerificationFailed(_) => "verification_failed",
+            Error::TerminalOutput => "terminal_output",
+            Error::UnhealthyStore(_) => "unhealthy_store",
+            Error::NoOwnRecipients => "no_own_recipients",
+            Error::EntryExists(_) => "entry_exists",
+            Error::InvalidRecord(..) => "invalid_record",
+            Error::InvalidConfig(..) => "invalid_config",
+            Error::BinaryEntry(_) => "binary_entry",
+            Error::Lockout => "lockout",
+            Error::ReadOnlyStore => "read_only_store",
+            Error::PurgeNotConfirmed => "purge_not_confirmed",
+            Error::RemoveNotConfirmed => "remove_not_confirmed",
+            Error::RemoveFailed(_) => "remove_failed",
+            Error::AmbiguousName(_) => "ambiguous_name",
+            Error::Unsupported(_) => "unsupported",
+            Error::UnknownAlias(_) => "unknown_alias",
+            Error::AliasCycle(_) => "alias_cycle",
+            Error::NothingSelected => "nothing_selected",
+            Error::Agent(_) => "agent",
+            Error::Clipboard(_) => "clipboard",
+            Error::SignalHandler(_) => "signal_handler",
+            Error::Interrupted => "interrupted",
+            Error::UnsupportedVersion(..) => "unsupported_version",
+            Error::NoEntries => "no_entries",
+            Error::ListFailed(_) => "list_failed",
+            Error::ReusedSecrets(_) => "reused_secrets",
+            Error::ReshareFailed(_) => "reshare_failed",
+            Error::UnexpectedFingerprint(..) => "unexpected_fingerprint",
+            Error::InvalidVariable(..) => "invalid_variable",
+        }
+    }
+
+    /// Writes the error to `output` in the provided format.
+    ///
+    /// The JSON format omits the details of errors that may contain the
+    /// contents of an entry or other input.
+    pub fn report_to<W: Write>(
+        &self,
+        format: ErrorFormat,
+        output: &mut W,
+    ) -> io::Result<()> {
+        match format {
+            ErrorFormat::Human => writeln!(output, "Error: {:?}", self),
+            ErrorFormat::Json => {
+                let message = match self {
+                    Error::Filetype(_) => "Invalid entry format".to_string(),
+                    Error::OsStringConversionError(_) => {
+                        "Could not parse invalid input".to_string()
+                    }
+                    err => err.to_string(),
+                };
+
+                let json = serde_json::json!({
+                    "kind": self.kind(),
+                    "message": message,
+                });
+                writeln!(output, "{}", json)
+            }
+        }
+    }
+}
+
+/// The format that errors are reported in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// A message for people to read.
+    #[default]
+    Human,
+    /// A JSON object with the `kind` of the error and a `message`.
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown error format: {}", s)),
         }
     }
 }
@@ -34,15 +330,38 @@ impl From<xflags::Error> for Error {
     }
 }
 
+impl From<vault::Error> for Error {
+    fn from(err: vault::Error) -> Self {
+        match err {
+            vault::Error::Storage(e) => e.into(),
+            vault::Error::Crypto(e) => Self::Crypto(e),
+            vault::Error::Filetype(e) => Self::Filetype(e),
+            vault::Error::NotFound(uuid) => {
+                Self::EntryNotFound(uuid.to_string())
+            }
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
-        Self::Storage(err)
+        match err.kind() {
+            io::ErrorKind::ReadOnlyFilesystem => Self::ReadOnlyStore,
+            _ => Self::Storage(err),
+        }
     }
 }
 
+/// The exit code when `--fail-empty` was passed and no entries were listed,
+/// so that scripts can tell an empty result apart from other failures.
+pub const EXIT_NO_ENTRIES: u8 = 3;
+
 impl Termination for Error {
     fn report(self) -> std::process::ExitCode {
         // TODO: Add more precise exit codes
-        std::process::ExitCode::FAILURE
+        match self {
+            Error::NoEntries => EXIT_NO_ENTRIES.into(),
+            _ => std::process::ExitCode::FAILURE,
+        }
     }
 }
@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use uuid::Uuid;
+
+use super::{util, Error};
+
+/// An optional index of entry names to UUIDs, used to resolve a name without
+/// decrypting every entry in the store.
+///
+/// The index is stored unencrypted in the store's `index` file, one
+/// `<uuid> <name>` pair per line, so it reveals the names of the entries to
+/// anyone who can read the store. It is only created by `ciphey reindex`.
+///
+/// The index is a cache: lookups are checked against the name of the decrypted
+/// entry, so a stale index slows a lookup down rather than returning an entry
+/// with a different name.
+pub struct NameIndex {
+    path: PathBuf,
+    names: BTreeMap<String, Uuid>,
+}
+
+impl NameIndex {
+    /// Returns the path of the index of the store at `store`.
+    pub fn path(store: &Path) -> PathBuf {
+        store.join("index")
+    }
+
+    /// Creates an empty index that will be saved to `path`.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            names: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the path of the file the index is saved to.
+    pub fn file(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads the index at `path`, or returns `None` if the store has no index.
+    pub fn load(path: PathBuf) -> io::Result<Option<Self>> {
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut index = Self::new(path);
+
+        for line in contents.lines() {
+            let invalid =
+                || io::Error::new(ErrorKind::InvalidData, "invalid index");
+
+            let (uuid, name) = line.split_once(' ').ok_or_else(invalid)?;
+            let uuid = Uuid::from_str(uuid).map_err(|_| invalid())?;
+            index.insert(name.to_string(), uuid);
+        }
+
+        Ok(Some(index))
+    }
+
+    /// Writes the index to its file.
+    ///
+    /// The index is written to a temporary file that then replaces the index,
+    /// so an interrupted save leaves the previous index in place. On Unix, the
+    /// file is only readable by its owner, as it reveals the names of entries.
+    pub fn save(&self) -> Result<(), Error> {
+        let mut contents = String::new();
+
+        for (name, uuid) in &self.names {
+            contents.push_str(&format!("{} {}\n", uuid, name));
+        }
+
+        // Left behind if a previous save was interrupted.
+        let temporary = self.path.with_extension("tmp");
+        match fs::remove_file(&temporary) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        util::create_private(&temporary, &contents)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+
+    /// Returns the UUID of the entry with the provided name.
+    pub fn get(&self, name: &str) -> Option<Uuid> {
+        self.names.get(name).copied()
+    }
+
+    /// Adds an entry to the index.
+    ///
+    /// Like a scan of the store, a name shared by several entries resolves to
+    /// the entry with the lowest UUID. Names containing a newline cannot be
+    /// stored and are skipped, so they are always resolved by decrypting the
+    /// store.
+    pub fn insert(&mut self, name: String, uuid: Uuid) {
+        if name.contains('\n') {
+            return;
+        }
+
+        let indexed = self.names.entry(name).or_insert(uuid);
+        *indexed = uuid.min(*indexed);
+    }
+
+    /// Removes the entry with the provided UUID from the index.
+    pub fn remove(&mut self, uuid: &Uuid) {
+        self.names.retain(|_, indexed| indexed != uuid);
+    }
+}
@@ -0,0 +1,185 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use ciphey_kvstore::KvStore;
+use libciphey::crypto;
+use libciphey::storage::{self, Reference};
+use uuid::Uuid;
+
+use super::{
+    decrypt_entry, ensure_supported, ensure_writable, recipients_of,
+    replace_entry, util, Error,
+};
+use crate::flags::util::parse_recipients;
+use crate::flags::Migrate;
+
+/// Upgrades an entry from the previous format version to the next one.
+pub type Migration = fn(KvStore) -> KvStore;
+
+/// The migrations of the store format, in order. Migration `n` upgrades
+/// entries from version `n` to version `n + 1`, so the current version is the
+/// number of migrations.
+///
+/// A migration must leave an entry that it already upgraded unchanged, since
+/// an interrupted migration is resumed from the start.
+pub const MIGRATIONS: &[Migration] = &[];
+
+/// Returns the path of the file that records the format version of the store
+/// at `store`.
+pub fn version_path(store: &Path) -> PathBuf {
+    store.join("VERSION")
+}
+
+/// Returns the path of the directory that entries are backed up to before
+/// they are upgraded from `version`.
+pub fn backup_path(store: &Path, version: usize) -> PathBuf {
+    store.join("backups").join(format!("format-v{}", version))
+}
+
+/// Reads the format version of the store at `store`. Stores without a
+/// `VERSION` file are at version 0.
+pub fn read_version(store: &Path) -> Result<usize, Error> {
+    let path = version_path(store);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(Error::Storage(e)),
+    };
+
+    contents.trim().parse().map_err(|_| {
+        let reason = format!("invalid version {:?}", contents.trim());
+        Error::InvalidConfig(path, reason)
+    })
+}
+
+/// Upgrades every entry of the store at `store` to the current format
+/// version, and records the new version.
+pub fn migrate<C, S, W>(
+    opts: &Migrate,
+    crypto: &C,
+    storage: &mut S,
+    store: &Path,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    W: Write,
+{
+    migrate_with(MIGRATIONS, opts, crypto, storage, store, output)
+}
+
+/// Upgrades every entry with `migrations`, as [`migrate`] does with
+/// [`MIGRATIONS`].
+///
+/// The encrypted entries are backed up to [`backup_path`] before any entry is
+/// rewritten. Backups that already exist are kept, so that they still hold
+/// the original entries when an interrupted migration is resumed.
+pub fn migrate_with<C, S, W>(
+    migrations: &[Migration],
+    opts: &Migrate,
+    crypto: &C,
+    storage: &mut S,
+    store: &Path,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    W: Write,
+{
+    let version = read_version(store)?;
+    let current = migrations.len();
+
+    if opts.store_format_version {
+        writeln!(output, "Store format version: {}", version)?;
+        writeln!(output, "Current format version: {}", current)?;
+        return Ok(());
+    }
+
+    if version > current {
+        return Err(Error::UnsupportedVersion(version, current));
+    } else if version == current {
+        writeln!(output, "Store is at format version {}", version)?;
+        return Ok(());
+    }
+
+    ensure_writable(storage)?;
+    ensure_supported(storage, |c| c.can_replace, "replacing entries")?;
+
+    let references = storage.sorted_entries()?;
+
+    let backups = backup_path(store, version);
+    backup_entries(&references, &backups)?;
+    writeln!(output, "Backed up entries to {}", backups.display())?;
+
+    let mut migrated = 0;
+    for (uuid, reference) in &references {
+        // The format version only covers key/value entries, so binary entries
+        // are carried over unchanged.
+        let mut entry = match decrypt_entry(reference, crypto) {
+            Ok(entry) => entry,
+            Err(Error::BinaryEntry(_)) => {
+                writeln!(output, "Kept binary entry {} unchanged", uuid)?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        for migration in &migrations[version..] {
+            entry = migration(entry);
+        }
+
+        // Entries keep their recipients, as far as they can be determined.
+        let known = recipients_of(crypto, reference)?;
+        let recipients: Vec<OsString> = match known {
+            Some(known) => known.into_iter().map(OsString::from).collect(),
+            None if opts.recipient.is_empty() => {
+                return Err(Error::UnknownRecipients)
+            }
+            None => opts.recipient.clone(),
+        };
+        let recipients = parse_recipients::<C::Recipient>(&recipients)?;
+        replace_entry(crypto, storage, uuid, entry, recipients)?;
+        migrated += 1;
+    }
+
+    fs::write(version_path(store), format!("{}\n", current))?;
+    writeln!(
+        output,
+        "Migrated {} entries from format version {} to {}",
+        migrated, version, current
+    )?;
+
+    Ok(())
+}
+
+/// Copies the encrypted contents of each entry into `dir`, as `<uuid>.age`,
+/// except for entries that are already backed up there. Backups are created
+/// as with [`util::create_private`].
+fn backup_entries<R: Reference>(
+    references: &[(Uuid, R)],
+    dir: &Path,
+) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+
+    for (uuid, reference) in references {
+        let path = dir.join(format!("{}.age", uuid.hyphenated()));
+        if path.exists() {
+            continue;
+        }
+
+        let mut ciphertext = Vec::new();
+        reference.reader()?.read_to_end(&mut ciphertext)?;
+        util::create_private(&path, &ciphertext).map_err(|e| match e {
+            Error::Storage(e) => {
+                let message = format!("{}: {}", path.display(), e);
+                Error::Storage(io::Error::new(e.kind(), message))
+            }
+            e => e,
+        })?;
+    }
+
+    Ok(())
+}
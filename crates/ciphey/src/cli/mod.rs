@@ -1,26 +1,57 @@
-use std::collections::HashSet;
-use std::io::{BufRead, Write};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+use std::{panic, thread};
 
-use ciphey_kvstore::{DisplayOptions, Key, KeyValuePair, KvStore, Value};
-use libciphey::crypto;
+use ciphey_kvstore::{
+    parse_tags, DisplayOptions, Key, KeyValuePair, KvStore, Redaction, Value,
+    MASK,
+};
+use libciphey::crypto::{self, Encrypted};
 use libciphey::filetype::Filetype;
 use libciphey::storage::{self, Reference};
+use libciphey::vault;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
 use uuid::Uuid;
 
 use crate::flags::util::{
-    parse_key_value_pairs, parse_os_str, parse_recipients,
+    parse_date, parse_field_list, parse_key_value_pairs, parse_os_str,
+    parse_recipients, parse_uuid,
+};
+use crate::flags::{
+    Cat, Ciphey, Doctor, Init, List, Merge, Mv, New, Purge, Remove, Reshare,
+    SetSensitive, Show,
 };
-use crate::flags::{Ciphey, List, New};
 
+#[cfg(unix)]
+pub mod agent;
+pub mod aliases;
+pub mod clipboard;
+pub mod config;
 pub mod defaults;
+pub mod entry;
 pub mod error;
+pub mod index;
+pub mod migrate;
+pub mod select;
 pub mod util;
 
+pub use aliases::Aliases;
+pub use config::Config;
+pub use entry::AnyEntry;
 pub use error::*;
+pub use index::NameIndex;
+pub use select::select_name;
 
-use self::util::prompt_input;
+use self::clipboard::Clipboard;
+use self::util::{prompt_input, Echo};
 
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum SecretVisibility {
     Show,
     Hide,
@@ -38,150 +69,2489 @@ pub fn help() {
 }
 
 /// Initializes a new vault at the provided path.
-pub fn init<S>(storage: &mut S) -> Result<(), Error>
+///
+/// Fails if the store already exists, unless `force` is set.
+pub fn init<S>(storage: &mut S, force: bool) -> Result<(), Error>
 where
     S: storage::Backend,
 {
-    storage.create()?;
+    match storage.create() {
+        Err(e) if force && e.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Creates the optional files of the store at `store` that `opts` asks for.
+///
+/// `generate_key` returns the contents of a new identity file and the
+/// recipient of its identity. The recipient is added to `.recipients`, and the
+/// identity file to the store's `.gitignore`, so that the private key is not
+/// committed along with the store. Other files that already exist are kept as
+/// they are.
+pub fn scaffold<F, W>(
+    opts: &Init,
+    store: &Path,
+    generate_key: F,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    F: FnOnce() -> (String, String),
+    W: Write,
+{
+    let mut generated = false;
+
+    if opts.generate_key {
+        let path = defaults::identities_file(store);
+        if path.exists() {
+            writeln!(output, "Kept existing {}", path.display())?;
+        } else {
+            let (identity, recipient) = generate_key();
+            util::create_private(&path, &identity)?;
+            writeln!(output, "Created {}", path.display())?;
+            writeln!(output, "Generated age identity: {}", recipient)?;
+
+            let ignored = format!("/{}", defaults::IDENTITIES_PATH.join("/"));
+            for (file, line) in [
+                (defaults::gitignore_file(store), ignored.as_str()),
+                (defaults::recipients_file(store), recipient.as_str()),
+            ] {
+                if util::append_line(&file, line)? {
+                    writeln!(output, "Added {} to {}", line, file.display())?;
+                }
+            }
+            writeln!(
+                output,
+                "Pass --age-keyfile {} to use it",
+                path.display()
+            )?;
+            generated = true;
+        }
+    }
+
+    let mut files = Vec::new();
+    if opts.scaffold && !generated {
+        files.push((defaults::recipients_file(store), String::new()));
+    }
+    if opts.scaffold {
+        files.push((Config::path(store), Config::template()));
+    }
+
+    for (path, contents) in files {
+        if path.exists() {
+            writeln!(output, "Kept existing {}", path.display())?;
+        } else {
+            util::create_private(&path, &contents)?;
+            writeln!(output, "Created {}", path.display())?;
+        }
+    }
+
     Ok(())
 }
 
+/// Fills in the recipients of `new` if none are passed on the command line:
+/// `team`, the recipients of the team's `.age-recipients` file if one was
+/// found, unless `--no-team-recipients` is passed, or else `defaults`.
+pub fn default_new_recipients(
+    opts: &mut New,
+    team: Option<Vec<OsString>>,
+    defaults: Vec<OsString>,
+) {
+    if !opts.recipient.is_empty() || opts.recipients_stdin {
+        return;
+    }
+
+    opts.recipient = match team {
+        Some(team) if !opts.no_team_recipients => team,
+        _ => defaults,
+    };
+}
+
 /// Creates a new entry in the provided vault.
-pub fn new<C, S, R, W>(
+///
+/// Values longer than the limits of `config` are rejected, or written with a
+/// warning to `warnings`.
+#[allow(clippy::too_many_arguments)]
+pub fn new<C, S, R, W, E>(
     opts: &New,
+    config: &Config,
     crypto: &C,
     storage: &mut S,
+    index: Option<&mut NameIndex>,
     input: &mut R,
     output: &mut W,
+    warnings: &mut E,
 ) -> Result<(), Error>
 where
     C: crypto::Backend,
     S: storage::Backend,
     R: BufRead,
     W: Write,
+    E: Write,
 {
-    let recipients = parse_recipients::<C::Recipient>(&opts.recipient)?;
+    ensure_writable(storage)?;
+    ensure_supported(storage, |c| c.can_add, "adding entries")?;
+
+    if opts.batch {
+        return new_batch(
+            opts, config, crypto, storage, index, input, output, warnings,
+        );
+    }
+    if opts.raw_stdin {
+        return new_raw(
+            opts, config, crypto, storage, index, input, output, warnings,
+        );
+    }
+
+    let secret_key = secret_key(opts, config)?;
+    let recipients = new_recipients(opts, crypto, input)?;
+    if opts.recipient_fingerprint {
+        write_fingerprints(&recipients, output)?;
+    }
+
+    let uuid = match &opts.uuid {
+        Some(uuid) => parse_uuid(uuid)?,
+        None => Uuid::new_v4(),
+    };
+
+    let timeout = opts.prompt_timeout.map(Duration::from_secs);
 
     // Prompt for name if it was not passed in as an argument
     let name = match &opts.name {
         Some(s) => parse_os_str(s, "Invalid Name")
             .map(str::to_string)
             .map_err(Error::Xflags),
-        None => prompt_input(false, "Entry Name: ", input, output)
-            .map_err(Error::Input),
+        None => {
+            prompt_input(Echo::Shown, "Entry Name: ", input, output, timeout)
+                .map_err(Error::Input)
+        }
     }?;
 
     // Prompt for secret if it was not passed in as an argument
     let secret = match &opts.secret {
         // Secret was passed in as argument
-        Some(s) => parse_os_str(s, "Invalid Secret")
-            .map(str::to_string)
-            .map_err(Error::Xflags),
+        // The secret is not included in the error, unlike other arguments.
+        Some(s) => s.to_str().map(str::to_string).ok_or_else(|| {
+            Error::Xflags(xflags::Error::new("Invalid Secret: not valid UTF-8"))
+        }),
         // Prompt for secret
         None => {
-            prompt_input(true, "Secret: ", input, output).map_err(Error::Input)
+            let echo = if opts.mask_input {
+                Echo::Masked
+            } else {
+                Echo::Hidden
+            };
+            prompt_input(echo, "Secret: ", input, output, timeout)
+                .map_err(Error::Input)
         }
     }?;
 
     // Parse all other key/value pairs passed in as arguments
     let mut key_value_pairs = parse_key_value_pairs(&opts.key)?;
+    if opts.normalize_keys {
+        normalize_keys(&mut key_value_pairs, config);
+    }
+    if opts.strict_keys {
+        check_strict_keys(&key_value_pairs, config)
+            .map_err(|e| Error::Filetype(Box::new(e)))?;
+    }
 
-    // It's convenient to have the name as the first field, so insert it at
-    // the front of the list.
-    key_value_pairs
-        .insert(0, KeyValuePair::new("name", Value::Insensitive(name)));
+    let store = new_store(name.clone(), secret_key, secret, key_value_pairs);
+    validate_new_store(&store, config, warnings)?;
+    let reference = write_new_entry(crypto, storage, uuid, store, recipients)?;
 
-    key_value_pairs
-        .insert(1, KeyValuePair::new("secret", Value::Sensitive(secret)));
+    if let Some(index) = index {
+        index.insert(name, uuid);
+        index.save()?;
+    }
 
-    let store = KvStore::new(key_value_pairs);
+    writeln!(output, "Created new entry at path: {}", &reference)?;
 
-    // Save the content to storage
-    let uuid = Uuid::new_v4();
-    let mut reference = storage.add_entry(&uuid)?;
-    let writer = reference.writer()?;
-    // TODO: Handle crypto error
-    let mut encrypted = crypto.encrypt_output(writer, recipients).unwrap();
+    Ok(())
+}
 
-    store.serialize(&mut encrypted)?;
+/// Creates an entry for each record read from `input`.
+///
+/// Each line is a record of `key=value` pairs separated by semicolons or tabs,
+/// which must include a `name` and a secret under the `--secret-key`, which
+/// is `secret` by default. Every record is parsed before any entry is
+/// created, so an invalid record leaves the store unchanged.
+#[allow(clippy::too_many_arguments)]
+fn new_batch<C, S, R, W, E>(
+    opts: &New,
+    config: &Config,
+    crypto: &C,
+    storage: &mut S,
+    mut index: Option<&mut NameIndex>,
+    input: &mut R,
+    output: &mut W,
+    warnings: &mut E,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    R: BufRead,
+    W: Write,
+    E: Write,
+{
+    if opts.name.is_some()
+        || opts.secret.is_some()
+        || opts.uuid.is_some()
+        || opts.recipients_stdin
+        || opts.raw_stdin
+    {
+        return Err(Error::Xflags(xflags::Error::new(
+            "--batch cannot be combined with --name, --secret, --uuid, \
+             --recipients-stdin, or --raw-stdin",
+        )));
+    }
 
-    writeln!(output, "Created new entry at path: {}", &reference)?;
+    let secret_key = secret_key(opts, config)?;
+    let recipients = new_recipients(opts, crypto, input)?;
+    if opts.recipient_fingerprint {
+        write_fingerprints(&recipients, output)?;
+    }
+
+    let mut records = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        let line = line.map_err(Error::Input)?;
+        let line_number = number + 1;
+
+        let mut key_value_pairs = line
+            .split([';', '\t'])
+            .filter(|field| !field.is_empty())
+            .map(KeyValuePair::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::InvalidRecord(line_number, e.to_string()))?;
+
+        // Skip blank lines
+        if key_value_pairs.is_empty() {
+            continue;
+        }
+
+        // Normalized before the name and secret are looked up, so that
+        // `Name=...` is found too.
+        if opts.normalize_keys {
+            normalize_keys(&mut key_value_pairs, config);
+        }
+        if opts.strict_keys {
+            check_strict_keys(&key_value_pairs, config).map_err(|e| {
+                Error::InvalidRecord(line_number, e.to_string())
+            })?;
+        }
+
+        // Every pair other than the name and secret is kept as an extra field
+        let mut take = |key: &Key| {
+            let position =
+                key_value_pairs.iter().position(|pair| pair.key == *key)?;
+            Some(key_value_pairs.remove(position).value.as_str().to_string())
+        };
+
+        let missing = |key: &Key| {
+            Error::InvalidRecord(line_number, format!("missing {}", key))
+        };
+        let name = take(&Key::Name).ok_or_else(|| missing(&Key::Name))?;
+        let secret = take(&secret_key).ok_or_else(|| missing(&secret_key))?;
+
+        let store = new_store(
+            name.clone(),
+            secret_key.clone(),
+            secret,
+            key_value_pairs,
+        );
+        validate_new_store(&store, config, warnings).map_err(|e| match e {
+            Error::Filetype(e) => {
+                Error::InvalidRecord(line_number, e.to_string())
+            }
+            e => e,
+        })?;
+
+        records.push((name, store));
+    }
+
+    let count = records.len();
+    let written = records.into_iter().try_for_each(|(name, store)| {
+        let uuid = Uuid::new_v4();
+        write_new_entry(crypto, storage, uuid, store, recipients.clone())?;
+
+        if let Some(index) = index.as_mut() {
+            index.insert(name, uuid);
+        }
+        Ok::<_, Error>(())
+    });
+
+    // Save the index even if a record could not be written, so that the
+    // entries written before it can be found by name.
+    if let Some(index) = index {
+        index.save()?;
+    }
+    written?;
+
+    writeln!(output, "Created {} entries", count)?;
 
     Ok(())
 }
 
-/// Lists all entries within the provided vault.
-pub fn list<C, S, W>(
-    opts: &List,
-    secret_visibility: SecretVisibility,
+/// Creates an entry from the key/value pairs read from `input`, without
+/// prompting for any of its fields.
+///
+/// The input must parse as a [`KvStore`] with a name, so that garbage is never
+/// stored. It is written in the canonical form of the format, without any
+/// comments or blank lines.
+#[allow(clippy::too_many_arguments)]
+fn new_raw<C, S, R, W, E>(
+    opts: &New,
+    config: &Config,
     crypto: &C,
     storage: &mut S,
+    index: Option<&mut NameIndex>,
+    input: &mut R,
     output: &mut W,
+    warnings: &mut E,
 ) -> Result<(), Error>
 where
     C: crypto::Backend,
     S: storage::Backend,
+    R: BufRead,
     W: Write,
+    E: Write,
 {
-    let entries = storage.entries()?;
+    if opts.name.is_some()
+        || opts.secret.is_some()
+        || opts.secret_key.is_some()
+        || !opts.key.is_empty()
+        || opts.recipients_stdin
+    {
+        return Err(Error::Xflags(xflags::Error::new(
+            "--raw-stdin cannot be combined with --name, --secret, \
+             --secret-key, --key, or --recipients-stdin",
+        )));
+    }
 
-    let references = entries.values();
+    let recipients = new_recipients(opts, crypto, input)?;
+    if opts.recipient_fingerprint {
+        write_fingerprints(&recipients, output)?;
+    }
 
-    // Display statistics if quiet flag is not set
-    if !opts.quiet {
-        let count = &references.len();
+    let uuid = match &opts.uuid {
+        Some(uuid) => parse_uuid(uuid)?,
+        None => Uuid::new_v4(),
+    };
 
-        // Because English is weird
-        let plural = if *count == 1 { "Entry" } else { "Entries" };
+    let store = KvStore::deserialize(input)
+        .map_err(|e| Error::Filetype(Box::new(e)))?;
+    let mut key_value_pairs: Vec<KeyValuePair> = store.into_iter().collect();
+    if opts.normalize_keys {
+        normalize_keys(&mut key_value_pairs, config);
+    }
+    if opts.strict_keys {
+        check_strict_keys(&key_value_pairs, config)
+            .map_err(|e| Error::Filetype(Box::new(e)))?;
+    }
+
+    let store = KvStore::new(key_value_pairs);
+    validate_new_store(&store, config, warnings)?;
+    let name = entry_name(&store).to_string();
+    let reference = write_new_entry(crypto, storage, uuid, store, recipients)?;
+
+    if let Some(index) = index {
+        index.insert(name, uuid);
+        index.save()?;
+    }
+
+    writeln!(output, "Created new entry at path: {}", reference)?;
+
+    Ok(())
+}
+
+/// Returns the recipients that a new entry should be encrypted to.
+fn new_recipients<C, R>(
+    opts: &New,
+    crypto: &C,
+    input: &mut R,
+) -> Result<Vec<C::Recipient>, Error>
+where
+    C: crypto::Backend,
+    R: BufRead,
+{
+    let mut recipient_list = opts.recipient.clone();
 
-        writeln!(output, "Found {} {}", count, plural)?;
+    if opts.recipients_stdin {
+        // The name prompt would read from the same input, after the
+        // recipients have consumed all of it.
+        if opts.name.is_none() {
+            return Err(Error::Xflags(xflags::Error::new(
+                "--recipients-stdin requires --name, since both would be read \
+                 from stdin",
+            )));
+        }
+
+        let mut contents = String::new();
+        input.read_to_string(&mut contents).map_err(Error::Input)?;
+
+        for recipient in util::parse_recipients_list(&contents) {
+            if !recipient_list.contains(&recipient) {
+                recipient_list.push(recipient);
+            }
+        }
     }
 
-    for reference in references {
-        // Print a separator between every entry
-        // TODO: Should this be included on the first entry?
-        writeln!(output, "---")?;
+    let mut recipients = parse_recipients::<C::Recipient>(&recipient_list)?;
 
-        let reader = reference.reader()?;
+    // Recipients are public keys, so they are safe to log.
+    for recipient in &recipient_list {
+        debug!(?recipient, "Encrypting to recipient");
+    }
 
-        // Get a decrpted reader over the contents of the entry
-        let mut decrypted = crypto
-            .decrypt_input(reader)
+    if opts.recipient_self {
+        let own = crypto
+            .own_recipients()
             .map_err(|err| Error::Crypto(Box::new(err)))?;
 
-        // Display options for all KvStores
-        let show_secrets = match secret_visibility {
-            SecretVisibility::Show => true,
-            SecretVisibility::Hide => false,
-        };
+        if own.is_empty() {
+            return Err(Error::NoOwnRecipients);
+        }
+
+        debug!(count = own.len(), "Encrypting to own recipients");
+        recipients.extend(own);
+    }
 
-        let store = KvStore::deserialize(&mut decrypted)
-            .map_err(Box::new)
-            .map_err(|e| Error::Filetype(e as Box<dyn std::error::Error>))?;
+    check_lockout(crypto, &recipients, opts.allow_lockout)?;
+    check_fingerprints(&recipients, &opts.expect_fingerprint)?;
 
-        // Enable default keys, or no keys if "no-default" flag is set
-        let mut enabled_keys: HashSet<Key> = if !opts.no_default {
-            HashSet::from_iter(defaults::KEYS.iter().cloned())
-        } else {
-            HashSet::new()
-        };
+    Ok(recipients)
+}
 
-        // Enable any additional keys that the user explicitly asked to show.
-        for key in &opts.display {
-            let key = key
-                .clone()
-                .into_string()
-                .map_err(Error::OsStringConversionError)?;
-            enabled_keys.insert(Key::from(key.as_str()));
+/// Removes every entry from the store.
+///
+/// Unless `--force` is passed, the user must type "purge" to confirm, and
+/// nothing is removed otherwise.
+pub fn purge<S, R, W>(
+    opts: &Purge,
+    storage: &mut S,
+    mut index: Option<&mut NameIndex>,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    S: storage::Backend,
+    R: BufRead,
+    W: Write,
+{
+    ensure_writable(storage)?;
+    ensure_supported(storage, |c| c.can_remove, "removing entries")?;
+
+    let mut uuids: Vec<Uuid> = storage.entries()?.into_keys().collect();
+    uuids.sort();
+
+    if !opts.force {
+        let prompt = format!(
+            "This will remove {} entries. Type \"purge\" to confirm: ",
+            uuids.len()
+        );
+        let confirmation = util::prompt_shown_input(&prompt, input, output)
+            .map_err(Error::Input)?;
+
+        if confirmation != "purge" {
+            return Err(Error::PurgeNotConfirmed);
         }
+    }
 
-        let opts = DisplayOptions {
-            show_all: opts.all,
-            enabled_keys,
-        };
+    let removed = uuids.iter().try_for_each(|uuid| {
+        info!(%uuid, "Removing entry");
+        storage.remove_entry(uuid)?;
+        if let Some(index) = index.as_deref_mut() {
+            index.remove(uuid);
+        }
+        Ok::<_, Error>(())
+    });
 
-        store.display(output, opts, show_secrets)?;
+    // Save the index even if an entry could not be removed, so that it does
+    // not map names to the entries that already were.
+    if let Some(index) = index {
+        index.save()?;
+    }
+    removed?;
+
+    writeln!(output, "Removed {} entries", uuids.len())?;
+
+    Ok(())
+}
+
+/// Removes every entry that matches all of the `--filter` pairs and the
+/// `--glob` pattern, after listing the entries and confirming.
+///
+/// Every entry is decrypted to evaluate the filters. An entry that cannot be
+/// removed is reported without stopping the removal of the others.
+pub fn remove<C, S, R, W>(
+    opts: &Remove,
+    crypto: &C,
+    storage: &mut S,
+    index: Option<&mut NameIndex>,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    R: BufRead,
+    W: Write,
+{
+    if opts.filter.is_empty() && opts.glob.is_none() {
+        let message = "remove requires --filter or --glob";
+        return Err(xflags::Error::new(message).into());
+    }
+
+    ensure_writable(storage)?;
+    ensure_supported(storage, |c| c.can_remove, "removing entries")?;
+
+    // A `tags` filter selects entries the way `list --tag` does, rather than
+    // requiring the whole list of tags to match.
+    let filters = parse_key_value_pairs(&opts.filter)?;
+    let matches_filters = |store: &KvStore| {
+        filters.iter().all(|pair| match pair.key {
+            Key::Tags => {
+                let tags = parse_tags(pair.value.as_str());
+                matches_tags(&store.tags(), &tags, false)
+            }
+            _ => {
+                let value = store.get(&pair.key).map(Value::as_str);
+                value == Some(pair.value.as_str())
+            }
+        })
+    };
+
+    let entries = match &opts.glob {
+        Some(pattern) => {
+            let pattern = parse_os_str(pattern, "Invalid glob")?;
+            find_entries_matching(pattern, crypto, storage)?
+        }
+        None => {
+            named_entries(crypto, storage)?.collect::<Result<Vec<_>, _>>()?
+        }
+    };
+
+    let entries: Vec<(Uuid, KvStore)> = entries
+        .into_iter()
+        .filter(|(_, store)| matches_filters(store))
+        .collect();
+
+    if entries.is_empty() {
+        writeln!(output, "No entries match")?;
+        return Ok(());
+    }
+
+    for (uuid, store) in &entries {
+        writeln!(output, "{} {}", uuid, entry_name(store))?;
+    }
+
+    if !opts.force {
+        let prompt = format!("Remove {} entries? [y/N]: ", entries.len());
+        let confirmation = util::prompt_shown_input(&prompt, input, output)
+            .map_err(Error::Input)?;
+
+        if !matches!(confirmation.as_str(), "y" | "Y" | "yes") {
+            return Err(Error::RemoveNotConfirmed);
+        }
+    }
+
+    let mut removed = Vec::new();
+    let mut failures = 0;
+
+    for (uuid, _) in &entries {
+        info!(%uuid, "Removing entry");
+        match storage.remove_entry(uuid) {
+            Ok(()) => removed.push(*uuid),
+            Err(err) => {
+                writeln!(output, "Failed to remove {}: {}", uuid, err)?;
+                failures += 1;
+            }
+        }
+    }
+
+    if let Some(index) = index {
+        for uuid in &removed {
+            index.remove(uuid);
+        }
+        index.save()?;
+    }
+
+    writeln!(output, "Removed {} entries", removed.len())?;
+
+    match failures {
+        0 => Ok(()),
+        failures => Err(Error::RemoveFailed(failures)),
+    }
+}
+
+/// Fails early if the store cannot be modified, rather than partway through a
+/// command.
+fn ensure_writable<S>(storage: &S) -> Result<(), Error>
+where
+    S: storage::Backend,
+{
+    match storage.is_read_only()? {
+        true => Err(Error::ReadOnlyStore),
+        false => Ok(()),
+    }
+}
+
+/// Fails early if the store does not support an operation that the command
+/// performs, described by `operation`.
+fn ensure_supported<S>(
+    storage: &S,
+    supported: impl Fn(&storage::Capabilities) -> bool,
+    operation: &'static str,
+) -> Result<(), Error>
+where
+    S: storage::Backend,
+{
+    match supported(&storage.capabilities()) {
+        true => Ok(()),
+        false => Err(Error::Unsupported(operation)),
+    }
+}
+
+/// Refuses to encrypt to recipients that do not include any of the loaded
+/// identities, unless the user allowed it. Backends that cannot tell whether
+/// the recipients include an identity are not checked.
+fn check_lockout<C>(
+    crypto: &C,
+    recipients: &[C::Recipient],
+    allow_lockout: bool,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+{
+    if allow_lockout {
+        return Ok(());
+    }
+
+    let included = crypto
+        .includes_own_recipient(recipients)
+        .map_err(|err| Error::Crypto(Box::new(err)))?;
+
+    match included {
+        Some(false) => Err(Error::Lockout),
+        Some(true) | None => Ok(()),
+    }
+}
+
+/// Fails unless every recipient has one of the `expected` fingerprints, so
+/// that a mistyped recipient is caught before anything is encrypted to it.
+/// Every recipient is accepted if no fingerprints are expected.
+fn check_fingerprints<T>(
+    recipients: &[T],
+    expected: &[String],
+) -> Result<(), Error>
+where
+    T: crypto::Recipient,
+{
+    if expected.is_empty() {
+        return Ok(());
+    }
+
+    // Fingerprints are compared without their separators or case, so that
+    // they can be typed either way.
+    let normalize = |fingerprint: &str| -> String {
+        fingerprint
+            .chars()
+            .filter(|c| *c != ':' && !c.is_whitespace())
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    };
+    let expected: HashSet<String> = expected
+        .iter()
+        .map(|fingerprint| normalize(fingerprint))
+        .collect();
+
+    for recipient in recipients {
+        let fingerprint = recipient.fingerprint();
+        if !expected.contains(&normalize(&fingerprint)) {
+            return Err(Error::UnexpectedFingerprint(
+                recipient.to_string(),
+                fingerprint,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the fingerprint and encoding of each distinct recipient, in order
+/// of their fingerprints. Recipients are public keys, so they are safe to
+/// print.
+fn write_fingerprints<'a, T, W>(
+    recipients: impl IntoIterator<Item = &'a T>,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    T: crypto::Recipient + 'a,
+    W: Write,
+{
+    let recipients: BTreeSet<(String, String)> = recipients
+        .into_iter()
+        .map(|recipient| (recipient.fingerprint(), recipient.to_string()))
+        .collect();
+
+    for (fingerprint, recipient) in recipients {
+        writeln!(output, "{} {}", fingerprint, recipient)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the recipients of the entry at `reference`, if the backend can tell.
+///
+/// Only the start of the entry is read at first, since headers are usually
+/// much shorter than [`defaults::HEADER_PEEK_LENGTH`]. The whole entry is
+/// read if that fails, such as for a header with many recipients.
+fn recipients_of<C, R>(
+    crypto: &C,
+    reference: &R,
+) -> Result<Option<Vec<String>>, Error>
+where
+    C: crypto::Backend,
+    R: Reference,
+{
+    let start = reference.reader_range(0..defaults::HEADER_PEEK_LENGTH)?;
+    match crypto.recipients_of(start) {
+        Ok(recipients) => return Ok(recipients),
+        Err(err) => debug!(%err, "Reading the whole entry for its recipients"),
+    }
+
+    crypto
+        .recipients_of(reference.reader()?)
+        .map_err(|err| Error::Crypto(Box::new(err)))
+}
+
+/// Validates the contents of a new entry, including the length limits of
+/// `config`. Values over the soft limit are reported to `warnings`.
+fn validate_new_store<W: Write>(
+    store: &KvStore,
+    config: &Config,
+    warnings: &mut W,
+) -> Result<(), Error> {
+    store.validate().map_err(|e| Error::Filetype(Box::new(e)))?;
+
+    if config.max_value_length > 0 {
+        store
+            .validate_lengths(config.max_value_length)
+            .map_err(|e| Error::Filetype(Box::new(e)))?;
+    }
+
+    if config.warn_value_length > 0 {
+        for key in store.oversized(config.warn_value_length) {
+            writeln!(
+                warnings,
+                "Warning: the value of '{}' is longer than {} bytes",
+                key, config.warn_value_length
+            )?;
+        }
     }
 
     Ok(())
 }
+
+/// Replaces the key of each pair with its normalized form.
+fn normalize_keys(key_value_pairs: &mut [KeyValuePair], config: &Config) {
+    for pair in key_value_pairs {
+        let key = std::mem::replace(&mut pair.key, Key::Name);
+        pair.key = config.normalize_key(key);
+    }
+}
+
+/// Fails on the first pair whose key `new --strict-keys` does not accept.
+fn check_strict_keys(
+    key_value_pairs: &[KeyValuePair],
+    config: &Config,
+) -> Result<(), ciphey_kvstore::Error> {
+    let allowed = config.strict_keys();
+    for pair in key_value_pairs {
+        Key::parse_known(&pair.key.to_string(), &allowed)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the key that `new` stores the secret under. It is normalized and
+/// checked like the keys of the other pairs.
+fn secret_key(opts: &New, config: &Config) -> Result<Key, Error> {
+    let key = match &opts.secret_key {
+        Some(key) => parse_os_str(key, "Invalid Secret Key")?,
+        None => return Ok(Key::from("secret")),
+    };
+
+    let mut key = Key::parse_valid(key.trim())
+        .map_err(|e| Error::Filetype(Box::new(e)))?;
+    if opts.normalize_keys {
+        key = config.normalize_key(key);
+    }
+    if opts.strict_keys {
+        Key::parse_known(&key.to_string(), &config.strict_keys())
+            .map_err(|e| Error::Filetype(Box::new(e)))?;
+    }
+
+    Ok(key)
+}
+
+/// Builds the contents of a new entry, with the name and secret as its first
+/// fields.
+fn new_store(
+    name: String,
+    secret_key: Key,
+    secret: String,
+    mut key_value_pairs: Vec<KeyValuePair>,
+) -> KvStore {
+    // It's convenient to have the name as the first field, so insert it at
+    // the front of the list.
+    key_value_pairs
+        .insert(0, KeyValuePair::new("name", Value::Insensitive(name)));
+
+    key_value_pairs
+        .insert(1, KeyValuePair::new(secret_key, Value::sensitive(secret)));
+
+    KvStore::new(key_value_pairs)
+}
+
+/// Writes a new entry with the provided UUID to storage.
+fn write_new_entry<C, S>(
+    crypto: &C,
+    storage: &mut S,
+    uuid: Uuid,
+    store: KvStore,
+    recipients: Vec<C::Recipient>,
+) -> Result<S::Reference, Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+{
+    info!(%uuid, "Writing new entry");
+
+    // Storage backends refuse to overwrite an existing entry.
+    vault::write_entry(crypto, storage, uuid, store, recipients).map_err(
+        |err| match err {
+            vault::Error::Storage(e)
+                if e.kind() == io::ErrorKind::AlreadyExists =>
+            {
+                Error::EntryExists(uuid)
+            }
+            err => err.into(),
+        },
+    )
+}
+
+/// Lists all entries within the provided vault.
+///
+/// Entries that cannot be read are reported by UUID to `errors` after the
+/// other entries have been listed.
+pub fn list<C, S, W, E>(
+    opts: &List,
+    secret_visibility: SecretVisibility,
+    config: &Config,
+    crypto: &C,
+    storage: &mut S,
+    output: &mut W,
+    errors: &mut E,
+) -> Result<(), Error>
+where
+    C: crypto::Backend + Sync,
+    S: storage::Backend,
+    S::Reference: Sync,
+    W: Write,
+    E: Write,
+{
+    let tags = opts
+        .tag
+        .iter()
+        .map(|tag| parse_os_str(tag, "Invalid Tag").map(|tag| tag.trim()))
+        .map(|tag| tag.map(String::from))
+        .collect::<Result<BTreeSet<String>, _>>()?;
+    let is_selected =
+        |store: &KvStore| matches_tags(&store.tags(), &tags, opts.any_tag);
+
+    // Sorted so that the output order is deterministic.
+    let mut references = storage.sorted_entries()?;
+
+    let since = opts.since.as_ref().map(|d| parse_date(d, false));
+    let until = opts.until.as_ref().map(|d| parse_date(d, true));
+    let (since, until) = (since.transpose()?, until.transpose()?);
+
+    if since.is_some() || until.is_some() {
+        let mut selected = Vec::new();
+        for (uuid, reference) in references {
+            let modified = match reference.modified() {
+                Ok(modified) => Some(modified),
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => None,
+                Err(e) => return Err(e.into()),
+            };
+
+            let in_range = match modified {
+                Some(modified) => {
+                    since.is_none_or(|since| modified >= since)
+                        && until.is_none_or(|until| modified <= until)
+                }
+                None => opts.include_undated,
+            };
+
+            if in_range {
+                selected.push((uuid, reference));
+            }
+        }
+        references = selected;
+    }
+
+    // Entries that could not be read are reported once the others have been
+    // listed.
+    let mut failed = Vec::new();
+
+    match (opts.print0, opts.names_only) {
+        (true, true) => {
+            let message = "--names-only cannot be used with --print0";
+            return Err(xflags::Error::new(message).into());
+        }
+        (true, false) => {
+            let window = Window::new(opts);
+            let count = list_print0(
+                crypto,
+                &references,
+                is_selected,
+                window,
+                output,
+                &mut failed,
+            )?;
+            report_failures(&failed, errors)?;
+            return check_listed(opts, count);
+        }
+        (false, true) => {
+            let window = Window::new(opts);
+            let count = list_names(
+                crypto,
+                &references,
+                is_selected,
+                window,
+                output,
+                &mut failed,
+            )?;
+            report_failures(&failed, errors)?;
+            return check_listed(opts, count);
+        }
+        (false, false) => {}
+    }
+
+    // Display options for all KvStores
+    let show_secrets = match secret_visibility {
+        SecretVisibility::Show => true,
+        SecretVisibility::Hide => false,
+    };
+
+    // Enable the store's default keys, or no keys if "no-default" flag is set
+    let mut enabled_keys: HashSet<Key> = if !opts.no_default {
+        HashSet::from_iter(config.default_fields.iter().cloned())
+    } else {
+        HashSet::new()
+    };
+
+    // Enable any additional keys that the user explicitly asked to show.
+    for key in &opts.display {
+        let key = key
+            .clone()
+            .into_string()
+            .map_err(Error::OsStringConversionError)?;
+        enabled_keys.insert(Key::from(key.as_str()));
+    }
+
+    for fields in &opts.fields {
+        enabled_keys.extend(parse_field_list(fields)?);
+    }
+
+    let display_options = DisplayOptions {
+        show_all: opts.all,
+        enabled_keys,
+        only_sensitive: opts.only_sensitive,
+        redaction: opts.redaction.clone().unwrap_or_default(),
+        mask: opts.mask_char.unwrap_or(MASK),
+        field_order: match &opts.field_order {
+            Some(fields) => parse_field_list(fields)?,
+            None => Vec::new(),
+        },
+    };
+
+    let render = |(uuid, reference): &(Uuid, S::Reference)| {
+        let opts = display_options.clone();
+        let result =
+            render_entry(reference, crypto, is_selected, opts, show_secrets);
+        (*uuid, result)
+    };
+
+    let jobs = opts.jobs.unwrap_or(1).max(1);
+
+    // Each entry is rendered to its own buffer so that the output is written
+    // in order, regardless of the order in which the entries were decrypted.
+    // Errors are collected alongside the output of the other entries.
+    let rendered: Vec<_> = if jobs > 1 {
+        let chunk_size = references.len().div_ceil(jobs).max(1);
+
+        thread::scope(|s| {
+            let handles: Vec<_> = references
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    s.spawn(|| chunk.iter().map(render).collect::<Vec<_>>())
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| {
+                    handle.join().unwrap_or_else(|e| panic::resume_unwind(e))
+                })
+                .collect()
+        })
+    } else {
+        references.iter().map(render).collect()
+    };
+
+    // Entries that are not selected are only known once decrypted.
+    let mut entries = Vec::new();
+    for (uuid, result) in rendered {
+        match result {
+            Ok(Some(entry)) => entries.push(entry),
+            Ok(None) => {}
+            Err(err) => failed.push((uuid, err)),
+        }
+    }
+
+    let total = entries.len();
+    let window = Window::new(opts);
+    let rendered: Vec<Vec<u8>> = entries
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| window.contains(*index))
+        .map(|(_, entry)| entry)
+        .collect();
+
+    let count = rendered.len();
+    let separator = match &opts.separator {
+        Some(separator) => parse_os_str(separator, "Invalid Separator")?,
+        None => "---",
+    };
+
+    // The separator is written between the statistics and each entry, so
+    // that it only precedes the first entry if something was written first.
+    let mut separate = opts.leading_separator;
+
+    // Display statistics if quiet flag is not set
+    if !opts.quiet {
+        // Because English is weird
+        let plural = if total == 1 { "Entry" } else { "Entries" };
+
+        writeln!(output, "Found {} {}", total, plural)?;
+        separate = true;
+    }
+
+    for entry in rendered {
+        if separate {
+            writeln!(output, "{}", separator)?;
+        }
+        separate = true;
+
+        output.write_all(&entry)?;
+    }
+
+    if window.is_limited() && !opts.quiet {
+        writeln!(output, "{}", separator)?;
+        match count {
+            0 => writeln!(output, "Showing none of {}", total)?,
+            count => writeln!(
+                output,
+                "Showing {}-{} of {}",
+                window.offset + 1,
+                window.offset + count,
+                total
+            )?,
+        }
+    }
+
+    report_failures(&failed, errors)?;
+    check_listed(opts, count)
+}
+
+/// Reports each entry that could not be listed by its UUID, failing with the
+/// number of such entries. Binary entries are skipped with a hint to read
+/// them with `cat` instead.
+fn report_failures<E: Write>(
+    failed: &[(Uuid, Error)],
+    errors: &mut E,
+) -> Result<(), Error> {
+    let mut count = 0;
+    for (uuid, err) in failed {
+        let reason = match err {
+            Error::BinaryEntry(_) => {
+                writeln!(
+                    errors,
+                    "Skipping binary entry {}, use `ciphey cat --uuid {}` to \
+                     read it",
+                    uuid, uuid
+                )?;
+                continue;
+            }
+            // Parse errors may contain the contents of the entry, so only
+            // report the kind of failure.
+            Error::Filetype(_) => "invalid entry format".to_string(),
+            err => err.to_string(),
+        };
+
+        writeln!(errors, "FAILED {}: {}", uuid, reason)?;
+        count += 1;
+    }
+
+    match count {
+        0 => Ok(()),
+        count => Err(Error::ListFailed(count)),
+    }
+}
+
+/// The entries that `list` displays, by their position among the selected
+/// entries, as set by `--offset` and `--max-entries`.
+#[derive(Clone, Copy)]
+struct Window {
+    offset: usize,
+    max: Option<usize>,
+}
+
+impl Window {
+    fn new(opts: &List) -> Self {
+        Self {
+            offset: opts.offset.unwrap_or(0),
+            max: opts.max_entries,
+        }
+    }
+
+    /// Returns whether the entry at `index` is displayed.
+    fn contains(&self, index: usize) -> bool {
+        let index = match index.checked_sub(self.offset) {
+            Some(index) => index,
+            None => return false,
+        };
+        self.max.is_none_or(|max| index < max)
+    }
+
+    /// Returns whether any entries may be left out.
+    fn is_limited(&self) -> bool {
+        self.offset > 0 || self.max.is_some()
+    }
+}
+
+/// Fails with [`Error::NoEntries`] if `--fail-empty` was passed and `count`
+/// entries, after filtering, is zero. Called once the output is written.
+fn check_listed(opts: &List, count: usize) -> Result<(), Error> {
+    if opts.fail_empty && count == 0 {
+        return Err(Error::NoEntries);
+    }
+
+    Ok(())
+}
+
+/// Lists the name of each entry in `window`, one per line. No other output is
+/// written.
+///
+/// Returns the number of entries listed.
+fn list_names<C, R, W>(
+    crypto: &C,
+    references: &[(Uuid, R)],
+    is_selected: impl Fn(&KvStore) -> bool,
+    window: Window,
+    output: &mut W,
+    failed: &mut Vec<(Uuid, Error)>,
+) -> Result<usize, Error>
+where
+    C: crypto::Backend,
+    R: Reference,
+    W: Write,
+{
+    let mut selected = 0;
+    let mut count = 0;
+    for (uuid, reference) in references {
+        let store = match decrypt_entry(reference, crypto) {
+            Ok(store) => store,
+            Err(err) => {
+                failed.push((*uuid, err));
+                continue;
+            }
+        };
+        if !is_selected(&store) {
+            continue;
+        }
+        if window.contains(selected) {
+            writeln!(output, "{}", entry_name(&store))?;
+            count += 1;
+        }
+        selected += 1;
+    }
+
+    Ok(count)
+}
+
+/// Lists the UUID and name of each entry in `window`, terminating every entry
+/// with a NUL character. No other output is written.
+///
+/// Returns the number of entries listed.
+fn list_print0<C, R, W>(
+    crypto: &C,
+    references: &[(Uuid, R)],
+    is_selected: impl Fn(&KvStore) -> bool,
+    window: Window,
+    output: &mut W,
+    failed: &mut Vec<(Uuid, Error)>,
+) -> Result<usize, Error>
+where
+    C: crypto::Backend,
+    R: Reference,
+    W: Write,
+{
+    let mut selected = 0;
+    let mut count = 0;
+    for (uuid, reference) in references {
+        let store = match decrypt_entry(reference, crypto) {
+            Ok(store) => store,
+            Err(err) => {
+                failed.push((*uuid, err));
+                continue;
+            }
+        };
+        if !is_selected(&store) {
+            continue;
+        }
+        if window.contains(selected) {
+            let name = store.get(&Key::Name).map_or("", Value::as_str);
+            write!(output, "{} {}\0", uuid, name)?;
+            count += 1;
+        }
+        selected += 1;
+    }
+
+    Ok(count)
+}
+
+/// Returns whether an entry with `entry_tags` is selected by the `--tag`
+/// filters in `tags`, which require every tag unless `any` is set.
+///
+/// Every entry is selected if there are no filters.
+pub fn matches_tags(
+    entry_tags: &BTreeSet<String>,
+    tags: &BTreeSet<String>,
+    any: bool,
+) -> bool {
+    if tags.is_empty() {
+        true
+    } else if any {
+        !entry_tags.is_disjoint(tags)
+    } else {
+        tags.is_subset(entry_tags)
+    }
+}
+
+/// Renders an entry, or returns `None` if it is not selected.
+fn render_entry<C, R>(
+    reference: &R,
+    crypto: &C,
+    is_selected: impl Fn(&KvStore) -> bool,
+    opts: DisplayOptions,
+    show_secrets: bool,
+) -> Result<Option<Vec<u8>>, Error>
+where
+    C: crypto::Backend,
+    R: Reference,
+{
+    let store = decrypt_entry(reference, crypto)?;
+    if !is_selected(&store) {
+        return Ok(None);
+    }
+
+    let mut buf = Vec::new();
+    store.display(&mut buf, opts, show_secrets)?;
+
+    Ok(Some(buf))
+}
+
+/// Shows a single field of the entry with the provided name.
+///
+/// Sensitive fields are redacted unless `secret_visibility` is
+/// [`SecretVisibility::Show`]. A sensitive field is never printed raw while
+/// secrets are hidden.
+pub fn show<C, S, W>(
+    opts: &Show,
+    secret_visibility: SecretVisibility,
+    crypto: &C,
+    storage: &S,
+    index: Option<&NameIndex>,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    W: Write,
+{
+    let (name, is_glob) = match (&opts.name, &opts.glob) {
+        (Some(name), None) => (parse_os_str(name, "Invalid Name")?, false),
+        (None, Some(pattern)) => (parse_os_str(pattern, "Invalid glob")?, true),
+        (Some(_), Some(_)) => {
+            let message = "--glob cannot be combined with a name";
+            return Err(xflags::Error::new(message).into());
+        }
+        (None, None) => {
+            let message = "A name is required, or --glob or --select";
+            return Err(xflags::Error::new(message).into());
+        }
+    };
+
+    let key = field_key(&opts.key, &opts.secret_key)?;
+
+    let show_secrets = secret_visibility == SecretVisibility::Show;
+    let redaction = opts.redaction.clone().unwrap_or_default();
+    let mask = opts.mask_char.unwrap_or(MASK);
+    let show_field = |store, raw, output: &mut W| {
+        show_field(store, &key, raw, show_secrets, &redaction, mask, output)
+    };
+
+    if !is_glob {
+        let (_, store) = find_entry(name, crypto, storage, index)?;
+        return show_field(&store, opts.raw, output);
+    }
+
+    let stores: Vec<KvStore> = find_entries_matching(name, crypto, storage)?
+        .into_iter()
+        .map(|(_, store)| store)
+        .collect();
+
+    match stores.as_slice() {
+        [] => Err(Error::EntryNotFound(name.to_string())),
+        [store] => show_field(store, opts.raw, output),
+        stores if !opts.all => {
+            let names = stores
+                .iter()
+                .map(|store| entry_name(store).to_string())
+                .collect();
+            Err(Error::AmbiguousName(names))
+        }
+        stores => {
+            // Each match is labeled with its name so that they can be told
+            // apart.
+            for store in stores {
+                if opts.raw {
+                    show_field(store, true, output)?;
+                    writeln!(output)?;
+                } else {
+                    writeln!(output, "---")?;
+                    writeln!(output, "name: {}", entry_name(store))?;
+                    show_field(store, false, output)?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Returns the key of the field that `show` and `copy` read: `--key`, or else
+/// `--secret-key`, checked like `new --secret-key`, or else `secret`.
+fn field_key(
+    key: &Option<OsString>,
+    secret_key: &Option<OsString>,
+) -> Result<Key, Error> {
+    match (key, secret_key) {
+        (Some(key), None) => Ok(Key::from(parse_os_str(key, "Invalid Key")?)),
+        (None, Some(key)) => {
+            let key = parse_os_str(key, "Invalid Secret Key")?;
+            Key::parse_valid(key.trim())
+                .map_err(|e| Error::Filetype(Box::new(e)))
+        }
+        (Some(_), Some(_)) => {
+            let message = "--key cannot be combined with --secret-key";
+            Err(xflags::Error::new(message).into())
+        }
+        (None, None) => Ok(Key::from("secret")),
+    }
+}
+
+/// Copies a field of an entry to the clipboard, and clears it after the
+/// timeout or once a message is received on `interrupted`.
+///
+/// Fails with [`Error::Interrupted`] after clearing the clipboard if the
+/// wait was interrupted.
+pub fn copy<C, S, B, W>(
+    opts: &crate::flags::Copy,
+    crypto: &C,
+    storage: &S,
+    index: Option<&NameIndex>,
+    clipboard: &mut B,
+    interrupted: &Receiver<()>,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    B: Clipboard,
+    W: Write,
+{
+    let key = field_key(&opts.key, &opts.secret_key)?;
+
+    let store = match (&opts.name, &opts.glob) {
+        (Some(name), None) => {
+            let name = parse_os_str(name, "Invalid Name")?;
+            find_entry(name, crypto, storage, index)?.1
+        }
+        (None, Some(pattern)) => {
+            let pattern = parse_os_str(pattern, "Invalid glob")?;
+            find_entry_matching(pattern, crypto, storage)?.1
+        }
+        (Some(_), Some(_)) => {
+            let message = "--glob cannot be combined with a name";
+            return Err(xflags::Error::new(message).into());
+        }
+        (None, None) => {
+            let message = "A name is required, or --glob";
+            return Err(xflags::Error::new(message).into());
+        }
+    };
+    let name = entry_name(&store);
+    let value = store
+        .get(&key)
+        .ok_or_else(|| Error::FieldNotFound(key.clone()))?;
+
+    if opts.no_wait {
+        clipboard.set(value.as_str()).map_err(Error::Clipboard)?;
+        writeln!(output, "Copied {} of {} to the clipboard", key, name)?;
+        return Ok(());
+    }
+
+    let seconds = opts.timeout.unwrap_or(defaults::CLIPBOARD_TIMEOUT);
+    writeln!(
+        output,
+        "Copied {} of {} to the clipboard, clearing in {} seconds",
+        key, name, seconds
+    )?;
+    output.flush()?;
+
+    let timeout = Duration::from_secs(seconds);
+    let interrupted = clipboard::copy_and_clear(
+        clipboard,
+        value.as_str(),
+        timeout,
+        interrupted,
+    )
+    .map_err(Error::Clipboard)?;
+
+    match interrupted {
+        true => Err(Error::Interrupted),
+        false => Ok(()),
+    }
+}
+
+/// Writes the field with the provided key, or only its value if `raw`.
+fn show_field<W>(
+    store: &KvStore,
+    key: &Key,
+    raw: bool,
+    show_secrets: bool,
+    redaction: &Redaction,
+    mask: char,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    W: Write,
+{
+    let value = store
+        .get(key)
+        .ok_or_else(|| Error::FieldNotFound(key.clone()))?;
+
+    if raw {
+        // Redacted output is useless to scripts, so refuse instead.
+        if value.is_sensitive() && !show_secrets {
+            return Err(Error::SecretHidden(key.clone()));
+        }
+
+        write!(output, "{}", value.as_str())?;
+    } else {
+        let opts = DisplayOptions {
+            show_all: false,
+            enabled_keys: HashSet::from([key.clone()]),
+            only_sensitive: false,
+            redaction: redaction.clone(),
+            mask,
+            field_order: Vec::new(),
+        };
+
+        store.display(output, opts, show_secrets)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the name of an entry, or an empty string if it has none.
+fn entry_name(store: &KvStore) -> &str {
+    store.get(&Key::Name).map(Value::as_str).unwrap_or_default()
+}
+
+/// Writes the raw decrypted contents of an entry to `output`, without parsing
+/// them.
+///
+/// The entry is selected by UUID, or by name if it is in the key/value format.
+/// Unless forced, nothing is written if `output_is_terminal` is `true`.
+pub fn cat<C, S, W>(
+    opts: &Cat,
+    crypto: &C,
+    storage: &S,
+    index: Option<&NameIndex>,
+    output: &mut W,
+    output_is_terminal: bool,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    C::Error: From<io::Error>,
+    S: storage::Backend,
+    W: Write,
+{
+    if output_is_terminal && !opts.force {
+        return Err(Error::TerminalOutput);
+    }
+
+    let buffer_size = match opts.reader_buffer_size {
+        Some(0) => {
+            let message = "--reader-buffer-size must be at least 1 byte";
+            return Err(xflags::Error::new(message).into());
+        }
+        Some(size) => size,
+        None => defaults::READER_BUFFER_SIZE,
+    };
+
+    let uuid = match (&opts.name, &opts.uuid) {
+        (None, Some(uuid)) => parse_uuid(uuid)?,
+        (Some(name), None) => {
+            let name = parse_os_str(name, "Invalid Name")?;
+            find_entry(name, crypto, storage, index)?.0
+        }
+        _ => {
+            return Err(Error::Xflags(xflags::Error::new(
+                "Exactly one of a name or --uuid is required",
+            )))
+        }
+    };
+
+    let reference = storage
+        .entries()?
+        .remove(&uuid)
+        .ok_or_else(|| Error::EntryNotFound(uuid.to_string()))?;
+
+    let reader = BufReader::with_capacity(buffer_size, reference.reader()?);
+    crypto
+        .decrypt_to(reader, output)
+        .map_err(|err| Error::Crypto(Box::new(err)))?;
+
+    Ok(())
+}
+
+/// Re-encrypts the entry with the provided name to an updated set of
+/// recipients. The entry keeps its UUID.
+///
+/// The new recipients are the entry's current recipients, plus any added
+/// recipients, minus any removed recipients. If the crypto backend cannot
+/// determine the entry's current recipients, the command fails rather than
+/// dropping them.
+///
+/// With `--recipients-from-entry`, the entry's `recipients` field, a comma or
+/// newline separated list, is used as its current recipients instead. With
+/// `--replace`, the current recipients are dropped and only the added
+/// recipients are used.
+///
+/// With `--all`, every entry is re-encrypted instead, see [`reshare_all`].
+pub fn reshare<C, S, W>(
+    opts: &Reshare,
+    crypto: &C,
+    storage: &mut S,
+    index: Option<&NameIndex>,
+    progress: util::Progress,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    W: Write,
+{
+    ensure_writable(storage)?;
+    ensure_supported(storage, |c| c.can_replace, "replacing entries")?;
+
+    if opts.replace && (opts.recipients_from_entry || !opts.remove.is_empty()) {
+        let message =
+            "--replace cannot be used with --recipients-from-entry or --remove";
+        return Err(xflags::Error::new(message).into());
+    }
+    check_replace(opts.replace, &opts.recipient)?;
+
+    let name = match (&opts.name, opts.all) {
+        (Some(_), true) => {
+            let message = "--all cannot be used with an entry name";
+            return Err(xflags::Error::new(message).into());
+        }
+        (None, false) => {
+            let message = "an entry name or --all is required";
+            return Err(xflags::Error::new(message).into());
+        }
+        (None, true) => {
+            return reshare_all(opts, crypto, storage, progress, output)
+        }
+        (Some(name), false) => parse_os_str(name, "Invalid Name")?,
+    };
+
+    if opts.keep_going {
+        let message = "--keep-going can only be used with --all";
+        return Err(xflags::Error::new(message).into());
+    }
+
+    let (uuid, store) = find_entry(name, crypto, storage, index)?;
+    let reference = storage
+        .entries()?
+        .remove(&uuid)
+        .ok_or_else(|| Error::EntryNotFound(name.to_string()))?;
+
+    let recipients = reshare_recipients(opts, crypto, &reference, &store)?;
+    if opts.recipient_fingerprint {
+        write_fingerprints(&recipients, output)?;
+    }
+    let reference = replace_entry(crypto, storage, &uuid, store, recipients)?;
+
+    writeln!(output, "Updated recipients of entry at path: {}", reference)?;
+
+    Ok(())
+}
+
+/// Re-encrypts every entry to its updated set of recipients.
+///
+/// Every entry is decrypted before any is written. Without `--keep-going`,
+/// the first entry that fails stops the command, before anything is written
+/// if it failed to decrypt. With it, the entries that fail are skipped and
+/// reported once the others are written.
+///
+/// Like a single entry, an entry whose current recipients cannot be
+/// determined fails rather than being re-encrypted to only the added
+/// recipients, unless `--replace` is passed.
+///
+/// `progress` counts each entry twice, once as it is decrypted and once as it
+/// is written.
+fn reshare_all<C, S, W>(
+    opts: &Reshare,
+    crypto: &C,
+    storage: &mut S,
+    mut progress: util::Progress,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    W: Write,
+{
+    let references = storage.sorted_entries()?;
+
+    let mut reshared = Vec::new();
+    let mut skipped = Vec::new();
+
+    let steps = references.len() * 2;
+    for (done, (uuid, reference)) in references.iter().enumerate() {
+        let result = decrypt_entry(reference, crypto).and_then(|store| {
+            let recipients =
+                reshare_recipients(opts, crypto, reference, &store)?;
+            Ok((*uuid, store, recipients))
+        });
+
+        if let Some(progress) = progress.as_mut() {
+            progress(done + 1, steps);
+        }
+
+        match result {
+            Ok(entry) => reshared.push(entry),
+            Err(err) => skipped.push((*uuid, err)),
+        }
+
+        if !opts.keep_going && !skipped.is_empty() {
+            break;
+        }
+    }
+
+    let total = references.len();
+    drop(references);
+
+    if opts.recipient_fingerprint {
+        let recipients = reshared.iter().flat_map(|(_, _, r)| r);
+        write_fingerprints(recipients, output)?;
+    }
+
+    let mut updated = 0;
+    if opts.keep_going || skipped.is_empty() {
+        for (done, (uuid, store, recipients)) in
+            reshared.into_iter().enumerate()
+        {
+            match replace_entry(crypto, storage, &uuid, store, recipients) {
+                Ok(_) => updated += 1,
+                Err(err) => skipped.push((uuid, err)),
+            }
+
+            if let Some(progress) = progress.as_mut() {
+                progress(total + done + 1, steps);
+            }
+
+            if !opts.keep_going && !skipped.is_empty() {
+                break;
+            }
+        }
+    }
+
+    for (uuid, err) in &skipped {
+        // Parse errors may contain the contents of the entry, so only report
+        // the kind of failure.
+        let reason = match err {
+            Error::Filetype(_) => "invalid entry format".to_string(),
+            err => err.to_string(),
+        };
+
+        let outcome = if opts.keep_going { "SKIPPED" } else { "FAILED" };
+        writeln!(output, "{} {}: {}", outcome, uuid, reason)?;
+    }
+
+    writeln!(
+        output,
+        "Updated recipients of {} of {} entries",
+        updated, total
+    )?;
+
+    match skipped.len() {
+        0 => Ok(()),
+        failures => Err(Error::ReshareFailed(failures)),
+    }
+}
+
+/// Returns the recipients that `reshare` re-encrypts the entry at `reference`
+/// to: its current recipients, without the removed ones, and with the added
+/// ones.
+fn reshare_recipients<C, R>(
+    opts: &Reshare,
+    crypto: &C,
+    reference: &R,
+    store: &KvStore,
+) -> Result<Vec<C::Recipient>, Error>
+where
+    C: crypto::Backend,
+    R: Reference,
+{
+    let current = if opts.replace {
+        Some(Vec::new())
+    } else if opts.recipients_from_entry {
+        let key = Key::from(defaults::RECIPIENTS_KEY);
+        let list = store.get(&key).ok_or(Error::FieldNotFound(key))?;
+        Some(util::split_recipients(list.as_str()))
+    } else {
+        recipients_of(crypto, reference)?
+    };
+
+    let removed = opts
+        .remove
+        .iter()
+        .map(|r| parse_os_str(r, "Recipient contains invalid characters"))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Re-encrypting to only the added recipients would silently lock out the
+    // current recipients, so that must be asked for with `--replace`.
+    let mut recipients: Vec<OsString> = match current {
+        Some(current) => current.into_iter().map(OsString::from).collect(),
+        None => return Err(Error::UnknownRecipients),
+    };
+
+    recipients.retain(|r| !removed.iter().any(|removed| r == removed));
+    recipients.extend(opts.recipient.iter().cloned());
+
+    let recipients = parse_recipients::<C::Recipient>(&recipients)?;
+    check_lockout(crypto, &recipients, opts.allow_lockout)?;
+    check_fingerprints(&recipients, &opts.expect_fingerprint)?;
+
+    Ok(recipients)
+}
+
+/// Changes the sensitivity of a field of the entry with the provided name.
+/// The entry keeps its UUID.
+///
+/// The entry is re-encrypted to its current recipients and any recipients
+/// passed with `-r`. If the crypto backend cannot determine the current
+/// recipients, the command fails unless `--replace` is passed to re-encrypt
+/// the entry to only the recipients passed with `-r`.
+pub fn set_sensitive<C, S, W>(
+    opts: &SetSensitive,
+    crypto: &C,
+    storage: &mut S,
+    index: Option<&NameIndex>,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    W: Write,
+{
+    ensure_writable(storage)?;
+    ensure_supported(storage, |c| c.can_replace, "replacing entries")?;
+
+    check_replace(opts.replace, &opts.recipient)?;
+
+    let name = parse_os_str(&opts.name, "Invalid Name")?;
+    let key = Key::from(parse_os_str(&opts.key, "Invalid Key")?);
+    let (uuid, mut store) = find_entry(name, crypto, storage, index)?;
+
+    store
+        .get_mut(&key)
+        .ok_or_else(|| Error::FieldNotFound(key.clone()))?
+        .set_sensitive(!opts.insensitive);
+
+    let reference = storage
+        .entries()?
+        .remove(&uuid)
+        .ok_or_else(|| Error::EntryNotFound(name.to_string()))?;
+
+    let current = match opts.replace {
+        true => Some(Vec::new()),
+        false => recipients_of(crypto, &reference)?,
+    };
+
+    // Re-encrypting to only the added recipients would silently lock out the
+    // current recipients, so that must be asked for with `--replace`.
+    let mut recipients: Vec<OsString> = match current {
+        Some(current) => current.into_iter().map(OsString::from).collect(),
+        None => return Err(Error::UnknownRecipients),
+    };
+    for recipient in &opts.recipient {
+        if !recipients.contains(recipient) {
+            recipients.push(recipient.clone());
+        }
+    }
+
+    let recipients = parse_recipients::<C::Recipient>(&recipients)?;
+    let reference = replace_entry(crypto, storage, &uuid, store, recipients)?;
+
+    let sensitivity = if opts.insensitive {
+        "insensitive"
+    } else {
+        "sensitive"
+    };
+    writeln!(
+        output,
+        "Marked '{}' as {} in: {}",
+        key, sensitivity, reference
+    )?;
+
+    Ok(())
+}
+
+/// Fails if `--replace` is passed without any recipients to replace the
+/// current recipients with.
+fn check_replace(replace: bool, added: &[OsString]) -> Result<(), Error> {
+    if replace && added.is_empty() {
+        let message = "--replace requires at least one recipient";
+        return Err(xflags::Error::new(message).into());
+    }
+    Ok(())
+}
+
+/// Replaces the contents of the entry with the provided UUID with `store`,
+/// encrypted to `recipients`.
+fn replace_entry<C, S>(
+    crypto: &C,
+    storage: &mut S,
+    uuid: &Uuid,
+    store: KvStore,
+    recipients: Vec<C::Recipient>,
+) -> Result<S::Reference, Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+{
+    // Encrypt the entry before touching the original so that a failure to
+    // encrypt never loses data. Backends that can replace the data atomically
+    // do not lose it on a failure to write either.
+    let mut ciphertext = Vec::new();
+    let mut encrypted = crypto
+        .encrypt_output(&mut ciphertext, recipients)
+        .map_err(|err| Error::Crypto(Box::new(err)))?;
+    store.serialize(&mut encrypted)?;
+    encrypted.finish()?;
+
+    Ok(storage.replace_entry(uuid, &ciphertext)?)
+}
+
+/// Moves the entry with the provided name from `source` to `destination`,
+/// re-encrypting it to the recipients in the destination store's default
+/// recipients file. The entry keeps its UUID.
+///
+/// The entry is only removed from `source` once it has been written to
+/// `destination`, so a failure never loses data. Unless `--allow-lockout` is
+/// passed, the destination's recipients must include a loaded identity.
+pub fn mv<C, S, D, W>(
+    opts: &Mv,
+    crypto: &C,
+    source: &mut S,
+    index: Option<&mut NameIndex>,
+    destination: &mut D,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    D: storage::Backend,
+    W: Write,
+{
+    ensure_writable(source)?;
+    ensure_writable(destination)?;
+    ensure_supported(source, |c| c.can_remove, "removing entries")?;
+    ensure_supported(destination, |c| c.can_add, "adding entries")?;
+
+    let name = parse_os_str(&opts.name, "Invalid Name")?;
+    let (uuid, store) = find_entry(name, crypto, source, index.as_deref())?;
+
+    let recipients_file = defaults::recipients_file(&opts.to);
+    let recipients = util::read_recipients_file(&recipients_file)?;
+    let recipients = parse_recipients::<C::Recipient>(&recipients)?;
+    // The source entry is removed, so this may be the only copy.
+    check_lockout(crypto, &recipients, opts.allow_lockout)?;
+    check_fingerprints(&recipients, &opts.expect_fingerprint)?;
+    if opts.recipient_fingerprint {
+        write_fingerprints(&recipients, output)?;
+    }
+
+    let mut ciphertext = Vec::new();
+    let mut encrypted = crypto
+        .encrypt_output(&mut ciphertext, recipients)
+        .map_err(|err| Error::Crypto(Box::new(err)))?;
+    store.serialize(&mut encrypted)?;
+    encrypted.finish()?;
+
+    let mut reference = destination.add_entry(&uuid)?;
+    let mut writer = reference.writer()?;
+    // The writer creates the entry, so it is removed again if it cannot be
+    // written in full, or a retry would find it already exists. It must be on
+    // disk before the source entry is removed.
+    let written = writer
+        .write_all(&ciphertext)
+        .and_then(|_| writer.flush())
+        .and_then(|_| reference.sync());
+    if let Err(e) = written {
+        let _ = destination.remove_entry(&uuid);
+        return Err(e.into());
+    }
+    info!(%uuid, path = %reference, "Moved entry");
+
+    source.remove_entry(&uuid)?;
+
+    if let Some(index) = index {
+        index.remove(&uuid);
+        index.save()?;
+    }
+
+    writeln!(output, "Moved entry to path: {}", reference)?;
+
+    Ok(())
+}
+
+/// Merges two entries into a new entry, encrypted to the recipients of both.
+///
+/// The merged entry is not validated, since `keep-both` intentionally keeps
+/// keys that appear in both entries more than once.
+pub fn merge<C, S, W>(
+    opts: &Merge,
+    crypto: &C,
+    storage: &mut S,
+    index: Option<&mut NameIndex>,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    W: Write,
+{
+    ensure_writable(storage)?;
+    ensure_supported(storage, |c| c.can_add, "adding entries")?;
+    if opts.remove {
+        ensure_supported(storage, |c| c.can_remove, "removing entries")?;
+    }
+
+    let mut entries = storage.entries()?;
+    let mut uuids = Vec::new();
+    let mut stores = Vec::new();
+    let mut recipients: Vec<OsString> = Vec::new();
+    let mut unknown_recipients = false;
+
+    for name in [&opts.a, &opts.b] {
+        let name = parse_os_str(name, "Invalid Name")?;
+        let (uuid, store) =
+            find_entry(name, crypto, storage, index.as_deref())?;
+
+        // Merging an entry with itself is a mistake, and with --remove it
+        // would lose the entry.
+        if uuids.contains(&uuid) {
+            let message = "Cannot merge an entry with itself";
+            return Err(xflags::Error::new(message).into());
+        }
+
+        let reference = entries
+            .remove(&uuid)
+            .ok_or_else(|| Error::EntryNotFound(name.to_string()))?;
+        let current = recipients_of(crypto, &reference)?;
+
+        match current {
+            Some(current) => {
+                recipients.extend(current.into_iter().map(OsString::from))
+            }
+            None => unknown_recipients = true,
+        }
+
+        uuids.push(uuid);
+        stores.push(store);
+    }
+
+    // Encrypting to only the provided recipients could silently lock out the
+    // recipients of either entry.
+    if unknown_recipients && opts.recipient.is_empty() {
+        return Err(Error::UnknownRecipients);
+    }
+    recipients.extend(opts.recipient.iter().cloned());
+    let recipients = parse_recipients::<C::Recipient>(&recipients)?;
+    check_lockout(crypto, &recipients, false)?;
+
+    let other = stores.pop().expect("two entries were found");
+    let store = stores.pop().expect("two entries were found");
+    let store = store.merge(other, opts.strategy.unwrap_or_default());
+    let name = entry_name(&store).to_string();
+
+    let uuid = Uuid::new_v4();
+    let reference = write_new_entry(crypto, storage, uuid, store, recipients)?;
+
+    if opts.remove {
+        for uuid in &uuids {
+            info!(%uuid, "Removing merged entry");
+            storage.remove_entry(uuid)?;
+        }
+    }
+
+    if let Some(index) = index {
+        if opts.remove {
+            for uuid in &uuids {
+                index.remove(uuid);
+            }
+        }
+        index.insert(name, uuid);
+        index.save()?;
+    }
+
+    writeln!(output, "Merged entries into: {}", reference)?;
+
+    Ok(())
+}
+
+/// Rebuilds the name index of the store from the names of its entries.
+///
+/// Every entry must be decrypted, so the index is only saved if every entry
+/// can be decrypted and parsed.
+pub fn reindex<C, S, W>(
+    crypto: &C,
+    storage: &S,
+    index: &mut NameIndex,
+    mut progress: util::Progress,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    W: Write,
+{
+    ensure_writable(storage)?;
+
+    let mut rebuilt = NameIndex::new(index.file().to_path_buf());
+    let mut count = 0;
+
+    let entries = decrypted_entries(crypto, storage)?;
+    let total = entries.len();
+
+    for (done, entry) in entries.enumerate() {
+        let (uuid, store) = entry?;
+
+        if let Some(progress) = progress.as_mut() {
+            progress(done + 1, total);
+        }
+
+        if let Some(name) = store.get(&Key::Name) {
+            rebuilt.insert(name.as_str().to_string(), uuid);
+            count += 1;
+        }
+    }
+
+    rebuilt.save()?;
+    *index = rebuilt;
+
+    writeln!(output, "Indexed {} entries", count)?;
+
+    Ok(())
+}
+
+/// Reports anomalies in the structure of the store, such as stray files, and
+/// `config_error`, the error from loading the config of the store, if any.
+///
+/// With `--fix`, offers to repair each anomaly that the store can repair,
+/// and checks the store again afterwards. A malformed config is never
+/// repaired.
+pub fn doctor<S, R, W>(
+    opts: &Doctor,
+    config_error: Option<Error>,
+    storage: &mut S,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    S: storage::Backend,
+    R: BufRead,
+    W: Write,
+{
+    writeln!(output, "Checking store at: {}", storage.location())?;
+
+    if let Some(err) = &config_error {
+        writeln!(output, "{}", err)?;
+    }
+    let config_problems = usize::from(config_error.is_some());
+
+    let warnings = storage.health_check()?;
+
+    for warning in &warnings {
+        writeln!(output, "{}", warning)?;
+    }
+
+    if warnings.is_empty() && config_problems == 0 {
+        writeln!(output, "No problems found")?;
+        return Ok(());
+    } else if !opts.fix {
+        return Err(Error::UnhealthyStore(warnings.len() + config_problems));
+    }
+
+    for warning in &warnings {
+        let action = match warning.repair_action() {
+            Some(action) => action,
+            None => continue,
+        };
+
+        // Removing files is always confirmed, even with --yes.
+        if !opts.yes || warning.is_destructive() {
+            let prompt = format!("{}? [y/N]: ", action);
+            let confirmation = util::prompt_shown_input(&prompt, input, output)
+                .map_err(Error::Input)?;
+            if !matches!(confirmation.as_str(), "y" | "Y" | "yes") {
+                writeln!(output, "Skipped: {}", action)?;
+                continue;
+            }
+        }
+
+        match storage.repair(warning) {
+            Ok(()) => writeln!(output, "Done: {}", action)?,
+            Err(e) => writeln!(output, "Failed: {}: {}", action, e)?,
+        }
+    }
+
+    let remaining = storage.health_check()?.len() + config_problems;
+    if remaining == 0 {
+        writeln!(output, "No problems remain")?;
+        Ok(())
+    } else {
+        Err(Error::UnhealthyStore(remaining))
+    }
+}
+
+/// Checks that every entry in the store can be decrypted and parsed, and
+/// reports any entries that cannot.
+///
+/// The contents of the entries are discarded and never written to `output`.
+pub fn verify<C, S, W>(
+    crypto: &C,
+    storage: &S,
+    mut progress: util::Progress,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    W: Write,
+{
+    let references = storage.sorted_entries()?;
+
+    let mut failed = 0;
+
+    for (done, (uuid, reference)) in references.iter().enumerate() {
+        let result = decrypt_entry(reference, crypto);
+
+        if let Some(progress) = progress.as_mut() {
+            progress(done + 1, references.len());
+        }
+
+        if let Err(err) = result {
+            failed += 1;
+
+            // Parse errors may contain the contents of the entry, so only
+            // report the kind of failure.
+            let reason = match err {
+                Error::Filetype(_) => "invalid entry format".to_string(),
+                err => err.to_string(),
+            };
+
+            writeln!(output, "FAILED {}: {}", uuid, reason)?;
+        }
+    }
+
+    writeln!(
+        output,
+        "Verified {} entries: {} OK, {} failed",
+        references.len(),
+        references.len() - failed,
+        failed
+    )?;
+
+    if failed > 0 {
+        return Err(Error::VerificationFailed(failed));
+    }
+
+    Ok(())
+}
+
+/// Reports groups of entries that share the value of a secret field, and
+/// fails if there are any.
+///
+/// Only a hash of each value is kept while the entries are compared, and
+/// entries are reported by UUID and name, so no secret is ever written.
+pub fn audit<C, S, W>(
+    crypto: &C,
+    storage: &S,
+    mut progress: util::Progress,
+    output: &mut W,
+) -> Result<(), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    W: Write,
+{
+    let entries = decrypted_entries(crypto, storage)?;
+    let total = entries.len();
+
+    // The entries that hold each value, keyed by the value's hash. Ordered by
+    // hash, so that the output does not depend on the order of a hash map.
+    let mut holders: BTreeMap<[u8; 32], BTreeSet<(Uuid, String)>> =
+        BTreeMap::new();
+
+    for (done, entry) in entries.enumerate() {
+        let (uuid, store) = entry?;
+        let name = entry_name(&store).to_string();
+
+        for pair in store.iter().filter(|pair| is_audited(pair)) {
+            let hash = Sha256::digest(pair.value.as_str().as_bytes()).into();
+            holders
+                .entry(hash)
+                .or_default()
+                .insert((uuid, name.clone()));
+        }
+
+        if let Some(progress) = progress.as_mut() {
+            progress(done + 1, total);
+        }
+    }
+
+    // An entry that repeats a value in several of its own fields is only
+    // counted once, so it does not form a group on its own.
+    let mut groups: Vec<_> = holders
+        .into_values()
+        .filter(|entries| entries.len() > 1)
+        .collect();
+    groups.sort();
+
+    for entries in &groups {
+        writeln!(output, "Secret shared by {} entries:", entries.len())?;
+        for (uuid, name) in entries {
+            writeln!(output, "  {} {}", uuid, name)?;
+        }
+    }
+
+    writeln!(
+        output,
+        "Audited {} entries: {} shared secrets",
+        total,
+        groups.len()
+    )?;
+
+    if !groups.is_empty() {
+        return Err(Error::ReusedSecrets(groups.len()));
+    }
+
+    Ok(())
+}
+
+/// Returns whether `audit` compares the value of a field: the `secret` and
+/// `password` fields, and any other sensitive field.
+fn is_audited(pair: &KeyValuePair) -> bool {
+    let secret = pair.key == Key::Password || pair.key == Key::from("secret");
+    !pair.value.as_str().is_empty() && (secret || pair.value.is_sensitive())
+}
+
+/// Finds the first entry in the store whose name matches `name`.
+fn find_entry<C, S>(
+    name: &str,
+    crypto: &C,
+    storage: &S,
+    index: Option<&NameIndex>,
+) -> Result<(Uuid, KvStore), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+{
+    let is_named = |store: &KvStore| {
+        store.get(&Key::Name).map(Value::as_str) == Some(name)
+    };
+
+    // Only the indexed entry needs to be decrypted, but the index may be
+    // stale, so the entry's name must still be checked.
+    if let Some(uuid) = index.and_then(|index| index.get(name)) {
+        if let Some(reference) = storage.entries()?.remove(&uuid) {
+            match decrypt_entry(&reference, crypto) {
+                Ok(store) if is_named(&store) => return Ok((uuid, store)),
+                Ok(_) | Err(Error::BinaryEntry(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        debug!(%uuid, "Name index is stale, decrypting all entries");
+    }
+
+    for entry in named_entries(crypto, storage)? {
+        let (uuid, store) = entry?;
+
+        if is_named(&store) {
+            return Ok((uuid, store));
+        }
+    }
+
+    Err(Error::EntryNotFound(name.to_string()))
+}
+
+/// Returns every entry whose name matches the glob `pattern`, ordered by UUID.
+fn find_entries_matching<C, S>(
+    pattern: &str,
+    crypto: &C,
+    storage: &S,
+) -> Result<Vec<(Uuid, KvStore)>, Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+{
+    let matcher = globset::Glob::new(pattern)
+        .map_err(|e| xflags::Error::new(format!("Invalid glob: {}", e)))?
+        .compile_matcher();
+
+    let mut matches = Vec::new();
+
+    for entry in named_entries(crypto, storage)? {
+        let (uuid, store) = entry?;
+
+        if store
+            .get(&Key::Name)
+            .is_some_and(|name| matcher.is_match(name.as_str()))
+        {
+            matches.push((uuid, store));
+        }
+    }
+
+    Ok(matches)
+}
+
+/// Returns the only entry whose name matches the glob `pattern`.
+///
+/// Fails with [`Error::AmbiguousName`] if more than one entry matches.
+fn find_entry_matching<C, S>(
+    pattern: &str,
+    crypto: &C,
+    storage: &S,
+) -> Result<(Uuid, KvStore), Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+{
+    let mut matches = find_entries_matching(pattern, crypto, storage)?;
+
+    match matches.len() {
+        0 => Err(Error::EntryNotFound(pattern.to_string())),
+        1 => Ok(matches.remove(0)),
+        _ => {
+            let names = matches
+                .iter()
+                .map(|(_, store)| entry_name(store).to_string())
+                .collect();
+            Err(Error::AmbiguousName(names))
+        }
+    }
+}
+
+/// Decrypts the entries of `storage` in the order of
+/// [`storage::Backend::sorted_entries`], like [`libciphey::vault::Vault::list`]
+/// but reading entries in any [`entry::Format`].
+pub fn decrypted_entries<'a, C, S>(
+    crypto: &'a C,
+    storage: &S,
+) -> Result<
+    impl ExactSizeIterator<Item = Result<(Uuid, KvStore), Error>> + 'a,
+    Error,
+>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    S::Reference: 'a,
+{
+    let references = storage.sorted_entries()?;
+
+    Ok(references.into_iter().map(|(uuid, reference)| {
+        decrypt_entry(&reference, crypto).map(|store| (uuid, store))
+    }))
+}
+
+/// Decrypts the entries like [`decrypted_entries`], but skips the entries that
+/// are not text, since they have no name or fields to be looked up by.
+fn named_entries<'a, C, S>(
+    crypto: &'a C,
+    storage: &S,
+) -> Result<impl Iterator<Item = Result<(Uuid, KvStore), Error>> + 'a, Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    S::Reference: 'a,
+{
+    Ok(
+        decrypted_entries(crypto, storage)?.filter(|entry| match entry {
+            Err(Error::BinaryEntry(path)) => {
+                debug!(%path, "Skipping binary entry");
+                false
+            }
+            _ => true,
+        }),
+    )
+}
+
+/// Decrypts and deserializes a single entry.
+fn decrypt_entry<C, R>(reference: &R, crypto: &C) -> Result<KvStore, Error>
+where
+    C: crypto::Backend,
+    R: Reference,
+{
+    info!(path = %reference, "Reading entry");
+    let start = Instant::now();
+
+    let store = match vault::read_entry(crypto, reference)? {
+        AnyEntry::KvStore(store) => store,
+        AnyEntry::Raw => return Err(Error::BinaryEntry(reference.to_string())),
+    };
+
+    debug!(elapsed = ?start.elapsed(), "Decrypted entry");
+
+    Ok(store)
+}
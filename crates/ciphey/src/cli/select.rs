@@ -0,0 +1,129 @@
+use std::ffi::OsString;
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::process::{Command, Stdio};
+
+use libciphey::{crypto, storage};
+
+use super::util::prompt_shown_input;
+use super::{entry_name, named_entries, Error};
+
+/// Picks the name of an entry in the store interactively.
+///
+/// Refuses to run unless stdout is a terminal, since the picked entry would
+/// otherwise be written somewhere the user cannot see it being picked.
+pub fn select_name<C, S, R, W>(
+    crypto: &C,
+    storage: &S,
+    output_is_terminal: bool,
+    input: &mut R,
+    output: &mut W,
+) -> Result<OsString, Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    R: BufRead,
+    W: Write,
+{
+    if !output_is_terminal {
+        let message = "--select requires stdout to be a terminal";
+        return Err(xflags::Error::new(message).into());
+    }
+
+    let mut names = Vec::new();
+    for entry in named_entries(crypto, storage)? {
+        let (_, store) = entry?;
+        names.push(entry_name(&store).to_string());
+    }
+    names.sort();
+    names.dedup();
+
+    select(&names, input, output)?
+        .map(OsString::from)
+        .ok_or(Error::NothingSelected)
+}
+
+/// Picks one of `names` with `fzf`, or with a numbered menu if `fzf` is not
+/// installed. Returns `None` if nothing was picked.
+pub fn select<R, W>(
+    names: &[String],
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<Option<String>>
+where
+    R: BufRead,
+    W: Write,
+{
+    if names.is_empty() {
+        return Ok(None);
+    }
+
+    match select_fzf(names) {
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            select_menu(names, input, output)
+        }
+        result => result,
+    }
+}
+
+/// Picks one of `names` with `fzf`, which draws on the terminal itself.
+///
+/// Returns a [`ErrorKind::NotFound`] error if `fzf` is not installed.
+pub fn select_fzf(names: &[String]) -> io::Result<Option<String>> {
+    let mut child = Command::new("fzf")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    for name in names {
+        match writeln!(stdin, "{}", name) {
+            // fzf stops reading once the user has picked a name.
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => break,
+            result => result?,
+        }
+    }
+    drop(stdin);
+
+    // fzf exits unsuccessfully if nothing was picked.
+    let picked = child.wait_with_output()?;
+    if !picked.status.success() {
+        return Ok(None);
+    }
+
+    let name = String::from_utf8_lossy(&picked.stdout);
+    Ok(Some(name.trim_end_matches('\n').to_string()))
+}
+
+/// Picks one of `names` by number from a menu written to `output`.
+///
+/// The prompt is repeated until a listed number is entered. An empty answer
+/// picks nothing.
+pub fn select_menu<R, W>(
+    names: &[String],
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<Option<String>>
+where
+    R: BufRead,
+    W: Write,
+{
+    let width = names.len().to_string().len();
+    for (number, name) in (1..).zip(names) {
+        writeln!(output, "{:>width$}) {}", number, name, width = width)?;
+    }
+
+    let prompt = format!("Select an entry [1-{}]: ", names.len());
+    loop {
+        let answer = prompt_shown_input(&prompt, input, output)?;
+        if answer.is_empty() {
+            return Ok(None);
+        }
+
+        match answer.trim().parse::<usize>() {
+            Ok(number) if (1..=names.len()).contains(&number) => {
+                return Ok(Some(names[number - 1].clone()))
+            }
+            _ => writeln!(output, "Enter a number from 1 to {}", names.len())?,
+        }
+    }
+}
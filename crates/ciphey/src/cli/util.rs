@@ -1,30 +1,98 @@
-use std::io::{BufRead, Write};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
 
+use ciphey_kvstore::MASK;
+use indicatif::{ProgressBar, ProgressDrawTarget};
 use rpassword::prompt_password;
 
+use super::{defaults, Error, SecretVisibility};
+
+/// A callback that is passed the number of entries processed so far and the
+/// total number of entries, after each entry is processed.
+pub type Progress<'a> = Option<&'a mut dyn FnMut(usize, usize)>;
+
+/// Returns a progress bar drawn to stderr, or `None` if `quiet` is set or
+/// stderr is not a terminal.
+pub fn progress_bar(quiet: bool) -> Option<ProgressBar> {
+    if quiet || !io::stderr().is_terminal() {
+        return None;
+    }
+
+    Some(ProgressBar::with_draw_target(
+        None,
+        ProgressDrawTarget::stderr(),
+    ))
+}
+
+/// Calls `f` with a [`Progress`] callback that updates a [`progress_bar`],
+/// which is cleared once `f` returns.
+pub fn with_progress<T>(quiet: bool, f: impl FnOnce(Progress) -> T) -> T {
+    let bar = progress_bar(quiet);
+
+    let mut report = bar.as_ref().map(|bar| {
+        move |done: usize, total: usize| {
+            bar.set_length(total as u64);
+            bar.set_position(done as u64);
+        }
+    });
+
+    let result = f(report
+        .as_mut()
+        .map(|report| report as &mut dyn FnMut(usize, usize)));
+
+    if let Some(bar) = &bar {
+        bar.finish_and_clear();
+    }
+
+    result
+}
+
 /// Prompt the user for a line of text.
+///
+/// If `timeout` is set and no line is entered in time, a
+/// [`io::ErrorKind::TimedOut`] error is returned. Hidden and masked input is
+/// read from the terminal rather than `input`, so the timeout applies to it
+/// even when stdin is not a TTY. Shown input only times out if `input` is a
+/// [`TimeoutReader`].
 pub fn prompt_input<R, W>(
-    hidden: bool,
+    echo: Echo,
     prompt: &str,
     input: &mut R,
     output: &mut W,
-) -> std::io::Result<String>
+    timeout: Option<Duration>,
+) -> io::Result<String>
 where
     R: BufRead,
     W: Write,
 {
-    if hidden {
-        prompt_password(prompt)
-    } else {
-        prompt_shown_input(prompt, input, output)
+    match echo {
+        Echo::Shown => prompt_shown_input(prompt, input, output),
+        Echo::Hidden => prompt_hidden_input(prompt, timeout),
+        Echo::Masked => prompt_masked_input(prompt, timeout),
     }
 }
 
+/// How typed input is echoed by [`prompt_input`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Echo {
+    /// The input is echoed as it is typed.
+    Shown,
+    /// Nothing is echoed.
+    Hidden,
+    /// A `*` is echoed for each typed character, but never the character.
+    Masked,
+}
+
 pub fn prompt_shown_input<R, W>(
     prompt: &str,
     input: &mut R,
     output: &mut W,
-) -> std::io::Result<String>
+) -> io::Result<String>
 where
     R: BufRead,
     W: Write,
@@ -40,3 +108,569 @@ where
 
     Ok(value)
 }
+
+/// Prompts for a line of hidden input from the terminal.
+///
+/// The password is read on a separate thread so that the prompt can be
+/// abandoned once `timeout` elapses. The terminal's settings are restored
+/// before returning, since the abandoned read leaves echo disabled.
+fn prompt_hidden_input(
+    prompt: &str,
+    timeout: Option<Duration>,
+) -> io::Result<String> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return prompt_password(prompt),
+    };
+
+    let terminal = terminal::Settings::save();
+
+    let (sender, receiver) = mpsc::channel();
+    let prompt = prompt.to_string();
+    thread::spawn(move || sender.send(prompt_password(prompt)));
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(_) => {
+            terminal.restore();
+            eprintln!();
+            Err(timed_out())
+        }
+    }
+}
+
+/// Prompts for a line of input from the terminal, echoing a `*` for each
+/// typed character.
+///
+/// Falls back to [`prompt_hidden_input`] if there is no terminal. The
+/// terminal's settings are restored before returning, including when the
+/// prompt is interrupted with Ctrl-C or abandoned after `timeout`.
+fn prompt_masked_input(
+    prompt: &str,
+    timeout: Option<Duration>,
+) -> io::Result<String> {
+    // Saved before raw mode is enabled, to restore after a timeout.
+    let terminal = terminal::Settings::save();
+
+    let mut tty = match terminal::RawTerminal::open()? {
+        Some(tty) => tty,
+        None => return prompt_hidden_input(prompt, timeout),
+    };
+    tty.echo(prompt)?;
+
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return read_masked(&mut tty, MASK),
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || sender.send(read_masked(&mut tty, MASK)));
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(_) => {
+            terminal.restore();
+            eprintln!();
+            Err(timed_out())
+        }
+    }
+}
+
+/// A terminal that reads typed characters one at a time without echoing
+/// them, so that masked input can be read from it.
+pub trait MaskedTerminal {
+    /// Reads the next typed character, or `None` at the end of input.
+    fn read_char(&mut self) -> io::Result<Option<char>>;
+
+    /// Writes `text` to the terminal.
+    fn echo(&mut self, text: &str) -> io::Result<()>;
+}
+
+/// Reads a line from `terminal`, echoing `mask` for each character instead
+/// of the character itself.
+///
+/// Backspace removes the last character and Ctrl-U removes them all. Other
+/// control characters are ignored, except Ctrl-C, which fails with an
+/// [`io::ErrorKind::Interrupted`] error, since the terminal does not send
+/// signals while it reads masked input. The CSI and SS3 escape sequences that
+/// keys such as the arrows and Home send are ignored as a whole.
+pub fn read_masked<T: MaskedTerminal>(
+    terminal: &mut T,
+    mask: char,
+) -> io::Result<String> {
+    let mut line = String::new();
+    let erase = "\u{8} \u{8}";
+
+    loop {
+        let mut c = terminal.read_char()?;
+        if c == Some('\u{1b}') {
+            c = match terminal.read_char()? {
+                // CSI: parameter and intermediate bytes up to a final byte.
+                Some('[') => {
+                    while let Some(c) = terminal.read_char()? {
+                        if !('\u{20}'..='\u{3f}').contains(&c) {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                // SS3: a single final byte.
+                Some('O') => {
+                    terminal.read_char()?;
+                    continue;
+                }
+                c => c,
+            };
+        }
+
+        match c {
+            None | Some('\n' | '\r') => break,
+            Some('\u{3}') => {
+                terminal.echo("\n")?;
+                let message = "interrupted while reading input";
+                return Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    message,
+                ));
+            }
+            Some('\u{7f}' | '\u{8}') => {
+                if line.pop().is_some() {
+                    terminal.echo(erase)?;
+                }
+            }
+            Some('\u{15}') => {
+                terminal.echo(&erase.repeat(line.chars().count()))?;
+                line.clear();
+            }
+            Some(c) if c.is_control() => {}
+            Some(c) => {
+                line.push(c);
+                terminal.echo(mask.encode_utf8(&mut [0; 4]))?;
+            }
+        }
+    }
+
+    terminal.echo("\n")?;
+    Ok(line)
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "timed out waiting for input")
+}
+
+// The reading thread only exits early if the underlying reader panicked.
+fn reader_exited() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the input reader exited")
+}
+
+/// A reader that reads from another reader on a background thread, so that
+/// each read can time out.
+///
+/// Data is only read from the underlying reader when it is requested, so no
+/// more input is consumed than a direct read would. A read that times out
+/// returns a [`io::ErrorKind::TimedOut`] error; its data is returned by the
+/// next read if it arrives.
+pub struct TimeoutReader {
+    requests: Sender<()>,
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    pending: bool,
+    buffer: Vec<u8>,
+    position: usize,
+    timeout: Option<Duration>,
+}
+
+impl TimeoutReader {
+    pub fn new<R>(mut reader: R, timeout: Option<Duration>) -> Self
+    where
+        R: Read + Send + 'static,
+    {
+        let (requests, requested) = mpsc::channel::<()>();
+        let (sender, chunks) = mpsc::channel();
+
+        thread::spawn(move || {
+            while requested.recv().is_ok() {
+                let mut chunk = vec![0; 8 * 1024];
+                let result = reader.read(&mut chunk).map(|len| {
+                    chunk.truncate(len);
+                    chunk
+                });
+
+                if sender.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            requests,
+            chunks,
+            pending: false,
+            buffer: Vec::new(),
+            position: 0,
+            timeout,
+        }
+    }
+}
+
+impl Read for TimeoutReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.fill_buf()?.read(buf)?;
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for TimeoutReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position >= self.buffer.len() {
+            if !self.pending {
+                self.requests.send(()).map_err(|_| reader_exited())?;
+                self.pending = true;
+            }
+
+            let chunk = match self.timeout {
+                Some(timeout) => match self.chunks.recv_timeout(timeout) {
+                    Ok(chunk) => chunk,
+                    Err(RecvTimeoutError::Timeout) => return Err(timed_out()),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(reader_exited())
+                    }
+                },
+                None => self.chunks.recv().map_err(|_| reader_exited())?,
+            };
+
+            self.pending = false;
+            self.buffer = chunk?;
+            self.position = 0;
+        }
+
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position = (self.position + amt).min(self.buffer.len());
+    }
+}
+
+#[cfg(unix)]
+mod terminal {
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Read, Write};
+    use std::mem::MaybeUninit;
+    use std::os::unix::io::AsRawFd;
+
+    use super::MaskedTerminal;
+
+    /// The settings of the controlling terminal.
+    pub struct Settings(Option<(File, libc::termios)>);
+
+    impl Settings {
+        /// Saves the current settings of the controlling terminal, if there
+        /// is one.
+        pub fn save() -> Self {
+            let tty = match File::open("/dev/tty") {
+                Ok(tty) => tty,
+                Err(_) => return Self(None),
+            };
+
+            let mut termios = MaybeUninit::uninit();
+            // SAFETY: `tty` is an open file descriptor and `termios` is only
+            // read after `tcgetattr` reports that it was initialized.
+            let termios = unsafe {
+                if libc::tcgetattr(tty.as_raw_fd(), termios.as_mut_ptr()) != 0 {
+                    return Self(None);
+                }
+                termios.assume_init()
+            };
+
+            Self(Some((tty, termios)))
+        }
+
+        /// Restores the saved settings.
+        pub fn restore(&self) {
+            if let Some((tty, termios)) = &self.0 {
+                // SAFETY: `tty` is an open file descriptor and `termios` was
+                // initialized by `tcgetattr`.
+                unsafe {
+                    libc::tcsetattr(tty.as_raw_fd(), libc::TCSANOW, termios);
+                }
+            }
+        }
+    }
+
+    /// The controlling terminal, switched to reading characters one at a
+    /// time without echoing them or sending signals. Its settings are
+    /// restored when it is dropped.
+    pub struct RawTerminal {
+        tty: File,
+        saved: Settings,
+    }
+
+    impl RawTerminal {
+        /// Opens the controlling terminal in raw mode, or returns `None` if
+        /// there is no terminal.
+        pub fn open() -> io::Result<Option<Self>> {
+            let saved = Settings::save();
+            let mut termios = match &saved.0 {
+                Some((_, termios)) => *termios,
+                None => return Ok(None),
+            };
+            let tty = match OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/tty")
+            {
+                Ok(tty) => tty,
+                Err(_) => return Ok(None),
+            };
+
+            termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+            // SAFETY: `tty` is an open file descriptor and `termios` is a copy
+            // of settings initialized by `tcgetattr`.
+            let result = unsafe {
+                libc::tcsetattr(tty.as_raw_fd(), libc::TCSANOW, &termios)
+            };
+            if result != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Some(Self { tty, saved }))
+        }
+    }
+
+    impl Drop for RawTerminal {
+        fn drop(&mut self) {
+            self.saved.restore();
+        }
+    }
+
+    impl MaskedTerminal for RawTerminal {
+        fn read_char(&mut self) -> io::Result<Option<char>> {
+            let mut bytes = [0; 4];
+            if self.tty.read(&mut bytes[..1])? == 0 {
+                return Ok(None);
+            }
+
+            // The length of a UTF-8 sequence is given by its first byte.
+            let len = match bytes[0] {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            self.tty.read_exact(&mut bytes[1..len])?;
+
+            match std::str::from_utf8(&bytes[..len]) {
+                Ok(c) => Ok(c.chars().next()),
+                Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+        }
+
+        fn echo(&mut self, text: &str) -> io::Result<()> {
+            self.tty.write_all(text.as_bytes())?;
+            self.tty.flush()
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod terminal {
+    use std::io;
+
+    use super::MaskedTerminal;
+
+    pub struct Settings;
+
+    impl Settings {
+        pub fn save() -> Self {
+            Self
+        }
+
+        pub fn restore(&self) {}
+    }
+
+    /// Raw terminal input is not supported, so masked input falls back to
+    /// hidden input.
+    pub enum RawTerminal {}
+
+    impl RawTerminal {
+        pub fn open() -> io::Result<Option<Self>> {
+            Ok(None)
+        }
+    }
+
+    impl MaskedTerminal for RawTerminal {
+        fn read_char(&mut self) -> io::Result<Option<char>> {
+            match *self {}
+        }
+
+        fn echo(&mut self, _text: &str) -> io::Result<()> {
+            match *self {}
+        }
+    }
+}
+
+/// Splits a list of recipients separated by commas or newlines.
+///
+/// Surrounding whitespace is trimmed and empty items, such as those left by a
+/// trailing comma, are skipped.
+pub fn split_recipients(list: &str) -> Vec<String> {
+    list.split([',', '\n'])
+        .map(str::trim)
+        .filter(|recipient| !recipient.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Finds the team's recipients file, by looking in the directory of the store
+/// and each of its parents up to the root of the git repository holding it.
+///
+/// Returns `None` if there is no such file, or if the store is not in a git
+/// repository.
+pub fn find_team_recipients(store: &Path) -> Option<PathBuf> {
+    let store = fs::canonicalize(store).unwrap_or_else(|_| store.to_path_buf());
+
+    let mut found = None;
+    for dir in store.ancestors() {
+        let path = dir.join(defaults::TEAM_RECIPIENTS_FILE);
+        if found.is_none() && path.is_file() {
+            found = Some(path);
+        }
+        if dir.join(".git").exists() {
+            return found;
+        }
+    }
+
+    None
+}
+
+/// Reads a recipients file, which lists one recipient per line.
+///
+/// Blank lines and lines starting with `#` are ignored.
+pub fn read_recipients_file(path: &Path) -> Result<Vec<OsString>, Error> {
+    let contents = fs::read_to_string(path)?;
+    Ok(parse_recipients_list(&contents))
+}
+
+/// Parses a list of recipients in the format of a recipients file.
+pub fn parse_recipients_list(contents: &str) -> Vec<OsString> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(OsString::from)
+        .collect()
+}
+
+/// The path of the controlling terminal.
+pub const TTY_PATH: &str = "/dev/tty";
+
+/// Opens the terminal at `tty` to read answers to prompts from, so that stdin
+/// can carry data.
+///
+/// The terminal is opened if `force` is set, or if stdin is not a terminal.
+/// In the latter case, a terminal that cannot be opened is ignored and `None`
+/// is returned, so that prompts fall back to stdin.
+pub fn prompt_tty(
+    force: bool,
+    stdin_is_terminal: bool,
+    tty: &Path,
+) -> io::Result<Option<File>> {
+    if !cfg!(unix) {
+        return match force {
+            true => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "prompting from the terminal is only supported on Unix",
+            )),
+            false => Ok(None),
+        };
+    }
+
+    if force {
+        File::open(tty).map(Some)
+    } else if !stdin_is_terminal {
+        Ok(File::open(tty).ok())
+    } else {
+        Ok(None)
+    }
+}
+
+/// Creates a new file at `path` holding `contents`, failing if it exists.
+///
+/// On Unix, the file is only readable by its owner.
+pub fn create_private(
+    path: &Path,
+    contents: impl AsRef<[u8]>,
+) -> Result<(), Error> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(contents.as_ref())?;
+    Ok(())
+}
+
+/// Adds `line` to the end of the file at `path`, creating the file as with
+/// [`create_private`] if it does not exist.
+///
+/// Returns `false` without changing the file if it already has the line.
+pub fn append_line(path: &Path, line: &str) -> Result<bool, Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            create_private(path, format!("{}\n", line))?;
+            return Ok(true);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if contents.lines().any(|existing| existing.trim() == line) {
+        return Ok(false);
+    }
+
+    let separator = match contents.is_empty() || contents.ends_with('\n') {
+        true => "",
+        false => "\n",
+    };
+    let mut file = OpenOptions::new().append(true).open(path)?;
+    writeln!(file, "{}{}", separator, line)?;
+    Ok(true)
+}
+
+/// Opens a file to write command output to.
+///
+/// On Unix, a newly created file is only readable by its owner. If secrets are
+/// shown, an existing file that is readable by other users is rejected.
+pub fn open_output(
+    path: &Path,
+    secret_visibility: SecretVisibility,
+) -> Result<File, Error> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        options.mode(0o600);
+
+        if secret_visibility == SecretVisibility::Show && path.exists() {
+            let mode = path.metadata()?.permissions().mode();
+            if mode & 0o077 != 0 {
+                return Err(Error::InsecureOutput(path.to_path_buf()));
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = secret_visibility;
+
+    Ok(options.open(path)?)
+}
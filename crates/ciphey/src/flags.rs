@@ -1,29 +1,111 @@
 use std::ffi::OsString;
 use std::path::PathBuf;
 
+use ciphey_kvstore::{MergeStrategy, Redaction};
+
+use crate::cli::ErrorFormat;
+
 xflags::xflags! {
     /// A password manager based on age cryptography.
     cmd ciphey {
         /// Show help message
         optional -h, --help
-        /// The path to the ciphey store, defaults to ~/.local/share/ciphey
+        /// The path to the ciphey store, defaults to $CIPHEY_STORE or
+        /// ~/.local/share/ciphey
         optional -p, --path path: PathBuf
-        /// Display secret data in output.
+        /// Display secret data in output. Defaults to $CIPHEY_SHOW.
         optional --show
+        /// Log diagnostics to stderr, repeat for more detail (e.g. -vv).
+        repeated -v, --verbose
+        /// An age identity file to decrypt with. Can be repeated. Defaults to
+        /// $CIPHEY_IDENTITY, $RAGE_IDENTITY, $AGE_IDENTITY, or
+        /// ~/.config/rage/keys.txt.
+        repeated --age-keyfile path: PathBuf
+        /// Read answers to prompts from /dev/tty rather than stdin. Enabled
+        /// automatically if stdin is not a terminal and /dev/tty is available.
+        optional --prompt-from-tty
+        /// How to report errors: `human` (the default) or `json`.
+        optional --error-format format: ErrorFormat
+        /// Retry reading the store this many times after a transient error,
+        /// e.g. on a network filesystem. Defaults to 0.
+        optional --io-retries retries: u32
+        /// Milliseconds to wait before the first retry, doubled after each
+        /// retry. Defaults to 100.
+        optional --io-retry-delay delay: u64
         /// Initializes a store at the provided path or the ciphey default
-        cmd init {}
+        cmd init {
+            /// Also create an empty `.recipients` file and a commented
+            /// `config.toml` in the store.
+            optional --scaffold
+            /// Generate a new age identity into the store's `identities` file,
+            /// and add its recipient to `.recipients`.
+            optional --generate-key
+            /// Initialize the store even if it already exists. Existing files
+            /// are never overwritten.
+            optional --force
+        }
         /// Create a new password entry
         cmd new {
             /// The name of the entry.
             optional -n, --name name: OsString
-            /// Additional recipients who can access the entry.
+            /// Additional recipients who can access the entry, or `@<alias>`
+            /// for a recipient in the store's aliases file.
             repeated -r, --recipient recipients: OsString
+            /// Also encrypt to the recipients in an age recipients file, in the
+            /// format of `age -R`. Can be repeated.
+            repeated --age-recipients-file path: PathBuf
+            /// Also encrypt to the recipients of the loaded identities.
+            optional --recipient-self
+            /// Do not encrypt to the recipients of the team's `.age-recipients`
+            /// file, which is otherwise used if no recipients are passed. The
+            /// file is looked for from the store up to its git repository's
+            /// root.
+            optional --no-team-recipients
+            /// Encrypt the entry even if none of the recipients belong to the
+            /// loaded identities, so that it cannot be decrypted here.
+            optional --allow-lockout
+            /// Read additional recipients from stdin, one per line.
+            /// Requires --name, since the name prompt also reads stdin.
+            optional --recipients-stdin
+            /// Print the fingerprint of each recipient before writing.
+            optional --recipient-fingerprint
+            /// Abort unless every recipient has one of the fingerprints, as
+            /// printed by --recipient-fingerprint. Can be repeated.
+            repeated --expect-fingerprint fingerprint: String
             /// Add additional key/value pairs to the entry.
             /// Key and value are split by the first equals sign.
             /// EXAMPLE: ciphey new -k email=user@example.com
             repeated -k, --key pair: OsString
             /// Optionally pass entry secret in via command line.
             optional -s, --secret secret: OsString
+            /// The key to store the secret under, such as `password` or
+            /// `api_token`. Defaults to `secret`. With --batch, each record
+            /// holds its secret under this key.
+            optional --secret-key key: OsString
+            /// Echo a `*` for each character typed at the secret prompt,
+            /// instead of nothing. Input that is not from a terminal is read
+            /// hidden as usual.
+            optional --mask-input
+            /// Give up on a prompt that is not answered within this many
+            /// seconds.
+            optional --prompt-timeout secs: u64
+            /// The UUID of the entry. Defaults to a random UUID.
+            optional -u, --uuid uuid: OsString
+            /// Create an entry for each line of stdin. Each line holds
+            /// key=value pairs separated by semicolons or tabs, and must
+            /// include a name and a secret.
+            /// EXAMPLE: name=email;secret=hunter2;username=user
+            optional --batch
+            /// Read the whole entry from stdin, in the key/value format, instead
+            /// of prompting for its fields. The entry must have a name.
+            optional --raw-stdin
+            /// Trim and lowercase the keys of --key and --batch pairs, and
+            /// replace aliases such as `user` with the key in the config's
+            /// `key_aliases`, such as `username`.
+            optional --normalize-keys
+            /// Reject keys other than the well-known ones, the keys ciphey
+            /// uses, and the config's `allowed_keys`, such as to catch typos.
+            optional --strict-keys
         }
         /// Lists the name and username of each entry.
         /// By default, shows 'name', 'username', 'email', and 'url'.
@@ -35,20 +117,299 @@ xflags::xflags! {
             /// Also display values for the provided key.
             /// EXAMPLE: ciphey list --display tags
             repeated -d, --display key: OsString
+            /// Also display values for each key in a comma-separated list.
+            /// EXAMPLE: ciphey list --fields name,username,url
+            repeated --fields keys: OsString
             // TODO: should this become a ciphey-wide command?
             /// Only display explicitly requested output. Useful for scripts.
             optional --quiet
+            /// Only display sensitive fields, such as to check which fields
+            /// are secret. Combine with --all to display every sensitive
+            /// field.
+            optional --only-sensitive
+            /// How to redact hidden secrets: `matched` (the default) shows a
+            /// `*` per byte, up to 16, `fixed:<count>` always shows that many
+            /// `*`s, and `placeholder[:<text>]` shows `<hidden>` or the text.
+            optional --redaction style: Redaction
+            /// The character to mask hidden secrets with. Defaults to `*`.
+            optional --mask-char char: char
+            /// Display fields in the order of this comma-separated list of
+            /// keys. Other fields follow in their stored order.
+            /// EXAMPLE: ciphey list --field-order name,username,password
+            optional --field-order keys: OsString
+            /// Only list entries with this tag. If repeated, entries must have
+            /// every tag.
+            repeated --tag tag: OsString
+            /// List entries with any of the --tag tags, instead of all of them.
+            optional --any-tag
+            /// Only list entries modified at or after this date, such as
+            /// 2024-01-01 or 2024-01-01T12:00:00+02:00. Bare dates are in UTC.
+            optional --since date: OsString
+            /// Only list entries modified at or before this date. A bare date
+            /// includes the whole day.
+            optional --until date: OsString
+            /// Also list entries whose modification time is unknown when
+            /// filtering by date.
+            optional --include-undated
+            /// Decrypt entries across the provided number of threads.
+            optional -j, --jobs jobs: usize
+            /// Write the entries to the provided file instead of stdout.
+            optional -o, --output path: PathBuf
+            /// Only print the UUID and name of each entry, separated by NUL
+            /// characters instead of newlines. Useful with `xargs -0`.
+            optional --print0
+            /// Only print the name of each entry, one per line. Useful with
+            /// fuzzy finders such as `fzf`.
+            optional --names-only
+            /// Exit with code 3 if no entries are listed, after writing the
+            /// output as usual. Useful for scripts to detect an empty result.
+            optional --fail-empty
+            /// The line written between entries. Defaults to `---`.
+            optional --separator separator: OsString
+            /// Also write the separator before the first entry when nothing
+            /// precedes it, such as with --quiet.
+            optional --leading-separator
+            /// List at most this many entries, after skipping --offset
+            /// entries. Entries are in a stable order, so pages do not
+            /// overlap.
+            optional --max-entries count: usize
+            /// Skip this many entries before listing any.
+            optional --offset count: usize
+        }
+        /// Shows a single field of the entry with the provided name.
+        /// By default, shows the 'secret' field.
+        cmd show
+            /// The name of the entry.
+            optional name: OsString
+        {
+            /// The key of the field to show.
+            optional -k, --key key: OsString
+            /// The key that the secret was stored under with
+            /// `new --secret-key`. Defaults to `secret`.
+            optional --secret-key key: OsString
+            /// Print only the value, without the key or a trailing newline.
+            optional --raw
+            /// Show the entry whose name matches this glob pattern, such as
+            /// 'aws-*' or '*@example.com', instead of passing a name.
+            optional --glob pattern: OsString
+            /// Show every entry that matches the --glob pattern, instead of
+            /// requiring a single match.
+            optional --all
+            /// How to redact a hidden secret, as in `list --redaction`.
+            optional --redaction style: Redaction
+            /// The character to mask a hidden secret with, as in
+            /// `list --mask-char`.
+            optional --mask-char char: char
+            /// Pick the entry interactively if no name is given, with `fzf`
+            /// if it is installed, or else from a numbered menu.
+            optional --select
+        }
+        /// Copies a single field of the entry with the provided name to the
+        /// clipboard, and clears the clipboard after a timeout, or once the
+        /// process is interrupted. By default, copies the 'secret' field.
+        cmd copy
+            /// The name of the entry.
+            optional name: OsString
+        {
+            /// The key of the field to copy.
+            optional -k, --key key: OsString
+            /// Copy from the entry whose name matches this glob pattern, as
+            /// in `show --glob`, instead of passing a name.
+            optional --glob pattern: OsString
+            /// The key that the secret was stored under with
+            /// `new --secret-key`. Defaults to `secret`.
+            optional --secret-key key: OsString
+            /// The number of seconds to wait before clearing the clipboard.
+            /// Defaults to 45.
+            optional --timeout seconds: u64
+            /// Exit right after copying, without clearing the clipboard.
+            optional --no-wait
+        }
+        /// Re-encrypts an entry to an updated set of recipients.
+        cmd reshare
+            /// The name of the entry. Omit with --all.
+            optional name: OsString
+        {
+            /// Re-encrypt every entry. Every entry is decrypted before any is
+            /// written, so that a failure leaves the store unchanged.
+            optional --all
+            /// With --all, re-encrypt every entry that can be, skip the rest,
+            /// and report the skipped entries at the end.
+            optional --keep-going
+            /// Recipients to add to the entry.
+            repeated -r, --recipient recipients: OsString
+            /// Add the recipients in an age recipients file, in the format of
+            /// `age -R`. Can be repeated.
+            repeated --age-recipients-file path: PathBuf
+            /// Recipients to remove from the entry.
+            repeated --remove recipients: OsString
+            /// Use the entry's `recipients` field as its current recipients.
+            optional --recipients-from-entry
+            /// Re-encrypt to only the added recipients, dropping the current
+            /// ones. Required if the current recipients cannot be determined,
+            /// as with age.
+            optional --replace
+            /// Re-encrypt the entry even if none of the recipients belong to
+            /// the loaded identities, so that it cannot be decrypted here.
+            optional --allow-lockout
+            /// Print the fingerprint of each recipient before writing.
+            optional --recipient-fingerprint
+            /// Abort unless every recipient has one of the fingerprints, as
+            /// printed by --recipient-fingerprint. Can be repeated.
+            repeated --expect-fingerprint fingerprint: String
+            /// With --all, do not show progress.
+            optional --quiet
+        }
+        /// Marks a field of an entry as sensitive, or as insensitive.
+        cmd set-sensitive
+            /// The name of the entry.
+            required name: OsString
+            /// The key of the field.
+            required key: OsString
+        {
+            /// Mark the field as insensitive instead.
+            optional --insensitive
+            /// Recipients to re-encrypt the entry to, in addition to its
+            /// current recipients.
+            repeated -r, --recipient recipients: OsString
+            /// Re-encrypt to only the recipients passed with -r, dropping the
+            /// current ones. Required if the current recipients cannot be
+            /// determined, as with age.
+            optional --replace
+        }
+        /// Writes the raw decrypted contents of an entry to stdout.
+        cmd cat
+            /// The name of the entry.
+            optional name: OsString
+        {
+            /// Select the entry by UUID instead of by name. Required for
+            /// entries that are not in the key/value format.
+            optional -u, --uuid uuid: OsString
+            /// Write to stdout even if it is a terminal.
+            optional --force
+            /// The number of bytes to read from storage at a time. Larger
+            /// buffers can speed up large entries. Defaults to 64 KiB.
+            optional --reader-buffer-size bytes: usize
+        }
+        /// Moves an entry to another store, re-encrypting it to the
+        /// destination store's recipients.
+        cmd mv
+            /// The name of the entry.
+            required name: OsString
+        {
+            /// The path to the destination store.
+            required --to dest: PathBuf
+            /// Move the entry even if none of the destination's recipients
+            /// belong to the loaded identities, so that it cannot be decrypted
+            /// here.
+            optional --allow-lockout
+            /// Print the fingerprint of each recipient before writing.
+            optional --recipient-fingerprint
+            /// Abort unless every recipient has one of the fingerprints, as
+            /// printed by --recipient-fingerprint. Can be repeated.
+            repeated --expect-fingerprint fingerprint: String
+        }
+        /// Merges the fields of two entries into a new entry.
+        cmd merge
+            /// The name of the first entry.
+            required a: OsString
+            /// The name of the second entry.
+            required b: OsString
+        {
+            /// How to resolve keys that both entries contain: `keep-self`
+            /// keeps the first entry's value (the default), `keep-other` keeps
+            /// the second entry's value, and `keep-both` keeps both values.
+            optional --strategy strategy: MergeStrategy
+            /// Recipients to encrypt the merged entry to, in addition to the
+            /// recipients of both entries if they can be determined.
+            repeated -r, --recipient recipients: OsString
+            /// Remove the original entries after merging.
+            optional --remove
+        }
+        /// Removes every entry from the store, after typing "purge" to confirm.
+        ///
+        /// Entry files are deleted, not overwritten, so their encrypted
+        /// contents may remain recoverable from the disk.
+        cmd purge {
+            /// Do not ask for confirmation.
+            optional --force
+        }
+        /// Removes every entry that matches the provided filters, after
+        /// confirming.
+        cmd remove {
+            /// Only remove entries with this key/value pair. A `tags` pair
+            /// matches entries with each of its tags, as with `list --tag`.
+            /// EXAMPLE: ciphey remove --filter tags=old
+            repeated --filter pair: OsString
+            /// Only remove entries whose name matches this glob pattern.
+            optional --glob pattern: OsString
+            /// Do not ask for confirmation.
+            optional --force
+        }
+        /// Rebuilds the index used to look up entries by name.
+        cmd reindex {
+            /// Do not show progress.
+            optional --quiet
+        }
+        /// Checks the store for files that are not valid entries.
+        cmd doctor {
+            /// Offer to repair each problem that can be repaired, such as by
+            /// renaming an entry with the wrong extension.
+            optional --fix
+            /// Repair problems without asking, except for those that remove
+            /// files, which are always confirmed.
+            optional --yes
+        }
+        /// Upgrades every entry to the current format version, after backing
+        /// up the encrypted entries, and records the version in the store's
+        /// `VERSION` file. Does nothing if the store is up to date.
+        cmd migrate {
+            /// Print the format version of the store and the current format
+            /// version, without migrating.
+            optional --store-format-version
+            /// Recipients to re-encrypt entries to if their current
+            /// recipients cannot be determined.
+            repeated -r, --recipient recipients: OsString
+        }
+        /// Checks that every entry can be decrypted and parsed.
+        cmd verify {
+            /// Do not show progress.
+            optional --quiet
+        }
+        /// Caches the passphrase of passphrase-encrypted entries in memory,
+        /// like ssh-agent, so that it is only entered once per session. Prints
+        /// the $CIPHEY_AGENT_SOCK variable to export, then runs until stopped.
+        /// The passphrase is never written to disk.
+        ///
+        /// EXAMPLE: ciphey agent --socket ~/.ciphey.sock &
+        cmd agent {
+            /// The path of the socket to listen on. Defaults to a socket in a
+            /// new private directory in the temporary directory.
+            optional --socket path: PathBuf
+            /// Stop the agent at --socket or $CIPHEY_AGENT_SOCK, wiping the
+            /// cached passphrase.
+            optional --stop
+        }
+        /// Reports groups of entries that share a secret or password, by name
+        /// and UUID. The secrets themselves are never shown.
+        cmd audit {
+            /// Do not show progress.
+            optional --quiet
         }
         default cmd help {}
     }
 }
 
 pub mod util {
+    use std::collections::HashSet;
     use std::ffi::OsString;
     use std::str::FromStr;
+    use std::time::{Duration, SystemTime};
 
-    use ciphey_kvstore::KeyValuePair;
+    use chrono::{DateTime, NaiveDate};
+    use ciphey_kvstore::{Key, KeyValuePair};
     use libciphey::crypto::Recipient;
+    use uuid::Uuid;
     use xflags::Error;
 
     use crate::cli;
@@ -63,10 +424,14 @@ pub mod util {
     }
 
     /// Parses a list of recipients passed in as command line arguments.
+    ///
+    /// Duplicate recipients are removed, keeping the order in which each
+    /// recipient first appears.
     pub fn parse_recipients<R>(recipients: &[OsString]) -> Result<Vec<R>, Error>
     where
         R: Recipient,
     {
+        let mut seen: HashSet<&str> = HashSet::new();
         let mut parsed_recipients: Vec<R> = Vec::new();
         for recipient in recipients {
             // Parse OsString as Rust String
@@ -75,6 +440,11 @@ pub mod util {
                 "Recipient contains invalid characters",
             )?;
 
+            // `Recipient` does not require `Eq`, so compare the strings
+            if !seen.insert(recipient) {
+                continue;
+            }
+
             // Parse recipient String as a Recipient for the crypto backend
             let recipient = recipient
                 .to_string()
@@ -88,6 +458,59 @@ pub mod util {
         Ok(parsed_recipients)
     }
 
+    /// Parses a UUID passed in as a command line argument.
+    pub fn parse_uuid(uuid: &OsString) -> Result<Uuid, Error> {
+        let uuid = parse_os_str(uuid, "Invalid UUID")?;
+        Uuid::from_str(uuid)
+            .map_err(|_| Error::new(format!("Invalid UUID: {}", uuid)))
+    }
+
+    /// Parses a date passed in as a command line argument, either in RFC 3339
+    /// format or as a bare `YYYY-MM-DD` date in UTC.
+    ///
+    /// A bare date is the start of the day, or its last moment if
+    /// `end_of_day`, so that a range ending on the date includes the whole
+    /// day.
+    pub fn parse_date(
+        date: &OsString,
+        end_of_day: bool,
+    ) -> Result<SystemTime, Error> {
+        let date = parse_os_str(date, "Invalid date")?;
+
+        if let Ok(time) = DateTime::parse_from_rfc3339(date) {
+            return Ok(SystemTime::from(time));
+        }
+
+        let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| Error::new(format!("Invalid date: {}", date)))?;
+        let start =
+            SystemTime::from(day.and_time(Default::default()).and_utc());
+
+        Ok(match end_of_day {
+            true => {
+                start + Duration::from_secs(24 * 60 * 60)
+                    - Duration::from_nanos(1)
+            }
+            false => start,
+        })
+    }
+
+    /// Parses a comma-separated list of keys, such as `name, username`.
+    ///
+    /// Whitespace around each key is ignored, but empty keys are rejected.
+    pub fn parse_field_list(fields: &OsString) -> Result<Vec<Key>, Error> {
+        let fields = parse_os_str(fields, "Invalid characters in fields")?;
+
+        fields
+            .split(',')
+            .map(str::trim)
+            .map(|field| match field {
+                "" => Err(Error::new(format!("Empty field in: {}", fields))),
+                field => Ok(Key::from(field)),
+            })
+            .collect()
+    }
+
     pub fn parse_key_value_pairs(
         key_value_pairs: &[OsString],
     ) -> Result<Vec<KeyValuePair>, cli::Error> {
@@ -105,10 +528,7 @@ pub mod util {
             .into_iter()
             .map(KeyValuePair::from_str)
             .collect::<Result<Vec<_>, _>>()
-            .map_err(Box::new)
-            .map_err(|e| {
-                cli::Error::Filetype(e as Box<dyn std::error::Error>)
-            })?;
+            .map_err(|e| cli::Error::Filetype(Box::new(e)))?;
 
         Ok(key_value_pairs)
     }
@@ -1,11 +1,23 @@
 #![feature(generic_associated_types, io_error_more)]
-use std::io::{stdin, stdout, BufReader};
+use std::env;
+use std::ffi::OsString;
+use std::io::{
+    self, stderr, stdin, stdout, BufRead, BufReader, IsTerminal, Read, Write,
+};
+use std::path::{Path, PathBuf};
+use std::process::{ExitCode, Termination};
+use std::sync::mpsc;
+use std::time::Duration;
 
-use cli::{defaults, SecretVisibility};
+use ::age::secrecy::SecretString;
+use cli::util::TimeoutReader;
+use cli::{ErrorFormat, NameIndex};
 use flags::Ciphey;
+use libciphey::storage::retry::Retrying;
 use libciphey_fs::Filesystem;
+use tracing::Level;
 
-use crate::backends::crypto::transparent::Transparent;
+use crate::backends::crypto::age::{self, Age};
 
 #[cfg(test)]
 pub mod tests;
@@ -14,53 +26,378 @@ mod backends;
 mod cli;
 mod flags;
 
-fn main() -> Result<(), cli::Error> {
+/// The environment that a command runs in. `main` runs commands in the
+/// environment of the process, and tests in one that they control.
+struct Env<'a> {
+    /// Looks up an environment variable.
+    var: &'a dyn Fn(&str) -> Option<OsString>,
+    /// Opens stdin, which each command does at most once.
+    stdin: &'a dyn Fn() -> Box<dyn Read + Send>,
+    stdin_is_terminal: bool,
+    stdout: &'a mut dyn Write,
+    stdout_is_terminal: bool,
+    /// The terminal that prompts are read from if stdin is not a terminal.
+    tty: &'a Path,
+}
+
+fn main() -> ExitCode {
+    let var = |name: &str| env::var_os(name);
+    let open_stdin = || -> Box<dyn Read + Send> { Box::new(stdin()) };
+    let mut output = stdout();
+    let env = Env {
+        var: &var,
+        stdin: &open_stdin,
+        stdin_is_terminal: stdin().is_terminal(),
+        stdout_is_terminal: output.is_terminal(),
+        stdout: &mut output,
+        tty: Path::new(cli::util::TTY_PATH),
+    };
+
     // Parse arguments into generated xflags structs
-    let args = Ciphey::from_env()?;
+    let (error_format, result) = match Ciphey::from_env() {
+        Ok(args) => (args.error_format.unwrap_or_default(), run(args, env)),
+        Err(err) => (ErrorFormat::default(), Err(cli::Error::Xflags(err))),
+    };
 
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            // Nothing more can be reported if stderr cannot be written to.
+            let _ = err.report_to(error_format, &mut stderr());
+            err.report()
+        }
+    }
+}
+
+fn run(args: Ciphey, env: Env) -> Result<(), cli::Error> {
     // Check for help flag
     if args.help {
         cli::help();
         return Ok(());
     }
 
-    // Indicates whether to show or hide secret material in the output
-    let visibility = if args.show {
-        SecretVisibility::Show
-    } else {
-        SecretVisibility::default()
-    };
+    init_logging(args.verbose);
 
-    // The provided path to the ciphey store. If no path was provided, the
-    // default path will be used.
-    let store_path = args.path.unwrap_or_else(defaults::store_dir);
+    // The store, identities and visibility, from the flags, the environment
+    // or the defaults. The config of the store is only loaded by the commands
+    // that use it.
+    let settings = cli::config::resolve(&args, env.var)?;
+    let store_path = settings.store.clone();
+    let visibility = settings.visibility;
 
-    // TODO: Add mechanism for detecting/choosing crypto algorithm
-    let crypto = Transparent {};
-    let mut storage = Filesystem::new(&store_path)?;
+    let identities = age::load_identities(&settings.identity_files)
+        .map_err(|e| cli::Error::Crypto(Box::new(e)))?;
+    let crypto =
+        Age::new(identities).with_passphrase(passphrase_source(env.var));
+    let retries = args.io_retries.unwrap_or(0);
+    let retry_delay = Duration::from_millis(args.io_retry_delay.unwrap_or(100));
+    let retrying = |storage| Retrying::new(storage, retries, retry_delay);
 
-    let mut output = stdout();
-    let input = stdin();
-    let mut input = BufReader::new(input);
+    let mut storage = retrying(Filesystem::new(&store_path)?);
+    let index_path = NameIndex::path(&store_path);
+    let mut index = NameIndex::load(index_path.clone())?;
+
+    let mut output = env.stdout;
+
+    // Recipients may be passed as `@<alias>`.
+    let aliases = || cli::Aliases::load(&cli::Aliases::path(&store_path));
+
+    let prompt_from_tty = args.prompt_from_tty;
+    let prompt_tty = || {
+        cli::util::prompt_tty(prompt_from_tty, env.stdin_is_terminal, env.tty)
+    };
+    // Answers to prompts, from the terminal if it was opened.
+    let prompt_input = || -> io::Result<Box<dyn BufRead>> {
+        Ok(match prompt_tty()? {
+            Some(tty) => Box::new(BufReader::new(tty)),
+            None => Box::new(BufReader::new((env.stdin)())),
+        })
+    };
 
     match args.subcommand {
         flags::CipheyCmd::Help(_) => {
             cli::help();
             Ok(())
         }
-        flags::CipheyCmd::Init(..) => {
-            cli::init(&mut storage)?;
-            println!(
+        flags::CipheyCmd::Init(opts) => {
+            cli::init(&mut storage, opts.force)?;
+            cli::scaffold(
+                &opts,
+                &store_path,
+                age::generate_identity,
+                &mut output,
+            )?;
+            writeln!(
+                output,
                 "Successfully created vault at path: {}",
                 store_path.display()
-            );
+            )?;
             Ok(())
         }
-        flags::CipheyCmd::New(opts) => {
-            cli::new(&opts, &crypto, &mut storage, &mut input, &mut output)
+        flags::CipheyCmd::New(mut opts) => {
+            let config = settings.config()?;
+            add_age_recipients(&mut opts.recipient, &opts.age_recipients_file)?;
+            let team = match opts.no_team_recipients {
+                true => None,
+                false => cli::util::find_team_recipients(&store_path),
+            };
+            let team =
+                team.map(|path| read_age_recipients(&path)).transpose()?;
+            cli::default_new_recipients(
+                &mut opts,
+                team,
+                settings.default_recipients(&config),
+            );
+            opts.recipient = aliases()?.resolve(&opts.recipient)?;
+            let timeout = opts.prompt_timeout.map(Duration::from_secs);
+            // Batches and recipients are data rather than answers to prompts,
+            // so they are always read from stdin.
+            let input: Box<dyn Read + Send> = match prompt_tty()? {
+                Some(tty) if !opts.batch && !opts.recipients_stdin => {
+                    Box::new(tty)
+                }
+                _ => (env.stdin)(),
+            };
+            let mut input = TimeoutReader::new(input, timeout);
+            cli::new(
+                &opts,
+                &config,
+                &crypto,
+                &mut storage,
+                index.as_mut(),
+                &mut input,
+                &mut output,
+                &mut stderr(),
+            )
+        }
+        flags::CipheyCmd::List(opts) => {
+            let config = settings.config()?;
+            match &opts.output {
+                Some(path) => {
+                    let mut file = cli::util::open_output(path, visibility)?;
+                    cli::list(
+                        &opts,
+                        visibility,
+                        &config,
+                        &crypto,
+                        &mut storage,
+                        &mut file,
+                        &mut stderr(),
+                    )
+                }
+                None => cli::list(
+                    &opts,
+                    visibility,
+                    &config,
+                    &crypto,
+                    &mut storage,
+                    &mut output,
+                    &mut stderr(),
+                ),
+            }
+        }
+        flags::CipheyCmd::Reshare(mut opts) => {
+            add_age_recipients(&mut opts.recipient, &opts.age_recipients_file)?;
+            let aliases = aliases()?;
+            opts.recipient = aliases.resolve(&opts.recipient)?;
+            opts.remove = aliases.resolve(&opts.remove)?;
+            let index = index.as_ref();
+            cli::util::with_progress(opts.quiet || !opts.all, |progress| {
+                cli::reshare(
+                    &opts,
+                    &crypto,
+                    &mut storage,
+                    index,
+                    progress,
+                    &mut output,
+                )
+            })
+        }
+        flags::CipheyCmd::Mv(opts) => {
+            let mut destination = retrying(Filesystem::new(&opts.to)?);
+            cli::mv(
+                &opts,
+                &crypto,
+                &mut storage,
+                index.as_mut(),
+                &mut destination,
+                &mut output,
+            )
+        }
+        flags::CipheyCmd::Reindex(opts) => {
+            let mut index = index.unwrap_or_else(|| NameIndex::new(index_path));
+            cli::util::with_progress(opts.quiet, |progress| {
+                cli::reindex(
+                    &crypto,
+                    &storage,
+                    &mut index,
+                    progress,
+                    &mut output,
+                )
+            })
+        }
+        flags::CipheyCmd::Purge(opts) => cli::purge(
+            &opts,
+            &mut storage,
+            index.as_mut(),
+            &mut prompt_input()?,
+            &mut output,
+        ),
+        flags::CipheyCmd::Merge(mut opts) => {
+            opts.recipient = aliases()?.resolve(&opts.recipient)?;
+            cli::merge(
+                &opts,
+                &crypto,
+                &mut storage,
+                index.as_mut(),
+                &mut output,
+            )
+        }
+        flags::CipheyCmd::Remove(opts) => cli::remove(
+            &opts,
+            &crypto,
+            &mut storage,
+            index.as_mut(),
+            &mut prompt_input()?,
+            &mut output,
+        ),
+        flags::CipheyCmd::Doctor(opts) => cli::doctor(
+            &opts,
+            settings.config().err(),
+            &mut storage,
+            &mut prompt_input()?,
+            &mut output,
+        ),
+        flags::CipheyCmd::Verify(opts) => {
+            cli::util::with_progress(opts.quiet, |progress| {
+                cli::verify(&crypto, &storage, progress, &mut output)
+            })
         }
-        flags::CipheyCmd::List(mut opts) => {
-            cli::list(&mut opts, visibility, &crypto, &mut storage, &mut output)
+        flags::CipheyCmd::Audit(opts) => {
+            cli::util::with_progress(opts.quiet, |progress| {
+                cli::audit(&crypto, &storage, progress, &mut output)
+            })
         }
+        flags::CipheyCmd::SetSensitive(mut opts) => {
+            opts.recipient = aliases()?.resolve(&opts.recipient)?;
+            let index = index.as_ref();
+            cli::set_sensitive(&opts, &crypto, &mut storage, index, &mut output)
+        }
+        flags::CipheyCmd::Migrate(mut opts) => {
+            opts.recipient = aliases()?.resolve(&opts.recipient)?;
+            cli::migrate::migrate(
+                &opts,
+                &crypto,
+                &mut storage,
+                &store_path,
+                &mut output,
+            )
+        }
+        flags::CipheyCmd::Cat(opts) => {
+            let index = index.as_ref();
+            let is_terminal = env.stdout_is_terminal;
+            cli::cat(&opts, &crypto, &storage, index, &mut output, is_terminal)
+        }
+        #[cfg(unix)]
+        flags::CipheyCmd::Agent(opts) => {
+            let socket = (env.var)(cli::agent::SOCK_VAR).map(PathBuf::from);
+            cli::agent::agent(&opts, socket, &mut output)
+        }
+        #[cfg(not(unix))]
+        flags::CipheyCmd::Agent(_) => {
+            Err(xflags::Error::new("the agent requires unix sockets").into())
+        }
+        flags::CipheyCmd::Copy(opts) => {
+            // SIGINT, SIGTERM and SIGHUP end the wait early, so that the
+            // clipboard is still cleared when the terminal is closed.
+            let (interrupt, interrupted) = mpsc::channel();
+            ctrlc::set_handler(move || {
+                let _ = interrupt.send(());
+            })
+            .map_err(|e| cli::Error::SignalHandler(io::Error::other(e)))?;
+
+            let mut clipboard = cli::clipboard::SystemClipboard::default();
+            cli::copy(
+                &opts,
+                &crypto,
+                &storage,
+                index.as_ref(),
+                &mut clipboard,
+                &interrupted,
+                &mut output,
+            )
+        }
+        flags::CipheyCmd::Show(mut opts) => {
+            if opts.select && opts.name.is_none() && opts.glob.is_none() {
+                opts.name = Some(cli::select_name(
+                    &crypto,
+                    &storage,
+                    env.stdout_is_terminal,
+                    &mut prompt_input()?,
+                    &mut stderr(),
+                )?);
+            }
+            let index = index.as_ref();
+            cli::show(&opts, visibility, &crypto, &storage, index, &mut output)
+        }
+    }
+}
+
+/// Prompts for the passphrase of passphrase-encrypted entries, asking the
+/// agent at $CIPHEY_AGENT_SOCK, looked up with `var`, for it first if one is
+/// running.
+fn passphrase_source(
+    var: &dyn Fn(&str) -> Option<OsString>,
+) -> Box<dyn age::PassphraseSource> {
+    let prompt =
+        || rpassword::prompt_password("Passphrase: ").map(SecretString::new);
+
+    #[cfg(unix)]
+    let source = {
+        let socket = var(cli::agent::SOCK_VAR).map(PathBuf::from);
+        cli::agent::AgentPassphrase::new(socket, prompt)
+    };
+    #[cfg(not(unix))]
+    let source = {
+        let _ = var;
+        prompt
+    };
+
+    Box::new(source)
+}
+
+/// Adds the recipients in age recipients files to the recipients passed on the
+/// command line.
+fn add_age_recipients(
+    recipients: &mut Vec<OsString>,
+    files: &[PathBuf],
+) -> Result<(), cli::Error> {
+    for file in files {
+        recipients.extend(read_age_recipients(file)?);
     }
+
+    Ok(())
+}
+
+/// Reads the recipients in an age recipients file.
+fn read_age_recipients(path: &Path) -> Result<Vec<OsString>, cli::Error> {
+    let recipients = age::read_recipients_file(path)
+        .map_err(|e| cli::Error::Crypto(Box::new(e)))?;
+    Ok(recipients.into_iter().map(OsString::from).collect())
+}
+
+/// Initializes a subscriber that logs to stderr. The level of detail is
+/// determined by the number of times the verbose flag was passed.
+fn init_logging(verbosity: u32) {
+    let level = match verbosity {
+        0 => return,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .init();
 }
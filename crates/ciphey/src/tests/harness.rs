@@ -0,0 +1,185 @@
+//! A harness that runs command lines through `run`, like `main` does, but with
+//! the environment, stdin and stdout injected, so that whole sessions can be
+//! tested without depending on the environment of the build or test process.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use super::temporary_path;
+use crate::backends::crypto::age;
+use crate::cli::config::{self, Settings};
+use crate::cli::{self, defaults};
+use crate::flags::Ciphey;
+use crate::Env;
+
+/// Runs commands in an environment where `$HOME` is a new temporary
+/// directory, with a new age identity that entries are encrypted to by
+/// default.
+pub struct Harness {
+    home: PathBuf,
+    vars: HashMap<String, OsString>,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        let home = temporary_path();
+        fs::create_dir(&home).unwrap();
+
+        let (identity, recipient) = age::generate_identity();
+        let identity_file = home.join("keys.txt");
+        fs::write(&identity_file, identity).unwrap();
+
+        let mut vars = HashMap::new();
+        vars.insert("HOME".to_string(), home.clone().into_os_string());
+        vars.insert(defaults::IDENTITY_VAR.to_string(), identity_file.into());
+        vars.insert(defaults::RECIPIENTS_VAR.to_string(), recipient.into());
+        Self { home, vars }
+    }
+
+    /// Returns the temporary home directory, even if `$HOME` was unset.
+    pub fn home(&self) -> &Path {
+        &self.home
+    }
+
+    /// Sets an environment variable for the commands that are run, or unsets
+    /// it if `value` is `None`.
+    pub fn set_var(&mut self, name: &str, value: Option<&str>) {
+        match value {
+            Some(value) => self.vars.insert(name.to_string(), value.into()),
+            None => self.vars.remove(name),
+        };
+    }
+
+    /// Resolves the settings of a command line, excluding the program name.
+    pub fn settings(&self, args: &[&str]) -> Result<Settings, cli::Error> {
+        config::resolve(&parse(args)?, |name| self.vars.get(name).cloned())
+    }
+
+    /// Runs a command line, excluding the program name, with `input` as
+    /// stdin, and returns what it wrote to stdout.
+    ///
+    /// Prompts are always read from `input`, since there is no terminal.
+    pub fn run(
+        &self,
+        args: &[&str],
+        input: &str,
+    ) -> Result<String, cli::Error> {
+        let var = |name: &str| self.vars.get(name).cloned();
+        let input = input.as_bytes().to_vec();
+        let open_stdin =
+            || -> Box<dyn Read + Send> { Box::new(Cursor::new(input.clone())) };
+        let tty = self.home.join("tty");
+        let mut output = Vec::new();
+
+        let env = Env {
+            var: &var,
+            stdin: &open_stdin,
+            stdin_is_terminal: false,
+            stdout: &mut output,
+            stdout_is_terminal: false,
+            tty: &tty,
+        };
+        crate::run(parse(args)?, env)?;
+
+        Ok(String::from_utf8(output).unwrap())
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.home);
+    }
+}
+
+// Parses the provided command line arguments, excluding the program name.
+fn parse(args: &[&str]) -> Result<Ciphey, xflags::Error> {
+    Ciphey::from_vec(args.iter().map(OsString::from).collect())
+}
+
+#[test]
+fn test_default_store_is_in_home() {
+    let mut harness = Harness::new();
+    let store = harness.home().join(".local/share/ciphey");
+    assert_eq!(harness.settings(&["list"]).unwrap().store, store);
+
+    // The store variable takes precedence over the home directory.
+    harness.set_var("CIPHEY_STORE", Some("/elsewhere"));
+    let settings = harness.settings(&["list"]).unwrap();
+    assert_eq!(settings.store, PathBuf::from("/elsewhere"));
+
+    // Without either, the store must be passed explicitly.
+    harness.set_var("CIPHEY_STORE", None);
+    harness.set_var("HOME", None);
+    let err = harness.settings(&["list"]).unwrap_err();
+    assert!(matches!(err, cli::Error::Xflags(_)));
+    assert!(err.to_string().contains("--path"));
+    let settings = harness.settings(&["--path", "/store", "list"]).unwrap();
+    assert_eq!(settings.store, PathBuf::from("/store"));
+}
+
+#[test]
+fn test_init_new_list_remove() {
+    let harness = Harness::new();
+    let store = harness.home().join(".local/share/ciphey");
+
+    // The store is created in the home directory.
+    let output = harness.run(&["init", "--scaffold"], "").unwrap();
+    let expected = format!(
+        "Created {}\nCreated {}\nSuccessfully created vault at path: {}\n",
+        store.join(".recipients").display(),
+        store.join("config.toml").display(),
+        store.display()
+    );
+    assert_eq!(output, expected);
+    assert!(store.join("entries").is_dir());
+
+    let err = harness.run(&["init"], "").unwrap_err();
+    assert!(matches!(err, cli::Error::Storage(_)));
+
+    // Each new entry is reported with the path of its file.
+    let entries = store.join("entries");
+    let args = [
+        "new",
+        "--name",
+        "example",
+        "--secret",
+        "hunter2",
+        "--key",
+        "username=alice",
+    ];
+    for args in [&args[..], &["new", "--name", "other", "--secret", "x"]] {
+        let output = harness.run(args, "").unwrap();
+        let path = output
+            .strip_prefix("Created new entry at path: ")
+            .and_then(|path| path.strip_suffix('\n'))
+            .map(PathBuf::from)
+            .unwrap();
+        assert_eq!(path.parent(), Some(entries.as_path()));
+        assert!(path.is_file());
+    }
+
+    // Entries are encrypted to the identity of the environment.
+    let output = harness.run(&["--show", "show", "example", "--raw"], "");
+    assert_eq!(output.unwrap(), "hunter2");
+
+    // The listing shows the default fields of every entry, and no secrets.
+    let output = harness.run(&["list"], "").unwrap();
+    assert!(output.starts_with("Found 2 Entries\n"));
+    assert!(output.contains("---\nname: example\nusername: alice\n"));
+    assert!(output.contains("---\nname: other\n"));
+    assert!(!output.contains("hunter2"));
+
+    // Removing asks for confirmation, and nothing is removed without it.
+    let remove = ["remove", "--filter", "name=example"];
+    let err = harness.run(&remove, "n\n").unwrap_err();
+    assert!(matches!(err, cli::Error::RemoveNotConfirmed));
+    let output = harness.run(&remove, "y\n").unwrap();
+    assert!(output.contains(" example\nRemove 1 entries? [y/N]: "));
+    assert!(output.ends_with("Removed 1 entries\n"));
+
+    let output = harness.run(&["list"], "").unwrap();
+    assert_eq!(output, "Found 1 Entry\n---\nname: other\n");
+}
@@ -1 +1,4119 @@
+//! These tests exercise the command line functions end to end against a
+//! temporary filesystem store and the transparent crypto backend.
 
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{self, empty, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fs, thread};
+
+use ciphey_kvstore::{Key, Value};
+use libciphey::crypto;
+use libciphey::storage::memory::InMemory;
+use libciphey::storage::{self, Backend, Reference};
+use libciphey_fs::Filesystem;
+use uuid::Uuid;
+
+use crate::backends::crypto::transparent::{self, Transparent};
+use crate::cli::{self, Config, SecretVisibility};
+use crate::flags::{
+    Cat, Ciphey, CipheyCmd, List, Mv, New, Purge, Remove, Reshare, Show,
+};
+
+mod harness;
+
+// Returns a pseudorandom alphanumeric string of length `len`.
+pub fn random_string(len: usize) -> String {
+    std::iter::repeat_with(fastrand::alphanumeric)
+        .take(len)
+        .collect()
+}
+
+// Gets a path to a new directory with a random file name in the system's
+// temporary directory.
+//
+// It does NOT create the directory on the filesystem, only generates the path.
+pub fn temporary_path() -> PathBuf {
+    let mut path = std::env::temp_dir();
+    path.extend(Some(random_string(32)));
+    path
+}
+
+// Creates and initializes a new store in a temporary directory.
+pub fn temporary_store() -> Filesystem {
+    let mut storage = Filesystem::new(&temporary_path()).unwrap();
+    cli::init(&mut storage, false).unwrap();
+    storage
+}
+
+// Parses the provided command line arguments, excluding the program name.
+pub fn parse_args(args: &[&str]) -> CipheyCmd {
+    let args = args.iter().map(OsString::from).collect();
+    Ciphey::from_vec(args).unwrap().subcommand
+}
+
+// Parses the provided arguments to the `new` command.
+pub fn new_opts(args: &[&str]) -> New {
+    match parse_args(&[&["new"], args].concat()) {
+        CipheyCmd::New(opts) => opts,
+        _ => unreachable!(),
+    }
+}
+
+// Parses the provided arguments to the `list` command.
+pub fn list_opts(args: &[&str]) -> List {
+    match parse_args(&[&["list"], args].concat()) {
+        CipheyCmd::List(opts) => opts,
+        _ => unreachable!(),
+    }
+}
+
+// Parses the provided arguments to the `cat` command.
+pub fn cat_opts(args: &[&str]) -> Cat {
+    match parse_args(&[&["cat"], args].concat()) {
+        CipheyCmd::Cat(opts) => opts,
+        _ => unreachable!(),
+    }
+}
+
+// Parses the provided arguments to the `mv` command.
+pub fn mv_opts(args: &[&str]) -> Mv {
+    match parse_args(&[&["mv"], args].concat()) {
+        CipheyCmd::Mv(opts) => opts,
+        _ => unreachable!(),
+    }
+}
+
+// Parses the provided arguments to the `purge` command.
+pub fn purge_opts(args: &[&str]) -> Purge {
+    match parse_args(&[&["purge"], args].concat()) {
+        CipheyCmd::Purge(opts) => opts,
+        _ => unreachable!(),
+    }
+}
+
+// Parses the provided arguments to the `remove` command.
+pub fn remove_opts(args: &[&str]) -> Remove {
+    match parse_args(&[&["remove"], args].concat()) {
+        CipheyCmd::Remove(opts) => opts,
+        _ => unreachable!(),
+    }
+}
+
+// Parses the provided arguments to the `reshare` command.
+pub fn reshare_opts(args: &[&str]) -> Reshare {
+    match parse_args(&[&["reshare"], args].concat()) {
+        CipheyCmd::Reshare(opts) => opts,
+        _ => unreachable!(),
+    }
+}
+
+// Returns the raw contents of every entry in the store, keyed by UUID.
+pub fn raw_entries<S: storage::Backend>(storage: &S) -> Vec<(Uuid, String)> {
+    let mut entries: Vec<(Uuid, String)> = storage
+        .entries()
+        .unwrap()
+        .into_iter()
+        .map(|(uuid, reference)| {
+            let mut data = String::new();
+            reference
+                .reader()
+                .unwrap()
+                .read_to_string(&mut data)
+                .unwrap();
+            (uuid, data)
+        })
+        .collect();
+    entries.sort();
+    entries
+}
+
+// Parses the provided arguments to the `show` command.
+pub fn show_opts(args: &[&str]) -> Show {
+    match parse_args(&[&["show"], args].concat()) {
+        CipheyCmd::Show(opts) => opts,
+        _ => unreachable!(),
+    }
+}
+
+// Parses the provided arguments to the `copy` command.
+pub fn copy_opts(args: &[&str]) -> crate::flags::Copy {
+    match parse_args(&[&["copy"], args].concat()) {
+        CipheyCmd::Copy(opts) => opts,
+        _ => unreachable!(),
+    }
+}
+
+// Parses the provided arguments to the `doctor` command.
+pub fn doctor_opts(args: &[&str]) -> crate::flags::Doctor {
+    match parse_args(&[&["doctor"], args].concat()) {
+        CipheyCmd::Doctor(opts) => opts,
+        _ => unreachable!(),
+    }
+}
+
+// Parses the provided arguments to the `migrate` command.
+pub fn migrate_opts(args: &[&str]) -> crate::flags::Migrate {
+    match parse_args(&[&["migrate"], args].concat()) {
+        CipheyCmd::Migrate(opts) => opts,
+        _ => unreachable!(),
+    }
+}
+
+// Adds an entry with the provided name, secret, and additional key/value
+// pairs to the store without prompting.
+pub fn add_entry<S: storage::Backend>(
+    storage: &mut S,
+    name: &str,
+    secret: &str,
+    keys: &[&str],
+) {
+    let mut args = vec!["--name", name, "--secret", secret];
+    for key in keys {
+        args.extend(["--key", key]);
+    }
+
+    let mut output = Vec::new();
+    cli::new(
+        &new_opts(&args),
+        &Config::default(),
+        &Transparent {},
+        storage,
+        None,
+        &mut empty(),
+        &mut output,
+        &mut io::sink(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_show_raw_has_no_trailing_newline() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "example", "hunter2", &[]);
+
+    let opts = show_opts(&["example", "--raw"]);
+    let mut output = Vec::new();
+    cli::show(
+        &opts,
+        SecretVisibility::Show,
+        &Transparent {},
+        &storage,
+        None,
+        &mut output,
+    )
+    .unwrap();
+
+    // Only the value itself should be written.
+    assert_eq!(output, b"hunter2");
+}
+
+#[test]
+fn test_show_raw_sensitive_requires_show() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "example", "hunter2", &[]);
+
+    let opts = show_opts(&["example", "--raw"]);
+    let mut output = Vec::new();
+    let err = cli::show(
+        &opts,
+        SecretVisibility::Hide,
+        &Transparent {},
+        &storage,
+        None,
+        &mut output,
+    )
+    .unwrap_err();
+
+    assert!(matches!(err, cli::Error::SecretHidden(_)));
+    assert!(output.is_empty());
+}
+
+#[test]
+fn test_show_field() {
+    let mut storage = temporary_store();
+    add_entry(
+        &mut storage,
+        "example",
+        "hunter2",
+        &["email=user@example.com"],
+    );
+
+    let opts = show_opts(&["example", "--key", "email"]);
+    let mut output = Vec::new();
+    cli::show(
+        &opts,
+        SecretVisibility::Hide,
+        &Transparent {},
+        &storage,
+        None,
+        &mut output,
+    )
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "email: user@example.com\n"
+    );
+}
+
+// A writer that appends to a shared buffer so that log output can be
+// inspected after it is written.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_verbose_does_not_alter_output() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "example", "hunter2", &["username=user"]);
+
+    let opts = list_opts(&["--all"]);
+
+    // List the entries without any logging enabled.
+    let mut expected = Vec::new();
+    cli::list(
+        &opts,
+        SecretVisibility::Show,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        &mut expected,
+        &mut io::sink(),
+    )
+    .unwrap();
+
+    // List the entries again with the most verbose logging enabled.
+    let logs = SharedBuffer::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_writer(move || writer.clone())
+        .finish();
+
+    let mut output = Vec::new();
+    tracing::subscriber::with_default(subscriber, || {
+        cli::list(
+            &opts,
+            SecretVisibility::Show,
+            &Config::default(),
+            &Transparent {},
+            &mut storage,
+            &mut output,
+            &mut io::sink(),
+        )
+    })
+    .unwrap();
+
+    // The output should be identical and the logs must not leak the secret.
+    assert_eq!(expected, output);
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(logs.contains("Reading entry"));
+    assert!(!logs.contains("hunter2"));
+}
+
+// A crypto backend that takes a fixed amount of time to decrypt each entry,
+// simulating expensive cryptography, and records the most decryptions that
+// were in progress at once.
+#[derive(Default)]
+struct Slow {
+    delay: Duration,
+    active: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl Slow {
+    fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            ..Default::default()
+        }
+    }
+}
+
+impl crypto::Backend for Slow {
+    type Decrypted<R: Read> = transparent::Decrypted<R>;
+    type Encrypted<W: Write> = transparent::Encrypted<W>;
+    type Error = io::Error;
+    type Recipient = transparent::Recipient;
+
+    fn encrypt_output<W: Write>(
+        &self,
+        output: W,
+        recipients: Vec<Self::Recipient>,
+    ) -> Result<Self::Encrypted<W>, Self::Error> {
+        Transparent {}.encrypt_output(output, recipients)
+    }
+
+    fn decrypt_input<R: Read>(
+        &self,
+        ciphertext: R,
+    ) -> Result<Self::Decrypted<R>, Self::Error> {
+        let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(active, Ordering::SeqCst);
+        thread::sleep(self.delay);
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        Transparent {}.decrypt_input(ciphertext)
+    }
+}
+
+#[test]
+fn test_list_parallel() {
+    let mut storage = temporary_store();
+    for i in 0..8 {
+        add_entry(&mut storage, &format!("entry-{}", i), "hunter2", &[]);
+    }
+
+    let crypto = Slow::new(Duration::from_millis(50));
+    let opts = list_opts(&["--all"]);
+
+    let mut sequential = Vec::new();
+    cli::list(
+        &opts,
+        SecretVisibility::Hide,
+        &Config::default(),
+        &crypto,
+        &mut storage,
+        &mut sequential,
+        &mut io::sink(),
+    )
+    .unwrap();
+    assert_eq!(crypto.peak.load(Ordering::SeqCst), 1);
+
+    let crypto = Slow::new(Duration::from_millis(50));
+    let opts = list_opts(&["--all", "--jobs", "4"]);
+
+    let mut parallel = Vec::new();
+    cli::list(
+        &opts,
+        SecretVisibility::Hide,
+        &Config::default(),
+        &crypto,
+        &mut storage,
+        &mut parallel,
+        &mut io::sink(),
+    )
+    .unwrap();
+
+    // Entries are decrypted concurrently, but the output must be identical
+    // and in the same order.
+    assert!(crypto.peak.load(Ordering::SeqCst) > 1);
+    assert_eq!(sequential, parallel);
+}
+
+#[test]
+fn test_list_reports_failed_entries() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "email", "hunter2", &[]);
+    add_entry(&mut storage, "bank", "hunter3", &[]);
+
+    // An entry with a valid header but an invalid body.
+    let corrupted = Uuid::new_v4();
+    let mut reference = storage.add_entry(&corrupted).unwrap();
+    write!(reference.writer().unwrap(), "---\nhunter4").unwrap();
+
+    let args: [&[&str]; 4] = [
+        &["--no-default", "-d", "name"],
+        &["--no-default", "-d", "name", "--jobs", "2"],
+        &["--names-only"],
+        &["--print0"],
+    ];
+    for args in args {
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        let err = cli::list(
+            &list_opts(args),
+            SecretVisibility::Hide,
+            &Config::default(),
+            &Transparent {},
+            &mut storage,
+            &mut output,
+            &mut errors,
+        )
+        .unwrap_err();
+        assert!(matches!(err, cli::Error::ListFailed(1)));
+
+        // The other entries are still listed.
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("email"));
+        assert!(output.contains("bank"));
+
+        let errors = String::from_utf8(errors).unwrap();
+        assert_eq!(
+            errors,
+            format!("FAILED {}: invalid entry format\n", corrupted)
+        );
+        assert!(!output.contains("hunter4"));
+    }
+}
+
+#[test]
+fn test_decrypted_entries() {
+    let mut storage = InMemory::default();
+    storage.create().unwrap();
+
+    add_entry(&mut storage, "first", "hunter2", &[]);
+    add_entry(&mut storage, "second", "hunter3", &["username=user"]);
+
+    let crypto = Transparent {};
+    let entries: Vec<_> = cli::decrypted_entries(&crypto, &storage)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    assert_eq!(entries.len(), 2);
+
+    // Entries are ordered by UUID.
+    assert!(entries[0].0 < entries[1].0);
+
+    let mut names: Vec<&str> = entries
+        .iter()
+        .filter_map(|(_, store)| store.get(&Key::Name).map(Value::as_str))
+        .collect();
+    names.sort();
+    assert_eq!(names, ["first", "second"]);
+}
+
+#[test]
+fn test_decrypted_entries_reports_invalid_entry() {
+    let mut storage = InMemory::default();
+    storage.create().unwrap();
+
+    add_entry(&mut storage, "valid", "hunter2", &[]);
+
+    // Write an entry that is not a valid key/value store.
+    let mut reference = storage.add_entry(&Uuid::new_v4()).unwrap();
+    let mut writer = reference.writer().unwrap();
+    writeln!(writer, "---\nnot a key value pair").unwrap();
+
+    let crypto = Transparent {};
+    let entries: Vec<_> =
+        cli::decrypted_entries(&crypto, &storage).unwrap().collect();
+
+    // The invalid entry is reported without ending the iteration.
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries.iter().filter(|e| e.is_ok()).count(), 1);
+    assert!(entries
+        .iter()
+        .any(|e| matches!(e, Err(cli::Error::Filetype(_)))));
+}
+
+#[test]
+#[cfg(unix)]
+// Tests that output files are only accessible by their owner, and that
+// secrets are never written to a file that other users can read.
+fn test_open_output_permissions() {
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    // A new file should be created with restrictive permissions.
+    let path = temporary_path();
+    drop(cli::util::open_output(&path, SecretVisibility::Show).unwrap());
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // An existing world-readable file should be rejected when showing secrets.
+    let path = temporary_path();
+    fs::write(&path, "").unwrap();
+    fs::set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+
+    let err =
+        cli::util::open_output(&path, SecretVisibility::Show).unwrap_err();
+    assert!(matches!(err, cli::Error::InsecureOutput(_)));
+
+    // Without secrets, the existing file can be written.
+    assert!(cli::util::open_output(&path, SecretVisibility::Hide).is_ok());
+}
+
+#[test]
+fn test_reshare() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "example", "hunter2", &[]);
+
+    // Set the initial recipients of the entry.
+    let opts = reshare_opts(&["example", "-r", "Key A", "-r", "Key B"]);
+    cli::reshare(
+        &opts,
+        &Transparent {},
+        &mut storage,
+        None,
+        None,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let before = raw_entries(&storage);
+    assert!(before[0]
+        .1
+        .starts_with("ciphey-transparent: 1\n-> Key A\n-> Key B\n---\n"));
+
+    // Add one recipient and remove another.
+    let opts = reshare_opts(&["example", "-r", "Key C", "--remove", "Key A"]);
+    cli::reshare(
+        &opts,
+        &Transparent {},
+        &mut storage,
+        None,
+        None,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let after = raw_entries(&storage);
+    assert_eq!(after.len(), 1);
+    assert!(after[0]
+        .1
+        .starts_with("ciphey-transparent: 1\n-> Key B\n-> Key C\n---\n"));
+
+    // The UUID and contents of the entry are unchanged.
+    assert_eq!(before[0].0, after[0].0);
+    let contents = |data: &str| data.split_once("---\n").unwrap().1.to_string();
+    assert_eq!(contents(&before[0].1), contents(&after[0].1));
+}
+
+#[test]
+fn test_reshare_all() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "a", "hunter2", &[]);
+    add_entry(&mut storage, "b", "hunter3", &[]);
+
+    // An entry that cannot be decrypted.
+    let broken = Uuid::new_v4();
+    let mut reference = storage.add_entry(&broken).unwrap();
+    write!(reference.writer().unwrap(), "not an entry").unwrap();
+
+    let reshare = |args: &[&str], storage: &mut Filesystem| {
+        let mut output = Vec::new();
+        let result = cli::reshare(
+            &reshare_opts(args),
+            &Transparent {},
+            storage,
+            None,
+            None,
+            &mut output,
+        );
+        (result, String::from_utf8(output).unwrap())
+    };
+
+    // Without --keep-going, nothing is written.
+    let before = raw_entries(&storage);
+    let (result, output) = reshare(&["--all", "-r", "Key A"], &mut storage);
+    assert!(matches!(result, Err(cli::Error::ReshareFailed(1))));
+    assert!(output.contains(&format!("FAILED {}: ", broken)));
+    assert!(output.ends_with("Updated recipients of 0 of 3 entries\n"));
+    assert_eq!(raw_entries(&storage), before);
+
+    let (result, output) =
+        reshare(&["--all", "--keep-going", "-r", "Key A"], &mut storage);
+    assert!(matches!(result, Err(cli::Error::ReshareFailed(1))));
+    assert!(output.contains(&format!("SKIPPED {}: ", broken)));
+    assert!(output.ends_with("Updated recipients of 2 of 3 entries\n"));
+
+    for (uuid, data) in raw_entries(&storage) {
+        if uuid == broken {
+            assert_eq!(data, "not an entry");
+        } else {
+            assert!(data.starts_with("ciphey-transparent: 1\n-> Key A\n---\n"));
+        }
+    }
+
+    assert!(reshare(&["a", "--all"], &mut storage).0.is_err());
+    assert!(reshare(&["a", "--keep-going"], &mut storage).0.is_err());
+    assert!(reshare(&[], &mut storage).0.is_err());
+
+    // Progress counts each entry as it is decrypted and as it is written.
+    storage.remove_entry(&broken).unwrap();
+    let mut reports = Vec::new();
+    let mut progress = |done, total| reports.push((done, total));
+    cli::reshare(
+        &reshare_opts(&["--all", "-r", "Key B"]),
+        &Transparent {},
+        &mut storage,
+        None,
+        Some(&mut progress),
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(reports, [(1, 4), (2, 4), (3, 4), (4, 4)]);
+}
+
+#[test]
+fn test_recipient_fingerprints() {
+    use libciphey::crypto::Recipient as _;
+
+    // The fingerprint of "Key A" is the start of its SHA-256 digest.
+    let fingerprint = "c2d4:1d6d:f383:4681";
+    let recipient = transparent::Recipient::from("Key A".to_string());
+    assert_eq!(recipient.fingerprint(), fingerprint);
+
+    let mut storage = temporary_store();
+    let new = |args: &[&str], storage: &mut Filesystem| {
+        let mut output = Vec::new();
+        let result = cli::new(
+            &new_opts(&[&["-n", "a", "-s", "b"], args].concat()),
+            &Config::default(),
+            &Transparent {},
+            storage,
+            None,
+            &mut empty(),
+            &mut output,
+            &mut io::sink(),
+        );
+        (result, String::from_utf8(output).unwrap())
+    };
+
+    let (result, output) =
+        new(&["-r", "Key A", "--recipient-fingerprint"], &mut storage);
+    result.unwrap();
+    assert!(output.starts_with(&format!("{} Key A\n", fingerprint)));
+
+    // The expected fingerprint may be typed without separators or in upper
+    // case.
+    let args = ["-r", "Key A", "--expect-fingerprint", "C2D41D6DF3834681"];
+    new(&args, &mut storage).0.unwrap();
+
+    // A recipient without an expected fingerprint aborts before writing.
+    let args = [
+        "-r",
+        "Key A",
+        "-r",
+        "Key B",
+        "--expect-fingerprint",
+        fingerprint,
+    ];
+    let (result, _) = new(&args, &mut storage);
+    assert!(matches!(
+        result,
+        Err(cli::Error::UnexpectedFingerprint(recipient, _)) if recipient == "Key B"
+    ));
+    assert_eq!(storage.entries().unwrap().len(), 2);
+
+    let before = raw_entries(&storage);
+    let opts = reshare_opts(&[
+        "--all",
+        "-r",
+        "Key C",
+        "--expect-fingerprint",
+        fingerprint,
+    ]);
+    let result = cli::reshare(
+        &opts,
+        &Transparent {},
+        &mut storage,
+        None,
+        None,
+        &mut Vec::new(),
+    );
+    assert!(result.is_err());
+    assert_eq!(raw_entries(&storage), before);
+}
+
+#[test]
+fn test_reshare_recipients_from_entry() {
+    let mut storage = temporary_store();
+    add_entry(
+        &mut storage,
+        "example",
+        "hunter2",
+        &["recipients= Key A,Key B , ,"],
+    );
+
+    let opts = reshare_opts(&[
+        "example",
+        "--recipients-from-entry",
+        "--remove",
+        "Key B",
+        "-r",
+        "Key C",
+    ]);
+    cli::reshare(
+        &opts,
+        &Transparent {},
+        &mut storage,
+        None,
+        None,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let entries = raw_entries(&storage);
+    assert!(entries[0]
+        .1
+        .starts_with("ciphey-transparent: 1\n-> Key A\n-> Key C\n---\n"));
+}
+
+#[test]
+fn test_reshare_recipients_from_entry_missing() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "example", "hunter2", &[]);
+
+    let opts = reshare_opts(&["example", "--recipients-from-entry"]);
+    let err = cli::reshare(
+        &opts,
+        &Transparent {},
+        &mut storage,
+        None,
+        None,
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, cli::Error::FieldNotFound(_)));
+}
+
+#[test]
+fn test_reshare_unknown_recipients() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "example", "hunter2", &["recipients=Key A"]);
+    let before = raw_entries(&storage);
+
+    // A backend that cannot determine the recipients of an entry, like age.
+    let crypto = Slow::default();
+    let reshare = |args: &[&str], storage: &mut Filesystem| {
+        cli::reshare(
+            &reshare_opts(args),
+            &crypto,
+            storage,
+            None,
+            None,
+            &mut Vec::new(),
+        )
+    };
+    let set_sensitive = |args: &[&str], storage: &mut Filesystem| {
+        let opts = match parse_args(&[&["set-sensitive"], args].concat()) {
+            CipheyCmd::SetSensitive(opts) => opts,
+            _ => unreachable!(),
+        };
+        cli::set_sensitive(&opts, &crypto, storage, None, &mut Vec::new())
+    };
+
+    // Adding recipients would drop the unknown current ones.
+    let err = reshare(&["example", "-r", "Key B"], &mut storage).unwrap_err();
+    assert!(matches!(err, cli::Error::UnknownRecipients));
+    for args in [&["--all", "-r", "Key B"][..], &["--all", "--keep-going"]] {
+        let err = reshare(args, &mut storage).unwrap_err();
+        assert!(matches!(err, cli::Error::ReshareFailed(1)));
+    }
+    let args = ["example", "secret", "-r", "Key B"];
+    let err = set_sensitive(&args, &mut storage).unwrap_err();
+    assert!(matches!(err, cli::Error::UnknownRecipients));
+    assert_eq!(raw_entries(&storage), before);
+
+    // --replace needs recipients to replace them with.
+    let err = reshare(&["example", "--replace"], &mut storage).unwrap_err();
+    assert!(matches!(err, cli::Error::Xflags(_)));
+    let args = ["example", "-r", "Key B", "--replace", "--remove", "Key A"];
+    let err = reshare(&args, &mut storage).unwrap_err();
+    assert!(matches!(err, cli::Error::Xflags(_)));
+    let args = ["example", "secret", "--replace"];
+    let err = set_sensitive(&args, &mut storage).unwrap_err();
+    assert!(matches!(err, cli::Error::Xflags(_)));
+    assert_eq!(raw_entries(&storage), before);
+
+    // The current recipients can be taken from the entry, or replaced.
+    let args = ["example", "--recipients-from-entry", "-r", "Key B"];
+    reshare(&args, &mut storage).unwrap();
+    let entries = raw_entries(&storage);
+    assert!(entries[0]
+        .1
+        .starts_with("ciphey-transparent: 1\n-> Key A\n-> Key B\n---\n"));
+
+    let args = ["example", "secret", "--insensitive", "-r", "Key C"];
+    set_sensitive(&[&args[..], &["--replace"]].concat(), &mut storage).unwrap();
+    let entries = raw_entries(&storage);
+    assert!(entries[0]
+        .1
+        .starts_with("ciphey-transparent: 1\n-> Key C\n---\n"));
+}
+
+#[test]
+fn test_split_recipients() {
+    use crate::cli::util::split_recipients;
+
+    assert_eq!(split_recipients("a,b"), ["a", "b"]);
+    assert_eq!(split_recipients(" a , b ,"), ["a", "b"]);
+    assert_eq!(split_recipients("a\nb\n\n"), ["a", "b"]);
+    assert_eq!(split_recipients("a,\n b,,"), ["a", "b"]);
+    assert!(split_recipients(" , ").is_empty());
+}
+
+#[test]
+fn test_list_print0() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "first entry", "hunter2", &[]);
+    add_entry(&mut storage, "second entry", "hunter3", &[]);
+
+    let opts = list_opts(&["--print0"]);
+    let mut output = Vec::new();
+    cli::list(
+        &opts,
+        SecretVisibility::Show,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        &mut output,
+        &mut io::sink(),
+    )
+    .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(!output.contains('\n'));
+
+    // Every record is terminated by a NUL character.
+    let records: Vec<&str> = output.split_terminator('\0').collect();
+    assert_eq!(records.len(), 2);
+    assert!(output.ends_with('\0'));
+
+    let mut names: Vec<&str> = records
+        .iter()
+        .map(|record| {
+            let (uuid, name) = record.split_once(' ').unwrap();
+            assert!(Uuid::parse_str(uuid).is_ok());
+            name
+        })
+        .collect();
+    names.sort();
+    assert_eq!(names, ["first entry", "second entry"]);
+}
+
+#[test]
+fn test_verify() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "valid", "hunter2", &[]);
+
+    // An entry with a valid header but an invalid body.
+    let corrupted = Uuid::new_v4();
+    let mut reference = storage.add_entry(&corrupted).unwrap();
+    write!(reference.writer().unwrap(), "---\nhunter3").unwrap();
+
+    let mut output = Vec::new();
+    let err =
+        cli::verify(&Transparent {}, &storage, None, &mut output).unwrap_err();
+    assert!(matches!(err, cli::Error::VerificationFailed(1)));
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains(&format!("FAILED {}", corrupted)));
+    assert!(output.contains("2 entries: 1 OK, 1 failed"));
+
+    // Secret material is never printed.
+    assert!(!output.contains("hunter2"));
+    assert!(!output.contains("hunter3"));
+}
+
+#[test]
+fn test_audit() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "email", "hunter2", &[]);
+    add_entry(&mut storage, "bank", "correct horse", &["password=hunter2"]);
+    // Repeating a value within one entry is not reuse.
+    add_entry(&mut storage, "forum", "battery", &["password=battery"]);
+
+    let audit = |storage: &Filesystem| {
+        let mut output = Vec::new();
+        let result = cli::audit(&Transparent {}, storage, None, &mut output);
+        (result, String::from_utf8(output).unwrap())
+    };
+
+    let (result, output) = audit(&storage);
+    assert!(matches!(result, Err(cli::Error::ReusedSecrets(1))));
+    assert!(output.contains("Secret shared by 2 entries:\n"));
+    assert!(output.contains(" email\n"));
+    assert!(output.contains(" bank\n"));
+    assert!(!output.contains("forum"));
+    assert!(output.ends_with("Audited 3 entries: 1 shared secrets\n"));
+
+    // Entries are identified by UUID, since names may repeat.
+    for (uuid, data) in raw_entries(&storage) {
+        let reported = output.contains(&uuid.to_string());
+        assert_eq!(reported, !data.contains("name=forum\n"));
+    }
+
+    // Secret material is never printed.
+    assert!(!output.contains("hunter2"));
+    assert!(!output.contains("battery"));
+
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "email", "hunter2", &[]);
+    add_entry(&mut storage, "bank", "hunter3", &[]);
+    let (result, output) = audit(&storage);
+    result.unwrap();
+    assert_eq!(output, "Audited 2 entries: 0 shared secrets\n");
+}
+
+#[test]
+fn test_verify_progress() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "a", "1", &[]);
+    add_entry(&mut storage, "b", "2", &[]);
+
+    let mut plain = Vec::new();
+    cli::verify(&Transparent {}, &storage, None, &mut plain).unwrap();
+
+    // Progress is reported per entry through the callback, which draws to
+    // stderr, so the report on stdout is unchanged.
+    let mut reports = Vec::new();
+    let mut progress = |done, total| reports.push((done, total));
+    let mut output = Vec::new();
+    cli::verify(&Transparent {}, &storage, Some(&mut progress), &mut output)
+        .unwrap();
+
+    assert_eq!(reports, [(1, 2), (2, 2)]);
+    assert_eq!(output, plain);
+
+    // Nothing is drawn when progress is suppressed.
+    assert!(cli::util::progress_bar(true).is_none());
+}
+
+// A reader that blocks forever without yielding any data.
+struct Never;
+
+impl Read for Never {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            thread::park();
+        }
+    }
+}
+
+#[test]
+fn test_prompt_shown_input_timeout() {
+    use crate::cli::util::{prompt_shown_input, TimeoutReader};
+
+    let timeout = Some(Duration::from_millis(50));
+    let mut input = TimeoutReader::new(Never, timeout);
+    let mut output = Vec::new();
+
+    let err = prompt_shown_input("Entry Name: ", &mut input, &mut output)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(output, b"Entry Name: ");
+}
+
+#[test]
+fn test_prompt_shown_input_within_timeout() {
+    use crate::cli::util::{prompt_shown_input, TimeoutReader};
+
+    let timeout = Some(Duration::from_secs(10));
+    let mut input = TimeoutReader::new(&b"example\nrest\n"[..], timeout);
+
+    let name = prompt_shown_input("Entry Name: ", &mut input, &mut Vec::new())
+        .unwrap();
+    assert_eq!(name, "example");
+
+    // Only the requested line is consumed.
+    let mut rest = String::new();
+    input.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "rest\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_prompt_tty() {
+    use crate::cli::util::{prompt_shown_input, prompt_tty};
+
+    // A file stands in for the terminal.
+    let tty = temporary_path();
+    std::fs::write(&tty, "example\n").unwrap();
+
+    // Prompts read from stdin when it is a terminal, unless forced.
+    assert!(prompt_tty(false, true, &tty).unwrap().is_none());
+
+    for (force, stdin_is_terminal) in [(true, true), (false, false)] {
+        let mut input = prompt_tty(force, stdin_is_terminal, &tty)
+            .unwrap()
+            .map(io::BufReader::new)
+            .unwrap();
+        let name =
+            prompt_shown_input("Entry Name: ", &mut input, &mut Vec::new())
+                .unwrap();
+        assert_eq!(name, "example");
+    }
+
+    // A missing terminal falls back to stdin, unless forced.
+    let missing = temporary_path();
+    assert!(prompt_tty(false, false, &missing).unwrap().is_none());
+    assert!(prompt_tty(true, false, &missing).is_err());
+
+    std::fs::remove_file(tty).unwrap();
+}
+
+#[test]
+fn test_read_masked() {
+    use std::collections::VecDeque;
+
+    use crate::cli::util::{read_masked, MaskedTerminal};
+
+    // Types scripted characters and records what is echoed.
+    struct ScriptedTerminal {
+        typed: VecDeque<char>,
+        echoed: String,
+    }
+
+    impl MaskedTerminal for ScriptedTerminal {
+        fn read_char(&mut self) -> io::Result<Option<char>> {
+            Ok(self.typed.pop_front())
+        }
+
+        fn echo(&mut self, text: &str) -> io::Result<()> {
+            self.echoed.push_str(text);
+            Ok(())
+        }
+    }
+
+    let terminal = |typed: &str| ScriptedTerminal {
+        typed: typed.chars().collect(),
+        echoed: String::new(),
+    };
+
+    // Backspace erases a character, Ctrl-U the whole line, and other control
+    // characters are ignored.
+    let mut typed =
+        terminal("hunx\u{7f}\u{8}\u{8}\u{8}\u{8}oops\u{15}hu\u{1b}ntér2\nrest");
+    let line = read_masked(&mut typed, '*').unwrap();
+    assert_eq!(line, "huntér2");
+    assert_eq!(typed.typed.iter().collect::<String>(), "rest");
+
+    // Only masks, erasures and the final newline are echoed.
+    let erase = "\u{8} \u{8}";
+    let expected =
+        format!("****{}****{}*******\n", erase.repeat(4), erase.repeat(4));
+    assert_eq!(typed.echoed, expected);
+
+    // Escape sequences, such as those of the arrow, Home and F1 keys, are
+    // ignored as a whole.
+    let mut typed = terminal("a\u{1b}[Ab\u{1b}[1;5Hc\u{1b}OPd\n");
+    assert_eq!(read_masked(&mut typed, '*').unwrap(), "abcd");
+    assert_eq!(typed.echoed, "****\n");
+
+    // The end of input ends the line.
+    let mut typed = terminal("abc");
+    assert_eq!(read_masked(&mut typed, '*').unwrap(), "abc");
+
+    // Ctrl-C interrupts the prompt.
+    let mut typed = terminal("ab\u{3}cd\n");
+    let err = read_masked(&mut typed, '*').unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    assert!(!typed.echoed.contains(['a', 'b']));
+}
+
+#[test]
+fn test_mv() {
+    let mut source = temporary_store();
+    add_entry(&mut source, "example", "hunter2", &["username=user"]);
+    let before = raw_entries(&source);
+
+    let dest_path = temporary_path();
+    let mut destination = Filesystem::new(&dest_path).unwrap();
+    cli::init(&mut destination, false).unwrap();
+    std::fs::write(dest_path.join(".recipients"), "# Team\nKey A\n\nKey B\n")
+        .unwrap();
+
+    let opts = mv_opts(&["example", "--to", dest_path.to_str().unwrap()]);
+    cli::mv(
+        &opts,
+        &Transparent {},
+        &mut source,
+        None,
+        &mut destination,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    assert!(raw_entries(&source).is_empty());
+
+    // The entry keeps its UUID and contents, encrypted to the destination's
+    // recipients.
+    let after = raw_entries(&destination);
+    assert_eq!(after.len(), 1);
+    assert_eq!(after[0].0, before[0].0);
+    let (header, contents) = after[0].1.split_once("---\n").unwrap();
+    assert_eq!(header, "ciphey-transparent: 1\n-> Key A\n-> Key B\n");
+    assert!(before[0].1.ends_with(contents));
+}
+
+#[test]
+fn test_mv_failure_keeps_entry() {
+    let mut source = temporary_store();
+    add_entry(&mut source, "example", "hunter2", &[]);
+
+    // The destination store has not been created, so adding the entry fails.
+    let dest_path = temporary_path();
+    std::fs::create_dir(&dest_path).unwrap();
+    std::fs::write(dest_path.join(".recipients"), "Key A\n").unwrap();
+    let mut destination = Filesystem::new(&dest_path).unwrap();
+
+    let opts = mv_opts(&["example", "--to", dest_path.to_str().unwrap()]);
+    cli::mv(
+        &opts,
+        &Transparent {},
+        &mut source,
+        None,
+        &mut destination,
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+
+    assert_eq!(raw_entries(&source).len(), 1);
+}
+
+/// A storage backend that creates entries but fails to write to them, as if
+/// the disk were full.
+struct FullDisk(Filesystem);
+
+/// An entry of [`FullDisk`].
+struct FullEntry(libciphey_fs::file::File);
+
+impl std::fmt::Display for FullEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Reference for FullEntry {
+    type Reader = fs::File;
+    type Writer = io::Sink;
+
+    fn reader(&self) -> io::Result<Self::Reader> {
+        self.0.reader()
+    }
+
+    fn writer(&mut self) -> io::Result<Self::Writer> {
+        self.0.writer()?;
+        Ok(io::sink())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Err(io::ErrorKind::StorageFull.into())
+    }
+}
+
+impl storage::Backend for FullDisk {
+    type Reference = FullEntry;
+
+    fn create(&mut self) -> io::Result<()> {
+        self.0.create()
+    }
+
+    fn location(&self) -> String {
+        self.0.location()
+    }
+
+    fn entries(&self) -> io::Result<HashMap<Uuid, Self::Reference>> {
+        let entries = self.0.entries()?.into_iter();
+        Ok(entries
+            .map(|(uuid, file)| (uuid, FullEntry(file)))
+            .collect())
+    }
+
+    fn add_entry(&mut self, uuid: &Uuid) -> io::Result<Self::Reference> {
+        self.0.add_entry(uuid).map(FullEntry)
+    }
+
+    fn remove_entry(&mut self, uuid: &Uuid) -> io::Result<()> {
+        self.0.remove_entry(uuid)
+    }
+}
+
+#[test]
+fn test_mv_unwritten_entry_is_removed() {
+    let mut source = temporary_store();
+    add_entry(&mut source, "example", "hunter2", &[]);
+
+    let dest_path = temporary_path();
+    let mut destination = Filesystem::new(&dest_path).unwrap();
+    cli::init(&mut destination, false).unwrap();
+    std::fs::write(dest_path.join(".recipients"), "Key A\n").unwrap();
+
+    let opts = mv_opts(&["example", "--to", dest_path.to_str().unwrap()]);
+    let mut full = FullDisk(Filesystem::new(&dest_path).unwrap());
+    cli::mv(
+        &opts,
+        &Transparent {},
+        &mut source,
+        None,
+        &mut full,
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+
+    // The entry that could not be synced is removed, and the source is kept.
+    assert!(raw_entries(&destination).is_empty());
+    assert_eq!(raw_entries(&source).len(), 1);
+
+    // So the move can be retried once the destination has space.
+    cli::mv(
+        &opts,
+        &Transparent {},
+        &mut source,
+        None,
+        &mut destination,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert!(raw_entries(&source).is_empty());
+    assert_eq!(raw_entries(&destination).len(), 1);
+}
+
+#[test]
+fn test_reindex_and_lookup() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "example", "hunter2", &[]);
+
+    // An entry that cannot be decrypted, which is scanned before any other.
+    let mut corrupt = storage.add_entry(&Uuid::nil()).unwrap();
+    corrupt
+        .writer()
+        .unwrap()
+        .write_all(b"not an entry")
+        .unwrap();
+
+    let show = |storage: &Filesystem, index: Option<&cli::NameIndex>| {
+        let mut output = Vec::new();
+        cli::show(
+            &show_opts(&["example", "--raw"]),
+            SecretVisibility::Show,
+            &Transparent {},
+            storage,
+            index,
+            &mut output,
+        )
+        .map(|_| output)
+    };
+
+    // Without an index, every entry must be decrypted.
+    assert!(show(&storage, None).is_err());
+
+    // Rebuilding the index fails while an entry cannot be decrypted.
+    let path = temporary_path();
+    let mut index = cli::NameIndex::new(path.clone());
+    cli::reindex(&Transparent {}, &storage, &mut index, None, &mut Vec::new())
+        .unwrap_err();
+    assert!(cli::NameIndex::load(path.clone()).unwrap().is_none());
+
+    storage.remove_entry(&Uuid::nil()).unwrap();
+    cli::reindex(&Transparent {}, &storage, &mut index, None, &mut Vec::new())
+        .unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        // The index reveals the names of entries to anyone who can read it.
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    let mut corrupt = storage.add_entry(&Uuid::nil()).unwrap();
+    corrupt
+        .writer()
+        .unwrap()
+        .write_all(b"not an entry")
+        .unwrap();
+
+    // With an index, only the named entry is decrypted.
+    let index = cli::NameIndex::load(path).unwrap().unwrap();
+    assert_eq!(show(&storage, Some(&index)).unwrap(), b"hunter2");
+}
+
+#[test]
+fn test_stale_index() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "first", "hunter2", &[]);
+    add_entry(&mut storage, "second", "hunter3", &[]);
+
+    // Point "first" at the wrong entry.
+    let entries = raw_entries(&storage);
+    let second = entries.iter().find(|(_, data)| data.contains("second"));
+    let mut index = cli::NameIndex::new(temporary_path());
+    index.insert("first".to_string(), second.unwrap().0);
+
+    let mut output = Vec::new();
+    cli::show(
+        &show_opts(&["first", "--raw"]),
+        SecretVisibility::Show,
+        &Transparent {},
+        &storage,
+        Some(&index),
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(output, b"hunter2");
+}
+
+#[test]
+fn test_new_updates_index() {
+    let mut storage = temporary_store();
+    let path = temporary_path();
+    let mut index = cli::NameIndex::new(path.clone());
+
+    let opts = new_opts(&["--name", "example", "--secret", "hunter2"]);
+    cli::new(
+        &opts,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        Some(&mut index),
+        &mut empty(),
+        &mut Vec::new(),
+        &mut io::sink(),
+    )
+    .unwrap();
+
+    let uuid = raw_entries(&storage)[0].0;
+    let index = cli::NameIndex::load(path).unwrap().unwrap();
+    assert_eq!(index.get("example"), Some(uuid));
+}
+
+#[test]
+fn test_new_recipients_stdin() {
+    let mut storage = temporary_store();
+
+    let opts = new_opts(&[
+        "--name",
+        "example",
+        "--secret",
+        "hunter2",
+        "-r",
+        "Key A",
+        "--recipients-stdin",
+    ]);
+    let mut input = "# Generated\nKey B\n\n  Key A  \nKey C\n".as_bytes();
+
+    cli::new(
+        &opts,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        None,
+        &mut input,
+        &mut Vec::new(),
+        &mut io::sink(),
+    )
+    .unwrap();
+
+    let entries = raw_entries(&storage);
+    assert!(entries[0].1.starts_with(
+        "ciphey-transparent: 1\n-> Key A\n-> Key B\n-> Key C\n---\n"
+    ));
+}
+
+#[test]
+fn test_new_recipients_stdin_requires_name() {
+    let mut storage = temporary_store();
+
+    let opts = new_opts(&["--secret", "hunter2", "--recipients-stdin"]);
+    let err = cli::new(
+        &opts,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        None,
+        &mut "Key A\n".as_bytes(),
+        &mut Vec::new(),
+        &mut io::sink(),
+    )
+    .unwrap_err();
+
+    assert!(matches!(err, cli::Error::Xflags(_)));
+    assert!(raw_entries(&storage).is_empty());
+}
+
+#[test]
+fn test_vault() {
+    use ciphey_kvstore::{KeyValuePair, KvStore};
+    use libciphey::vault::{self, Vault};
+
+    let mut storage = InMemory::default();
+    storage.create().unwrap();
+    let mut vault: Vault<_, _, KvStore> = Vault::new(Transparent {}, storage);
+
+    let entry = KvStore::new(vec![
+        KeyValuePair::new("name", Value::Insensitive("example".to_string())),
+        KeyValuePair::new("secret", Value::sensitive("hunter2")),
+    ]);
+    let recipients = vec![transparent::Recipient::from("Key A".to_string())];
+    let uuid = vault.add(entry, recipients).unwrap();
+
+    let entry = vault.get(&uuid).unwrap();
+    assert_eq!(entry.get(&Key::from("secret")).unwrap().as_str(), "hunter2");
+
+    let listed: Vec<Uuid> = vault
+        .list()
+        .unwrap()
+        .map(|entry| entry.unwrap().0)
+        .collect();
+    assert_eq!(listed, [uuid]);
+
+    vault.remove(&uuid).unwrap();
+    assert!(matches!(vault.get(&uuid), Err(vault::Error::NotFound(_))));
+    assert!(matches!(
+        vault.remove(&uuid),
+        Err(vault::Error::NotFound(_))
+    ));
+}
+
+#[test]
+fn test_vault_rejects_invalid_entry() {
+    use ciphey_kvstore::{KeyValuePair, KvStore};
+    use libciphey::vault::{self, Vault};
+
+    let mut storage = InMemory::default();
+    storage.create().unwrap();
+    let mut vault: Vault<_, _, KvStore> = Vault::new(Transparent {}, storage);
+
+    // An entry requires a name.
+    let entry = KvStore::new(vec![KeyValuePair::new(
+        "secret",
+        Value::sensitive("hunter2"),
+    )]);
+    let err = vault.add(entry, Vec::new()).unwrap_err();
+    assert!(matches!(err, vault::Error::Filetype(_)));
+    assert!(vault.storage().entries().unwrap().is_empty());
+}
+
+#[test]
+fn test_vault_removes_unfinished_entry() {
+    use ciphey_kvstore::{KeyValuePair, KvStore};
+    use libciphey::vault::{self, Vault};
+
+    let mut storage = InMemory::default();
+    storage.create().unwrap();
+    let mut vault: Vault<_, _, KvStore> = Vault::new(Unfinishable, storage);
+
+    let entry = KvStore::new(vec![KeyValuePair::new(
+        "name",
+        Value::Insensitive("example".to_string()),
+    )]);
+    let err = vault.add(entry, Vec::new()).unwrap_err();
+    assert!(matches!(err, vault::Error::Storage(_)));
+    assert!(vault.storage().entries().unwrap().is_empty());
+}
+
+#[test]
+fn test_cat() {
+    let mut storage = temporary_store();
+
+    // Arbitrary bytes that are not in the key/value format.
+    let data: Vec<u8> = (0..=255).chain([0, b'\n', 255]).collect();
+
+    let uuid = Uuid::new_v4();
+    let mut reference = storage.add_entry(&uuid).unwrap();
+    let writer = reference.writer().unwrap();
+    let mut encrypted =
+        crypto::Backend::encrypt_output(&Transparent {}, writer, Vec::new())
+            .unwrap();
+    encrypted.write_all(&data).unwrap();
+
+    let opts = cat_opts(&["--uuid", &uuid.to_string()]);
+    let mut output = Vec::new();
+    cli::cat(&opts, &Transparent {}, &storage, None, &mut output, false)
+        .unwrap();
+    assert_eq!(output, data);
+
+    // Raw data is not written to a terminal unless forced.
+    let err =
+        cli::cat(&opts, &Transparent {}, &storage, None, &mut output, true)
+            .unwrap_err();
+    assert!(matches!(err, cli::Error::TerminalOutput));
+
+    let opts = cat_opts(&["--uuid", &uuid.to_string(), "--force"]);
+    let mut output = Vec::new();
+    cli::cat(&opts, &Transparent {}, &storage, None, &mut output, true)
+        .unwrap();
+    assert_eq!(output, data);
+}
+
+#[test]
+fn test_cat_reader_buffer_size() {
+    let mut storage = temporary_store();
+
+    // A large entry that spans several buffers of each size below.
+    let data: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+    let uuid = Uuid::new_v4();
+    let mut reference = storage.add_entry(&uuid).unwrap();
+    let writer = reference.writer().unwrap();
+    let mut encrypted =
+        crypto::Backend::encrypt_output(&Transparent {}, writer, Vec::new())
+            .unwrap();
+    encrypted.write_all(&data).unwrap();
+    drop(encrypted);
+
+    let uuid = uuid.to_string();
+    let cat = |args: &[&str]| {
+        let args = [&["--uuid", uuid.as_str()], args].concat();
+        let mut output = Vec::new();
+        cli::cat(
+            &cat_opts(&args),
+            &Transparent {},
+            &storage,
+            None,
+            &mut output,
+            false,
+        )
+        .map(|_| output)
+    };
+
+    for args in [
+        &[][..],
+        &["--reader-buffer-size", "1"],
+        &["--reader-buffer-size", "1048576"],
+    ] {
+        let output = cat(args).unwrap();
+        assert_eq!(output.len(), data.len());
+        // Avoids printing megabytes of data if the output differs.
+        assert!(output == data);
+    }
+
+    let err = cat(&["--reader-buffer-size", "0"]).unwrap_err();
+    assert!(matches!(err, cli::Error::Xflags(_)));
+}
+
+#[test]
+fn test_lookup_by_name_skips_binary_entries() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "example", "hunter2", &[]);
+
+    // A binary entry that sorts before every other entry.
+    let blob = Uuid::nil();
+    let mut reference = storage.add_entry(&blob).unwrap();
+    let writer = reference.writer().unwrap();
+    let mut encrypted =
+        crypto::Backend::encrypt_output(&Transparent {}, writer, Vec::new())
+            .unwrap();
+    encrypted.write_all(&[0, 159, 146, 150]).unwrap();
+    drop(encrypted);
+
+    let mut output = Vec::new();
+    cli::show(
+        &show_opts(&["example"]),
+        SecretVisibility::Show,
+        &Transparent {},
+        &storage,
+        None,
+        &mut output,
+    )
+    .unwrap();
+    assert!(String::from_utf8(output).unwrap().contains("hunter2"));
+
+    let mut output = Vec::new();
+    cli::cat(
+        &cat_opts(&["example"]),
+        &Transparent {},
+        &storage,
+        None,
+        &mut output,
+        false,
+    )
+    .unwrap();
+    assert!(String::from_utf8(output).unwrap().contains("hunter2"));
+
+    // The binary entry cannot be found by name, only by UUID.
+    let err = cli::cat(
+        &cat_opts(&["missing"]),
+        &Transparent {},
+        &storage,
+        None,
+        &mut Vec::new(),
+        false,
+    )
+    .unwrap_err();
+    assert!(matches!(err, cli::Error::EntryNotFound(_)));
+
+    for args in [&["--glob", "ex*"][..], &["--filter", "name=example"]] {
+        // The example entry is found, so removing it asks for confirmation.
+        let err = cli::remove(
+            &remove_opts(args),
+            &Transparent {},
+            &mut storage,
+            None,
+            &mut &b"n\n"[..],
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, cli::Error::RemoveNotConfirmed));
+    }
+    assert_eq!(storage.entries().unwrap().len(), 2);
+}
+
+#[test]
+fn test_cat_by_name() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "example", "hunter2", &[]);
+
+    let opts = cat_opts(&["example"]);
+    let mut output = Vec::new();
+    cli::cat(&opts, &Transparent {}, &storage, None, &mut output, false)
+        .unwrap();
+    assert_eq!(output, b"name=example\nsecret!=hunter2\n");
+}
+
+#[test]
+fn test_merge() {
+    let merge = |args: &[&str],
+                 storage: &mut Filesystem,
+                 index: Option<&mut cli::NameIndex>| {
+        let opts = match parse_args(&[&["merge"], args].concat()) {
+            CipheyCmd::Merge(opts) => opts,
+            _ => unreachable!(),
+        };
+        let mut output = Vec::new();
+        cli::merge(&opts, &Transparent {}, storage, index, &mut output)
+            .map(|_| String::from_utf8(output).unwrap())
+    };
+
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "a", "1", &["url=x"]);
+    add_entry(&mut storage, "b", "2", &["email=e"]);
+
+    let err = merge(&["a", "a"], &mut storage, None).unwrap_err();
+    assert!(matches!(err, cli::Error::Xflags(_)));
+
+    // The originals are kept by default.
+    let args = &["a", "b", "--strategy", "keep-other"];
+    merge(args, &mut storage, None).unwrap();
+    let entries = raw_entries(&storage);
+    assert_eq!(entries.len(), 3);
+    assert!(entries.iter().any(|(_, entry)| {
+        entry.ends_with("name=b\nsecret!=2\nurl=x\nemail=e\n")
+    }));
+
+    let mut storage = temporary_store();
+    let mut index = cli::NameIndex::new(temporary_path());
+    add_entry(&mut storage, "a", "1", &["url=x"]);
+    add_entry(&mut storage, "b", "2", &["email=e"]);
+
+    let output =
+        merge(&["a", "b", "--remove"], &mut storage, Some(&mut index)).unwrap();
+    assert!(output.starts_with("Merged entries into: "));
+
+    let entries = raw_entries(&storage);
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0]
+        .1
+        .ends_with("name=a\nsecret!=1\nurl=x\nemail=e\n"));
+    assert_eq!(index.get("a"), Some(entries[0].0));
+}
+
+#[test]
+fn test_set_sensitive() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "example", "hunter2", &["username=user"]);
+    let before = raw_entries(&storage);
+    assert!(before[0].1.contains("\nusername=user\n"));
+
+    let args = ["set-sensitive", "example", "username"];
+    let opts = match parse_args(&args) {
+        CipheyCmd::SetSensitive(opts) => opts,
+        _ => unreachable!(),
+    };
+    cli::set_sensitive(
+        &opts,
+        &Transparent {},
+        &mut storage,
+        None,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let after = raw_entries(&storage);
+    assert_eq!(after[0].0, before[0].0);
+    assert!(after[0].1.contains("\nusername!=user\n"));
+
+    let args = ["set-sensitive", "example", "username", "--insensitive"];
+    let opts = match parse_args(&args) {
+        CipheyCmd::SetSensitive(opts) => opts,
+        _ => unreachable!(),
+    };
+    cli::set_sensitive(
+        &opts,
+        &Transparent {},
+        &mut storage,
+        None,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    assert_eq!(raw_entries(&storage), before);
+}
+
+#[test]
+fn test_list_fields() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "example", "hunter2", &["a=1", "b=2", "c=3"]);
+
+    let mut list = |args: &[&str]| {
+        let mut output = Vec::new();
+        cli::list(
+            &list_opts(args),
+            SecretVisibility::Hide,
+            &Config::default(),
+            &Transparent {},
+            &mut storage,
+            &mut output,
+            &mut io::sink(),
+        )
+        .map(|_| String::from_utf8(output).unwrap())
+    };
+
+    let fields = list(&["-n", "--fields", " a , b"]).unwrap();
+    assert_eq!(fields, list(&["-n", "-d", "a", "-d", "b"]).unwrap());
+    assert!(fields.contains("a: 1") && fields.contains("b: 2"));
+    assert!(!fields.contains("c: 3"));
+
+    // Fields compose with the default keys and `-d`.
+    let fields = list(&["--fields", "a", "-d", "c"]).unwrap();
+    assert_eq!(fields, list(&["-d", "a", "-d", "c"]).unwrap());
+
+    assert!(list(&["--fields", "a,,b"]).is_err());
+    assert!(list(&["--fields", "a, "]).is_err());
+}
+
+#[test]
+fn test_new_recipient_self_without_identities() {
+    let mut storage = temporary_store();
+
+    let opts = new_opts(&["--name", "a", "--secret", "b", "--recipient-self"]);
+    let err = cli::new(
+        &opts,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        None,
+        &mut empty(),
+        &mut Vec::new(),
+        &mut io::sink(),
+    )
+    .unwrap_err();
+
+    assert!(matches!(err, cli::Error::NoOwnRecipients));
+    assert!(raw_entries(&storage).is_empty());
+}
+
+// A store that adds every entry under the same UUID, so that the second entry
+// collides with the first.
+struct Colliding(Filesystem, Uuid);
+
+impl storage::Backend for Colliding {
+    type Reference = <Filesystem as storage::Backend>::Reference;
+
+    fn create(&mut self) -> io::Result<()> {
+        self.0.create()
+    }
+
+    fn location(&self) -> String {
+        self.0.location()
+    }
+
+    fn entries(&self) -> io::Result<HashMap<Uuid, Self::Reference>> {
+        self.0.entries()
+    }
+
+    fn add_entry(&mut self, _uuid: &Uuid) -> io::Result<Self::Reference> {
+        self.0.add_entry(&self.1)
+    }
+
+    fn remove_entry(&mut self, uuid: &Uuid) -> io::Result<()> {
+        self.0.remove_entry(uuid)
+    }
+}
+
+#[test]
+fn test_new_duplicate_uuid() {
+    let mut storage = Colliding(temporary_store(), Uuid::new_v4());
+
+    add_entry(&mut storage, "first", "1", &[]);
+
+    let opts = new_opts(&["--name", "second", "--secret", "2"]);
+    let err = cli::new(
+        &opts,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        None,
+        &mut empty(),
+        &mut Vec::new(),
+        &mut io::sink(),
+    )
+    .unwrap_err();
+
+    assert!(matches!(err, cli::Error::EntryExists(_)));
+    assert!(err.to_string().contains("already exists"));
+
+    // The existing entry is left untouched.
+    let entries = raw_entries(&storage);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].0, storage.1);
+    assert!(entries[0].1.contains("name=first"));
+}
+
+// A crypto backend whose encrypted writers fail to finish, as if the final
+// frame of the ciphertext could not be written.
+struct Unfinishable;
+
+struct Unfinished<W: Write>(W);
+
+impl<W: Write> Write for Unfinished<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> crypto::Encrypted<W> for Unfinished<W> {
+    type Error = io::Error;
+
+    fn finish(self) -> io::Result<W> {
+        Err(io::ErrorKind::WriteZero.into())
+    }
+}
+
+impl crypto::Backend for Unfinishable {
+    type Decrypted<R: Read> = transparent::Decrypted<R>;
+    type Encrypted<W: Write> = Unfinished<W>;
+    type Error = io::Error;
+    type Recipient = transparent::Recipient;
+
+    fn encrypt_output<W: Write>(
+        &self,
+        output: W,
+        _recipients: Vec<Self::Recipient>,
+    ) -> Result<Self::Encrypted<W>, Self::Error> {
+        Ok(Unfinished(output))
+    }
+
+    fn decrypt_input<R: Read>(
+        &self,
+        ciphertext: R,
+    ) -> Result<Self::Decrypted<R>, Self::Error> {
+        Transparent {}.decrypt_input(ciphertext)
+    }
+}
+
+#[test]
+fn test_new_unfinished_entry_is_removed() {
+    let mut storage = temporary_store();
+    let uuid = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+    let opts = new_opts(&["--name", "a", "--secret", "b", "--uuid", uuid]);
+
+    let err = cli::new(
+        &opts,
+        &Config::default(),
+        &Unfinishable,
+        &mut storage,
+        None,
+        &mut empty(),
+        &mut Vec::new(),
+        &mut io::sink(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, cli::Error::Storage(_)));
+    assert!(raw_entries(&storage).is_empty());
+
+    // The UUID can be used again.
+    cli::new(
+        &opts,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        None,
+        &mut empty(),
+        &mut Vec::new(),
+        &mut io::sink(),
+    )
+    .unwrap();
+    assert_eq!(raw_entries(&storage).len(), 1);
+}
+
+#[test]
+fn test_new_with_uuid() {
+    let mut storage = temporary_store();
+    let uuid = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+
+    let args = ["--name", "a", "--secret", "b", "--uuid", uuid];
+    let new = |storage: &mut Filesystem| {
+        cli::new(
+            &new_opts(&args),
+            &Config::default(),
+            &Transparent {},
+            storage,
+            None,
+            &mut empty(),
+            &mut Vec::new(),
+            &mut io::sink(),
+        )
+    };
+    new(&mut storage).unwrap();
+
+    let opts = cat_opts(&["--uuid", uuid]);
+    let mut output = Vec::new();
+    cli::cat(&opts, &Transparent {}, &storage, None, &mut output, false)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("name=a") && output.contains("secret!=b"));
+
+    // The existing entry is not overwritten.
+    let err = new(&mut storage).unwrap_err();
+    assert!(matches!(err, cli::Error::EntryExists(_)));
+
+    let opts = new_opts(&["--name", "a", "--secret", "b", "--uuid", "x"]);
+    let err = cli::new(
+        &opts,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        None,
+        &mut empty(),
+        &mut Vec::new(),
+        &mut io::sink(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, cli::Error::Xflags(_)));
+}
+
+#[test]
+fn test_new_deduplicates_recipients() {
+    let mut storage = temporary_store();
+
+    let opts =
+        new_opts(&["-n", "c", "-s", "d", "-r", "B", "-r", "A", "-r", "B"]);
+    cli::new(
+        &opts,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        None,
+        &mut empty(),
+        &mut Vec::new(),
+        &mut io::sink(),
+    )
+    .unwrap();
+
+    let (_, data) = &raw_entries(&storage)[0];
+    assert!(data.starts_with("ciphey-transparent: 1\n-> B\n-> A\n---\n"));
+}
+
+#[test]
+fn test_new_batch() {
+    let mut storage = temporary_store();
+    let mut index = cli::NameIndex::new(temporary_path());
+
+    let mut input: &[u8] =
+        b"name=a;secret=1;url=example.com\n\nsecret=2\tname=b\n";
+    let mut output = Vec::new();
+    cli::new(
+        &new_opts(&["--batch"]),
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        Some(&mut index),
+        &mut input,
+        &mut output,
+        &mut io::sink(),
+    )
+    .unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "Created 2 entries\n");
+
+    let entries = raw_entries(&storage);
+    assert_eq!(entries.len(), 2);
+    for (name, secret) in [("a", "1"), ("b", "2")] {
+        let uuid = index.get(name).unwrap();
+        let (_, data) = entries.iter().find(|(u, _)| *u == uuid).unwrap();
+        // The name and secret always come first, and the secret is sensitive.
+        let fields = format!("name={}\nsecret!={}", name, secret);
+        assert!(data.contains(&fields));
+    }
+    assert!(entries
+        .iter()
+        .any(|(_, data)| data.contains("url=example.com")));
+}
+
+#[test]
+fn test_new_batch_invalid_record() {
+    let mut storage = temporary_store();
+
+    let new = |storage: &mut Filesystem, mut input: &[u8]| {
+        cli::new(
+            &new_opts(&["--batch"]),
+            &Config::default(),
+            &Transparent {},
+            storage,
+            None,
+            &mut input,
+            &mut Vec::new(),
+            &mut io::sink(),
+        )
+    };
+
+    let err = new(&mut storage, b"name=a;secret=1\nname=b\n").unwrap_err();
+    assert!(matches!(err, cli::Error::InvalidRecord(2, _)));
+    assert_eq!(err.to_string(), "Invalid record on line 2: missing secret");
+
+    let err = new(&mut storage, b"name=a;secret").unwrap_err();
+    assert!(matches!(err, cli::Error::InvalidRecord(1, _)));
+
+    // No entries are created from a batch with an invalid record.
+    assert!(raw_entries(&storage).is_empty());
+}
+
+#[test]
+fn test_new_batch_write_failure() {
+    // Every entry is added under the same UUID, so the second record fails to
+    // write after the first was written.
+    let mut storage = Colliding(temporary_store(), Uuid::new_v4());
+    let index_path = temporary_path();
+    let mut index = cli::NameIndex::new(index_path.clone());
+
+    let mut input: &[u8] = b"name=a;secret=1\nname=b;secret=2\n";
+    let err = cli::new(
+        &new_opts(&["--batch"]),
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        Some(&mut index),
+        &mut input,
+        &mut Vec::new(),
+        &mut io::sink(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, cli::Error::EntryExists(_)));
+
+    // The written entry can still be found by name.
+    let index = cli::NameIndex::load(index_path).unwrap().unwrap();
+    assert!(index.get("a").is_some());
+    assert_eq!(index.get("b"), None);
+    assert_eq!(raw_entries(&storage).len(), 1);
+}
+
+#[test]
+fn test_aliases() {
+    use crate::cli::Aliases;
+
+    let aliases = Aliases::parse(
+        "# The team\nalice = age1alice\n\n  bob=age1bob  \nteam = @alice\n",
+    )
+    .unwrap();
+
+    let resolve = |recipients: &[&str]| {
+        let recipients: Vec<OsString> =
+            recipients.iter().map(OsString::from).collect();
+        aliases.resolve(&recipients)
+    };
+
+    // Aliases are resolved, including aliases of aliases, and other
+    // recipients are unchanged.
+    let resolved = resolve(&["@bob", "age1carol", "@team"]).unwrap();
+    assert_eq!(resolved, ["age1bob", "age1carol", "age1alice"]);
+
+    let err = resolve(&["@alice", "@carol"]).unwrap_err();
+    assert!(matches!(err, cli::Error::UnknownAlias(name) if name == "carol"));
+
+    let cycle = Aliases::parse("a = @b\nb = @a\n").unwrap();
+    let err = cycle.resolve(&[OsString::from("@a")]).unwrap_err();
+    assert!(matches!(err, cli::Error::AliasCycle(name) if name == "a"));
+
+    for invalid in ["alice age1alice", "= age1", "@a = age1", "a =", "a=1\na=2"]
+    {
+        assert!(Aliases::parse(invalid).is_err(), "{}", invalid);
+    }
+    assert_eq!(
+        Aliases::parse("alice\n").unwrap_err(),
+        "line 1: missing '='"
+    );
+
+    assert_eq!(
+        Aliases::load(&temporary_path()).unwrap(),
+        Aliases::default()
+    );
+
+    let path = temporary_path();
+    std::fs::write(&path, "alice\n").unwrap();
+    let err = Aliases::load(&path).unwrap_err();
+    assert!(matches!(err, cli::Error::InvalidConfig(..)));
+}
+
+#[test]
+fn test_init_scaffold() {
+    use std::fs;
+
+    use crate::cli::defaults;
+
+    let path = temporary_path();
+    let mut storage = Filesystem::new(&path).unwrap();
+    cli::init(&mut storage, false).unwrap();
+
+    // Re-initializing an existing store requires --force.
+    let err = cli::init(&mut storage, false).unwrap_err();
+    assert!(
+        matches!(err, cli::Error::Storage(e) if e.kind() == io::ErrorKind::AlreadyExists)
+    );
+    cli::init(&mut storage, true).unwrap();
+
+    let scaffold = |args: &[&str]| {
+        let opts = match parse_args(&[&["init"], args].concat()) {
+            CipheyCmd::Init(opts) => opts,
+            _ => unreachable!(),
+        };
+        let generate_key =
+            || ("identity\n".to_string(), "recipient".to_string());
+        let mut output = Vec::new();
+        cli::scaffold(&opts, &path, generate_key, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    scaffold(&["--scaffold", "--generate-key"]);
+
+    let recipients = defaults::recipients_file(&path);
+    let identities = defaults::identities_file(&path);
+    let config = Config::path(&path);
+    assert_eq!(fs::read_to_string(&recipients).unwrap(), "recipient\n");
+    assert_eq!(fs::read_to_string(&identities).unwrap(), "identity\n");
+    // Every setting of the template is commented out at its default.
+    let template = fs::read_to_string(&config).unwrap();
+    let uncommented: Vec<&str> = template
+        .lines()
+        .filter_map(|line| line.strip_prefix("# "))
+        .filter(|line| line.contains(" = "))
+        .collect();
+    assert_eq!(uncommented.len(), 6);
+    let uncommented = uncommented.join("\n");
+    assert_eq!(Config::parse(&template).unwrap(), Config::default());
+    assert_eq!(Config::parse(&uncommented).unwrap(), Config::default());
+
+    #[cfg(unix)]
+    for file in [&recipients, &identities, &config] {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::metadata(file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    // The identity file is kept out of the store's git repository.
+    let gitignore = defaults::gitignore_file(&path);
+    assert_eq!(fs::read_to_string(&gitignore).unwrap(), "/identities\n");
+
+    // Existing files are never overwritten.
+    fs::write(&recipients, "existing\n").unwrap();
+    let output = scaffold(&["--scaffold", "--generate-key"]);
+    assert!(!output.contains("Created"));
+    assert_eq!(fs::read_to_string(&recipients).unwrap(), "existing\n");
+
+    // A new recipient is added to an existing recipients file.
+    fs::remove_file(&identities).unwrap();
+    fs::write(&gitignore, "*.tmp").unwrap();
+    let output = scaffold(&["--generate-key"]);
+    assert!(output
+        .contains(&format!("Added recipient to {}\n", recipients.display())));
+    let contents = fs::read_to_string(&recipients).unwrap();
+    assert_eq!(contents, "existing\nrecipient\n");
+    let contents = fs::read_to_string(&gitignore).unwrap();
+    assert_eq!(contents, "*.tmp\n/identities\n");
+}
+
+#[test]
+fn test_new_value_length_limits() {
+    let config =
+        Config::parse("warn_value_length = 4\nmax_value_length = 8").unwrap();
+    assert_eq!((config.warn_value_length, config.max_value_length), (4, 8));
+
+    let mut storage = temporary_store();
+    let mut new = |secret: &str, batch: bool| {
+        let (opts, input) = match batch {
+            false => (new_opts(&["-n", "a", "-s", secret]), String::new()),
+            true => {
+                (new_opts(&["--batch"]), format!("name=a;secret={}", secret))
+            }
+        };
+        let mut warnings = Vec::new();
+        cli::new(
+            &opts,
+            &config,
+            &Transparent {},
+            &mut storage,
+            None,
+            &mut input.as_bytes(),
+            &mut Vec::new(),
+            &mut warnings,
+        )
+        .map(|_| String::from_utf8(warnings).unwrap())
+    };
+    let warning = "Warning: the value of 'secret' is longer than 4 bytes\n";
+
+    // Values at or below the soft limit are written without a warning.
+    assert_eq!(new("123", false).unwrap(), "");
+    assert_eq!(new("1234", false).unwrap(), "");
+
+    // Values at or below the hard limit are written, with a warning above the
+    // soft limit.
+    assert_eq!(new("12345", false).unwrap(), warning);
+    assert_eq!(new("12345", true).unwrap(), warning);
+    assert_eq!(new("1234567", false).unwrap(), warning);
+    assert_eq!(new("12345678", false).unwrap(), warning);
+
+    let err = new("123456789", false).unwrap_err();
+    assert!(matches!(
+        err,
+        cli::Error::Filetype(e) if e.to_string().contains("over the limit of 8")
+    ));
+    let err = new("123456789", true).unwrap_err();
+    assert!(matches!(err, cli::Error::InvalidRecord(1, _)));
+
+    // Nothing is written for rejected entries.
+    assert_eq!(storage.entries().unwrap().len(), 6);
+}
+
+#[test]
+fn test_normalize_keys() {
+    let config = Config::default();
+    assert_eq!(config.normalize_key(Key::from("User")), Key::Username);
+    assert_eq!(config.normalize_key(Key::from(" E-Mail ")), Key::Email);
+    assert_eq!(config.normalize_key(Key::from("passwd")), Key::Password);
+    assert_eq!(config.normalize_key(Key::from("TOTP")), Key::from("totp"));
+    assert_eq!(config.normalize_key(Key::Url), Key::Url);
+
+    // Configured aliases replace the default ones.
+    let config = Config::parse(r#"key_aliases = { PIN = "secret" }"#).unwrap();
+    assert_eq!(config.normalize_key(Key::from("pin")), Key::from("secret"));
+    assert_eq!(config.normalize_key(Key::from("User")), Key::from("user"));
+
+    let mut storage = temporary_store();
+    let mut new = |args: &[&str], input: &str| {
+        cli::new(
+            &new_opts(args),
+            &Config::default(),
+            &Transparent {},
+            &mut storage,
+            None,
+            &mut input.as_bytes(),
+            &mut Vec::new(),
+            &mut io::sink(),
+        )
+        .unwrap();
+    };
+
+    new(
+        &["-n", "a", "-s", "b", "-k", "User=alice", "--normalize-keys"],
+        "",
+    );
+    new(
+        &["--batch", "--normalize-keys"],
+        "Name=c;Secret=d;E-mail=e@f\n",
+    );
+    // Keys are kept as entered without the flag.
+    new(&["-n", "g", "-s", "h", "-k", "User=bob"], "");
+
+    let entries = raw_entries(&storage);
+    let find = |name: &str| {
+        let name = format!("name={}\n", name);
+        entries
+            .iter()
+            .find(|(_, data)| data.contains(&name))
+            .unwrap()
+            .1
+            .clone()
+    };
+    assert!(find("a").contains("\nusername=alice\n"));
+    assert!(find("c").contains("\nemail=e@f\n"));
+    assert!(find("g").contains("\nUser=bob\n"));
+}
+
+#[test]
+fn test_new_strict_keys() {
+    let config = Config::parse(r#"allowed_keys = ["totp"]"#).unwrap();
+    let mut storage = temporary_store();
+    let mut new = |args: &[&str], input: &str| {
+        cli::new(
+            &new_opts(args),
+            &config,
+            &Transparent {},
+            &mut storage,
+            None,
+            &mut input.as_bytes(),
+            &mut Vec::new(),
+            &mut io::sink(),
+        )
+    };
+
+    // Unknown keys are accepted by default.
+    new(&["-n", "a", "-s", "b", "-k", "passwrod=c"], "").unwrap();
+
+    let strict = ["-n", "a", "-s", "b", "--strict-keys"];
+    let err = new(&[&strict[..], &["-k", "passwrod=c"]].concat(), "");
+    assert!(matches!(
+        err,
+        Err(cli::Error::Filetype(e)) if e.to_string() == "Unknown key 'passwrod'"
+    ));
+    let err = new(&["--batch", "--strict-keys"], "name=a;secret=b;pasword=c\n");
+    assert!(matches!(err, Err(cli::Error::InvalidRecord(1, _))));
+
+    // Well-known keys, ciphey's own keys, and configured keys are accepted.
+    let keys = ["-k", "password=c", "-k", "icon=d", "-k", "totp=e"];
+    new(&[&strict[..], &keys].concat(), "").unwrap();
+
+    assert_eq!(storage.entries().unwrap().len(), 2);
+}
+
+#[test]
+fn test_new_raw_stdin() {
+    let mut storage = temporary_store();
+    let new = |args: &[&str], input: &str, storage: &mut Filesystem| {
+        let mut output = Vec::new();
+        let result = cli::new(
+            &new_opts(&[&["--raw-stdin"], args].concat()),
+            &Config::default(),
+            &Transparent {},
+            storage,
+            None,
+            &mut input.as_bytes(),
+            &mut output,
+            &mut io::sink(),
+        );
+        (result, String::from_utf8(output).unwrap())
+    };
+
+    let uuid = Uuid::new_v4().to_string();
+    let input = "# Migrated\nname=a\nusername=user\npin!=1234\n";
+    let (result, output) = new(&["-u", &uuid], input, &mut storage);
+    result.unwrap();
+    // Nothing is prompted for.
+    assert!(output.starts_with("Created new entry at path: "));
+
+    let entries = raw_entries(&storage);
+    assert_eq!(entries[0].0.to_string(), uuid);
+    assert!(entries[0]
+        .1
+        .ends_with("---\nname=a\nusername=user\npin!=1234\n"));
+
+    // Input that is not a valid entry is rejected.
+    for input in [
+        "name=b\nnot a pair\n",
+        "username=user\n",
+        "name=b\nname=c\n",
+    ] {
+        let (result, _) = new(&[], input, &mut storage);
+        assert!(matches!(result, Err(cli::Error::Filetype(_))));
+    }
+    let (result, _) = new(&["-n", "b"], "name=b\n", &mut storage);
+    assert!(matches!(result, Err(cli::Error::Xflags(_))));
+
+    assert_eq!(storage.entries().unwrap().len(), 1);
+}
+
+#[test]
+fn test_new_secret_key() {
+    let mut storage = temporary_store();
+    let new = |args: &[&str], input: &str, storage: &mut Filesystem| {
+        cli::new(
+            &new_opts(args),
+            &Config::default(),
+            &Transparent {},
+            storage,
+            None,
+            &mut input.as_bytes(),
+            &mut Vec::new(),
+            &mut io::sink(),
+        )
+    };
+
+    let args = ["-n", "a", "-s", "hunter2", "--secret-key", "api_token"];
+    new(&args, "", &mut storage).unwrap();
+    let entries = raw_entries(&storage);
+    assert!(entries[0].1.ends_with("name=a\napi_token!=hunter2\n"));
+
+    // The secret is shown by its key.
+    let show = |args: &[&str], storage: &Filesystem| {
+        let mut output = Vec::new();
+        cli::show(
+            &show_opts(&[&["a", "--raw"], args].concat()),
+            SecretVisibility::Show,
+            &Transparent {},
+            storage,
+            None,
+            &mut output,
+        )
+        .map(|_| output)
+    };
+    let output = show(&["--secret-key", "api_token"], &storage).unwrap();
+    assert_eq!(output, b"hunter2");
+    let err = show(&[], &storage).unwrap_err();
+    assert!(matches!(err, cli::Error::FieldNotFound(_)));
+    let err = show(&["--secret-key", "api_token", "-k", "name"], &storage);
+    assert!(matches!(err, Err(cli::Error::Xflags(_))));
+
+    // Batch records hold their secret under the key too.
+    let input = "name=b;password=hunter3\n";
+    new(
+        &["--batch", "--secret-key", "password"],
+        input,
+        &mut storage,
+    )
+    .unwrap();
+    let entries = raw_entries(&storage);
+    assert!(entries
+        .iter()
+        .any(|(_, entry)| entry.ends_with("name=b\npassword!=hunter3\n")));
+
+    let err = new(
+        &["--batch", "--secret-key", "password"],
+        "name=c;secret=d\n",
+        &mut storage,
+    );
+    assert!(matches!(err, Err(cli::Error::InvalidRecord(1, _))));
+
+    for key in ["a=b", "pin!", ""] {
+        let args = ["-n", "c", "-s", "d", "--secret-key", key];
+        let err = new(&args, "", &mut storage);
+        assert!(matches!(
+            err,
+            Err(cli::Error::Filetype(e)) if e.to_string().starts_with("Invalid key")
+        ));
+    }
+    assert_eq!(storage.entries().unwrap().len(), 2);
+}
+
+#[test]
+fn test_config() {
+    let config =
+        Config::parse(r#"default_fields = ["name", "username", "totp"]"#)
+            .unwrap();
+    let fields = [Key::Name, Key::Username, Key::from("totp")];
+    assert_eq!(config.default_fields, fields);
+
+    // Missing settings and files fall back to the defaults.
+    assert_eq!(Config::parse("").unwrap(), Config::default());
+    assert_eq!(Config::load(&temporary_path()).unwrap(), Config::default());
+
+    assert!(Config::parse("default_fields = \"name\"").is_err());
+    assert!(Config::parse("unknown = 1").is_err());
+
+    let path = temporary_path();
+    std::fs::write(&path, "default_fields = [").unwrap();
+    let err = Config::load(&path).unwrap_err();
+    assert!(matches!(err, cli::Error::InvalidConfig(..)));
+}
+
+#[test]
+fn test_resolve_settings() {
+    let store = temporary_path();
+    std::fs::create_dir(&store).unwrap();
+    let path = store.to_str().unwrap().to_string();
+
+    let resolve = |args: &[&str], vars: &[(&str, &str)]| {
+        let args = args.iter().map(OsString::from).collect();
+        let args = Ciphey::from_vec(args).unwrap();
+        let vars: HashMap<String, OsString> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), OsString::from(value)))
+            .collect();
+        cli::config::resolve(&args, |name| vars.get(name).cloned())
+    };
+
+    // The environment sets the defaults.
+    let vars = [
+        ("CIPHEY_STORE", path.as_str()),
+        ("CIPHEY_IDENTITY", "env-keys.txt"),
+        ("CIPHEY_RECIPIENTS", "a, b"),
+        ("CIPHEY_CRYPTO", "age"),
+        ("CIPHEY_SHOW", "yes"),
+        ("RAGE_IDENTITY", "rage-keys.txt"),
+    ];
+    let settings = resolve(&["list"], &vars).unwrap();
+    assert_eq!(settings.store, store);
+    assert_eq!(settings.identity_files, [PathBuf::from("env-keys.txt")]);
+    assert_eq!(settings.recipients, ["a", "b"]);
+    assert_eq!(settings.visibility, SecretVisibility::Show);
+
+    // The config file overrides the environment.
+    std::fs::write(Config::path(&store), r#"recipients = ["c"]"#).unwrap();
+    let settings = resolve(&["list"], &vars).unwrap();
+    let config = settings.config().unwrap();
+    assert_eq!(config.recipients, ["c"]);
+    assert_eq!(settings.default_recipients(&config), ["c"]);
+    assert_eq!(settings.default_recipients(&Config::default()), ["a", "b"]);
+
+    // A malformed config only fails the commands that load it.
+    std::fs::write(Config::path(&store), "recipients = 1").unwrap();
+    let settings = resolve(&["list"], &vars).unwrap();
+    let err = settings.config().unwrap_err();
+    assert!(matches!(err, cli::Error::InvalidConfig(..)));
+    std::fs::remove_file(Config::path(&store)).unwrap();
+
+    // The flags override both.
+    let other = temporary_path();
+    let args = [
+        "--path",
+        other.to_str().unwrap(),
+        "--age-keyfile",
+        "flag-keys.txt",
+        "list",
+    ];
+    let settings = resolve(&args, &vars).unwrap();
+    assert_eq!(settings.store, other);
+    assert_eq!(settings.identity_files, [PathBuf::from("flag-keys.txt")]);
+    assert_eq!(settings.recipients, ["a", "b"]);
+    let args = ["--path", &path, "--show", "list"];
+    let settings = resolve(&args, &[("CIPHEY_SHOW", "0")]);
+    assert_eq!(settings.unwrap().visibility, SecretVisibility::Show);
+
+    // Empty variables are unset, and the identity variables of age apply.
+    let vars = [("CIPHEY_IDENTITY", ""), ("RAGE_IDENTITY", "rage-keys.txt")];
+    let settings = resolve(&["--path", &path, "list"], &vars).unwrap();
+    assert_eq!(settings.identity_files, [PathBuf::from("rage-keys.txt")]);
+    assert_eq!(settings.visibility, SecretVisibility::Hide);
+
+    for vars in [[("CIPHEY_CRYPTO", "gpg")], [("CIPHEY_SHOW", "maybe")]] {
+        let err = resolve(&["--path", &path, "list"], &vars).unwrap_err();
+        assert!(matches!(err, cli::Error::InvalidVariable(..)));
+    }
+}
+
+#[test]
+fn test_list_config_default_fields() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "a", "b", &["username=c", "totp=d"]);
+
+    let config = Config::parse(r#"default_fields = ["totp"]"#).unwrap();
+    let list = |args: &[&str], storage: &mut Filesystem| {
+        let mut output = Vec::new();
+        cli::list(
+            &list_opts(args),
+            SecretVisibility::Hide,
+            &config,
+            &Transparent {},
+            storage,
+            &mut output,
+            &mut io::sink(),
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    let output = list(&["--quiet"], &mut storage);
+    assert_eq!(output, "totp: d\n");
+
+    // `--no-default` ignores the configured fields.
+    let output = list(&["--quiet", "--no-default", "-d", "name"], &mut storage);
+    assert_eq!(output, "name: a\n");
+}
+
+#[test]
+fn test_list_binary_entry() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "email", "hunter2", &[]);
+
+    let binary = Uuid::new_v4();
+    let mut reference = storage.add_entry(&binary).unwrap();
+    let mut writer = reference.writer().unwrap();
+    writer.write_all(b"---\n\xff\xfe\x00\n").unwrap();
+
+    let args: [&[&str]; 3] = [&[], &["--names-only"], &["--print0"]];
+    for args in args {
+        // Binary entries are skipped rather than failing the listing.
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        cli::list(
+            &list_opts(args),
+            SecretVisibility::Hide,
+            &Config::default(),
+            &Transparent {},
+            &mut storage,
+            &mut output,
+            &mut errors,
+        )
+        .unwrap();
+
+        assert!(String::from_utf8(output).unwrap().contains("email"));
+        assert_eq!(
+            String::from_utf8(errors).unwrap(),
+            format!(
+                "Skipping binary entry {}, use `ciphey cat --uuid {}` to \
+                 read it\n",
+                binary, binary
+            )
+        );
+    }
+}
+
+#[test]
+fn test_entry_sniff() {
+    use cli::entry::Format;
+
+    assert_eq!(Format::sniff(b"{\"name\": \"a\"}"), Format::Json);
+    assert_eq!(Format::sniff(b" \n\t{}"), Format::Json);
+    assert_eq!(Format::sniff(b"name=a\n"), Format::KvStore);
+    assert_eq!(Format::sniff(b""), Format::KvStore);
+    assert_eq!(Format::sniff(b"\xff\xfe\x00"), Format::Raw);
+}
+
+#[test]
+fn test_read_entry() {
+    let read = |contents: &[u8]| {
+        cli::entry::read_entry(&mut &contents[..]).map(|entry| match entry {
+            cli::AnyEntry::KvStore(store) => Some(store),
+            cli::AnyEntry::Raw => None,
+        })
+    };
+
+    let json = read(b"{\"name\": \"a\", \"secret\": {\"sensitive\": \"b\"}}")
+        .unwrap()
+        .unwrap();
+    let kvstore = read(b"name=a\nsecret!=b\n").unwrap().unwrap();
+    for store in [json, kvstore] {
+        assert_eq!(store.get(&Key::Name).unwrap().as_str(), "a");
+        let secret = store.get(&Key::from("secret")).unwrap();
+        assert_eq!(secret.as_str(), "b");
+        assert!(secret.is_sensitive());
+    }
+
+    assert!(read(b"\xff\xfe\x00").unwrap().is_none());
+
+    // The error does not quote the contents of the entry.
+    let err = read(b"{\"name\": 12345}").err().unwrap();
+    assert!(matches!(err, cli::entry::Error::Json(_)));
+    assert!(!err.to_string().contains("12345"));
+}
+
+#[test]
+fn test_list_mixed_formats() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "a", "s1", &[]);
+
+    let mut reference = storage.add_entry(&Uuid::new_v4()).unwrap();
+    let mut writer = reference.writer().unwrap();
+    writer
+        .write_all(b"---\n{\"name\": \"b\", \"username\": \"c\"}\n")
+        .unwrap();
+    drop(writer);
+
+    let mut output = Vec::new();
+    cli::list(
+        &list_opts(&["--quiet", "-d", "name", "-d", "username"]),
+        SecretVisibility::Hide,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        &mut output,
+        &mut io::sink(),
+    )
+    .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("name: a\n"));
+    assert!(output.contains("name: b\nusername: c\n"));
+}
+
+#[test]
+#[cfg(unix)]
+// Tests that a store without write permissions can be read, and that commands
+// which modify it fail before prompting.
+fn test_read_only_store() {
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = temporary_path();
+    let mut storage = Filesystem::new(&path).unwrap();
+    cli::init(&mut storage, false).unwrap();
+    add_entry(&mut storage, "example", "hunter2", &[]);
+    fs::set_permissions(&path, Permissions::from_mode(0o500)).unwrap();
+
+    let mut output = Vec::new();
+    cli::list(
+        &list_opts(&[]),
+        SecretVisibility::Hide,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        &mut output,
+        &mut io::sink(),
+    )
+    .unwrap();
+    assert!(String::from_utf8(output).unwrap().contains("name: example"));
+
+    let mut output = Vec::new();
+    cli::show(
+        &show_opts(&["example", "--raw"]),
+        SecretVisibility::Show,
+        &Transparent {},
+        &storage,
+        None,
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(output, b"hunter2");
+
+    // The prompts are never reached, so the input is left unread.
+    let mut input: &[u8] = b"name\n";
+    let err = cli::new(
+        &new_opts(&[]),
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        None,
+        &mut input,
+        &mut Vec::new(),
+        &mut io::sink(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, cli::Error::ReadOnlyStore));
+    assert_eq!(input, b"name\n");
+
+    fs::set_permissions(&path, Permissions::from_mode(0o700)).unwrap();
+    assert_eq!(raw_entries(&storage).len(), 1);
+}
+
+#[test]
+fn test_purge() {
+    let mut storage = temporary_store();
+    let mut index = cli::NameIndex::new(temporary_path());
+    add_entry(&mut storage, "a", "1", &[]);
+    add_entry(&mut storage, "b", "2", &[]);
+    index.insert("a".to_string(), raw_entries(&storage)[0].0);
+
+    let purge = |args: &[&str],
+                 mut input: &[u8],
+                 storage: &mut Filesystem,
+                 index: &mut cli::NameIndex| {
+        let mut output = Vec::new();
+        cli::purge(
+            &purge_opts(args),
+            storage,
+            Some(index),
+            &mut input,
+            &mut output,
+        )
+        .map(|_| String::from_utf8(output).unwrap())
+    };
+
+    // Nothing is removed without confirmation.
+    for input in [&b""[..], b"yes\n", b"PURGE\n"] {
+        let err = purge(&[], input, &mut storage, &mut index).unwrap_err();
+        assert!(matches!(err, cli::Error::PurgeNotConfirmed));
+    }
+    assert_eq!(raw_entries(&storage).len(), 2);
+
+    let output = purge(&[], b"purge\n", &mut storage, &mut index).unwrap();
+    assert!(output.starts_with("This will remove 2 entries."));
+    assert!(output.ends_with("Removed 2 entries\n"));
+    assert!(raw_entries(&storage).is_empty());
+    assert_eq!(index.get("a"), None);
+
+    add_entry(&mut storage, "c", "3", &[]);
+    let output = purge(&["--force"], b"", &mut storage, &mut index).unwrap();
+    assert_eq!(output, "Removed 1 entries\n");
+    assert!(raw_entries(&storage).is_empty());
+}
+
+// A store that fails to remove one entry.
+struct FailingRemove(Filesystem, Uuid);
+
+impl storage::Backend for FailingRemove {
+    type Reference = <Filesystem as storage::Backend>::Reference;
+
+    fn create(&mut self) -> io::Result<()> {
+        self.0.create()
+    }
+
+    fn location(&self) -> String {
+        self.0.location()
+    }
+
+    fn entries(&self) -> io::Result<HashMap<Uuid, Self::Reference>> {
+        self.0.entries()
+    }
+
+    fn add_entry(&mut self, uuid: &Uuid) -> io::Result<Self::Reference> {
+        self.0.add_entry(uuid)
+    }
+
+    fn remove_entry(&mut self, uuid: &Uuid) -> io::Result<()> {
+        if *uuid == self.1 {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        self.0.remove_entry(uuid)
+    }
+}
+
+#[test]
+fn test_purge_partial_failure() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "a", "1", &[]);
+    add_entry(&mut storage, "b", "2", &[]);
+
+    // Entries are removed in order of UUID, so the first is removed before
+    // the second fails.
+    let entries = raw_entries(&storage);
+    let (removed, failed) = (entries[0].0, entries[1].0);
+    let mut storage = FailingRemove(storage, failed);
+
+    let index_path = temporary_path();
+    let mut index = cli::NameIndex::new(index_path.clone());
+    index.insert("removed".to_string(), removed);
+    index.insert("failed".to_string(), failed);
+
+    let err = cli::purge(
+        &purge_opts(&["--force"]),
+        &mut storage,
+        Some(&mut index),
+        &mut empty(),
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, cli::Error::Storage(_)));
+
+    // The saved index no longer maps the name of the removed entry.
+    let index = cli::NameIndex::load(index_path).unwrap().unwrap();
+    assert_eq!(index.get("removed"), None);
+    assert_eq!(index.get("failed"), Some(failed));
+    assert_eq!(raw_entries(&storage.0).len(), 1);
+}
+
+#[test]
+fn test_remove_by_filter() {
+    let mut storage = temporary_store();
+    let mut index = cli::NameIndex::new(temporary_path());
+    // Tags are matched individually, as with `list --tag`.
+    add_entry(&mut storage, "a", "1", &["tags=work, old"]);
+    add_entry(&mut storage, "b", "2", &["tags=new"]);
+    add_entry(&mut storage, "c", "3", &["tags=old"]);
+    for entry in cli::decrypted_entries(&Transparent {}, &storage).unwrap() {
+        let (uuid, store) = entry.unwrap();
+        let name = store.get(&Key::Name).unwrap().as_str().to_string();
+        index.insert(name, uuid);
+    }
+
+    let remove = |args: &[&str],
+                  mut input: &[u8],
+                  storage: &mut Filesystem,
+                  index: &mut cli::NameIndex| {
+        let mut output = Vec::new();
+        cli::remove(
+            &remove_opts(args),
+            &Transparent {},
+            storage,
+            Some(index),
+            &mut input,
+            &mut output,
+        )
+        .map(|_| String::from_utf8(output).unwrap())
+    };
+
+    // A selector is required.
+    let err = remove(&[], b"y\n", &mut storage, &mut index).unwrap_err();
+    assert!(matches!(err, cli::Error::Xflags(_)));
+
+    let args = &["--filter", "tags=old"];
+    let err = remove(args, b"n\n", &mut storage, &mut index).unwrap_err();
+    assert!(matches!(err, cli::Error::RemoveNotConfirmed));
+    assert_eq!(raw_entries(&storage).len(), 3);
+
+    let output = remove(args, b"y\n", &mut storage, &mut index).unwrap();
+    assert!(output.contains(" a\n"));
+    assert!(output.contains(" c\n"));
+    assert!(!output.contains(" b\n"));
+    assert!(output.ends_with("Removed 2 entries\n"));
+
+    let remaining = raw_entries(&storage);
+    assert_eq!(remaining.len(), 1);
+    assert!(remaining[0].1.contains("name=b"));
+    assert_eq!(index.get("a"), None);
+    assert_eq!(index.get("b"), Some(remaining[0].0));
+
+    let output = remove(args, b"", &mut storage, &mut index).unwrap();
+    assert_eq!(output, "No entries match\n");
+
+    // Filters and the glob must all match.
+    let args = &["--glob", "b*", "--filter", "tags=old", "--force"];
+    let output = remove(args, b"", &mut storage, &mut index).unwrap();
+    assert_eq!(output, "No entries match\n");
+
+    let output =
+        remove(&["--glob", "b*", "--force"], b"", &mut storage, &mut index)
+            .unwrap();
+    assert!(output.ends_with("Removed 1 entries\n"));
+    assert!(raw_entries(&storage).is_empty());
+}
+
+#[test]
+fn test_json_errors() {
+    use ciphey_kvstore::KvStore;
+    use libciphey::filetype::Filetype;
+
+    use crate::cli::ErrorFormat;
+
+    let args = ["--error-format", "json", "doctor"].map(OsString::from);
+    let args = Ciphey::from_vec(args.to_vec()).unwrap();
+    assert_eq!(args.error_format, Some(ErrorFormat::Json));
+    let args = ["--error-format", "xml", "doctor"].map(OsString::from);
+    assert!(Ciphey::from_vec(args.to_vec()).is_err());
+
+    let storage = temporary_store();
+    let err = cli::show(
+        &show_opts(&["missing"]),
+        SecretVisibility::Hide,
+        &Transparent {},
+        &storage,
+        None,
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+
+    let mut stderr = Vec::new();
+    err.report_to(ErrorFormat::Json, &mut stderr).unwrap();
+    assert_eq!(
+        String::from_utf8(stderr).unwrap(),
+        "{\"kind\":\"entry_not_found\",\"message\":\"No entry found with \
+         name: missing\"}\n"
+    );
+
+    // Errors that may contain the contents of an entry are not reported.
+    let mut data: &[u8] = b"password!hunter2";
+    let err = KvStore::deserialize(&mut data).err().unwrap();
+    let err = cli::Error::Filetype(Box::new(err));
+    let mut stderr = Vec::new();
+    err.report_to(ErrorFormat::Json, &mut stderr).unwrap();
+    let stderr = String::from_utf8(stderr).unwrap();
+    assert!(stderr.contains("\"kind\":\"filetype\""));
+    assert!(!stderr.contains("hunter2"));
+}
+
+#[test]
+fn test_show_glob() {
+    let mut storage = temporary_store();
+    for name in ["aws-prod", "aws-dev", "gcp", "user@example.com"] {
+        add_entry(&mut storage, name, &format!("{}-secret", name), &[]);
+    }
+
+    let show = |args: &[&str]| {
+        let mut output = Vec::new();
+        cli::show(
+            &show_opts(&[&["--raw", "--glob"], args].concat()),
+            SecretVisibility::Show,
+            &Transparent {},
+            &storage,
+            None,
+            &mut output,
+        )
+        .map(|_| String::from_utf8(output).unwrap())
+    };
+
+    assert_eq!(show(&["*@example.com"]).unwrap(), "user@example.com-secret");
+    assert_eq!(show(&["g?p"]).unwrap(), "gcp-secret");
+    assert_eq!(show(&["aws-?ev"]).unwrap(), "aws-dev-secret");
+
+    let err = show(&["aws-*"]).unwrap_err();
+    assert!(
+        matches!(err, cli::Error::AmbiguousName(names) if names.len() == 2)
+    );
+
+    let output = show(&["aws-*", "--all"]).unwrap();
+    let mut secrets: Vec<&str> = output.lines().collect();
+    secrets.sort();
+    assert_eq!(secrets, ["aws-dev-secret", "aws-prod-secret"]);
+
+    assert!(matches!(show(&["a?s"]), Err(cli::Error::EntryNotFound(_))));
+    assert!(matches!(show(&["[a"]), Err(cli::Error::Xflags(_))));
+
+    // The pattern replaces the name, as with `remove --glob`.
+    let err = show(&["gcp", "gcp"]).unwrap_err();
+    assert!(matches!(err, cli::Error::Xflags(_)));
+}
+
+#[test]
+fn test_list_only_sensitive() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "a", "hunter2", &["pin!=1234", "note=x"]);
+
+    let mut output = Vec::new();
+    cli::list(
+        &list_opts(&["--quiet", "--all", "--only-sensitive"]),
+        SecretVisibility::Hide,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        &mut output,
+        &mut io::sink(),
+    )
+    .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output, "secret: *******\npin: ****\n");
+}
+
+#[test]
+fn test_list_redaction() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "a", "hunter2", &["pin!="]);
+
+    let mut output = Vec::new();
+    cli::list(
+        &list_opts(&["--quiet", "--all", "--redaction", "placeholder"]),
+        SecretVisibility::Hide,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        &mut output,
+        &mut io::sink(),
+    )
+    .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output, "name: a\nsecret: <hidden>\npin: <hidden>\n");
+}
+
+#[test]
+fn test_select_menu() {
+    use crate::cli::select::select_menu;
+
+    let names: Vec<String> = (1..=10).map(|n| format!("entry-{}", n)).collect();
+    let select = |input: &str| {
+        let mut output = Vec::new();
+        let selected =
+            select_menu(&names, &mut input.as_bytes(), &mut output).unwrap();
+        (selected, String::from_utf8(output).unwrap())
+    };
+
+    let (selected, output) = select("2\n");
+    assert_eq!(selected.as_deref(), Some("entry-2"));
+    assert!(output.starts_with(" 1) entry-1\n 2) entry-2\n"));
+    assert!(output.contains("10) entry-10\n"));
+
+    // Invalid answers are asked again.
+    let (selected, output) = select("0\nfoo\n11\n10\n");
+    assert_eq!(selected.as_deref(), Some("entry-10"));
+    assert_eq!(output.matches("Enter a number from 1 to 10").count(), 3);
+
+    // An empty answer or the end of input selects nothing.
+    assert_eq!(select("\n").0, None);
+    assert_eq!(select("").0, None);
+}
+
+#[test]
+fn test_show_select() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "a", "1", &[]);
+
+    let err = cli::show(
+        &show_opts(&["--select"]),
+        SecretVisibility::Show,
+        &Transparent {},
+        &storage,
+        None,
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, cli::Error::Xflags(_)));
+
+    // The picker only runs when stdout is a terminal.
+    let err = cli::select_name(
+        &Transparent {},
+        &storage,
+        false,
+        &mut empty(),
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, cli::Error::Xflags(_)));
+}
+
+#[test]
+fn test_list_names_only() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "email", "hunter2", &["username=user"]);
+    add_entry(&mut storage, "bank", "1234", &["tags=money"]);
+
+    let mut list = |args: &[&str]| {
+        let mut output = Vec::new();
+        cli::list(
+            &list_opts(args),
+            SecretVisibility::Show,
+            &Config::default(),
+            &Transparent {},
+            &mut storage,
+            &mut output,
+            &mut io::sink(),
+        )
+        .map(|_| String::from_utf8(output).unwrap())
+    };
+
+    let output = list(&["--names-only"]).unwrap();
+    let mut names: Vec<&str> = output.lines().collect();
+    names.sort();
+    assert_eq!(names, ["bank", "email"]);
+    assert!(output.ends_with('\n'));
+
+    // Filters still apply.
+    let output = list(&["--names-only", "--tag", "money"]).unwrap();
+    assert_eq!(output, "bank\n");
+
+    assert!(list(&["--names-only", "--print0"]).is_err());
+}
+
+#[test]
+fn test_list_fail_empty() {
+    use std::process::{ExitCode, Termination};
+
+    let mut storage = temporary_store();
+
+    let mut list = |args: &[&str]| {
+        let mut output = Vec::new();
+        let result = cli::list(
+            &list_opts(args),
+            SecretVisibility::Show,
+            &Config::default(),
+            &Transparent {},
+            &mut storage,
+            &mut output,
+            &mut io::sink(),
+        );
+        (result, String::from_utf8(output).unwrap())
+    };
+
+    // The output is still written for an empty store.
+    let (result, output) = list(&["--fail-empty"]);
+    assert_eq!(output, "Found 0 Entries\n");
+    let err = result.unwrap_err();
+    assert!(matches!(err, cli::Error::NoEntries));
+    assert_eq!(err.report(), ExitCode::from(cli::EXIT_NO_ENTRIES));
+
+    for args in [&["--quiet"][..], &["--names-only"], &["--print0"]] {
+        let (result, output) = list(&[args, &["--fail-empty"]].concat());
+        assert!(matches!(result, Err(cli::Error::NoEntries)));
+        assert_eq!(output, "");
+    }
+
+    // Without the flag, an empty store is not an error.
+    assert!(list(&[]).0.is_ok());
+
+    add_entry(&mut storage, "email", "hunter2", &["tags=work"]);
+    let mut list = |args: &[&str]| {
+        let mut output = Vec::new();
+        cli::list(
+            &list_opts(args),
+            SecretVisibility::Show,
+            &Config::default(),
+            &Transparent {},
+            &mut storage,
+            &mut output,
+            &mut io::sink(),
+        )
+    };
+    assert!(list(&["--fail-empty"]).is_ok());
+    assert!(list(&["--fail-empty", "--names-only"]).is_ok());
+
+    // Entries that are filtered out do not count.
+    let result = list(&["--fail-empty", "--tag", "home"]);
+    assert!(matches!(result, Err(cli::Error::NoEntries)));
+}
+
+#[test]
+fn test_list_window() {
+    let mut storage = temporary_store();
+    for name in ["a", "b", "c", "d", "e"] {
+        add_entry(&mut storage, name, "1", &[]);
+    }
+
+    let list = |args: &[&str], storage: &mut Filesystem| {
+        let mut output = Vec::new();
+        let args = [&["--no-default", "--display", "name"], args].concat();
+        cli::list(
+            &list_opts(&args),
+            SecretVisibility::Show,
+            &Config::default(),
+            &Transparent {},
+            storage,
+            &mut output,
+            &mut io::sink(),
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    // Entries are listed in a stable order, which the windows are cut from.
+    let names = list(&["--names-only"], &mut storage);
+    let names: Vec<&str> = names.lines().collect();
+    assert_eq!(names.len(), 5);
+
+    // At the start.
+    let output = list(&["--max-entries", "2"], &mut storage);
+    let expected = format!(
+        "Found 5 Entries\n---\nname: {}\n---\nname: {}\n---\nShowing 1-2 of \
+         5\n",
+        names[0], names[1]
+    );
+    assert_eq!(output, expected);
+
+    // In the middle.
+    let output = list(&["--offset", "2", "--max-entries", "2"], &mut storage);
+    assert!(output
+        .contains(&format!("name: {}\n---\nname: {}\n", names[2], names[3])));
+    assert!(output.ends_with("Showing 3-4 of 5\n"));
+
+    // A window that runs past the end is cut short.
+    let output = list(&["--offset", "4", "--max-entries", "2"], &mut storage);
+    assert!(output
+        .ends_with(&format!("name: {}\n---\nShowing 5-5 of 5\n", names[4])));
+
+    // Past the end.
+    let output = list(&["--offset", "7"], &mut storage);
+    assert_eq!(output, "Found 5 Entries\n---\nShowing none of 5\n");
+    let output = list(&["--offset", "7", "--quiet"], &mut storage);
+    assert_eq!(output, "");
+
+    let output = list(
+        &["--names-only", "--offset", "1", "--max-entries", "3"],
+        &mut storage,
+    );
+    assert_eq!(output.lines().collect::<Vec<_>>(), names[1..4]);
+}
+
+#[test]
+fn test_list_separator() {
+    let mut storage = temporary_store();
+
+    let list = |args: &[&str], storage: &mut Filesystem| {
+        let mut output = Vec::new();
+        let args = [&["--no-default", "--display", "name"], args].concat();
+        cli::list(
+            &list_opts(&args),
+            SecretVisibility::Show,
+            &Config::default(),
+            &Transparent {},
+            storage,
+            &mut output,
+            &mut io::sink(),
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(list(&[], &mut storage), "Found 0 Entries\n");
+    assert_eq!(list(&["--quiet"], &mut storage), "");
+    assert_eq!(list(&["--quiet", "--leading-separator"], &mut storage), "");
+
+    add_entry(&mut storage, "a", "1", &[]);
+    // The separator follows the statistics, but does not lead the output.
+    assert_eq!(list(&[], &mut storage), "Found 1 Entry\n---\nname: a\n");
+    assert_eq!(list(&["--quiet"], &mut storage), "name: a\n");
+    assert_eq!(
+        list(&["--quiet", "--leading-separator"], &mut storage),
+        "---\nname: a\n"
+    );
+
+    add_entry(&mut storage, "b", "2", &[]);
+    add_entry(&mut storage, "c", "3", &[]);
+    let output = list(&["--quiet"], &mut storage);
+    assert_eq!(output.matches("---\n").count(), 2);
+    assert!(!output.starts_with("---"));
+    assert!(!output.ends_with("---\n"));
+
+    let output = list(&["--separator", "==="], &mut storage);
+    assert!(output.starts_with("Found 3 Entries\n===\nname: "));
+    assert_eq!(output.matches("===\n").count(), 3);
+    assert!(!output.contains("---"));
+}
+
+#[test]
+fn test_mask_char() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "a", "hunter2", &[]);
+
+    let mut output = Vec::new();
+    cli::list(
+        &list_opts(&["--quiet", "--all", "--mask-char", "•"]),
+        SecretVisibility::Hide,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        &mut output,
+        &mut io::sink(),
+    )
+    .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output, "name: a\nsecret: •••••••\n");
+
+    let mut output = Vec::new();
+    cli::show(
+        &show_opts(&["a", "--mask-char", "#", "--redaction", "fixed:3"]),
+        SecretVisibility::Hide,
+        &Transparent {},
+        &storage,
+        None,
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(output, b"secret: ###\n");
+
+    // The mask must be a single character.
+    for mask in ["**", ""] {
+        let args = ["list", "--mask-char", mask].map(OsString::from);
+        assert!(Ciphey::from_vec(args.to_vec()).is_err());
+    }
+}
+
+#[test]
+fn test_matches_tags() {
+    let tags = |tags: &[&str]| -> std::collections::BTreeSet<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    };
+    let entry = tags(&["shared", "work"]);
+
+    // Every tag is required by default.
+    assert!(cli::matches_tags(&entry, &tags(&["work"]), false));
+    assert!(cli::matches_tags(&entry, &tags(&["work", "shared"]), false));
+    assert!(!cli::matches_tags(&entry, &tags(&["work", "old"]), false));
+
+    // Any tag is enough with --any-tag.
+    assert!(cli::matches_tags(&entry, &tags(&["work", "old"]), true));
+    assert!(!cli::matches_tags(&entry, &tags(&["old"]), true));
+
+    // Without filters, every entry is selected, even without tags.
+    for any in [false, true] {
+        assert!(cli::matches_tags(&tags(&[]), &tags(&[]), any));
+    }
+}
+
+#[test]
+fn test_list_tags() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "a", "1", &["tags=work, shared"]);
+    add_entry(&mut storage, "b", "2", &["tags=work"]);
+    add_entry(&mut storage, "c", "3", &["tags=personal"]);
+
+    let mut list = |args: &[&str]| {
+        let args = [&["--no-default", "--display", "name"], args].concat();
+        let mut output = Vec::new();
+        cli::list(
+            &list_opts(&args),
+            SecretVisibility::Hide,
+            &Config::default(),
+            &Transparent {},
+            &mut storage,
+            &mut output,
+            &mut io::sink(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let mut names: Vec<String> = output
+            .lines()
+            .filter_map(|line| line.strip_prefix("name: "))
+            .map(String::from)
+            .collect();
+        names.sort();
+        (output, names)
+    };
+
+    let (output, names) = list(&["--tag", "work"]);
+    assert!(output.starts_with("Found 2 Entries\n"));
+    assert_eq!(names, ["a", "b"]);
+
+    let (_, names) = list(&["--tag", "work", "--tag", " shared "]);
+    assert_eq!(names, ["a"]);
+
+    let (_, names) =
+        list(&["--tag", "shared", "--tag", "personal", "--any-tag"]);
+    assert_eq!(names, ["a", "c"]);
+
+    let (output, names) = list(&["--tag", "old"]);
+    assert_eq!(output, "Found 0 Entries\n");
+    assert!(names.is_empty());
+}
+
+#[test]
+fn test_list_field_order() {
+    let mut storage = temporary_store();
+    add_entry(
+        &mut storage,
+        "a",
+        "hunter2",
+        &["url=example.com", "username=user"],
+    );
+
+    let mut output = Vec::new();
+    cli::list(
+        &list_opts(&["--all", "--field-order", "name, username,secret"]),
+        SecretVisibility::Hide,
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        &mut output,
+        &mut io::sink(),
+    )
+    .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let fields: Vec<&str> = output
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(": "))
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(fields, ["name", "username", "secret", "url"]);
+}
+
+#[test]
+fn test_list_dates() {
+    let mut storage = temporary_store();
+    let day = |days: u64| {
+        std::time::UNIX_EPOCH + Duration::from_secs(days * 24 * 60 * 60)
+    };
+
+    // 2024-01-01, 2024-01-02 at noon and 2024-01-05 in UTC.
+    let dates = [
+        ("a", day(19723)),
+        ("b", day(19724) + Duration::from_secs(43200)),
+        ("c", day(19727)),
+    ];
+    for (name, modified) in dates {
+        add_entry(&mut storage, name, "secret", &[]);
+        let (uuid, _) = raw_entries(&storage)
+            .into_iter()
+            .find(|(_, data)| data.contains(&format!("name={}\n", name)))
+            .unwrap();
+        let path = PathBuf::from(storage.location())
+            .join(format!("entries/{}.age", uuid));
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    let list = |storage: &mut Filesystem, args: &[&str]| {
+        let args = [&["--no-default", "--display", "name"], args].concat();
+        let mut output = Vec::new();
+        cli::list(
+            &list_opts(&args),
+            SecretVisibility::Hide,
+            &Config::default(),
+            &Transparent {},
+            storage,
+            &mut output,
+            &mut io::sink(),
+        )
+        .map(|_| {
+            let mut names: Vec<String> = String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .filter_map(|line| line.strip_prefix("name: "))
+                .map(String::from)
+                .collect();
+            names.sort();
+            names
+        })
+    };
+
+    let names = list(&mut storage, &["--since", "2024-01-02"]).unwrap();
+    assert_eq!(names, ["b", "c"]);
+
+    // A bare `--until` date includes the whole day.
+    let names = list(&mut storage, &["--until", "2024-01-02"]).unwrap();
+    assert_eq!(names, ["a", "b"]);
+
+    let args = [
+        "--since",
+        "2024-01-02T13:00:00+02:00",
+        "--until",
+        "2024-01-04",
+    ];
+    let names = list(&mut storage, &args).unwrap();
+    assert_eq!(names, ["b"]);
+
+    assert!(list(&mut storage, &["--since", "yesterday"]).is_err());
+
+    // Entries in a store without modification times are undated.
+    let mut storage = InMemory::default();
+    cli::init(&mut storage, false).unwrap();
+    add_entry(&mut storage, "undated", "secret", &[]);
+    let list = |storage: &mut InMemory, args: &[&str]| {
+        let args = [&["--no-default", "--display", "name"], args].concat();
+        let mut output = Vec::new();
+        cli::list(
+            &list_opts(&args),
+            SecretVisibility::Hide,
+            &Config::default(),
+            &Transparent {},
+            storage,
+            &mut output,
+            &mut io::sink(),
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    let output = list(&mut storage, &["--since", "2024-01-01"]);
+    assert_eq!(output, "Found 0 Entries\n");
+
+    let output = list(
+        &mut storage,
+        &["--since", "2024-01-01", "--include-undated"],
+    );
+    assert!(output.contains("name: undated\n"));
+}
+
+#[test]
+fn test_new_validates_entry() {
+    let mut storage = temporary_store();
+
+    let mut new = |args: &[&str]| {
+        cli::new(
+            &new_opts(args),
+            &Config::default(),
+            &Transparent {},
+            &mut storage,
+            None,
+            &mut empty(),
+            &mut Vec::new(),
+            &mut io::sink(),
+        )
+    };
+
+    let err = new(&["--name", "", "--secret", "b"]).unwrap_err();
+    assert!(matches!(err, cli::Error::Filetype(_)));
+
+    let err = new(&["-n", "a", "-s", "b", "-k", "name=c"]).unwrap_err();
+    assert!(matches!(err, cli::Error::Filetype(_)));
+
+    let mut input: &[u8] = b"name=a;secret=1\nname=;secret=2\n";
+    let err = cli::new(
+        &new_opts(&["--batch"]),
+        &Config::default(),
+        &Transparent {},
+        &mut storage,
+        None,
+        &mut input,
+        &mut Vec::new(),
+        &mut io::sink(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, cli::Error::InvalidRecord(2, _)));
+
+    assert!(raw_entries(&storage).is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_agent_messages() {
+    use age::secrecy::{ExposeSecret, SecretString};
+    use cli::agent::{Request, Response};
+
+    let format = |request: Request| {
+        let mut line = Vec::new();
+        request.write_to(&mut line).unwrap();
+        String::from_utf8(line).unwrap()
+    };
+    assert_eq!(format(Request::Get), "GET\n");
+    assert_eq!(format(Request::Clear), "CLEAR\n");
+    assert_eq!(format(Request::Stop), "STOP\n");
+    let set = Request::Set(SecretString::new("hunter 2".to_string()));
+    assert_eq!(format(set), "SET hunter 2\n");
+
+    match Request::parse("SET hunter 2") {
+        Some(Request::Set(passphrase)) => {
+            assert_eq!(passphrase.expose_secret(), "hunter 2")
+        }
+        request => panic!("unexpected request: {:?}", request),
+    }
+    assert!(matches!(Request::parse("GET"), Some(Request::Get)));
+    assert!(Request::parse("GET extra").is_none());
+    assert!(Request::parse("get").is_none());
+    assert!(Request::parse("").is_none());
+
+    match Response::parse("PASSPHRASE hunter2") {
+        Some(Response::Passphrase(passphrase)) => {
+            assert_eq!(passphrase.expose_secret(), "hunter2")
+        }
+        response => panic!("unexpected response: {:?}", response),
+    }
+    assert!(matches!(Response::parse("EMPTY"), Some(Response::Empty)));
+    assert!(Response::parse("PASSPHRASE").is_none());
+
+    // A newline would end the request early.
+    let set = Request::Set(SecretString::new("hunter\n2".to_string()));
+    let err = set.write_to(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+/// Starts an agent on a socket in a new temporary directory.
+#[cfg(unix)]
+fn start_agent() -> (PathBuf, thread::JoinHandle<io::Result<()>>) {
+    let dir = temporary_path();
+    std::fs::create_dir(&dir).unwrap();
+    let socket = dir.join("agent.sock");
+
+    let listener = cli::agent::bind(&socket).unwrap();
+    let agent = cli::agent::Agent::new(Duration::from_millis(100));
+    let agent = thread::spawn(move || agent.serve(&listener));
+    (socket, agent)
+}
+
+#[cfg(unix)]
+#[test]
+fn test_agent_socket() {
+    use std::io::BufRead;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+
+    // Sends a raw line to the agent, as a client would.
+    let send = |socket: &PathBuf, line: &str| {
+        let mut stream = UnixStream::connect(socket).unwrap();
+        stream.write_all(line.as_bytes()).unwrap();
+        let mut response = String::new();
+        io::BufReader::new(stream).read_line(&mut response).unwrap();
+        response
+    };
+
+    let (socket, agent) = start_agent();
+    let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    assert_eq!(send(&socket, "GET\n"), "EMPTY\n");
+    assert_eq!(send(&socket, "SET hunter2\n"), "OK\n");
+    assert_eq!(send(&socket, "GET\n"), "PASSPHRASE hunter2\n");
+    assert_eq!(send(&socket, "SET correct horse\n"), "OK\n");
+    assert_eq!(send(&socket, "GET\n"), "PASSPHRASE correct horse\n");
+    assert_eq!(send(&socket, "BOGUS\n"), "INVALID\n");
+    assert_eq!(send(&socket, "CLEAR\n"), "OK\n");
+    assert_eq!(send(&socket, "GET\n"), "EMPTY\n");
+
+    // A client that hangs up without a request does not stop the agent.
+    drop(UnixStream::connect(&socket).unwrap());
+    assert_eq!(send(&socket, "SET hunter2\n"), "OK\n");
+
+    // Nor does a client that never sends its request.
+    let idle = UnixStream::connect(&socket).unwrap();
+    assert_eq!(send(&socket, "GET\n"), "PASSPHRASE hunter2\n");
+    drop(idle);
+
+    // An existing socket is never replaced.
+    let err = cli::agent::bind(&socket).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    let dir = socket.parent().unwrap();
+    assert_eq!(std::fs::read_dir(dir).unwrap().count(), 1);
+
+    assert_eq!(send(&socket, "STOP\n"), "OK\n");
+    agent.join().unwrap().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_agent_passphrase() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use age::secrecy::{ExposeSecret, SecretString};
+    use cli::agent::{AgentPassphrase, Request};
+
+    use crate::backends::crypto::age::PassphraseSource;
+
+    let prompts = Arc::new(AtomicUsize::new(0));
+    let source = |socket: Option<PathBuf>| {
+        let prompts = prompts.clone();
+        AgentPassphrase::new(socket, move || {
+            prompts.fetch_add(1, Ordering::SeqCst);
+            Ok(SecretString::new("hunter2".to_string()))
+        })
+    };
+    let passphrase = |source: &dyn PassphraseSource| {
+        source.passphrase().unwrap().expose_secret().to_string()
+    };
+
+    let (socket, agent) = start_agent();
+
+    // The first process prompts and caches the passphrase in the agent.
+    let first = source(Some(socket.clone()));
+    assert_eq!(passphrase(&first), "hunter2");
+    assert_eq!(passphrase(&first), "hunter2");
+    assert_eq!(prompts.load(Ordering::SeqCst), 1);
+
+    // Later processes get it from the agent.
+    let second = source(Some(socket.clone()));
+    assert_eq!(passphrase(&second), "hunter2");
+    assert_eq!(prompts.load(Ordering::SeqCst), 1);
+
+    // A rejected passphrase is forgotten by the agent too.
+    second.reject();
+    assert_eq!(passphrase(&source(Some(socket.clone()))), "hunter2");
+    assert_eq!(prompts.load(Ordering::SeqCst), 2);
+
+    cli::agent::request(&socket, &Request::Stop).unwrap();
+    agent.join().unwrap().unwrap();
+
+    // Without a reachable agent, the passphrase is prompted for.
+    assert_eq!(passphrase(&source(Some(socket))), "hunter2");
+    assert_eq!(passphrase(&source(None)), "hunter2");
+    assert_eq!(prompts.load(Ordering::SeqCst), 4);
+}
+
+// A clipboard that records every value it is set to.
+#[derive(Default)]
+struct RecordingClipboard {
+    history: Vec<String>,
+    // Contents that replace whatever is set, as if copied by another program.
+    replaced_by: Option<String>,
+}
+
+impl cli::clipboard::Clipboard for RecordingClipboard {
+    fn set(&mut self, contents: &str) -> io::Result<()> {
+        self.history.push(contents.to_string());
+        Ok(())
+    }
+
+    fn get(&mut self) -> io::Result<String> {
+        let contents = self.replaced_by.as_ref().or(self.history.last());
+        Ok(contents.cloned().unwrap_or_default())
+    }
+}
+
+#[test]
+fn test_copy_clears_clipboard() {
+    let mut storage = temporary_store();
+    add_entry(&mut storage, "a", "hunter2", &["username=b"]);
+
+    let copy = |args: &[&str], interrupt: bool, storage: &mut Filesystem| {
+        let (sender, interrupted) = std::sync::mpsc::channel();
+        if interrupt {
+            sender.send(()).unwrap();
+        }
+        let mut clipboard = RecordingClipboard::default();
+        let mut output = Vec::new();
+        let result = cli::copy(
+            &copy_opts(args),
+            &Transparent {},
+            storage,
+            None,
+            &mut clipboard,
+            &interrupted,
+            &mut output,
+        );
+        (result, clipboard.history)
+    };
+
+    // The clipboard is cleared once the timeout has passed.
+    let start = Instant::now();
+    let (result, history) = copy(&["a", "--timeout", "1"], false, &mut storage);
+    result.unwrap();
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    assert_eq!(history, ["hunter2", ""]);
+
+    // An interrupt clears the clipboard without waiting for the timeout.
+    let start = Instant::now();
+    let (result, history) = copy(&["a", "-k", "username"], true, &mut storage);
+    assert!(matches!(result, Err(cli::Error::Interrupted)));
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_eq!(history, ["b", ""]);
+
+    // `--no-wait` leaves the value on the clipboard.
+    let (result, history) = copy(&["a", "--no-wait"], false, &mut storage);
+    result.unwrap();
+    assert_eq!(history, ["hunter2"]);
+
+    // A secret stored under another key is copied by that key.
+    let args = ["a", "--secret-key", "username", "--no-wait"];
+    let (result, history) = copy(&args, false, &mut storage);
+    result.unwrap();
+    assert_eq!(history, ["b"]);
+
+    let (result, history) = copy(&["a", "-k", "url"], false, &mut storage);
+    assert!(matches!(result, Err(cli::Error::FieldNotFound(_))));
+    assert!(history.is_empty());
+
+    // Something else that was copied in the meantime is not cleared.
+    let (sender, interrupted) = std::sync::mpsc::channel();
+    sender.send(()).unwrap();
+    let mut clipboard = RecordingClipboard {
+        replaced_by: Some("other".to_string()),
+        ..Default::default()
+    };
+    let timeout = Duration::from_secs(10);
+    let result = cli::clipboard::copy_and_clear(
+        &mut clipboard,
+        "hunter2",
+        timeout,
+        &interrupted,
+    );
+    assert!(result.unwrap());
+    assert_eq!(clipboard.history, ["hunter2"]);
+}
+
+#[test]
+fn test_copy_glob() {
+    let mut storage = temporary_store();
+    for name in ["aws-prod", "aws-dev", "gcp", "user@example.com"] {
+        add_entry(&mut storage, name, &format!("{}-secret", name), &[]);
+    }
+
+    let copy = |args: &[&str]| {
+        let (_, interrupted) = std::sync::mpsc::channel();
+        let mut clipboard = RecordingClipboard::default();
+        let result = cli::copy(
+            &copy_opts(&[&["--no-wait", "--glob"], args].concat()),
+            &Transparent {},
+            &storage,
+            None,
+            &mut clipboard,
+            &interrupted,
+            &mut Vec::new(),
+        );
+        result.map(|_| clipboard.history)
+    };
+
+    assert_eq!(
+        copy(&["*@example.com"]).unwrap(),
+        ["user@example.com-secret"]
+    );
+    assert_eq!(copy(&["g?p"]).unwrap(), ["gcp-secret"]);
+    assert_eq!(copy(&["aws-?ev"]).unwrap(), ["aws-dev-secret"]);
+
+    // Only a single entry can be copied.
+    let err = copy(&["aws-*"]).unwrap_err();
+    assert!(
+        matches!(err, cli::Error::AmbiguousName(names) if names.len() == 2)
+    );
+
+    assert!(matches!(copy(&["a?s"]), Err(cli::Error::EntryNotFound(_))));
+    assert!(matches!(copy(&["gcp", "gcp"]), Err(cli::Error::Xflags(_))));
+}
+
+#[test]
+fn test_find_team_recipients() {
+    let outer = temporary_path();
+    let repo = outer.join("repo");
+    let store = repo.join("secrets").join("store");
+    fs::create_dir_all(&store).unwrap();
+    let team = |dir: &Path| dir.join(".age-recipients");
+
+    // Files outside of a git repository are never used.
+    fs::write(team(&outer), "").unwrap();
+    assert_eq!(cli::util::find_team_recipients(&store), None);
+
+    // The walk stops at the root of the repository.
+    fs::create_dir(repo.join(".git")).unwrap();
+    assert_eq!(cli::util::find_team_recipients(&store), None);
+
+    fs::write(team(&repo), "").unwrap();
+    let found = cli::util::find_team_recipients(&store).unwrap();
+    assert_eq!(found, team(&repo).canonicalize().unwrap());
+
+    // The closest file to the store is used.
+    fs::write(team(&store), "").unwrap();
+    let found = cli::util::find_team_recipients(&store).unwrap();
+    assert_eq!(found, team(&store).canonicalize().unwrap());
+}
+
+#[test]
+fn test_default_new_recipients() {
+    let recipients = |args: &[&str], team: Option<&str>| {
+        let mut opts = new_opts(args);
+        let team = team.map(|team| vec![OsString::from(team)]);
+        let defaults = vec![OsString::from("default")];
+        cli::default_new_recipients(&mut opts, team, defaults);
+        opts.recipient
+    };
+
+    // The team's recipients are used when none are passed.
+    assert_eq!(recipients(&[], Some("team")), ["team"]);
+    assert_eq!(recipients(&["-r", "a"], Some("team")), ["a"]);
+    let args = ["--no-team-recipients"];
+    assert_eq!(recipients(&args, Some("team")), ["default"]);
+    assert!(recipients(&["--recipients-stdin"], Some("team")).is_empty());
+
+    // Stores without a team file use the defaults.
+    assert_eq!(recipients(&[], None), ["default"]);
+}
+
+#[test]
+fn test_doctor_fix() {
+    let mut storage = temporary_store();
+    let entries = PathBuf::from(storage.location()).join("entries");
+    add_entry(&mut storage, "a", "b", &[]);
+    let before = raw_entries(&storage);
+    let uuid = before[0].0;
+    let doctor = |args: &[&str], answers: &str, storage: &mut Filesystem| {
+        let mut output = Vec::new();
+        let result = cli::doctor(
+            &doctor_opts(args),
+            None,
+            storage,
+            &mut answers.as_bytes(),
+            &mut output,
+        );
+        (result, String::from_utf8(output).unwrap())
+    };
+
+    // Seed a misnamed entry and an empty entry.
+    let misnamed = entries.join(format!("{}.age", uuid));
+    fs::rename(&misnamed, misnamed.with_extension("txt")).unwrap();
+    let empty = entries.join(format!("{}.age", Uuid::new_v4()));
+    fs::write(&empty, "").unwrap();
+
+    // Without --fix, problems are only reported.
+    let (result, _) = doctor(&[], "", &mut storage);
+    assert!(matches!(result, Err(cli::Error::UnhealthyStore(2))));
+
+    // Declined fixes are skipped.
+    let (result, output) = doctor(&["--fix"], "n\nn\n", &mut storage);
+    assert!(matches!(result, Err(cli::Error::UnhealthyStore(2))));
+    assert_eq!(output.matches("Skipped: ").count(), 2);
+
+    // --yes only confirms fixes that do not remove files.
+    let (result, output) = doctor(&["--fix", "--yes"], "n\n", &mut storage);
+    assert!(matches!(result, Err(cli::Error::UnhealthyStore(1))));
+    assert!(output
+        .contains(&format!("Skipped: Remove empty entry {}", empty.display())));
+    assert!(misnamed.exists());
+
+    let (result, output) = doctor(&["--fix", "--yes"], "y\n", &mut storage);
+    result.unwrap();
+    assert!(output.ends_with("No problems remain\n"));
+    assert!(!empty.exists());
+
+    // The store is healthy, and the repaired entry can be read.
+    let (result, output) = doctor(&[], "", &mut storage);
+    result.unwrap();
+    assert!(output.ends_with("No problems found\n"));
+    assert_eq!(raw_entries(&storage), before);
+
+    // A malformed config is reported rather than repaired.
+    let path = Config::path(Path::new(&storage.location()));
+    let config_error = cli::Error::InvalidConfig(path, "invalid".to_string());
+    let mut output = Vec::new();
+    let result = cli::doctor(
+        &doctor_opts(&["--fix", "--yes"]),
+        Some(config_error),
+        &mut storage,
+        &mut io::empty(),
+        &mut output,
+    );
+    assert!(matches!(result, Err(cli::Error::UnhealthyStore(1))));
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("config.toml: invalid\n"));
+}
+
+#[test]
+fn test_migrate() {
+    use cli::migrate::{self, Migration};
+
+    let mut storage = temporary_store();
+    let store = PathBuf::from(storage.location());
+    add_entry(&mut storage, "a", "b", &["url=example.com"]);
+    let original = raw_entries(&storage);
+    let uuid = original[0].0;
+
+    // A hypothetical version 1 renames `url` to `website`.
+    fn rename_url(store: ciphey_kvstore::KvStore) -> ciphey_kvstore::KvStore {
+        let mut store = store;
+        for kv_pair in store.iter_mut() {
+            if kv_pair.key == Key::Url {
+                kv_pair.key = Key::from("website");
+            }
+        }
+        store
+    }
+    let migrations: &[Migration] = &[rename_url];
+
+    let migrate = |args: &[&str], storage: &mut Filesystem| {
+        let mut output = Vec::new();
+        let opts = migrate_opts(args);
+        migrate::migrate_with(
+            migrations,
+            &opts,
+            &Transparent {},
+            storage,
+            &store,
+            &mut output,
+        )
+        .map(|_| String::from_utf8(output).unwrap())
+    };
+
+    // Stores without a VERSION file are at version 0.
+    let output = migrate(&["--store-format-version"], &mut storage).unwrap();
+    assert_eq!(
+        output,
+        "Store format version: 0\nCurrent format version: 1\n"
+    );
+
+    let output = migrate(&[], &mut storage).unwrap();
+    assert!(output.ends_with("Migrated 1 entries from format version 0 to 1\n"));
+    assert_eq!(migrate::read_version(&store).unwrap(), 1);
+    let migrated = raw_entries(&storage);
+    assert_eq!(migrated[0].0, uuid);
+    assert!(migrated[0].1.contains("website=example.com"));
+    assert!(!migrated[0].1.contains("url="));
+
+    // The original entry is backed up.
+    let backup = migrate::backup_path(&store, 0).join(format!("{}.age", uuid));
+    assert_eq!(fs::read_to_string(backup).unwrap(), original[0].1);
+
+    // Migrating an up-to-date store does nothing.
+    let output = migrate(&[], &mut storage).unwrap();
+    assert_eq!(output, "Store is at format version 1\n");
+    assert_eq!(raw_entries(&storage), migrated);
+
+    // Stores from a newer version of ciphey are refused.
+    fs::write(migrate::version_path(&store), "2\n").unwrap();
+    let err = migrate(&[], &mut storage).unwrap_err();
+    assert!(matches!(err, cli::Error::UnsupportedVersion(2, 1)));
+    fs::write(migrate::version_path(&store), "two\n").unwrap();
+    let err = migrate(&[], &mut storage).unwrap_err();
+    assert!(matches!(err, cli::Error::InvalidConfig(..)));
+}
+
+#[test]
+fn test_migrate_binary_entry() {
+    use cli::migrate;
+
+    let mut storage = temporary_store();
+    let store = PathBuf::from(storage.location());
+    add_entry(&mut storage, "a", "b", &[]);
+
+    let binary = Uuid::new_v4();
+    let contents = b"---\n\xff\xfe\x00\n";
+    let mut reference = storage.add_entry(&binary).unwrap();
+    reference.writer().unwrap().write_all(contents).unwrap();
+
+    let mut output = Vec::new();
+    migrate::migrate_with(
+        &[|store| store],
+        &migrate_opts(&[]),
+        &Transparent {},
+        &mut storage,
+        &store,
+        &mut output,
+    )
+    .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains(&format!("Kept binary entry {} unchanged", binary)));
+    assert!(output.ends_with("Migrated 1 entries from format version 0 to 1\n"));
+
+    let mut migrated = Vec::new();
+    let entries = storage.entries().unwrap();
+    entries[&binary]
+        .reader()
+        .unwrap()
+        .read_to_end(&mut migrated)
+        .unwrap();
+    assert_eq!(migrated, contents);
+
+    // Backups hold the encrypted entries, so only their owner can read them.
+    let backup =
+        migrate::backup_path(&store, 0).join(format!("{}.age", binary));
+    assert_eq!(fs::read(&backup).unwrap(), contents);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::metadata(&backup).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
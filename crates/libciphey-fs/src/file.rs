@@ -1,6 +1,9 @@
 use std::fmt::Display;
 use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use std::{fs, io};
 
 use libciphey::storage::Reference;
@@ -52,11 +55,43 @@ impl Reference for File {
         OpenOptions::new().read(true).write(false).open(&self.path)
     }
 
+    /// Seeks to the start of the range rather than reading up to it.
+    fn reader_range(
+        &self,
+        range: Range<u64>,
+    ) -> Result<io::Take<Self::Reader>, io::Error> {
+        let mut reader = self.reader()?;
+        reader.seek(SeekFrom::Start(range.start))?;
+        Ok(reader.take(range.end.saturating_sub(range.start)))
+    }
+
+    /// Returns a writer to a newly created file.
+    ///
+    /// On Unix, the file is only readable and writable by its owner.
     fn writer(&mut self) -> Result<Self::Writer, io::Error> {
-        OpenOptions::new()
+        let mut options = OpenOptions::new();
+        options
             .create_new(true) // Ensure that no entry is ever overwritten
-            .write(true)
-            .open(&self.path)
+            .write(true);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        options.open(&self.path)
+    }
+
+    /// Returns the last modification time of the file.
+    fn modified(&self) -> Result<SystemTime, io::Error> {
+        self.path.metadata()?.modified()
+    }
+
+    /// Flushes the contents of the file to disk. The file is opened for
+    /// writing, as some platforms only sync files that were.
+    fn sync(&self) -> Result<(), io::Error> {
+        OpenOptions::new().write(true).open(&self.path)?.sync_all()
     }
 }
 
@@ -175,6 +210,46 @@ mod tests {
         assert_eq!(token, buf);
     }
 
+    #[test]
+    fn test_reader_range() {
+        let path = temporary_path();
+        fs::write(&path, "0123456789").unwrap();
+        let file = File::new(&path).unwrap();
+
+        let read = |range| {
+            let mut buf = String::new();
+            let mut reader = file.reader_range(range).unwrap();
+            reader.read_to_string(&mut buf).unwrap();
+            buf
+        };
+
+        assert_eq!(read(0..4), "0123");
+        assert_eq!(read(3..7), "3456");
+        // Ranges are cut short at the end of the file.
+        assert_eq!(read(8..64), "89");
+        assert_eq!(read(16..32), "");
+    }
+
+    #[test]
+    fn test_digest() {
+        let path = temporary_path();
+        fs::write(&path, "").unwrap();
+        let file = File::new(&path).unwrap();
+
+        // The SHA-256 digest of no data.
+        let empty = file.digest().unwrap();
+        assert_eq!(empty[..4], [0xe3, 0xb0, 0xc4, 0x42]);
+        assert_eq!(file.digest().unwrap(), empty);
+
+        // The digest changes with the contents of the file.
+        fs::write(&path, random_string(128)).unwrap();
+        let changed = file.digest().unwrap();
+        assert_ne!(changed, empty);
+
+        fs::write(&path, random_string(128)).unwrap();
+        assert_ne!(file.digest().unwrap(), changed);
+    }
+
     #[test]
     fn test_writer() {
         // Generate a new path that does not exist.
@@ -204,4 +279,21 @@ mod tests {
         // The data in the buffer and the random token should match.
         assert_eq!(token, buf);
     }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_writer_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Generate a new path that does not exist.
+        let path = temporary_path();
+
+        // Create the file through a writer.
+        let mut file = File::new(&path).unwrap();
+        drop(file.writer().unwrap());
+
+        // The file should only be accessible by its owner.
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
 }
@@ -2,13 +2,16 @@
 
 use std::collections::HashMap;
 use std::ffi::OsStr;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::{fs, io};
+use std::time::SystemTime;
+use std::{fmt, fs, io};
 
 use directory::Directory;
 use file::File;
-use libciphey::storage::Backend;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use libciphey::storage::{Backend, Capabilities, Reference, Warning};
 use uuid::Uuid;
 
 pub mod directory;
@@ -17,6 +20,10 @@ pub mod file;
 #[cfg(test)]
 mod tests;
 
+/// The name of the file at the root of a store that lists items to exclude
+/// from the store, in gitignore syntax.
+pub const IGNORE_FILE: &str = ".cipheyignore";
+
 // A filesystem-based store
 pub struct Filesystem {
     root: Directory,
@@ -45,59 +52,208 @@ impl Filesystem {
         let path = self.entries_path()?;
         fs::read_dir(&path)
     }
-}
 
-impl Backend for Filesystem {
-    type Reference = File;
+    /// Loads the patterns of the store's [`IGNORE_FILE`], which are relative
+    /// to the root of the store. A missing file ignores nothing.
+    fn ignore(&self) -> Result<Gitignore, io::Error> {
+        let root: &Path = self.root.as_ref();
+        let path = root.join(IGNORE_FILE);
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
 
-    /// Returns a list of files that represent entries in the store.
-    fn entries(&self) -> Result<HashMap<Uuid, Self::Reference>, io::Error> {
-        let dir: fs::ReadDir = self.entries_dir()?;
+        let mut builder = GitignoreBuilder::new(root);
+        if path.exists() {
+            if let Some(e) = builder.add(&path) {
+                return Err(invalid(e));
+            }
+        }
 
-        let mut map = HashMap::new();
+        builder.build().map_err(invalid)
+    }
 
-        for entry in dir {
-            let entry = entry?;
-            // Extract the entry's path
-            let path = entry.path();
+    /// Returns the paths of the items in the entries directory, except those
+    /// that the store's [`IGNORE_FILE`] excludes.
+    fn entry_paths(&self) -> Result<Vec<PathBuf>, io::Error> {
+        let dir = self.entries_dir()?;
+        let ignore = self.ignore()?;
 
-            // Skip directories (this behavior may change in the future)
-            if path.is_dir() {
-                continue;
+        let mut paths = Vec::new();
+        for entry in dir {
+            let path = entry?.path();
+            if !ignore.matched(&path, path.is_dir()).is_ignore() {
+                paths.push(path);
             }
+        }
 
-            // Only try to parse files with the "age" extension (this behavior
-            // may change in the future)
-            if path.extension().map_or(true, |ext| ext != "age") {
-                continue;
+        Ok(paths)
+    }
+}
+
+impl Filesystem {
+    /// Returns the entries of the store, like [`Backend::entries`], along with
+    /// the items in the entries directory that were skipped because they are
+    /// not entries, and why.
+    ///
+    /// Items that the store's [`IGNORE_FILE`] excludes are neither entries nor
+    /// reported as skipped.
+    pub fn entries_verbose(
+        &self,
+    ) -> Result<(HashMap<Uuid, File>, Vec<Skipped>), io::Error> {
+        let mut map = HashMap::new();
+        let mut skipped = Vec::new();
+
+        for path in self.entry_paths()? {
+            match entry_uuid(&path) {
+                Ok(uuid) => {
+                    // Create an AsyncRead from the file
+                    let file = File::new(path)?;
+                    map.insert(uuid, file);
+                }
+                Err(reason) => skipped.push(Skipped { path, reason }),
             }
+        }
 
-            // Extract UUID from filename stem
-            let raw_stem: &OsStr = match path.file_stem() {
-                Some(s) => s,
-                None => continue,
-            };
+        skipped.sort_by(|a, b| a.path.cmp(&b.path));
 
-            // Only convert if valid UTF-8
-            let stem: &str = match raw_stem.to_str() {
-                Some(s) => s,
-                None => continue,
-            };
+        Ok((map, skipped))
+    }
+}
 
-            // Silently skip .age files with invalid filenames (this behavior
-            // may change in the future)
-            let uuid = match Uuid::from_str(stem) {
-                Ok(u) => u,
-                Err(_) => continue,
-            };
+/// An item in the entries directory that is not an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
+    /// The path of the item.
+    pub path: PathBuf,
+    /// Why the item is not an entry.
+    pub reason: SkipReason,
+}
 
-            // Create an AsyncRead from the file
-            let file = File::new(path)?;
+/// The reasons that an item in the entries directory is not an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The item is a directory.
+    Directory,
+    /// The item is a file without the `age` extension.
+    WrongExtension,
+    /// The name of the file is not valid UTF-8.
+    NonUtf8Name,
+    /// The name of the file, without its extension, is not a UUID.
+    InvalidUuid,
+}
 
-            map.insert(uuid, file);
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Directory => write!(f, "is a directory"),
+            SkipReason::WrongExtension => {
+                write!(f, "does not have the .age extension")
+            }
+            SkipReason::NonUtf8Name => write!(f, "name is not valid UTF-8"),
+            SkipReason::InvalidUuid => write!(f, "name is not a UUID"),
         }
+    }
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.reason)
+    }
+}
+
+/// Returns the UUID of the entry stored at `path`, or why the item at `path`
+/// is not an entry.
+fn entry_uuid(path: &Path) -> Result<Uuid, SkipReason> {
+    // Skip directories (this behavior may change in the future)
+    if path.is_dir() {
+        return Err(SkipReason::Directory);
+    }
+
+    // Only try to parse files with the "age" extension (this behavior may
+    // change in the future)
+    if path.extension().map_or(true, |ext| ext != "age") {
+        return Err(SkipReason::WrongExtension);
+    }
+
+    stem_uuid(path)
+}
+
+/// Returns the UUID in the name of the file at `path`, ignoring its extension.
+fn stem_uuid(path: &Path) -> Result<Uuid, SkipReason> {
+    // Extract UUID from filename stem
+    let raw_stem: &OsStr = path.file_stem().ok_or(SkipReason::InvalidUuid)?;
+
+    // Only convert if valid UTF-8
+    let stem: &str = raw_stem.to_str().ok_or(SkipReason::NonUtf8Name)?;
+
+    Uuid::from_str(stem).map_err(|_| SkipReason::InvalidUuid)
+}
+
+/// Returns the name of the file that stores the entry with the provided UUID.
+fn entry_filename(uuid: &Uuid) -> PathBuf {
+    let formatted_uuid = uuid.hyphenated().to_string();
+    let mut filename = PathBuf::new();
+    filename.set_file_name(formatted_uuid);
+    filename.set_extension("age");
+    filename
+}
+
+/// Returns whether the current user may write to `path`, according to the
+/// operating system rather than the permission bits alone.
+#[cfg(unix)]
+fn is_writable(path: &Path) -> Result<bool, io::Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    // SAFETY: `path` is a valid NUL-terminated string.
+    if unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0 {
+        return Ok(true);
+    }
+
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EACCES | libc::EROFS | libc::EPERM) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+#[cfg(not(unix))]
+fn is_writable(_path: &Path) -> Result<bool, io::Error> {
+    Ok(true)
+}
+
+impl Backend for Filesystem {
+    type Reference = File;
+
+    /// Returns a list of files that represent entries in the store.
+    ///
+    /// Items that are not entries are silently skipped. Use
+    /// [`Filesystem::entries_verbose`] to find out which were skipped.
+    fn entries(&self) -> Result<HashMap<Uuid, Self::Reference>, io::Error> {
+        self.entries_verbose().map(|(entries, _)| entries)
+    }
+
+    /// Returns the entries whose files were modified at or after `since`,
+    /// according to the filesystem's modification times.
+    fn entries_modified_since(
+        &self,
+        since: SystemTime,
+    ) -> Result<HashMap<Uuid, Self::Reference>, io::Error> {
+        let mut entries = self.entries()?;
+
+        // Remove every entry modified before the cutoff, propagating errors
+        // from reading the modification time.
+        let mut result = Ok(());
+        entries.retain(|_, file| match file.modified() {
+            Ok(modified) => modified >= since,
+            Err(e) => {
+                result = Err(e);
+                false
+            }
+        });
 
-        Ok(map)
+        result.map(|_| entries)
     }
 
     /// Adds an entry to the store.
@@ -113,17 +269,164 @@ impl Backend for Filesystem {
     ///
     /// This function will error if the "entries" directory is not present.
     fn add_entry(&mut self, uuid: &Uuid) -> Result<Self::Reference, io::Error> {
-        let formatted_uuid = uuid.hyphenated().to_string();
-        let mut filename = PathBuf::new();
-        filename.set_file_name(formatted_uuid);
-        filename.set_extension("age");
-
         let path = self.entries_path()?;
-        let file = path.subfile(filename)?;
+        let file = path.subfile(entry_filename(uuid))?;
 
         Ok(file)
     }
 
+    /// Removes an entry from the store by deleting its file.
+    fn remove_entry(&mut self, uuid: &Uuid) -> Result<(), io::Error> {
+        let mut path = self.entries_path()?.as_ref().to_path_buf();
+        path.push(entry_filename(uuid));
+
+        fs::remove_file(path)
+    }
+
+    /// Writes `data` to a temporary file in the entries directory and renames
+    /// it over the entry's file, so that a failure to write leaves the
+    /// original entry in place.
+    fn replace_entry(
+        &mut self,
+        uuid: &Uuid,
+        data: &[u8],
+    ) -> Result<Self::Reference, io::Error> {
+        let dir = self.entries_path()?;
+        let path = dir.as_ref().join(entry_filename(uuid));
+        if !path.is_file() {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+
+        let temporary =
+            dir.as_ref().join(format!(".{}.tmp", uuid.hyphenated()));
+        // A temporary file left behind by an earlier failure is stale.
+        match fs::remove_file(&temporary) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        let result = File::new(&temporary)?.writer().and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        });
+        if let Err(e) = result.and_then(|_| fs::rename(&temporary, &path)) {
+            let _ = fs::remove_file(&temporary);
+            return Err(e);
+        }
+
+        File::new(path)
+    }
+
+    /// Reports every item in the entries directory that is not a file named
+    /// `<uuid>.age`, except those that the store's [`IGNORE_FILE`] excludes,
+    /// and entries that are empty.
+    fn health_check(&self) -> Result<Vec<Warning>, io::Error> {
+        let entries = self.entries_path()?;
+        if !entries.as_ref().exists() {
+            return Ok(vec![Warning::MissingEntries(entries.to_string())]);
+        }
+
+        let mut warnings = Vec::new();
+
+        // Items are checked the same way that `entries` skips them.
+        for path in self.entry_paths()? {
+            let location = path.display().to_string();
+
+            let warning = match entry_uuid(&path) {
+                Ok(_) if path.metadata()?.len() == 0 => {
+                    Warning::EmptyEntry(location)
+                }
+                Ok(_) => continue,
+                // A file with a UUID name is likely a misnamed entry, while
+                // anything else is likely a stray file.
+                Err(SkipReason::WrongExtension) if stem_uuid(&path).is_ok() => {
+                    Warning::WrongExtension(location)
+                }
+                Err(SkipReason::Directory | SkipReason::WrongExtension) => {
+                    Warning::UnexpectedItem(location)
+                }
+                Err(SkipReason::NonUtf8Name | SkipReason::InvalidUuid) => {
+                    Warning::InvalidUuid(location)
+                }
+            };
+            warnings.push(warning);
+        }
+
+        warnings.sort_by_key(ToString::to_string);
+
+        Ok(warnings)
+    }
+
+    /// Renames entries with the wrong extension, removes empty entries, and
+    /// creates a missing entries directory. Only items directly in the
+    /// entries directory are repaired.
+    fn repair(&mut self, warning: &Warning) -> Result<(), io::Error> {
+        let entries = self.entries_path()?;
+        let entry_path = |location: &str| {
+            let path = PathBuf::from(location);
+            match path.parent() == Some(entries.as_ref()) {
+                true => Ok(path),
+                false => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("not in the entries directory: {}", location),
+                )),
+            }
+        };
+
+        match warning {
+            Warning::MissingEntries(_) => fs::create_dir_all(entries),
+            Warning::WrongExtension(location) => {
+                let path = entry_path(location)?;
+                let renamed = path.with_extension("age");
+                if renamed.exists() {
+                    let message = format!("{}", renamed.display());
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        message,
+                    ));
+                }
+                fs::rename(path, renamed)
+            }
+            Warning::EmptyEntry(location) => {
+                let path = entry_path(location)?;
+                // The entry may have been written since it was checked.
+                if path.metadata()?.len() != 0 {
+                    let message = format!("no longer empty: {}", location);
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        message,
+                    ));
+                }
+                fs::remove_file(path)
+            }
+            _ => Err(io::Error::from(io::ErrorKind::Unsupported)),
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::ALL
+    }
+
+    /// Reports the store as read-only if the permissions of its root or its
+    /// entries directory do not allow writing, or if the current user cannot
+    /// write to them, such as on a read-only mount.
+    fn is_read_only(&self) -> Result<bool, io::Error> {
+        for path in [self.root.clone(), self.entries_path()?] {
+            if fs::metadata(&path)?.permissions().readonly()
+                || !is_writable(path.as_ref())?
+            {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Returns the path of the root directory of the store.
+    fn location(&self) -> String {
+        self.root.to_string()
+    }
+
     fn create(&mut self) -> Result<(), io::Error> {
         let path = self.entries_path()?;
 
@@ -2,7 +2,7 @@
 
 use std::collections::HashMap;
 use std::fs::read_dir;
-use std::io::{copy, Read};
+use std::io::{copy, Read, Write};
 use std::path::PathBuf;
 use std::{fs, io};
 
@@ -220,3 +220,369 @@ fn test_add_entry_ok() {
         println!("{}: {}", uuid, read_data.len());
     }
 }
+
+#[test]
+// Tests that `Filesystem::remove_entry` removes only the requested entry
+fn test_remove_entry() {
+    // Create a temporary directory to use as a test store
+    let root_path = temporary_path();
+    let mut backend = Filesystem::new(&root_path).unwrap();
+    backend.create().unwrap();
+
+    // Add two entries to the store
+    let removed = Uuid::new_v4();
+    let kept = Uuid::new_v4();
+    for uuid in [&removed, &kept] {
+        let mut reference = backend.add_entry(uuid).unwrap();
+        reference.writer().unwrap();
+    }
+
+    backend.remove_entry(&removed).unwrap();
+
+    let entries = backend.entries().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert!(entries.contains_key(&kept));
+
+    // Removing an entry that does not exist is an error
+    let err = backend.remove_entry(&removed).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+// Tests that `Filesystem::replace_entry` swaps in the new data, and leaves the
+// original entry in place if it fails
+fn test_replace_entry() {
+    // Create a temporary directory to use as a test store
+    let root_path = temporary_path();
+    let mut backend = Filesystem::new(&root_path).unwrap();
+    backend.create().unwrap();
+
+    let uuid = Uuid::new_v4();
+    let mut reference = backend.add_entry(&uuid).unwrap();
+    reference.writer().unwrap().write_all(b"old").unwrap();
+
+    let reference = backend.replace_entry(&uuid, b"new").unwrap();
+    let mut data = String::new();
+    reference
+        .reader()
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+    assert_eq!(data, "new");
+
+    // Only the entry is left in the entries directory
+    let entries_path = root_path.join("entries");
+    assert_eq!(read_dir(&entries_path).unwrap().count(), 1);
+
+    // The temporary file cannot be written, so the original is kept
+    let temporary = entries_path.join(format!(".{}.tmp", uuid.hyphenated()));
+    fs::create_dir(&temporary).unwrap();
+    assert!(backend.replace_entry(&uuid, b"lost").is_err());
+    let mut data = String::new();
+    let entries = backend.entries().unwrap();
+    entries[&uuid]
+        .reader()
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+    assert_eq!(data, "new");
+
+    // Replacing an entry that does not exist is an error
+    let err = backend.replace_entry(&Uuid::new_v4(), b"new").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+// Tests that `Filesystem::entries_modified_since` only returns entries
+// modified at or after the cutoff
+fn test_entries_modified_since() {
+    use std::thread::sleep;
+    use std::time::{Duration, SystemTime};
+
+    // Create a temporary directory to use as a test store
+    let root_path = temporary_path();
+    let mut backend = Filesystem::new(&root_path).unwrap();
+    backend.create().unwrap();
+
+    // Add an entry before the cutoff
+    let old = Uuid::new_v4();
+    backend.add_entry(&old).unwrap().writer().unwrap();
+
+    // Wait long enough for the modification times to differ
+    sleep(Duration::from_millis(50));
+    let cutoff = SystemTime::now();
+    sleep(Duration::from_millis(50));
+
+    // Add an entry after the cutoff
+    let new = Uuid::new_v4();
+    backend.add_entry(&new).unwrap().writer().unwrap();
+
+    let entries = backend.entries_modified_since(cutoff).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert!(entries.contains_key(&new));
+
+    // Every entry was modified after the epoch
+    let entries = backend
+        .entries_modified_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    assert_eq!(entries.len(), 2);
+}
+
+#[test]
+// Tests that `Filesystem::health_check` reports items that `entries` skips
+fn test_health_check() {
+    use libciphey::storage::Warning;
+
+    // Create a temporary directory to use as a test store
+    let root_path = temporary_path();
+    let mut backend = Filesystem::new(&root_path).unwrap();
+    backend.create().unwrap();
+
+    // A healthy store has no warnings
+    backend
+        .add_entry(&Uuid::new_v4())
+        .unwrap()
+        .writer()
+        .unwrap()
+        .write_all(b"data")
+        .unwrap();
+    assert!(backend.health_check().unwrap().is_empty());
+
+    let entries_path = root_path.join("entries");
+    let uuid = Uuid::new_v4();
+    let stray = entries_path.join("notes.txt");
+    let wrong_extension = entries_path.join(format!("{}.txt", uuid));
+    let invalid_uuid = entries_path.join("not-a-uuid.age");
+    let directory = entries_path.join("subdirectory");
+    fs::write(&stray, "").unwrap();
+    fs::write(&wrong_extension, "").unwrap();
+    fs::write(&invalid_uuid, "").unwrap();
+    fs::create_dir(&directory).unwrap();
+
+    let warnings = backend.health_check().unwrap();
+    assert_eq!(warnings.len(), 4);
+    for warning in [
+        Warning::UnexpectedItem(stray.display().to_string()),
+        Warning::WrongExtension(wrong_extension.display().to_string()),
+        Warning::InvalidUuid(invalid_uuid.display().to_string()),
+        Warning::UnexpectedItem(directory.display().to_string()),
+    ] {
+        assert!(warnings.contains(&warning));
+    }
+
+    // The normal path still skips them
+    assert_eq!(backend.entries().unwrap().len(), 1);
+
+    // Names that are not UTF-8 cannot be UUIDs either.
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let non_utf8 = entries_path.join(OsStr::from_bytes(b"\xff\xfe.age"));
+        fs::write(&non_utf8, "").unwrap();
+        let warnings = backend.health_check().unwrap();
+        let location = non_utf8.display().to_string();
+        assert!(warnings.contains(&Warning::InvalidUuid(location)));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+// Tests that `Filesystem::entries_verbose` reports every item that `entries`
+// skips, with the reason it was skipped
+fn test_entries_verbose() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use crate::{SkipReason, Skipped};
+
+    // Create a temporary directory to use as a test store
+    let root_path = temporary_path();
+    let mut backend = Filesystem::new(&root_path).unwrap();
+    backend.create().unwrap();
+
+    let uuid = Uuid::new_v4();
+    backend
+        .add_entry(&uuid)
+        .unwrap()
+        .writer()
+        .unwrap()
+        .write_all(b"data")
+        .unwrap();
+
+    let entries_path = root_path.join("entries");
+    let directory = entries_path.join(format!("{}.age", Uuid::new_v4()));
+    let wrong_extension = entries_path.join(format!("{}.txt", Uuid::new_v4()));
+    let non_utf8 = entries_path.join(OsStr::from_bytes(b"\xff\xfe.age"));
+    let invalid_uuid = entries_path.join("not-a-uuid.age");
+    fs::create_dir(&directory).unwrap();
+    fs::write(&wrong_extension, "").unwrap();
+    fs::write(&non_utf8, "").unwrap();
+    fs::write(&invalid_uuid, "").unwrap();
+
+    let (entries, skipped) = backend.entries_verbose().unwrap();
+    assert_eq!(entries.keys().collect::<Vec<_>>(), vec![&uuid]);
+
+    let mut expected = vec![
+        Skipped {
+            path: directory,
+            reason: SkipReason::Directory,
+        },
+        Skipped {
+            path: wrong_extension,
+            reason: SkipReason::WrongExtension,
+        },
+        Skipped {
+            path: non_utf8,
+            reason: SkipReason::NonUtf8Name,
+        },
+        Skipped {
+            path: invalid_uuid.clone(),
+            reason: SkipReason::InvalidUuid,
+        },
+    ];
+    expected.sort_by(|a, b| a.path.cmp(&b.path));
+    assert_eq!(skipped, expected);
+
+    // Each skipped item is described with its path and reason
+    let description = format!("{}: name is not a UUID", invalid_uuid.display());
+    assert!(skipped.iter().any(|s| s.to_string() == description));
+
+    // The normal path silently skips them
+    assert_eq!(backend.entries().unwrap().len(), 1);
+}
+
+#[test]
+fn test_repair() {
+    use libciphey::storage::Warning;
+
+    let root_path = temporary_path();
+    let mut backend = Filesystem::new(&root_path).unwrap();
+
+    // A missing entries directory is created.
+    let entries_path = root_path.join("entries");
+    let missing = Warning::MissingEntries(entries_path.display().to_string());
+    let warnings = backend.health_check().unwrap();
+    assert_eq!(warnings, [missing]);
+    backend.repair(&warnings[0]).unwrap();
+    assert!(backend.health_check().unwrap().is_empty());
+
+    let uuid = Uuid::new_v4();
+    let wrong_extension = entries_path.join(format!("{}.txt", uuid));
+    let empty = entries_path.join(format!("{}.age", Uuid::new_v4()));
+    let stray = entries_path.join("notes.txt");
+    fs::write(&wrong_extension, "data").unwrap();
+    fs::write(&empty, "").unwrap();
+    fs::write(&stray, "").unwrap();
+
+    let mut warnings = backend.health_check().unwrap();
+    assert_eq!(warnings.len(), 3);
+    assert!(
+        warnings.contains(&Warning::EmptyEntry(empty.display().to_string()))
+    );
+    for warning in &warnings {
+        match warning {
+            Warning::UnexpectedItem(_) => {
+                let err = backend.repair(warning).unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+            }
+            warning => backend.repair(warning).unwrap(),
+        }
+    }
+
+    // Only the stray file is left, and the misnamed entry is readable.
+    warnings = backend.health_check().unwrap();
+    assert_eq!(
+        warnings,
+        [Warning::UnexpectedItem(stray.display().to_string())]
+    );
+    assert!(!empty.exists());
+    let entries = backend.entries().unwrap();
+    assert_eq!(entries.keys().collect::<Vec<_>>(), [&uuid]);
+
+    // Items outside of the entries directory are never touched.
+    let outside = root_path.join(format!("{}.txt", uuid));
+    fs::write(&outside, "").unwrap();
+    let warning = Warning::WrongExtension(outside.display().to_string());
+    let err = backend.repair(&warning).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(outside.exists());
+}
+
+#[test]
+fn test_ignore_file() {
+    let root_path = temporary_path();
+    let mut backend = Filesystem::new(&root_path).unwrap();
+    backend.create().unwrap();
+
+    let entries_path = root_path.join("entries");
+    let write_entry = |backend: &mut Filesystem, uuid| {
+        let mut writer = backend.add_entry(&uuid).unwrap().writer().unwrap();
+        writer.write_all(b"data").unwrap();
+    };
+    let uuid = Uuid::new_v4();
+    write_entry(&mut backend, uuid);
+    let ignored_entry = Uuid::new_v4();
+    write_entry(&mut backend, ignored_entry);
+    fs::write(entries_path.join(".DS_Store"), "").unwrap();
+    fs::write(entries_path.join("README"), "").unwrap();
+    fs::create_dir(entries_path.join("drafts")).unwrap();
+    assert_eq!(backend.health_check().unwrap().len(), 3);
+
+    // Patterns are relative to the root of the store.
+    let ignore = format!(
+        "# Stray files\n.DS_Store\n/entries/README\ndrafts/\n/entries/{}.age\n",
+        ignored_entry
+    );
+    fs::write(root_path.join(crate::IGNORE_FILE), ignore).unwrap();
+
+    assert!(backend.health_check().unwrap().is_empty());
+    let entries = backend.entries().unwrap();
+    assert_eq!(entries.keys().collect::<Vec<_>>(), [&uuid]);
+
+    // A pattern that is anchored elsewhere does not match.
+    fs::write(root_path.join(crate::IGNORE_FILE), "/README\n").unwrap();
+    assert_eq!(backend.health_check().unwrap().len(), 3);
+}
+
+#[test]
+fn test_location() {
+    let path = temporary_path();
+    let backend = Filesystem::new(&path).unwrap();
+
+    assert_eq!(backend.location(), path.display().to_string());
+}
+
+#[test]
+fn test_capabilities() {
+    use libciphey::storage::Capabilities;
+
+    let backend = Filesystem::new(&temporary_path()).unwrap();
+    let capabilities = backend.capabilities();
+
+    assert_eq!(capabilities, Capabilities::ALL);
+    assert!(capabilities.can_add && capabilities.can_remove);
+    assert!(capabilities.can_replace && capabilities.supports_metadata);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_is_read_only() {
+    use std::fs::Permissions;
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = temporary_path();
+    let mut backend = Filesystem::new(&path).unwrap();
+    backend.create().unwrap();
+    assert!(!backend.is_read_only().unwrap());
+
+    // Permission bits that forbid writing make the store read-only, even for
+    // users who could write to it anyway.
+    let entries = path.join("entries");
+    fs::set_permissions(&entries, Permissions::from_mode(0o500)).unwrap();
+    assert!(backend.is_read_only().unwrap());
+
+    fs::set_permissions(&entries, Permissions::from_mode(0o700)).unwrap();
+    assert!(!backend.is_read_only().unwrap());
+}
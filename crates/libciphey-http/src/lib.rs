@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, Cursor, ErrorKind, Read};
+use std::ops::Range;
+use std::str::FromStr;
+
+use libciphey::storage::{Backend, Capabilities, Reference};
+use url::Url;
+use uuid::Uuid;
+
+#[cfg(test)]
+mod tests;
+
+/// A read-only store served over HTTP.
+///
+/// The store is described by a JSON manifest that maps the UUID of each entry
+/// to the URL of its encrypted contents, which may be relative to the
+/// manifest:
+///
+/// ```json
+/// { "67e55044-10b1-426f-9247-bb680e5fe0c8": "entries/67e55044.age" }
+/// ```
+///
+/// Entries are decrypted locally, so the server does not need to be trusted
+/// with their contents. Entries cannot be added or removed.
+pub struct Http {
+    manifest: Url,
+    agent: ureq::Agent,
+}
+
+impl Http {
+    /// Creates a store described by the manifest at the provided URL.
+    ///
+    /// # Errors
+    /// Fails if `manifest` is not a valid URL. The manifest is not fetched
+    /// until the entries are read.
+    pub fn new(manifest: &str) -> Result<Self, io::Error> {
+        let manifest = Url::parse(manifest)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+
+        Ok(Self {
+            manifest,
+            agent: ureq::Agent::new(),
+        })
+    }
+}
+
+/// Fetches the body of the resource at `url`.
+fn get(agent: &ureq::Agent, url: &Url) -> Result<Vec<u8>, io::Error> {
+    let response = call(agent.get(url.as_str()), url)?;
+
+    let mut body = Vec::new();
+    response.into_reader().read_to_end(&mut body)?;
+    Ok(body)
+}
+
+/// Fetches the bytes of the resource at `url` in `range`, which must not be
+/// empty. Servers that ignore the `Range` header send the whole resource, so
+/// the range is then cut from it.
+fn get_range(
+    agent: &ureq::Agent,
+    url: &Url,
+    range: Range<u64>,
+) -> Result<Vec<u8>, io::Error> {
+    let header = format!("bytes={}-{}", range.start, range.end - 1);
+    let request = agent.get(url.as_str()).set("Range", &header);
+    let response = match call(request, url) {
+        Ok(response) => response,
+        // The range starts past the end of the resource.
+        Err(e) if e.kind() == ErrorKind::InvalidInput => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let partial = response.status() == 206;
+    let mut reader = response.into_reader();
+    if !partial {
+        io::copy(&mut (&mut reader).take(range.start), &mut io::sink())?;
+    }
+
+    let mut body = Vec::new();
+    reader
+        .take(range.end - range.start)
+        .read_to_end(&mut body)?;
+    Ok(body)
+}
+
+/// Sends `request` for the resource at `url`, mapping the statuses that
+/// callers handle to their error kinds.
+fn call(
+    request: ureq::Request,
+    url: &Url,
+) -> Result<ureq::Response, io::Error> {
+    request.call().map_err(|e| match e {
+        ureq::Error::Status(404, _) => {
+            io::Error::new(ErrorKind::NotFound, url.to_string())
+        }
+        ureq::Error::Status(416, _) => {
+            io::Error::new(ErrorKind::InvalidInput, url.to_string())
+        }
+        e => io::Error::other(e),
+    })
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(ErrorKind::Unsupported, "HTTP stores are read-only")
+}
+
+impl Backend for Http {
+    type Reference = Resource;
+
+    fn create(&mut self) -> Result<(), io::Error> {
+        Err(unsupported())
+    }
+
+    /// Fetches the manifest and returns a reference to each entry it lists.
+    fn entries(&self) -> Result<HashMap<Uuid, Self::Reference>, io::Error> {
+        let manifest = get(&self.agent, &self.manifest)?;
+        let invalid = |e: String| io::Error::new(ErrorKind::InvalidData, e);
+
+        let manifest: HashMap<String, String> =
+            serde_json::from_slice(&manifest)
+                .map_err(|e| invalid(e.to_string()))?;
+
+        let mut entries = HashMap::new();
+
+        for (uuid, url) in manifest {
+            let uuid = Uuid::from_str(&uuid)
+                .map_err(|_| invalid(format!("invalid UUID: {}", uuid)))?;
+            let url = self
+                .manifest
+                .join(&url)
+                .map_err(|_| invalid(format!("invalid URL: {}", url)))?;
+
+            let resource = Resource {
+                url,
+                agent: self.agent.clone(),
+            };
+            entries.insert(uuid, resource);
+        }
+
+        Ok(entries)
+    }
+
+    fn add_entry(
+        &mut self,
+        _uuid: &Uuid,
+    ) -> Result<Self::Reference, io::Error> {
+        Err(unsupported())
+    }
+
+    fn remove_entry(&mut self, _uuid: &Uuid) -> Result<(), io::Error> {
+        Err(unsupported())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_add: false,
+            can_remove: false,
+            can_replace: false,
+            supports_metadata: false,
+        }
+    }
+
+    fn is_read_only(&self) -> Result<bool, io::Error> {
+        Ok(true)
+    }
+
+    /// Returns the URL of the manifest.
+    fn location(&self) -> String {
+        self.manifest.to_string()
+    }
+}
+
+/// An entry in an [`Http`] store.
+pub struct Resource {
+    url: Url,
+    agent: ureq::Agent,
+}
+
+impl Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.url.fmt(f)
+    }
+}
+
+impl Reference for Resource {
+    type Reader = Cursor<Vec<u8>>;
+    type Writer = io::Sink;
+
+    /// Fetches the entry, buffering it in memory.
+    fn reader(&self) -> Result<Self::Reader, io::Error> {
+        get(&self.agent, &self.url).map(Cursor::new)
+    }
+
+    /// Fetches only the range, if the server supports range requests.
+    fn reader_range(
+        &self,
+        range: Range<u64>,
+    ) -> Result<io::Take<Self::Reader>, io::Error> {
+        let length = range.end.saturating_sub(range.start);
+        let body = match length {
+            0 => Vec::new(),
+            _ => get_range(&self.agent, &self.url, range)?,
+        };
+        Ok(Cursor::new(body).take(length))
+    }
+
+    fn writer(&mut self) -> Result<Self::Writer, io::Error> {
+        Err(unsupported())
+    }
+}
@@ -0,0 +1,185 @@
+//! These tests read from a mock HTTP server on a local port.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use libciphey::storage::{Backend, Reference};
+use uuid::Uuid;
+
+use crate::Http;
+
+// Serves the provided bodies by path until the test ends, and returns the URL
+// of the server. Any other path is not found.
+fn serve(routes: HashMap<&'static str, &'static str>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(&stream);
+
+            // The request line is "GET <path> HTTP/1.1", followed by headers
+            // that end with an empty line.
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let path = request.split(' ').nth(1).unwrap().to_string();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+            }
+
+            let (status, body) = match routes.get(path.as_str()) {
+                Some(body) => ("200 OK", *body),
+                None => ("404 Not Found", ""),
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: \
+                 close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    url
+}
+
+const FIRST: &str = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+const SECOND: &str = "a1a2a3a4-b1b2-c1c2-d1d2-d3d4d5d6d7d8";
+
+#[test]
+fn test_entries() {
+    let url = serve(HashMap::from([
+        (
+            "/store/manifest.json",
+            r#"{
+                "67e55044-10b1-426f-9247-bb680e5fe0c8": "first.age",
+                "a1a2a3a4-b1b2-c1c2-d1d2-d3d4d5d6d7d8": "/elsewhere/second.age"
+            }"#,
+        ),
+        ("/store/first.age", "first ciphertext"),
+        ("/elsewhere/second.age", "second ciphertext"),
+    ]));
+
+    let backend = Http::new(&format!("{}/store/manifest.json", url)).unwrap();
+    let entries = backend.entries().unwrap();
+    assert_eq!(entries.len(), 2);
+
+    // URLs are resolved relative to the manifest.
+    for (uuid, path, contents) in [
+        (FIRST, "/store/first.age", "first ciphertext"),
+        (SECOND, "/elsewhere/second.age", "second ciphertext"),
+    ] {
+        let resource = &entries[&uuid.parse::<Uuid>().unwrap()];
+        assert_eq!(resource.to_string(), format!("{}{}", url, path));
+
+        let mut data = String::new();
+        resource
+            .reader()
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, contents);
+    }
+}
+
+#[test]
+fn test_missing_resources() {
+    let url = serve(HashMap::from([(
+        "/manifest.json",
+        r#"{ "67e55044-10b1-426f-9247-bb680e5fe0c8": "missing.age" }"#,
+    )]));
+
+    let backend = Http::new(&format!("{}/manifest.json", url)).unwrap();
+    let entries = backend.entries().unwrap();
+    let err = entries.values().next().unwrap().reader().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+
+    let backend = Http::new(&format!("{}/missing.json", url)).unwrap();
+    let err = backend.entries().err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn test_reader_range() {
+    let url = serve(HashMap::from([
+        (
+            "/manifest.json",
+            r#"{ "67e55044-10b1-426f-9247-bb680e5fe0c8": "first.age" }"#,
+        ),
+        ("/first.age", "first ciphertext"),
+    ]));
+
+    let backend = Http::new(&format!("{}/manifest.json", url)).unwrap();
+    let entries = backend.entries().unwrap();
+    let resource = entries.values().next().unwrap();
+
+    // The mock server ignores the `Range` header, so the range is cut from
+    // the whole resource.
+    for (range, expected) in [(6..10, "ciph"), (10..64, "ertext"), (64..80, "")]
+    {
+        let mut data = String::new();
+        resource
+            .reader_range(range)
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, expected);
+    }
+}
+
+#[test]
+fn test_invalid_manifest() {
+    let url = serve(HashMap::from([
+        ("/list.json", r#"["first.age"]"#),
+        ("/uuid.json", r#"{ "not-a-uuid": "first.age" }"#),
+    ]));
+
+    for path in ["/list.json", "/uuid.json"] {
+        let backend = Http::new(&format!("{}{}", url, path)).unwrap();
+        let err = backend.entries().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    assert!(Http::new("not a url").is_err());
+}
+
+#[test]
+fn test_read_only() {
+    let mut backend = Http::new("http://127.0.0.1:1/manifest.json").unwrap();
+    let uuid = Uuid::parse_str(FIRST).unwrap();
+
+    assert!(backend.is_read_only().unwrap());
+    assert!(!backend.capabilities().can_add);
+    assert!(!backend.capabilities().can_remove);
+
+    for err in [
+        backend.create().unwrap_err(),
+        backend.add_entry(&uuid).err().unwrap(),
+        backend.remove_entry(&uuid).unwrap_err(),
+    ] {
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+    }
+}
+
+#[test]
+fn test_https() {
+    // Nothing listens on the port, so the request fails when connecting, after
+    // the scheme is accepted.
+    let backend = Http::new("https://127.0.0.1:1/manifest.json").unwrap();
+    let err = backend.entries().err().unwrap();
+    let err = err.get_ref().and_then(|e| e.downcast_ref::<ureq::Error>());
+    assert_eq!(
+        err.map(|e| e.kind()),
+        Some(ureq::ErrorKind::ConnectionFailed)
+    );
+}
@@ -1,5 +1,8 @@
 use std::error::Error;
-use std::io::{Read, Write};
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+
+use sha2::{Digest, Sha256};
 
 /// Marks that a type only writes encrypted data that is safe to persist to the
 /// storage backend. Types that implement Encrypted MUST only write encrypted
@@ -8,6 +11,14 @@ use std::io::{Read, Write};
 /// `W` is the type of the underlying writer.
 pub trait Encrypted<W: Write>: Write {
     type Error: Error;
+
+    /// Writes any buffered data and returns the underlying writer.
+    ///
+    /// The encrypted data may be incomplete until this is called, so it MUST
+    /// be called once all of the plaintext has been written.
+    fn finish(self) -> io::Result<W>
+    where
+        Self: Sized;
 }
 
 /// Marks that a type provides a decrypted stream of data.
@@ -18,7 +29,25 @@ pub trait Decrypted<R: Read>: Read {
 }
 
 /// A type that provides a public key for the [`crypto::Backend`] to encrypt to.
-pub trait Recipient: TryFrom<String> + Clone {}
+///
+/// The [`Display`] form of a recipient must be its canonical encoding, which
+/// can be parsed back into the same recipient.
+pub trait Recipient: TryFrom<String> + Clone + Display {
+    /// Returns a short fingerprint of the recipient, so that users can check
+    /// that they are encrypting to the right key.
+    ///
+    /// The fingerprint is the first 8 bytes of the SHA-256 digest of the
+    /// canonical encoding, as groups of 4 hex digits, such as
+    /// `1a2b:3c4d:5e6f:7a8b`.
+    fn fingerprint(&self) -> String {
+        let digest = Sha256::digest(self.to_string().as_bytes());
+        let groups: Vec<String> = digest[..8]
+            .chunks(2)
+            .map(|group| format!("{:02x}{:02x}", group[0], group[1]))
+            .collect();
+        groups.join(":")
+    }
+}
 
 /// Types that implement `CryptoBackend` are expected to be initialized with any
 /// identities necessary for decryption.
@@ -26,7 +55,7 @@ pub trait Backend {
     type Recipient: Recipient;
     type Decrypted<R: Read>: Decrypted<R>;
     type Encrypted<W: Write>: Encrypted<W>;
-    type Error: Error + 'static;
+    type Error: Error + Send + Sync + 'static;
 
     /// Creates a wrapper around a writer that will encrypt its input.
     /// Returns errors from the underlying writer while writing the header.
@@ -40,4 +69,113 @@ pub trait Backend {
         &self,
         ciphertext: R,
     ) -> Result<Self::Decrypted<R>, Self::Error>;
+
+    /// Decrypts `ciphertext` directly into `output`, returning the number of
+    /// plaintext bytes written.
+    ///
+    /// Backends may override this to avoid the intermediate copy.
+    fn decrypt_to<R: Read, W: Write + ?Sized>(
+        &self,
+        ciphertext: R,
+        output: &mut W,
+    ) -> Result<u64, Self::Error>
+    where
+        Self::Error: From<io::Error>,
+    {
+        let mut decrypted = self.decrypt_input(ciphertext)?;
+        Ok(io::copy(&mut decrypted, output)?)
+    }
+
+    /// Returns the recipients that correspond to the identities the backend
+    /// was initialized with, so that data can be encrypted to oneself.
+    ///
+    /// Identities whose recipient cannot be derived are skipped, so the list
+    /// is empty if no such identities were loaded.
+    fn own_recipients(&self) -> Result<Vec<Self::Recipient>, Self::Error> {
+        Ok(Vec::new())
+    }
+
+    /// Returns whether any of the recipients corresponds to an identity the
+    /// backend was initialized with, so that data encrypted to them could be
+    /// decrypted again.
+    ///
+    /// Returns `None` if the backend cannot tell.
+    fn includes_own_recipient(
+        &self,
+        _recipients: &[Self::Recipient],
+    ) -> Result<Option<bool>, Self::Error> {
+        Ok(None)
+    }
+
+    /// Returns the recipients that the ciphertext was encrypted to, in the
+    /// same string form that is used to construct a [`Self::Recipient`].
+    ///
+    /// Returns `None` if the backend cannot determine the recipients, which is
+    /// the case for most real cryptography.
+    fn recipients_of<R: Read>(
+        &self,
+        _ciphertext: R,
+    ) -> Result<Option<Vec<String>>, Self::Error> {
+        Ok(None)
+    }
+}
+
+/// The error of a [`DynCrypto`], which erases the error type of the backend.
+pub type DynError = Box<dyn Error + Send + Sync + 'static>;
+
+/// An object-safe form of [`Backend`], so that a backend can be chosen at
+/// runtime and held as a `Box<dyn DynCrypto>`.
+///
+/// Recipients are passed in the string form that is used to construct a
+/// [`Backend::Recipient`]. Every [`Backend`] implements `DynCrypto`, so its
+/// methods are prefixed to not clash with those of [`Backend`].
+pub trait DynCrypto {
+    /// Encrypts all of `plaintext` to `recipients` into `output`, returning
+    /// the number of plaintext bytes read.
+    fn dyn_encrypt_to(
+        &self,
+        plaintext: &mut dyn Read,
+        output: &mut dyn Write,
+        recipients: &[String],
+    ) -> Result<u64, DynError>;
+
+    /// Decrypts all of `ciphertext` into `output`, returning the number of
+    /// plaintext bytes written.
+    fn dyn_decrypt_to(
+        &self,
+        ciphertext: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<u64, DynError>;
+}
+
+impl<B: Backend> DynCrypto for B {
+    fn dyn_encrypt_to(
+        &self,
+        plaintext: &mut dyn Read,
+        output: &mut dyn Write,
+        recipients: &[String],
+    ) -> Result<u64, DynError> {
+        let recipients = recipients
+            .iter()
+            .map(|recipient| {
+                B::Recipient::try_from(recipient.clone()).map_err(|_| {
+                    format!("Invalid recipient: {}", recipient).into()
+                })
+            })
+            .collect::<Result<Vec<_>, DynError>>()?;
+
+        let mut encrypted = self.encrypt_output(output, recipients)?;
+        let written = io::copy(plaintext, &mut encrypted)?;
+        encrypted.finish()?;
+        Ok(written)
+    }
+
+    fn dyn_decrypt_to(
+        &self,
+        ciphertext: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<u64, DynError> {
+        let mut decrypted = self.decrypt_input(ciphertext)?;
+        Ok(io::copy(&mut decrypted, output)?)
+    }
 }
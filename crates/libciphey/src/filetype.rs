@@ -3,7 +3,7 @@ use std::io::{Error, Read, Write};
 use crate::crypto::Encrypted;
 
 pub trait Filetype: Sized {
-    type Error: std::error::Error;
+    type Error: std::error::Error + Send + Sync + 'static;
     type Options;
 
     /// Constructs a new instance of `Self` from data in the provided reader.
@@ -27,6 +27,15 @@ pub trait Filetype: Sized {
         W: Write,
         E: Encrypted<W>;
 
+    /// Checks invariants of the entry that its type cannot enforce, so that
+    /// invalid entries can be rejected before they are written.
+    ///
+    /// Entries that were already written are not required to be valid, so
+    /// this is only advisory when reading.
+    fn validate(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     /// Write the contents of the entry in a readable format suitable for
     /// displaying to a user.
     ///
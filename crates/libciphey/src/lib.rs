@@ -2,3 +2,7 @@
 pub mod crypto;
 pub mod filetype;
 pub mod storage;
+pub mod vault;
+
+#[cfg(test)]
+mod tests;
@@ -1,9 +1,16 @@
 use std::collections::HashMap;
 use std::fmt::Display;
-use std::io::{Error, Read, Write};
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::ops::Range;
+use std::time::SystemTime;
 
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
+pub mod memory;
+#[cfg(feature = "retry")]
+pub mod retry;
+
 /// Marks a type that holds the necessary information to create a reader or
 /// writer over the data in a `StorageBackend`.
 ///
@@ -21,6 +28,53 @@ pub trait Reference: Display {
 
     /// Returns a new instance of a writer to persist the data.
     fn writer(&mut self) -> Result<Self::Writer, Error>;
+
+    /// Returns a reader of the bytes of the underlying data in `range`, such
+    /// as to peek at the header of an entry without reading all of it.
+    ///
+    /// A range that extends past the end of the data is cut short, and an
+    /// empty or reversed range reads no data. By default, the bytes before the
+    /// range are read from a new reader and discarded, so references that can
+    /// seek or fetch part of the data should override it.
+    fn reader_range(
+        &self,
+        range: Range<u64>,
+    ) -> Result<io::Take<Self::Reader>, Error> {
+        let mut reader = self.reader()?;
+        io::copy(&mut (&mut reader).take(range.start), &mut io::sink())?;
+        Ok(reader.take(range.end.saturating_sub(range.start)))
+    }
+
+    /// Returns the SHA-256 digest of the underlying data, streamed from a new
+    /// reader.
+    ///
+    /// The digest is taken over the encrypted data, so it changes whenever an
+    /// entry is re-encrypted, even if its plaintext is unchanged. It detects
+    /// that an entry was rewritten, not that two entries hold the same
+    /// plaintext.
+    fn digest(&self) -> Result<[u8; 32], Error> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut self.reader()?, &mut hasher)?;
+        Ok(hasher.finalize().into())
+    }
+
+    /// Returns the time that the underlying data was last modified, by the
+    /// backend's clock.
+    ///
+    /// References that do not track modification times return an
+    /// [`ErrorKind::Unsupported`] error.
+    fn modified(&self) -> Result<SystemTime, Error> {
+        Err(Error::from(ErrorKind::Unsupported))
+    }
+
+    /// Ensures that the data written to the reference has reached durable
+    /// storage, such as before removing the only other copy of it.
+    ///
+    /// By default, nothing is done, for references whose writers do not leave
+    /// data in a cache.
+    fn sync(&self) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 pub trait Backend: Unpin {
@@ -30,13 +84,187 @@ pub trait Backend: Unpin {
     /// backend.
     fn create(&mut self) -> Result<(), Error>;
 
+    /// Describes where the store's data lives, such as a path or a URL, so
+    /// that diagnostics can report it without knowing the backend.
+    fn location(&self) -> String;
+
     /// Returns a map of all entries in the database. The key is the UUID of the
     /// entry and the value is a reference to the entry in the backend.
     fn entries(&self) -> Result<HashMap<Uuid, Self::Reference>, Error>;
 
+    /// Returns the entries of [`Backend::entries`] ordered by UUID, so that
+    /// they are visited in the same order every time.
+    fn sorted_entries(&self) -> Result<Vec<(Uuid, Self::Reference)>, Error> {
+        let mut entries: Vec<_> = self.entries()?.into_iter().collect();
+        entries.sort_by_key(|(uuid, _)| *uuid);
+        Ok(entries)
+    }
+
+    /// Returns a map of the entries that were modified at or after `since`.
+    ///
+    /// The comparison is inclusive: an entry modified exactly at `since` is
+    /// returned. Modification times are recorded by the backend's clock, so
+    /// clock skew between machines sharing a store can cause entries to be
+    /// missed or included unexpectedly.
+    ///
+    /// Backends that do not track modification times return an
+    /// [`ErrorKind::Unsupported`] error.
+    fn entries_modified_since(
+        &self,
+        since: SystemTime,
+    ) -> Result<HashMap<Uuid, Self::Reference>, Error> {
+        let _ = since;
+        Err(Error::from(ErrorKind::Unsupported))
+    }
+
     /// Adds an entry to the database with the provided UUID. The data to be
     /// persisted must be read from `source` in its entirety or return an error.
     ///
     /// Returns a reference to the newly created entry in the underlying store.
     fn add_entry(&mut self, uuid: &Uuid) -> Result<Self::Reference, Error>;
+
+    /// Removes the entry with the provided UUID from the database.
+    ///
+    /// Returns an [`std::io::ErrorKind::NotFound`] error if no such entry
+    /// exists.
+    fn remove_entry(&mut self, uuid: &Uuid) -> Result<(), Error>;
+
+    /// Replaces the data of the entry with the provided UUID with `data`.
+    ///
+    /// Returns an [`std::io::ErrorKind::NotFound`] error if no such entry
+    /// exists. By default, the entry is removed and added again, so a failure
+    /// to write the new data loses the entry. Backends that can swap in the
+    /// new data atomically should override it.
+    fn replace_entry(
+        &mut self,
+        uuid: &Uuid,
+        data: &[u8],
+    ) -> Result<Self::Reference, Error> {
+        self.remove_entry(uuid)?;
+        let mut reference = self.add_entry(uuid)?;
+        reference.writer()?.write_all(data)?;
+        Ok(reference)
+    }
+
+    /// Checks the structure of the store for anomalies that
+    /// [`Backend::entries`] silently skips.
+    ///
+    /// Returns an error only if the store cannot be checked at all.
+    fn health_check(&self) -> Result<Vec<Warning>, Error> {
+        Ok(Vec::new())
+    }
+
+    /// Repairs an anomaly reported by [`Backend::health_check`], as described
+    /// by [`Warning::repair_action`].
+    ///
+    /// Returns an [`std::io::ErrorKind::Unsupported`] error if the backend
+    /// cannot repair the anomaly.
+    fn repair(&mut self, _warning: &Warning) -> Result<(), Error> {
+        Err(Error::from(ErrorKind::Unsupported))
+    }
+
+    /// Returns the operations that the backend supports, so that callers can
+    /// refuse unsupported commands before doing any work.
+    ///
+    /// By default, entries can be added, removed, and replaced, but
+    /// modification times are not tracked.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            supports_metadata: false,
+            ..Capabilities::ALL
+        }
+    }
+
+    /// Returns whether the store can only be read, so that commands which
+    /// modify it can fail before doing any work.
+    fn is_read_only(&self) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
+
+/// The operations supported by a [`Backend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Entries can be added with [`Backend::add_entry`].
+    pub can_add: bool,
+    /// Entries can be removed with [`Backend::remove_entry`].
+    pub can_remove: bool,
+    /// An entry can be replaced with [`Backend::replace_entry`].
+    pub can_replace: bool,
+    /// Modification times are tracked, so
+    /// [`Backend::entries_modified_since`] is supported.
+    pub supports_metadata: bool,
+}
+
+impl Capabilities {
+    /// Every operation is supported.
+    pub const ALL: Self = Self {
+        can_add: true,
+        can_remove: true,
+        can_replace: true,
+        supports_metadata: true,
+    };
+}
+
+/// An anomaly found in the structure of a store by [`Backend::health_check`].
+///
+/// Each variant holds a description of where the anomaly is, such as a path.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// Something other than an entry is where entries are stored.
+    UnexpectedItem(String),
+    /// An entry has the wrong extension.
+    WrongExtension(String),
+    /// An entry's name is not a valid UUID.
+    InvalidUuid(String),
+    /// An entry is empty, so it cannot be decrypted.
+    EmptyEntry(String),
+    /// The directory that holds the entries is missing.
+    MissingEntries(String),
+}
+
+impl Warning {
+    /// Describes how [`Backend::repair`] fixes the anomaly, or returns `None`
+    /// if it must be fixed by hand.
+    pub fn repair_action(&self) -> Option<String> {
+        match self {
+            Warning::WrongExtension(location) => {
+                Some(format!("Rename {} to .age", location))
+            }
+            Warning::EmptyEntry(location) => {
+                Some(format!("Remove empty entry {}", location))
+            }
+            Warning::MissingEntries(location) => {
+                Some(format!("Create entries directory {}", location))
+            }
+            Warning::UnexpectedItem(_) | Warning::InvalidUuid(_) => None,
+        }
+    }
+
+    /// Returns whether repairing the anomaly removes data.
+    pub fn is_destructive(&self) -> bool {
+        matches!(self, Warning::EmptyEntry(_))
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::UnexpectedItem(location) => {
+                write!(f, "Unexpected item in entries: {}", location)
+            }
+            Warning::WrongExtension(location) => {
+                write!(f, "Entry has the wrong extension: {}", location)
+            }
+            Warning::InvalidUuid(location) => {
+                write!(f, "Entry name is not a valid UUID: {}", location)
+            }
+            Warning::EmptyEntry(location) => {
+                write!(f, "Entry is empty: {}", location)
+            }
+            Warning::MissingEntries(location) => {
+                write!(f, "Entries directory is missing: {}", location)
+            }
+        }
+    }
 }
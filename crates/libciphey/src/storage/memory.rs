@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, Cursor, Write};
+use std::sync::{Arc, Mutex};
+
+use uuid::Uuid;
+
+use super::{Backend, Reference};
+
+type Buffer = Arc<Mutex<Vec<u8>>>;
+
+/// A store that keeps every entry in memory.
+///
+/// Clones of an `InMemory` store share the same entries. This is useful for
+/// testing and for platforms without a filesystem.
+#[derive(Clone, Default)]
+pub struct InMemory {
+    entries: Arc<Mutex<Option<HashMap<Uuid, Buffer>>>>,
+}
+
+/// A reference to an entry in an [`InMemory`] store.
+#[derive(Clone)]
+pub struct Entry {
+    uuid: Uuid,
+    data: Buffer,
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "memory:{}", self.uuid)
+    }
+}
+
+/// A writer that appends to the data of an [`Entry`].
+pub struct EntryWriter(Buffer);
+
+impl Write for EntryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Reference for Entry {
+    type Reader = Cursor<Vec<u8>>;
+    type Writer = EntryWriter;
+
+    /// Returns a reader over a snapshot of the entry's current data.
+    fn reader(&self) -> Result<Self::Reader, io::Error> {
+        Ok(Cursor::new(self.data.lock().unwrap().clone()))
+    }
+
+    /// Returns a writer that replaces the entry's data.
+    fn writer(&mut self) -> Result<Self::Writer, io::Error> {
+        self.data.lock().unwrap().clear();
+        Ok(EntryWriter(self.data.clone()))
+    }
+}
+
+impl InMemory {
+    fn not_created() -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, "store has not been created")
+    }
+}
+
+impl Backend for InMemory {
+    type Reference = Entry;
+
+    fn create(&mut self) -> Result<(), io::Error> {
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.is_some() {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+
+        *entries = Some(HashMap::new());
+        Ok(())
+    }
+
+    /// The store only lives in the memory of the process.
+    fn location(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn entries(&self) -> Result<HashMap<Uuid, Self::Reference>, io::Error> {
+        let entries = self.entries.lock().unwrap();
+        let entries = entries.as_ref().ok_or_else(Self::not_created)?;
+
+        Ok(entries
+            .iter()
+            .map(|(uuid, data)| {
+                let entry = Entry {
+                    uuid: *uuid,
+                    data: data.clone(),
+                };
+                (*uuid, entry)
+            })
+            .collect())
+    }
+
+    /// Adds an entry to the store.
+    ///
+    /// Like other backends, an entry can never be overwritten by adding
+    /// another entry with the same UUID.
+    fn add_entry(&mut self, uuid: &Uuid) -> Result<Self::Reference, io::Error> {
+        let mut entries = self.entries.lock().unwrap();
+        let entries = entries.as_mut().ok_or_else(Self::not_created)?;
+
+        if entries.contains_key(uuid) {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+
+        let data = Buffer::default();
+        entries.insert(*uuid, data.clone());
+
+        Ok(Entry { uuid: *uuid, data })
+    }
+
+    /// Swaps in the new data under the lock, so readers see either the old or
+    /// the new data.
+    fn replace_entry(
+        &mut self,
+        uuid: &Uuid,
+        data: &[u8],
+    ) -> Result<Self::Reference, io::Error> {
+        let entries = self.entries.lock().unwrap();
+        let entries = entries.as_ref().ok_or_else(Self::not_created)?;
+
+        let buffer = entries
+            .get(uuid)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        *buffer.lock().unwrap() = data.to_vec();
+
+        Ok(Entry {
+            uuid: *uuid,
+            data: buffer.clone(),
+        })
+    }
+
+    fn remove_entry(&mut self, uuid: &Uuid) -> Result<(), io::Error> {
+        let mut entries = self.entries.lock().unwrap();
+        let entries = entries.as_mut().ok_or_else(Self::not_created)?;
+
+        entries
+            .remove(uuid)
+            .map(drop)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+}
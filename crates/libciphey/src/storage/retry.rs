@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use uuid::Uuid;
+
+use super::{Backend, Capabilities, Warning};
+
+/// A wrapper around a storage backend that retries operations which fail with
+/// transient errors, such as those from a flaky network filesystem.
+///
+/// Listing and adding entries are retried up to `retries` times when they
+/// fail with [`ErrorKind::Interrupted`], [`ErrorKind::TimedOut`], or
+/// [`ErrorKind::WouldBlock`]. The delay between attempts starts at `delay`
+/// and doubles after each attempt. Any other error, or the error of the final
+/// attempt, is returned as is.
+///
+/// Removing and replacing entries are never retried. The default
+/// [`Backend::replace_entry`] removes the entry before adding it again, so
+/// retrying it after a failure to add would only fail to find the entry.
+pub struct Retrying<S> {
+    inner: S,
+    retries: u32,
+    delay: Duration,
+}
+
+impl<S: Backend> Retrying<S> {
+    pub fn new(inner: S, retries: u32, delay: Duration) -> Self {
+        Self {
+            inner,
+            retries,
+            delay,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+/// Calls `operation` until it succeeds, fails with an error that is not
+/// transient, or has been retried `retries` times.
+fn retry<T, F>(
+    retries: u32,
+    mut delay: Duration,
+    mut operation: F,
+) -> Result<T, io::Error>
+where
+    F: FnMut() -> Result<T, io::Error>,
+{
+    for _ in 0..retries {
+        match operation() {
+            Err(e) if is_transient(&e) => {
+                thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+            }
+            result => return result,
+        }
+    }
+
+    operation()
+}
+
+/// Returns `true` if the operation that caused the error may succeed if it is
+/// attempted again.
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::Interrupted | ErrorKind::TimedOut | ErrorKind::WouldBlock
+    )
+}
+
+impl<S: Backend> Backend for Retrying<S> {
+    type Reference = S::Reference;
+
+    fn create(&mut self) -> Result<(), io::Error> {
+        self.inner.create()
+    }
+
+    fn entries(&self) -> Result<HashMap<Uuid, Self::Reference>, io::Error> {
+        retry(self.retries, self.delay, || self.inner.entries())
+    }
+
+    fn entries_modified_since(
+        &self,
+        since: SystemTime,
+    ) -> Result<HashMap<Uuid, Self::Reference>, io::Error> {
+        retry(self.retries, self.delay, || {
+            self.inner.entries_modified_since(since)
+        })
+    }
+
+    fn add_entry(&mut self, uuid: &Uuid) -> Result<Self::Reference, io::Error> {
+        let inner = &mut self.inner;
+        retry(self.retries, self.delay, || inner.add_entry(uuid))
+    }
+
+    fn remove_entry(&mut self, uuid: &Uuid) -> Result<(), io::Error> {
+        self.inner.remove_entry(uuid)
+    }
+
+    fn replace_entry(
+        &mut self,
+        uuid: &Uuid,
+        data: &[u8],
+    ) -> Result<Self::Reference, io::Error> {
+        self.inner.replace_entry(uuid, data)
+    }
+
+    fn health_check(&self) -> Result<Vec<Warning>, io::Error> {
+        self.inner.health_check()
+    }
+
+    fn repair(&mut self, warning: &Warning) -> Result<(), io::Error> {
+        self.inner.repair(warning)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn is_read_only(&self) -> Result<bool, io::Error> {
+        self.inner.is_read_only()
+    }
+
+    fn location(&self) -> String {
+        self.inner.location()
+    }
+}
@@ -0,0 +1,180 @@
+//! These tests aim to ensure that the generic storage helpers behave as
+//! expected.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Write};
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::storage::memory::{Entry, InMemory};
+use crate::storage::retry::Retrying;
+use crate::storage::Backend;
+
+// A backend that fails with the provided error kind a number of times before
+// delegating to an in-memory store.
+struct Flaky {
+    inner: InMemory,
+    failures: Cell<u32>,
+    kind: ErrorKind,
+}
+
+impl Flaky {
+    fn new(failures: u32, kind: ErrorKind) -> Self {
+        let mut inner = InMemory::default();
+        inner.create().unwrap();
+
+        Self {
+            inner,
+            failures: Cell::new(failures),
+            kind,
+        }
+    }
+
+    fn fail(&self) -> Result<(), io::Error> {
+        match self.failures.get() {
+            0 => Ok(()),
+            n => {
+                self.failures.set(n - 1);
+                Err(io::Error::from(self.kind))
+            }
+        }
+    }
+}
+
+impl Backend for Flaky {
+    type Reference = Entry;
+
+    fn create(&mut self) -> Result<(), io::Error> {
+        self.inner.create()
+    }
+
+    fn location(&self) -> String {
+        self.inner.location()
+    }
+
+    fn entries(&self) -> Result<HashMap<Uuid, Entry>, io::Error> {
+        self.fail()?;
+        self.inner.entries()
+    }
+
+    fn add_entry(&mut self, uuid: &Uuid) -> Result<Entry, io::Error> {
+        self.fail()?;
+        self.inner.add_entry(uuid)
+    }
+
+    fn remove_entry(&mut self, uuid: &Uuid) -> Result<(), io::Error> {
+        self.inner.remove_entry(uuid)
+    }
+}
+
+#[test]
+fn test_retry_transient_errors() {
+    let flaky = Flaky::new(3, ErrorKind::Interrupted);
+    let mut storage = Retrying::new(flaky, 3, Duration::from_millis(1));
+
+    // Each operation succeeds once the failures run out.
+    storage.add_entry(&Uuid::new_v4()).unwrap();
+    assert_eq!(storage.entries().unwrap().len(), 1);
+}
+
+#[test]
+fn test_retry_limit() {
+    let flaky = Flaky::new(3, ErrorKind::TimedOut);
+    let storage = Retrying::new(flaky, 2, Duration::from_millis(1));
+
+    // The error of the final attempt is returned.
+    let err = storage.entries().err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert!(storage.entries().is_ok());
+}
+
+#[test]
+fn test_no_retry_by_default() {
+    let flaky = Flaky::new(1, ErrorKind::WouldBlock);
+    let storage = Retrying::new(flaky, 0, Duration::from_millis(1));
+
+    assert!(storage.entries().is_err());
+    assert!(storage.entries().is_ok());
+}
+
+#[test]
+fn test_no_retry_permanent_errors() {
+    let flaky = Flaky::new(1, ErrorKind::PermissionDenied);
+    let storage = Retrying::new(flaky, 3, Duration::from_millis(1));
+
+    let err = storage.entries().err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn test_no_retry_replace() {
+    let mut flaky = Flaky::new(0, ErrorKind::Interrupted);
+    let uuid = Uuid::new_v4();
+    flaky.inner.add_entry(&uuid).unwrap();
+    flaky.failures.set(1);
+    let mut storage = Retrying::new(flaky, 3, Duration::from_millis(1));
+
+    // The default replace removes the entry before failing to add it, so the
+    // error of adding it is returned rather than retrying the removal.
+    let err = storage.replace_entry(&uuid, b"new").err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::Interrupted);
+}
+
+#[test]
+fn test_default_capabilities() {
+    use crate::storage::Capabilities;
+
+    // The in-memory store does not track modification times.
+    let expected = Capabilities {
+        supports_metadata: false,
+        ..Capabilities::ALL
+    };
+    assert_eq!(InMemory::default().capabilities(), expected);
+
+    let retrying = Retrying::new(InMemory::default(), 1, Duration::ZERO);
+    assert_eq!(retrying.capabilities(), expected);
+}
+
+#[test]
+fn test_sorted_entries() {
+    let mut storage = InMemory::default();
+    storage.create().unwrap();
+
+    let mut uuids: Vec<Uuid> = (0..8).map(|_| Uuid::new_v4()).collect();
+    for uuid in &uuids {
+        storage.add_entry(uuid).unwrap();
+    }
+    uuids.sort();
+
+    let sorted: Vec<Uuid> = storage
+        .sorted_entries()
+        .unwrap()
+        .into_iter()
+        .map(|(uuid, _)| uuid)
+        .collect();
+    assert_eq!(sorted, uuids);
+}
+
+#[test]
+fn test_replace_entry() {
+    use std::io::Read;
+
+    use crate::storage::Reference;
+
+    let mut storage = InMemory::default();
+    storage.create().unwrap();
+
+    let uuid = Uuid::new_v4();
+    let mut reference = storage.add_entry(&uuid).unwrap();
+    reference.writer().unwrap().write_all(b"old").unwrap();
+
+    storage.replace_entry(&uuid, b"new").unwrap();
+    let mut data = Vec::new();
+    reference.reader().unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"new");
+
+    let result = storage.replace_entry(&Uuid::new_v4(), b"new");
+    assert_eq!(result.err().map(|e| e.kind()), Some(ErrorKind::NotFound));
+}
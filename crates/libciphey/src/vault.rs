@@ -0,0 +1,205 @@
+use std::fmt::Display;
+use std::io;
+use std::marker::PhantomData;
+
+use uuid::Uuid;
+
+use crate::crypto::{self, Encrypted};
+use crate::filetype::Filetype;
+use crate::storage::{self, Reference};
+
+/// A store of entries of type `F`, encrypted with the crypto backend `C` and
+/// persisted in the storage backend `S`.
+///
+/// A `Vault` handles serializing and encrypting entries on their way into the
+/// store, and decrypting and deserializing them on their way out.
+pub struct Vault<C, S, F> {
+    crypto: C,
+    storage: S,
+    filetype: PhantomData<F>,
+}
+
+impl<C, S, F> Vault<C, S, F>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    F: Filetype,
+{
+    pub fn new(crypto: C, storage: S) -> Self {
+        Self {
+            crypto,
+            storage,
+            filetype: PhantomData,
+        }
+    }
+
+    pub fn crypto(&self) -> &C {
+        &self.crypto
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn storage_mut(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    /// Encrypts `entry` to `recipients` and adds it to the store under a new
+    /// random UUID, which is returned.
+    #[cfg(feature = "v4")]
+    pub fn add(
+        &mut self,
+        entry: F,
+        recipients: Vec<C::Recipient>,
+    ) -> Result<Uuid, Error> {
+        let uuid = Uuid::new_v4();
+        self.add_with_uuid(entry, recipients, uuid)
+    }
+
+    /// Encrypts `entry` to `recipients` and adds it to the store under
+    /// `uuid`, which is returned. Useful on platforms without a source of
+    /// randomness for [`Vault::add`].
+    ///
+    /// Fails without writing anything if [`Filetype::validate`] rejects the
+    /// entry.
+    pub fn add_with_uuid(
+        &mut self,
+        entry: F,
+        recipients: Vec<C::Recipient>,
+        uuid: Uuid,
+    ) -> Result<Uuid, Error> {
+        entry.validate().map_err(|e| Error::Filetype(Box::new(e)))?;
+        write_entry(&self.crypto, &mut self.storage, uuid, entry, recipients)?;
+
+        Ok(uuid)
+    }
+
+    /// Decrypts and deserializes the entry with the provided UUID.
+    pub fn get(&self, uuid: &Uuid) -> Result<F, Error> {
+        let reference = self
+            .storage
+            .entries()?
+            .remove(uuid)
+            .ok_or(Error::NotFound(*uuid))?;
+
+        self.decrypt(&reference)
+    }
+
+    /// Returns an iterator that decrypts and deserializes every entry in the
+    /// store, ordered by UUID.
+    ///
+    /// Entries are decrypted lazily as the iterator is advanced. An entry that
+    /// fails to decrypt or parse yields an error without ending the iteration.
+    pub fn list(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<(Uuid, F), Error>> + '_, Error>
+    {
+        let references = self.storage.sorted_entries()?;
+
+        Ok(references.into_iter().map(|(uuid, reference)| {
+            self.decrypt(&reference).map(|entry| (uuid, entry))
+        }))
+    }
+
+    /// Removes the entry with the provided UUID from the store.
+    pub fn remove(&mut self, uuid: &Uuid) -> Result<(), Error> {
+        self.storage.remove_entry(uuid).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::NotFound(*uuid),
+            _ => Error::Storage(e),
+        })
+    }
+
+    fn decrypt(&self, reference: &S::Reference) -> Result<F, Error> {
+        read_entry(&self.crypto, reference)
+    }
+}
+
+/// Encrypts `entry` to `recipients` and adds it to `storage` under `uuid`,
+/// returning a reference to the new entry.
+///
+/// This is how a [`Vault`] writes entries, for callers that borrow their
+/// backends instead of handing them to a vault. Unlike [`Vault::add`], the
+/// entry is not validated. If the entry cannot be written in full, it is
+/// removed again, so that no empty or truncated entry is left behind.
+pub fn write_entry<C, S, F>(
+    crypto: &C,
+    storage: &mut S,
+    uuid: Uuid,
+    entry: F,
+    recipients: Vec<C::Recipient>,
+) -> Result<S::Reference, Error>
+where
+    C: crypto::Backend,
+    S: storage::Backend,
+    F: Filetype,
+{
+    let mut reference = storage.add_entry(&uuid)?;
+    // A backend that refuses to open the writer has not written anything,
+    // and may refuse because the entry already exists, so there is nothing
+    // to remove yet.
+    let writer = reference.writer()?;
+
+    let written = crypto
+        .encrypt_output(writer, recipients)
+        .map_err(|e| Error::Crypto(Box::new(e)))
+        .and_then(|mut encrypted| {
+            entry.serialize(&mut encrypted)?;
+            encrypted.finish()?;
+            Ok(())
+        });
+
+    if let Err(e) = written {
+        // The error of writing the entry is more useful than that of
+        // removing it.
+        let _ = storage.remove_entry(&uuid);
+        return Err(e);
+    }
+
+    Ok(reference)
+}
+
+/// Decrypts and deserializes the entry at `reference`.
+///
+/// This is how a [`Vault`] reads entries, for callers that borrow their
+/// backends instead of handing them to a vault.
+pub fn read_entry<C, R, F>(crypto: &C, reference: &R) -> Result<F, Error>
+where
+    C: crypto::Backend,
+    R: Reference,
+    F: Filetype,
+{
+    let mut decrypted = crypto
+        .decrypt_input(reference.reader()?)
+        .map_err(|e| Error::Crypto(Box::new(e)))?;
+
+    F::deserialize(&mut decrypted).map_err(|e| Error::Filetype(Box::new(e)))
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Storage(io::Error),
+    Crypto(Box<dyn std::error::Error + Send + Sync>),
+    Filetype(Box<dyn std::error::Error + Send + Sync>),
+    /// No entry with the provided UUID exists in the store.
+    NotFound(Uuid),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Storage(e) => e.fmt(f),
+            Error::Crypto(e) => e.fmt(f),
+            Error::Filetype(e) => e.fmt(f),
+            Error::NotFound(uuid) => write!(f, "No entry with UUID {}", uuid),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Storage(e)
+    }
+}